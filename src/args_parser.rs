@@ -1,17 +1,52 @@
+use crate::network_params::Network;
 use std::env;
 use std::fs::canonicalize;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
+
+/// What the program was asked to do
+pub enum Command {
+    /// Run the node with the given config file
+    Run(PathBuf),
+    /// Check a blocks or headers backup file of the given network and exit, without starting the node
+    VerifyBackup(PathBuf, Network),
+}
 
 fn help() {
     eprintln!(
         "Usage:
 `$ ./nodo-rustico /path/to/node.conf`
+`$ ./nodo-rustico verify-backup /path/to/backup.dat [mainnet|testnet|regtest|signet]`
 "
     );
 }
 
-pub fn get_args() -> PathBuf {
+fn resolve_path(arg: &str, description: &str) -> PathBuf {
+    match canonicalize(Path::new(arg)) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Error: Couldn't resolve path to {description}. {e}");
+            exit(2);
+        }
+    }
+}
+
+/// Parses the backup file and the network it belongs to, testnet if none is given
+fn verify_backup_args(args: &[String]) -> Command {
+    let network = match args.get(3).map(|network| Network::from_str(network)) {
+        None => Network::Testnet,
+        Some(Ok(network)) => network,
+        Some(Err(e)) => {
+            eprintln!("Error: {e}");
+            help();
+            exit(1);
+        }
+    };
+    Command::VerifyBackup(resolve_path(&args[2], "backup file"), network)
+}
+
+pub fn get_args() -> Command {
     let args: Vec<String> = env::args().collect();
 
     match args.len() {
@@ -21,13 +56,13 @@ pub fn get_args() -> PathBuf {
             help();
             exit(1);
         }
-        2 => match canonicalize(Path::new(&args[1])) {
-            Ok(path) => path,
-            Err(e) => {
-                eprintln!("Error: Couldn't resolve path to config file. {e}");
-                exit(2);
-            }
-        },
+        2 if args[1] == "verify-backup" => {
+            eprintln!("Error: Backup file not provided");
+            help();
+            exit(1);
+        }
+        2 => Command::Run(resolve_path(&args[1], "config file")),
+        3 | 4 if args[1] == "verify-backup" => verify_backup_args(&args),
         _ => {
             eprintln!("Error: Too many arguments, only one was expected.");
             help();
@@ -0,0 +1,73 @@
+use crate::messages::block_header::HEADER_RECORD_SIZE;
+use crate::messages::{Block, Headers};
+use crate::network_params::{Network, NetworkParams};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::exit;
+
+/// Returns true if the bytes look like a headers backup: fixed size records, each ending with an empty transaction count
+fn is_headers_backup(bytes: &[u8]) -> bool {
+    let record_size = HEADER_RECORD_SIZE as usize;
+    !bytes.is_empty()
+        && bytes.len() % record_size == 0
+        && bytes
+            .chunks(record_size)
+            .all(|record| record[record_size - 1] == 0)
+}
+
+/// Checks every entry of a blocks or headers backup file of the given network, telling them apart by
+/// their layout. Returns a summary of what was checked, or the first error found with its offset.
+fn verify_backup(path: &Path, network_params: &NetworkParams) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    if is_headers_backup(&bytes) {
+        let count = Headers::verify_backup(&bytes, network_params)?;
+        return Ok(format!(
+            "Headers backup is valid, {} headers checked",
+            count
+        ));
+    }
+    let count = Block::verify_backup(&Block::decompress_backup(&bytes)?, network_params)?;
+    Ok(format!("Blocks backup is valid, {} blocks checked", count))
+}
+
+/// Verifies the backup file and exits without starting the node, with status 1 if it's invalid
+pub fn run(path: &Path, network: Network) -> io::Result<()> {
+    let network_params = NetworkParams::new(network, &[])?;
+    match verify_backup(path, &network_params) {
+        Ok(summary) => {
+            println!("{}", summary);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error: Backup {} is invalid. {}", path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrupt_blocks_backup_is_reported() -> io::Result<()> {
+        let file_name = "test_corrupt_backup.dat";
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        // a record size past the end of the file, and a record too short for a block
+        let mut messages = vec![];
+        for backup in [&[0xff; 9][..], &[0x03, 0x01, 0x02, 0x03][..]] {
+            fs::write(file_name, backup)?;
+            messages.push(verify_backup(Path::new(file_name), &regtest).map_err(|e| e.to_string()));
+        }
+        fs::remove_file(file_name)?;
+
+        assert!(messages[0]
+            .as_ref()
+            .is_err_and(|message| message.contains("Truncated block backup")));
+        assert!(messages[1]
+            .as_ref()
+            .is_err_and(|message| message.contains("Block at offset 0")));
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use crate::messages::{BlockHeader, HashId, Hashable};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Schedules the block downloads: the blocks waiting to be requested, and the ones requested from each peer
+/// that haven't arrived yet. Each request goes to a single peer, the one with the fewest blocks in flight,
+/// and a peer with `max_per_peer` of them gets no more requests until it delivers some, so a slow peer
+/// doesn't hold up hundreds of blocks. Blocks a peer doesn't deliver in time, or says it doesn't have, are
+/// queued again, and a peer that let one time out is only asked again when the others have no room.
+#[derive(Debug)]
+pub struct BlockRequests {
+    queue: VecDeque<BlockHeader>,
+    in_flight: HashMap<SocketAddr, Vec<(BlockHeader, Instant)>>, // along with when each was requested
+    slow_peers: HashSet<SocketAddr>, // peers that let a request time out, until they deliver a block
+    max_per_peer: usize,
+}
+
+impl BlockRequests {
+    pub fn new(max_per_peer: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            in_flight: HashMap::new(),
+            slow_peers: HashSet::new(),
+            max_per_peer,
+        }
+    }
+
+    /// Queues the blocks to be requested once a peer has room for them
+    pub fn queue(&mut self, headers: &[BlockHeader]) {
+        self.queue.extend(headers);
+    }
+
+    /// Takes up to `count` queued blocks for the given peer with the most room left, preferring the ones
+    /// that aren't slow, recording them as requested from it now. None if the queue is empty or every peer
+    /// is at the limit.
+    pub fn next_request(
+        &mut self,
+        peers: &[SocketAddr],
+        count: usize,
+        now: Instant,
+    ) -> Option<(SocketAddr, Vec<BlockHeader>)> {
+        let (peer, room) = peers
+            .iter()
+            .map(|peer| {
+                (
+                    *peer,
+                    self.max_per_peer.saturating_sub(self.in_flight(peer)),
+                )
+            })
+            .max_by_key(|(peer, room)| (*room > 0 && !self.slow_peers.contains(peer), *room))?;
+        let count = count.min(room).min(self.queue.len());
+        let headers: Vec<BlockHeader> = self.queue.drain(..count).collect();
+        if headers.is_empty() {
+            return None;
+        }
+        self.in_flight
+            .entry(peer)
+            .or_default()
+            .extend(headers.iter().map(|header| (*header, now)));
+        Some((peer, headers))
+    }
+
+    /// Returns the number of blocks requested from the peer that haven't arrived yet
+    pub fn in_flight(&self, peer: &SocketAddr) -> usize {
+        self.in_flight.get(peer).map_or(0, Vec::len)
+    }
+
+    /// Forgets the block once it arrives, making room for another request at the peers it was asked from,
+    /// which are no longer slow
+    pub fn delivered(&mut self, hash: &HashId) {
+        for (peer, requested) in self.in_flight.iter_mut() {
+            let before = requested.len();
+            requested.retain(|(header, _requested_at)| header.hash() != *hash);
+            if requested.len() < before {
+                self.slow_peers.remove(peer);
+            }
+        }
+    }
+
+    /// Queues again, ahead of the others, the blocks the peer answered it doesn't have
+    pub fn not_found(&mut self, peer: &SocketAddr, hashes: &[HashId]) {
+        let Some(requested) = self.in_flight.get_mut(peer) else {
+            return;
+        };
+        let (missing, kept) = requested
+            .drain(..)
+            .partition(|(header, _requested_at)| hashes.contains(&header.hash()));
+        *requested = kept;
+        self.requeue(missing);
+    }
+
+    /// Queues again, ahead of the others, every block requested from the peer, as when the request
+    /// couldn't be sent to it
+    pub fn requeue_peer(&mut self, peer: &SocketAddr) {
+        let requested = self.in_flight.remove(peer).unwrap_or_default();
+        self.requeue(requested);
+    }
+
+    /// Queues again, ahead of the others, the blocks requested from peers other than the given ones, which
+    /// disconnected before delivering them
+    pub fn requeue_gone_peers(&mut self, peers: &[SocketAddr]) {
+        let gone: Vec<SocketAddr> = self
+            .in_flight
+            .keys()
+            .filter(|peer| !peers.contains(peer))
+            .copied()
+            .collect();
+        for peer in gone {
+            self.slow_peers.remove(&peer);
+            self.requeue_peer(&peer);
+        }
+    }
+
+    /// Queues again, ahead of the others, the blocks requested longer than `timeout` ago, marking the peers
+    /// they were asked from as slow. Returns the peers that just became slow.
+    pub fn requeue_timed_out(&mut self, timeout: Duration, now: Instant) -> Vec<SocketAddr> {
+        let mut timed_out = Vec::new();
+        let mut newly_slow = Vec::new();
+        for (peer, requested) in self.in_flight.iter_mut() {
+            let (late, kept) = requested
+                .drain(..)
+                .partition(|(_header, requested_at)| now.duration_since(*requested_at) >= timeout);
+            *requested = kept;
+            if late.is_empty() {
+                continue;
+            }
+            if self.slow_peers.insert(*peer) {
+                newly_slow.push(*peer);
+            }
+            timed_out.extend(late);
+        }
+        self.requeue(timed_out);
+        newly_slow
+    }
+
+    fn requeue(&mut self, requested: Vec<(BlockHeader, Instant)>) {
+        for (header, _requested_at) in requested.into_iter().rev() {
+            self.queue.push_front(header);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(count: u32) -> Vec<BlockHeader> {
+        (0..count)
+            .map(|nonce| {
+                BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, nonce)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_peer_is_not_assigned_more_than_the_limit() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 18333));
+        let mut requests = BlockRequests::new(16);
+        let now = Instant::now();
+        let headers = headers(40);
+        requests.queue(&headers);
+
+        let (_peer, first) = requests.next_request(&[peer], 20, now).unwrap();
+        assert_eq!(first, headers[..16]);
+        assert!(requests.next_request(&[peer], 20, now).is_none());
+        assert_eq!(requests.in_flight(&peer), 16);
+
+        // delivering a block frees room for a single other one
+        requests.delivered(&first[0].hash());
+        let (_peer, second) = requests.next_request(&[peer], 20, now).unwrap();
+        assert_eq!(second, headers[16..17]);
+        assert_eq!(requests.in_flight(&peer), 16);
+
+        // once the peer disconnects its blocks are requested again first
+        requests.requeue_gone_peers(&[]);
+        assert_eq!(requests.in_flight(&peer), 0);
+        let other = SocketAddr::from(([127, 0, 0, 2], 18333));
+        let (_peer, third) = requests.next_request(&[other], 20, now).unwrap();
+        assert_eq!(third[0], headers[1]);
+    }
+
+    #[test]
+    fn test_late_and_not_found_blocks_are_requested_again_from_another_peer() {
+        let slow = SocketAddr::from(([127, 0, 0, 1], 18333));
+        let other = SocketAddr::from(([127, 0, 0, 2], 18333));
+        let mut requests = BlockRequests::new(4);
+        let now = Instant::now();
+        let headers = headers(8);
+        requests.queue(&headers);
+        let timeout = Duration::from_secs(60);
+
+        requests.next_request(&[slow], 2, now).unwrap();
+        assert!(requests
+            .requeue_timed_out(timeout, now + Duration::from_secs(30))
+            .is_empty());
+        let later = now + timeout;
+        assert_eq!(requests.requeue_timed_out(timeout, later), vec![slow]);
+        assert_eq!(requests.in_flight(&slow), 0);
+
+        // the slow peer is only asked once the other one is at the limit
+        let (peer, retried) = requests.next_request(&[slow, other], 2, later).unwrap();
+        assert_eq!((peer, retried), (other, headers[..2].to_vec()));
+        requests.next_request(&[slow, other], 2, later).unwrap();
+        let (peer, _headers) = requests.next_request(&[slow, other], 2, later).unwrap();
+        assert_eq!(peer, slow);
+
+        // a block the peer doesn't have is requested again first
+        requests.not_found(&other, &[headers[1].hash()]);
+        assert_eq!(requests.in_flight(&other), 3);
+        let (_peer, missing) = requests.next_request(&[other], 1, later).unwrap();
+        assert_eq!(missing, headers[1..2]);
+    }
+}
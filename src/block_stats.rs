@@ -0,0 +1,139 @@
+use crate::messages::{Block, HashId, Hashable};
+use crate::utxo::OutPoint;
+
+/// Aggregates of a block, like the ones reported by `getblockstats`. Fee rates are in satoshis per kilo
+/// virtual byte and, like the totals, leave the coinbase out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStats {
+    pub hash: HashId,
+    pub height: usize,
+    pub txs: usize,
+    pub total_out: u64, // value of the outputs other than the coinbase ones
+    pub total_fee: u64,
+    pub avg_fee_rate: u64, // total fee over the total virtual size
+    pub median_fee_rate: u64,
+    pub weight: usize,
+    pub size: usize,
+    pub subsidy: u64,
+}
+
+impl BlockStats {
+    /// Computes the stats of the block given its subsidy and the values of the outputs its inputs spend,
+    /// None if one of them is unknown
+    pub fn new(
+        block: &Block,
+        subsidy: u64,
+        prevout_value: impl Fn(&OutPoint) -> Option<u64>,
+    ) -> Option<Self> {
+        let (mut total_out, mut total_fee, mut total_vsize) = (0, 0, 0);
+        let mut fee_rates = vec![];
+        for txn in block.txns.iter().skip(1) {
+            let fee = txn.fee(&prevout_value)?;
+            total_out += txn.get_total_output_value();
+            total_fee += fee;
+            total_vsize += txn.vsize();
+            fee_rates.push(fee * 1000 / txn.vsize());
+        }
+        fee_rates.sort_unstable();
+        Some(Self {
+            hash: block.hash(),
+            height: block.header.height,
+            txs: block.txns.len(),
+            total_out,
+            total_fee,
+            avg_fee_rate: (total_fee * 1000).checked_div(total_vsize).unwrap_or(0),
+            median_fee_rate: median(&fee_rates),
+            weight: block.weight(),
+            size: block.size(),
+            subsidy,
+        })
+    }
+}
+
+/// Returns the middle value of the sorted values, the average of the two middle ones for an even count
+fn median(sorted: &[u64]) -> u64 {
+    let middle = sorted.len() / 2;
+    match sorted.len() {
+        0 => 0,
+        len if len % 2 == 0 => (sorted[middle - 1] + sorted[middle]) / 2,
+        _ => sorted[middle],
+    }
+}
+
+impl std::fmt::Display for BlockStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} height: {} txs: {} total out: {} fees: {} avg fee rate: {} median fee rate: {} weight: {} size: {} subsidy: {}",
+            self.hash,
+            self.height,
+            self.txs,
+            self.total_out,
+            self.total_fee,
+            self.avg_fee_rate,
+            self.median_fee_rate,
+            self.weight,
+            self.size,
+            self.subsidy
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::BlockHeader;
+    use crate::raw_transaction::tx_input::{CoinBaseInput, TxInputType};
+    use crate::raw_transaction::tx_output::TxOutput;
+    use crate::raw_transaction::RawTransaction;
+    use crate::test_fixtures::PAYMENT_TX_HEX;
+    use crate::utility::{decode_hex, to_io_err};
+    use std::io::{self, Cursor};
+
+    #[test]
+    fn test_stats_of_a_block_with_one_payment() -> io::Result<()> {
+        let bytes = decode_hex(PAYMENT_TX_HEX).map_err(to_io_err)?;
+        let payment = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+        let coinbase_input = CoinBaseInput {
+            _hash: HashId::default(),
+            _index: u32::MAX,
+            _script_bytes: 4,
+            height: 1,
+            _coinbase_script: vec![],
+            _sequence: u32::MAX,
+        };
+        let coinbase = RawTransaction {
+            version: 1,
+            tx_in_count: 1,
+            tx_in: TxInputType::CoinBaseInput(coinbase_input),
+            tx_out_count: 1,
+            tx_out: vec![TxOutput {
+                value: 5_000_010_000,
+                pk_script_bytes: 0,
+                pk_script: vec![],
+            }],
+            lock_time: 0,
+            witnesses: vec![],
+        };
+        let header = BlockHeader::new(1, HashId::default(), None, coinbase.txid(), 0, 0, 0);
+        let block = Block::new(header, 2, vec![coinbase, payment]);
+
+        // the payment of 225 bytes spends 1110000 satoshis to pay out 1100000
+        let stats = BlockStats::new(&block, 5_000_000_000, |_| Some(1_110_000));
+        let expected = BlockStats {
+            hash: block.hash(),
+            height: 0,
+            txs: 2,
+            total_out: 1_100_000,
+            total_fee: 10_000,
+            avg_fee_rate: 44_444,
+            median_fee_rate: 44_444,
+            weight: 4 * 370,
+            size: 370,
+            subsidy: 5_000_000_000,
+        };
+        assert_eq!(stats, Some(expected));
+        assert_eq!(BlockStats::new(&block, 5_000_000_000, |_| None), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use crate::messages::HashId;
+use crate::utility::to_io_err;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Height and hash of the tallest connected block, shared with the threads waiting for it to grow.
+/// Waiting doesn't hold the network controller lock, so blocks keep being connected meanwhile.
+#[derive(Debug, Clone)]
+pub struct ChainTip {
+    state: Arc<(Mutex<(usize, HashId)>, Condvar)>,
+}
+
+impl ChainTip {
+    /// Creates the tip at the given block
+    pub fn new(height: usize, hash: HashId) -> Self {
+        Self {
+            state: Arc::new((Mutex::new((height, hash)), Condvar::new())),
+        }
+    }
+
+    /// Records a connected block, waking the waiting threads if it's taller than the tip
+    pub fn block_connected(&self, height: usize, hash: HashId) -> io::Result<()> {
+        let (tip, new_block) = &*self.state;
+        let mut tip = tip.lock().map_err(to_io_err)?;
+        if height > tip.0 {
+            *tip = (height, hash);
+            new_block.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Returns the height and hash of the tip
+    pub fn tip(&self) -> io::Result<(usize, HashId)> {
+        let (tip, _new_block) = &*self.state;
+        Ok(*tip.lock().map_err(to_io_err)?)
+    }
+
+    /// Blocks until the tip reaches the given height and returns its hash then,
+    /// or fails with `TimedOut` if it doesn't within the timeout
+    pub fn wait_for_height(&self, height: usize, timeout: Duration) -> io::Result<HashId> {
+        let (tip, new_block) = &*self.state;
+        let tip = tip.lock().map_err(to_io_err)?;
+        let (tip, result) = new_block
+            .wait_timeout_while(tip, timeout, |(tip_height, _)| *tip_height < height)
+            .map_err(to_io_err)?;
+        if result.timed_out() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Tip is at height {}, below {}", tip.0, height),
+            ));
+        }
+        Ok(tip.1)
+    }
+}
+
+/// Status of a known chain tip, like the one reported by `getchaintips`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TipStatus {
+    Active,      // the tallest connected block
+    ValidFork,   // connected, but the tip of a branch off the active chain
+    HeadersOnly, // its block or one below it wasn't connected yet
+    Invalid,     // a block of its branch was rejected
+}
+
+/// A block no other known header builds on, or the tallest connected block
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainTipInfo {
+    pub hash: HashId,
+    pub height: usize,
+    pub branch_len: usize, // blocks from the tip down to the active chain, 0 for the active tip
+    pub status: TipStatus,
+}
+
+impl std::fmt::Display for ChainTipInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            TipStatus::Active => "active",
+            TipStatus::ValidFork => "valid-fork",
+            TipStatus::HeadersOnly => "headers-only",
+            TipStatus::Invalid => "invalid",
+        };
+        write!(
+            f,
+            "{} height: {} branch length: {} status: {}",
+            self.hash, self.height, self.branch_len, status
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_block_at_target_height_wakes_waiter() -> io::Result<()> {
+        let chain_tip = ChainTip::new(0, HashId::new([0; 32]));
+        let waiting_tip = chain_tip.clone();
+        let waiter = thread::spawn(move || waiting_tip.wait_for_height(3, Duration::from_secs(10)));
+
+        chain_tip.block_connected(1, HashId::new([1; 32]))?;
+        chain_tip.block_connected(3, HashId::new([3; 32]))?;
+        let hash = waiter
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Waiter panicked"))??;
+        assert_eq!(hash, HashId::new([3; 32]));
+
+        // a height already reached returns right away, a taller one times out
+        assert_eq!(
+            chain_tip.wait_for_height(2, Duration::ZERO)?,
+            HashId::new([3; 32])
+        );
+        let error = chain_tip
+            .wait_for_height(4, Duration::from_millis(10))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        Ok(())
+    }
+}
@@ -1,27 +1,161 @@
-use crate::logger::{Log, Logger};
+use crate::logger::{Log, Logger, MessageTracer};
+use crate::messages::block_header::Checkpoints;
 use crate::messages::constants::config::{
-    BLOCKS_FILE, HEADERS_FILE, LOG_FILE, PORT, QUIET, START_TIMESTAMP, TCP_TIMEOUT, VERBOSE,
+    ABANDON_PENDING_TX_TIMEOUT, BLOCKS_FILE, BLOCK_REQUEST_TIMEOUT, BROADCAST_PROPAGATION_TIMEOUT,
+    HEADERS_FILE, HEADER_STALL_TIMEOUT, LOG_FILE, MAX_BLOCKS_IN_FLIGHT_PER_PEER, MAX_FEE,
+    MAX_NON_FINAL_TX_BYTES, MAX_ORPHAN_TX_BYTES, MAX_TX_SIGOPS, MEMPOOL_EXPIRY_HOURS,
+    OVERVIEW_TX_COUNT, PORT, QUIET, START_TIMESTAMP, SYNC_STALL_TIMEOUT, TCP_TIMEOUT,
+    UTXO_SNAPSHOT_FILE, VERBOSE, WATCH_ADDRESSES_FILE,
 };
 use crate::messages::HashId;
-use crate::utility::get_parent_path;
+use crate::network_params::{Network, NetworkParams, COINBASE_MATURITY};
+use crate::socks5::OnionPeer;
+use crate::utility::{decode_hex, get_parent_path, to_io_err};
 use crate::wallet::Wallet;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Options of the wallets managed by the node
+#[derive(Clone)]
+struct WalletOptions {
+    wallets_dir: String,
+    default_wallet_addr: String,
+    watch_addresses_file: String,
+    hd_seed_file: String,
+    hd_accounts: u32,
+    hd_aggregate_change: bool,
+    partial_tx_dir: String,
+    spendable_fee_rate: u64,
+    min_confirmations: usize,
+    spend_own_change: bool,
+    abandon_pending_tx_seconds: u64,
+    overview_tx_count: usize,
+    warn_address_reuse: bool,
+    max_fee: u64,
+}
+
+/// Options of the connections with peers
+#[derive(Clone)]
+struct PeerOptions {
+    listen: bool,
+    proxy: Option<SocketAddr>,
+    trusted_peer: Option<SocketAddr>,
+    whitelist: Vec<WhitelistedPeer>,
+    onion_peers: Vec<OnionPeer>,
+    whitelist_allow_ibd: bool,
+    max_tx_sigops: usize,
+    block_relay_connections: usize,
+    held_txs: HeldTxOptions,
+    relay: bool,
+    require_peer_services: bool,
+    download_witness: bool,
+    broadcast_propagation_timeout_seconds: u64,
+    bandwidth: BandwidthOptions,
+}
+
+/// Options of the transactions held back from relay until their parents arrive or their lock time passes
+#[derive(Clone)]
+struct HeldTxOptions {
+    max_orphan_tx_bytes: usize,
+    hold_non_final_txs: bool,
+    max_non_final_tx_bytes: usize,
+}
+
+/// Options of the bandwidth spent serving and downloading blocks
+#[derive(Clone)]
+struct BandwidthOptions {
+    max_blocks_in_flight_per_peer: usize,
+    block_request_timeout_seconds: u64,
+    max_upload_target: u64,
+}
+
+/// A whitelisted peer, matching connections from any port of its ip unless a port was given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitelistedPeer {
+    ip: IpAddr,
+    port: Option<u16>,
+}
+
+impl WhitelistedPeer {
+    /// Returns true if the peer connects from the whitelisted ip, and port if one was given
+    pub fn matches(&self, peer: &SocketAddr) -> bool {
+        self.ip == peer.ip() && self.port.is_none_or(|port| port == peer.port())
+    }
+}
+
+impl From<SocketAddr> for WhitelistedPeer {
+    fn from(peer: SocketAddr) -> Self {
+        Self {
+            ip: peer.ip(),
+            port: Some(peer.port()),
+        }
+    }
+}
+
+impl From<IpAddr> for WhitelistedPeer {
+    fn from(ip: IpAddr) -> Self {
+        Self { ip, port: None }
+    }
+}
+
+impl FromStr for WhitelistedPeer {
+    type Err = io::Error;
+
+    /// Parses a peer in the format ip:port, or a bare ip matching any port
+    fn from_str(peer: &str) -> io::Result<Self> {
+        SocketAddr::from_str(peer)
+            .map(Self::from)
+            .or_else(|_| IpAddr::from_str(peer).map(Self::from))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Whitelisted peer is invalid"))
+    }
+}
+
+/// Returns true if the peer matches an entry of the whitelist
+pub fn whitelist_contains(whitelist: &[WhitelistedPeer], peer: &SocketAddr) -> bool {
+    whitelist.iter().any(|entry| entry.matches(peer))
+}
+
+/// Options of the files the chain and utxo set are backed up to
+#[derive(Clone)]
+struct StorageOptions {
+    headers_file: String,
+    blocks_file: String,
+    utxo_snapshot_file: String,
+    utxo_flush_interval: u64,
+    prune_below_height: usize,
+    max_headers_in_memory: usize,
+    stale_block_depth: usize,
+    compress_backups: bool,
+    mempool_file: String,
+    mempool_expiry_hours: u32,
+}
 
 #[derive(Clone)]
 pub struct Config {
     seed: String,
     port: u16,
+    peers: PeerOptions,
     start_timestamp: u32,
-    headers_file: String,
-    blocks_file: String,
+    storage: StorageOptions,
     tcp_timeout_seconds: u64,
+    header_stall_timeout_seconds: u64,
+    header_download_peers: usize,
+    sync_stall_timeout_seconds: u64,
+    sync_stall_reconnect: bool,
+    stop_at_height: usize,
+    rpc_port: u16,
+    max_download_kbps: u64,
     logger: Logger,
-    genesis_hash: HashId,
-    wallets_dir: String,
-    default_wallet_addr: String,
+    wallet: WalletOptions,
+    checkpoints: Checkpoints,
+    network_params: NetworkParams,
+    trace_messages: bool,
+    full_validation: bool,
+    headless: bool,
 }
 
 impl Config {
@@ -29,6 +163,60 @@ impl Config {
         self.tcp_timeout_seconds
     }
 
+    /// Returns the seconds to wait for headers before requesting them from another peer, None if disabled
+    pub fn get_header_stall_timeout(&self) -> Option<u64> {
+        Some(self.header_stall_timeout_seconds).filter(|timeout| *timeout > 0)
+    }
+
+    /// Returns the seconds without new headers or blocks after which sync is reported as stalled, None if disabled
+    pub fn get_sync_stall_timeout(&self) -> Option<u64> {
+        Some(self.sync_stall_timeout_seconds).filter(|timeout| *timeout > 0)
+    }
+
+    /// Returns true if peers found through the seed are connected when sync stalls
+    pub fn get_sync_stall_reconnect(&self) -> bool {
+        self.sync_stall_reconnect
+    }
+
+    /// Returns the number of peers headers are downloaded from in parallel, None if they're downloaded from one
+    pub fn get_header_download_peers(&self) -> Option<usize> {
+        Some(self.header_download_peers).filter(|peers| *peers > 1)
+    }
+
+    /// Returns the height at which the node exits once its tip reaches it, None to keep running
+    pub fn get_stop_at_height(&self) -> Option<usize> {
+        Some(self.stop_at_height).filter(|height| *height > 0)
+    }
+
+    /// Returns the local port the `waitforblockheight` and `waitfornewblock` requests are answered on,
+    /// None if the RPC server is disabled
+    pub fn get_rpc_port(&self) -> Option<u16> {
+        Some(self.rpc_port).filter(|port| *port > 0)
+    }
+
+    /// Returns the cap on the download rate in kilobits per second, None if downloads are unlimited
+    pub fn get_max_download_kbps(&self) -> Option<u64> {
+        Some(self.max_download_kbps).filter(|kbps| *kbps > 0)
+    }
+
+    /// Returns the SOCKS5 proxy peers are connected through, None to connect to them directly
+    pub fn get_proxy(&self) -> Option<SocketAddr> {
+        self.peers.proxy
+    }
+
+    /// Returns the onion peers connected to through the proxy besides the ones found through the seed
+    pub fn get_onion_peers(&self) -> &[OnionPeer] {
+        &self.peers.onion_peers
+    }
+
+    /// Returns the onion peer known by the given address, None if it isn't one
+    pub fn get_onion_peer(&self, addr: &SocketAddr) -> Option<&OnionPeer> {
+        self.peers
+            .onion_peers
+            .iter()
+            .find(|peer| peer.stand_in_addr() == *addr)
+    }
+
     /// Returns the start timestamp for sync
     pub fn get_start_timestamp(&self) -> u32 {
         self.start_timestamp
@@ -39,11 +227,51 @@ impl Config {
     }
 
     pub fn get_headers_file(&self) -> &str {
-        &self.headers_file
+        &self.storage.headers_file
+    }
+
+    pub fn get_utxo_snapshot_file(&self) -> &str {
+        &self.storage.utxo_snapshot_file
+    }
+
+    /// Returns the seconds between utxo snapshots, None if they're disabled
+    pub fn get_utxo_flush_interval(&self) -> Option<u64> {
+        Some(self.storage.utxo_flush_interval).filter(|interval| *interval > 0)
+    }
+
+    /// Returns the height below which blocks are pruned once a utxo snapshot holds their effects, None if disabled
+    pub fn get_prune_below_height(&self) -> Option<usize> {
+        Some(self.storage.prune_below_height).filter(|height| *height > 0)
+    }
+
+    /// Returns how many headers below the tallest are kept in memory, the older ones being read from
+    /// the headers backup when needed. None if every header is kept in memory.
+    pub fn get_max_headers_in_memory(&self) -> Option<usize> {
+        Some(self.storage.max_headers_in_memory).filter(|max| *max > 0)
+    }
+
+    /// Returns the depth below the tip at which blocks off the best chain are dropped from memory, None if they're kept
+    pub fn get_stale_block_depth(&self) -> Option<usize> {
+        Some(self.storage.stale_block_depth).filter(|depth| *depth > 0)
     }
 
     pub fn get_blocks_file(&self) -> &str {
-        &self.blocks_file
+        &self.storage.blocks_file
+    }
+
+    /// Returns the file pending transactions are saved to so they survive a restart, None if they aren't saved
+    pub fn get_mempool_file(&self) -> Option<&str> {
+        Some(self.storage.mempool_file.as_str()).filter(|file| !file.is_empty())
+    }
+
+    /// Returns the seconds after which a pending transaction read from the mempool file is dropped
+    pub fn get_mempool_expiry(&self) -> u32 {
+        self.storage.mempool_expiry_hours.saturating_mul(60 * 60)
+    }
+
+    /// Returns true if blocks are saved to the backup gzip compressed. Backups are read either way.
+    pub fn get_compress_backups(&self) -> bool {
+        self.storage.compress_backups
     }
 
     pub fn log(&self, content: &str, level: &str) {
@@ -53,29 +281,221 @@ impl Config {
         });
     }
 
-    pub fn get_genesis(&self) -> HashId {
-        self.genesis_hash
+    /// Returns true if verbose logs are written, so the ones that are costly to build can be skipped otherwise
+    pub fn is_verbose(&self) -> bool {
+        self.logger.verbose
+    }
+
+    /// Returns a tracer that logs every message exchanged with peers if `trace_messages` is on
+    pub fn get_message_tracer(&self) -> MessageTracer {
+        match self.trace_messages {
+            true => MessageTracer::new(self.logger.log_sender.clone()),
+            false => MessageTracer::default(),
+        }
     }
 
     pub fn get_listening_port(&self) -> u16 {
         self.port
     }
 
+    /// Returns the serialized bytes of the orphan transactions held until their parents arrive,
+    /// None to relay orphans right away
+    pub fn get_max_orphan_tx_bytes(&self) -> Option<usize> {
+        Some(self.peers.held_txs.max_orphan_tx_bytes).filter(|bytes| *bytes > 0)
+    }
+
+    /// Returns the number of connections with peers that only relay blocks and headers, not transactions
+    pub fn get_block_relay_connections(&self) -> usize {
+        self.peers.block_relay_connections
+    }
+
+    /// Returns the most signature operations a transaction may have to be relayed, None if disabled
+    pub fn get_max_tx_sigops(&self) -> Option<usize> {
+        Some(self.peers.max_tx_sigops).filter(|sigops| *sigops > 0)
+    }
+
+    /// Returns true if transactions whose lock time hasn't passed are held until it does, false to reject them
+    pub fn get_hold_non_final_txs(&self) -> bool {
+        self.peers.held_txs.hold_non_final_txs
+    }
+
+    /// Returns the serialized bytes of the non-final transactions held at once, the oldest evicted past them
+    pub fn get_max_non_final_tx_bytes(&self) -> usize {
+        self.peers.held_txs.max_non_final_tx_bytes
+    }
+
+    /// Returns the relay flag of our version message, false to ask peers not to send us transactions, in which
+    /// case the ones they send without being asked for are ignored
+    pub fn get_relay(&self) -> bool {
+        self.peers.relay
+    }
+
+    /// Returns true if the peers we connect to are dropped unless they serve full blocks (NODE_NETWORK) and,
+    /// when downloading witnesses, witness data (NODE_WITNESS)
+    pub fn get_require_peer_services(&self) -> bool {
+        self.peers.require_peer_services
+    }
+
+    /// Returns the seconds a peer has to announce a transaction we broadcast before it's reported as not
+    /// propagated, None if broadcasts aren't followed
+    pub fn get_broadcast_propagation_timeout(&self) -> Option<u64> {
+        Some(self.peers.broadcast_propagation_timeout_seconds).filter(|timeout| *timeout > 0)
+    }
+
+    /// Returns false if the node only connects to peers, without accepting incoming connections
+    pub fn get_listen(&self) -> bool {
+        self.peers.listen
+    }
+
     pub fn get_wallets_dir(&self) -> &str {
-        &self.wallets_dir
+        &self.wallet.wallets_dir
     }
 
     pub fn get_default_wallet_addr(&self) -> &str {
-        &self.default_wallet_addr
+        &self.wallet.default_wallet_addr
+    }
+
+    /// Returns the path of the file with addresses to watch, which imported addresses are added to. Empty if
+    /// watching addresses from a file is disabled.
+    pub fn get_watch_addresses_file(&self) -> &str {
+        &self.wallet.watch_addresses_file
+    }
+
+    /// Returns the path of the file with the hex encoded HD seed the accounts are derived from, empty if none
+    /// was configured
+    pub fn get_hd_seed_file(&self) -> &str {
+        &self.wallet.hd_seed_file
+    }
+
+    /// Returns the number of accounts of the HD seed loaded as wallets
+    pub fn get_hd_accounts(&self) -> u32 {
+        self.wallet.hd_accounts
+    }
+
+    /// Returns true if the balance of HD accounts includes the coins of their change address, false if those
+    /// are shown as a wallet of their own
+    pub fn get_hd_aggregate_change(&self) -> bool {
+        self.wallet.hd_aggregate_change
+    }
+
+    /// Returns the directory where transactions pending offline signatures are exchanged, empty if none was configured
+    pub fn get_partial_tx_dir(&self) -> &str {
+        &self.wallet.partial_tx_dir
+    }
+
+    /// Returns the fee rate (satoshis per byte) used to tell apart the utxos not worth spending, None if disabled
+    pub fn get_spendable_fee_rate(&self) -> Option<u64> {
+        Some(self.wallet.spendable_fee_rate).filter(|fee_rate| *fee_rate > 0)
+    }
+
+    /// Returns the blocks that must be built on top of the one confirming a coin before it's spent
+    pub fn get_min_confirmations(&self) -> usize {
+        self.wallet.min_confirmations
+    }
+
+    /// Returns true if the unconfirmed change of our own transactions can be spent before it confirms
+    pub fn get_spend_own_change(&self) -> bool {
+        self.wallet.spend_own_change
+    }
+
+    /// Returns true if sending to an address that already received coins warns about the address reuse
+    pub fn get_warn_address_reuse(&self) -> bool {
+        self.wallet.warn_address_reuse
+    }
+
+    /// Returns the highest fee (in satoshis) a transaction may pay before sending it needs confirmation by sending
+    /// it again, None if disabled
+    pub fn get_max_fee(&self) -> Option<u64> {
+        Some(self.wallet.max_fee).filter(|max_fee| *max_fee > 0)
+    }
+
+    /// Returns the number of the latest transactions of the active wallet shown on the overview
+    pub fn get_overview_tx_count(&self) -> usize {
+        self.wallet.overview_tx_count
+    }
+
+    /// Returns the seconds after which a transaction we broadcast that neither confirmed nor was relayed back
+    /// by a peer is abandoned, freeing the coins it spent. None if they're never abandoned
+    pub fn get_abandon_pending_tx_timeout(&self) -> Option<u64> {
+        Some(self.wallet.abandon_pending_tx_seconds).filter(|timeout| *timeout > 0)
+    }
+
+    /// Returns true if connected blocks are checked against the outputs they spend too (value balance, coinbase
+    /// amount and signatures), so the chain is verified without trusting the utxo snapshot
+    pub fn get_full_validation(&self) -> bool {
+        self.full_validation
+    }
+
+    /// Returns true if blocks are downloaded along with their witnesses so the witness commitment is checked,
+    /// false to save the bandwidth by downloading them stripped and trusting the witnesses are valid
+    pub fn get_download_witness(&self) -> bool {
+        self.peers.download_witness
+    }
+
+    /// Returns the most blocks a peer is asked for at once, it gets no more requests until it delivers some of them.
+    /// At least one block is asked for, or blocks would never be downloaded.
+    pub fn get_max_blocks_in_flight_per_peer(&self) -> usize {
+        self.peers.bandwidth.max_blocks_in_flight_per_peer.max(1)
+    }
+
+    /// Returns the seconds a peer has to deliver a requested block before it's requested from another one,
+    /// None if requests don't time out
+    pub fn get_block_request_timeout(&self) -> Option<u64> {
+        Some(self.peers.bandwidth.block_request_timeout_seconds).filter(|timeout| *timeout > 0)
+    }
+
+    /// Returns the bytes per day that can be uploaded before historical blocks stop being served, None if unlimited
+    pub fn get_max_upload_target(&self) -> Option<u64> {
+        Some(self.peers.bandwidth.max_upload_target).filter(|bytes| *bytes > 0)
+    }
+
+    /// Returns true if the node runs without the GTK interface, as it does anyway when there's no display
+    pub fn get_headless(&self) -> bool {
+        self.headless
+    }
+
+    pub fn get_checkpoints(&self) -> &Checkpoints {
+        &self.checkpoints
+    }
+
+    pub fn get_network_params(&self) -> &NetworkParams {
+        &self.network_params
+    }
+
+    /// Returns true if relay with the peer is allowed, which is always the case if no whitelist was configured
+    pub fn is_whitelisted(&self, peer: &SocketAddr) -> bool {
+        self.peers.whitelist.is_empty() || whitelist_contains(&self.peers.whitelist, peer)
+    }
+
+    pub fn get_whitelist(&self) -> &[WhitelistedPeer] {
+        &self.peers.whitelist
+    }
+
+    /// Returns the only peer headers and blocks are downloaded from, None to sync from any of them
+    pub fn get_trusted_peer(&self) -> Option<SocketAddr> {
+        self.peers.trusted_peer
+    }
+
+    /// Returns true if headers and blocks can be downloaded from the peer: only the trusted peer if there's one,
+    /// otherwise whitelisted peers, or any peer if those outside the whitelist can be used to sync
+    pub fn is_sync_peer(&self, peer: &SocketAddr) -> bool {
+        match self.peers.trusted_peer {
+            Some(trusted_peer) => *peer == trusted_peer,
+            None => self.peers.whitelist_allow_ibd || self.is_whitelisted(peer),
+        }
     }
 
     fn remove_or(hashmap: &mut HashMap<String, String>, key: &str, default: &str) -> String {
         hashmap.remove(key).unwrap_or(default.to_string())
     }
 
-    pub fn wallet_from_file(secret_key_file: String) -> io::Result<Option<Wallet>> {
+    /// Reads the wallet of the secret key in the given file, with its address in the format of the network
+    pub fn wallet_from_file(&self, secret_key_file: String) -> io::Result<Option<Wallet>> {
         match fs::read_to_string(&secret_key_file) {
-            Ok(file_content) => Ok(Some(file_content.as_str().try_into()?)),
+            Ok(file_content) => Ok(Some(Wallet::from_secret_key(
+                &file_content,
+                &self.get_network_params().address_format,
+            )?)),
             Err(_e) => {
                 let err_msg = format!("Could not read secret key file {}", secret_key_file);
                 Err(io::Error::new(io::ErrorKind::Other, err_msg))
@@ -83,31 +503,167 @@ impl Config {
         }
     }
 
+    /// Parses the value of the given key, or returns the default if it's missing or invalid
+    fn parse_or<T: FromStr>(hashmap: &mut HashMap<String, String>, key: &str, default: T) -> T {
+        Config::remove_or(hashmap, key, "")
+            .parse()
+            .unwrap_or(default)
+    }
+
+    fn wallet_options_from_hashmap(values: &mut HashMap<String, String>) -> WalletOptions {
+        WalletOptions {
+            wallets_dir: Config::remove_or(values, "wallets_dir", ""),
+            default_wallet_addr: Config::remove_or(values, "default_wallet_addr", ""),
+            watch_addresses_file: Config::remove_or(
+                values,
+                "watch_addresses_file",
+                WATCH_ADDRESSES_FILE,
+            ),
+            hd_seed_file: Config::remove_or(values, "hd_seed_file", ""),
+            hd_accounts: Config::parse_or(values, "hd_accounts", 1),
+            hd_aggregate_change: Config::parse_or(values, "hd_aggregate_change", true),
+            partial_tx_dir: Config::remove_or(values, "partial_tx_dir", ""),
+            spendable_fee_rate: Config::parse_or(values, "spendable_fee_rate", 0),
+            min_confirmations: Config::parse_or(values, "min_confirmations", 1),
+            spend_own_change: Config::parse_or(values, "spend_own_change", false),
+            abandon_pending_tx_seconds: Config::parse_or(
+                values,
+                "abandon_pending_tx_seconds",
+                ABANDON_PENDING_TX_TIMEOUT,
+            ),
+            overview_tx_count: Config::parse_or(values, "overview_tx_count", OVERVIEW_TX_COUNT),
+            warn_address_reuse: Config::parse_or(values, "warn_address_reuse", true),
+            max_fee: Config::parse_or(values, "max_fee", MAX_FEE),
+        }
+    }
+
+    fn storage_options_from_hashmap(
+        values: &mut HashMap<String, String>,
+    ) -> io::Result<StorageOptions> {
+        let utxo_flush_interval = Config::parse_or(values, "utxo_flush_interval", 0);
+        let prune_below_height = Config::parse_or(values, "prune_below_height", 0);
+        // blocks are only pruned once a utxo snapshot holds them, which is never without flushes
+        if prune_below_height > 0 && utxo_flush_interval == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Pruning needs utxo_flush_interval to be set",
+            ));
+        }
+        Ok(StorageOptions {
+            headers_file: Config::remove_or(values, "headers_file", HEADERS_FILE),
+            blocks_file: Config::remove_or(values, "blocks_file", BLOCKS_FILE),
+            utxo_snapshot_file: Config::remove_or(values, "utxo_snapshot_file", UTXO_SNAPSHOT_FILE),
+            utxo_flush_interval,
+            prune_below_height,
+            max_headers_in_memory: Config::parse_or(values, "max_headers_in_memory", 0),
+            stale_block_depth: Config::parse_or(values, "stale_block_depth", 0),
+            compress_backups: Config::parse_or(values, "compress_backups", false),
+            mempool_file: Config::remove_or(values, "mempool_file", ""),
+            mempool_expiry_hours: Config::parse_or(
+                values,
+                "mempool_expiry_hours",
+                MEMPOOL_EXPIRY_HOURS,
+            ),
+        })
+    }
+
+    fn peer_options_from_hashmap(values: &mut HashMap<String, String>) -> io::Result<PeerOptions> {
+        Ok(PeerOptions {
+            listen: Config::parse_or(values, "listen", true),
+            proxy: Self::addr_from_hashmap(values, "proxy", "Proxy address is invalid")?,
+            trusted_peer: Self::addr_from_hashmap(
+                values,
+                "trusted_peer",
+                "Trusted peer address is invalid",
+            )?,
+            whitelist: Self::whitelist_from_hashmap(values)?,
+            onion_peers: Self::onion_peers_from_hashmap(values)?,
+            whitelist_allow_ibd: Config::parse_or(values, "whitelist_allow_ibd", true),
+            max_tx_sigops: Config::parse_or(values, "max_tx_sigops", MAX_TX_SIGOPS),
+            block_relay_connections: Config::parse_or(values, "block_relay_connections", 0),
+            held_txs: Self::held_tx_options_from_hashmap(values),
+            relay: Config::parse_or(values, "relay", true),
+            require_peer_services: Config::parse_or(values, "require_peer_services", true),
+            download_witness: Config::parse_or(values, "download_witness", true),
+            broadcast_propagation_timeout_seconds: Config::parse_or(
+                values,
+                "broadcast_propagation_timeout_seconds",
+                BROADCAST_PROPAGATION_TIMEOUT,
+            ),
+            bandwidth: Self::bandwidth_options_from_hashmap(values),
+        })
+    }
+
+    fn held_tx_options_from_hashmap(values: &mut HashMap<String, String>) -> HeldTxOptions {
+        HeldTxOptions {
+            max_orphan_tx_bytes: Config::parse_or(
+                values,
+                "max_orphan_tx_bytes",
+                MAX_ORPHAN_TX_BYTES,
+            ),
+            hold_non_final_txs: Config::parse_or(values, "hold_non_final_txs", true),
+            max_non_final_tx_bytes: Config::parse_or(
+                values,
+                "max_non_final_tx_bytes",
+                MAX_NON_FINAL_TX_BYTES,
+            ),
+        }
+    }
+
+    fn bandwidth_options_from_hashmap(values: &mut HashMap<String, String>) -> BandwidthOptions {
+        BandwidthOptions {
+            max_blocks_in_flight_per_peer: Config::parse_or(
+                values,
+                "max_blocks_in_flight_per_peer",
+                MAX_BLOCKS_IN_FLIGHT_PER_PEER,
+            ),
+            block_request_timeout_seconds: Config::parse_or(
+                values,
+                "block_request_timeout_seconds",
+                BLOCK_REQUEST_TIMEOUT,
+            ),
+            max_upload_target: Config::parse_or(values, "max_upload_target", 0),
+        }
+    }
+
+    fn logger_from_hashmap(values: &mut HashMap<String, String>) -> Logger {
+        Logger::new(
+            Config::remove_or(values, "log_file", LOG_FILE),
+            Config::remove_or(values, "log_level", QUIET),
+        )
+    }
+
     fn from_hashmap(mut values: HashMap<String, String>) -> io::Result<Config> {
+        let start_timestamp = Config::parse_or(&mut values, "start_timestamp", START_TIMESTAMP);
         Ok(Config {
             seed: Config::remove_or(&mut values, "seed", ""),
-            port: Config::remove_or(&mut values, "listening_port", "")
-                .parse()
-                .unwrap_or(PORT),
-            start_timestamp: Config::remove_or(&mut values, "start_timestamp", "")
-                .parse()
-                .unwrap_or(START_TIMESTAMP),
-            logger: Logger::new(
-                Config::remove_or(&mut values, "log_file", LOG_FILE),
-                Config::remove_or(&mut values, "log_level", QUIET),
+            port: Config::parse_or(&mut values, "listening_port", PORT),
+            peers: Self::peer_options_from_hashmap(&mut values)?,
+            start_timestamp,
+            logger: Self::logger_from_hashmap(&mut values),
+            storage: Self::storage_options_from_hashmap(&mut values)?,
+            tcp_timeout_seconds: Config::parse_or(&mut values, "tcp_timeout_seconds", TCP_TIMEOUT),
+            header_stall_timeout_seconds: Config::parse_or(
+                &mut values,
+                "header_stall_timeout_seconds",
+                HEADER_STALL_TIMEOUT,
             ),
-            headers_file: Config::remove_or(&mut values, "headers_file", HEADERS_FILE),
-            blocks_file: Config::remove_or(&mut values, "blocks_file", BLOCKS_FILE),
-            tcp_timeout_seconds: Config::remove_or(&mut values, "tcp_timeout_seconds", "")
-                .parse()
-                .unwrap_or(TCP_TIMEOUT),
-            genesis_hash: Self::hash_from_string(&Config::remove_or(
+            header_download_peers: Config::parse_or(&mut values, "header_download_peers", 0),
+            sync_stall_timeout_seconds: Config::parse_or(
                 &mut values,
-                "genesis_hash",
-                "",
-            ))?,
-            wallets_dir: Config::remove_or(&mut values, "wallets_dir", ""),
-            default_wallet_addr: Config::remove_or(&mut values, "default_wallet_addr", ""),
+                "sync_stall_timeout_seconds",
+                SYNC_STALL_TIMEOUT,
+            ),
+            sync_stall_reconnect: Config::parse_or(&mut values, "sync_stall_reconnect", false),
+            stop_at_height: Config::parse_or(&mut values, "stop_at_height", 0),
+            rpc_port: Config::parse_or(&mut values, "rpc_port", 0),
+            max_download_kbps: Config::parse_or(&mut values, "max_download_kbps", 0),
+            wallet: Self::wallet_options_from_hashmap(&mut values),
+            checkpoints: Self::checkpoints_from_hashmap(&mut values)?,
+            network_params: Self::network_params_from_hashmap(&mut values)?,
+            trace_messages: Config::parse_or(&mut values, "trace_messages", false),
+            full_validation: Self::full_validation_from_hashmap(&mut values, start_timestamp)?,
+            headless: Config::parse_or(&mut values, "headless", false),
         })
     }
 
@@ -129,6 +685,110 @@ impl Config {
         Ok(config)
     }
 
+    /// Parses whether blocks are checked against the outputs they spend, which needs every block downloaded
+    /// from the genesis so those outputs are known
+    fn full_validation_from_hashmap(
+        values: &mut HashMap<String, String>,
+        start_timestamp: u32,
+    ) -> io::Result<bool> {
+        match Config::parse_or(values, "full_validation", false) {
+            true if start_timestamp > 0 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Full validation needs start_timestamp=0",
+            )),
+            full_validation => Ok(full_validation),
+        }
+    }
+
+    /// Parses a comma separated list of checkpoints in the format height:hash
+    fn checkpoints_from_hashmap(values: &mut HashMap<String, String>) -> io::Result<Checkpoints> {
+        let mut checkpoints = Checkpoints::new();
+        for checkpoint in Config::remove_or(values, "checkpoints", "")
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+        {
+            let (height, hash) = checkpoint.split_once(':').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Checkpoint should be in the format height:hash",
+                )
+            })?;
+            let height: usize = height.trim().parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Checkpoint height is invalid")
+            })?;
+            checkpoints.insert(height, Self::hash_from_string(hash.trim())?);
+        }
+        Ok(checkpoints)
+    }
+
+    /// Parses the whitelist as a comma separated list of peer addresses in the format ip:port, or just ip to
+    /// match inbound peers, which connect from ephemeral ports
+    fn whitelist_from_hashmap(
+        values: &mut HashMap<String, String>,
+    ) -> io::Result<Vec<WhitelistedPeer>> {
+        Config::remove_or(values, "whitelist", "")
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(WhitelistedPeer::from_str)
+            .collect()
+    }
+
+    /// Parses the comma separated onion peers in the format host.onion:port
+    fn onion_peers_from_hashmap(
+        values: &mut HashMap<String, String>,
+    ) -> io::Result<Vec<OnionPeer>> {
+        Config::remove_or(values, "onion_peers", "")
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(OnionPeer::from_str)
+            .collect()
+    }
+
+    /// Parses an address in the format ip:port, none being set if it's empty
+    fn addr_from_hashmap(
+        values: &mut HashMap<String, String>,
+        key: &str,
+        invalid_msg: &str,
+    ) -> io::Result<Option<SocketAddr>> {
+        match Config::remove_or(values, key, "").trim() {
+            "" => Ok(None),
+            addr => SocketAddr::from_str(addr)
+                .map(Some)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, invalid_msg)),
+        }
+    }
+
+    /// Builds the network parameters from the network name, an optional hex signet challenge and
+    /// the coinbase maturity, which can only be lowered on regtest.
+    /// If a genesis hash is given, it must match the genesis header of the network.
+    fn network_params_from_hashmap(
+        values: &mut HashMap<String, String>,
+    ) -> io::Result<NetworkParams> {
+        let network = Config::remove_or(values, "network", "testnet");
+        let signet_challenge = Config::remove_or(values, "signet_challenge", "");
+        let genesis_hash = Config::remove_or(values, "genesis_hash", "");
+        let signet_challenge = decode_hex(&signet_challenge).map_err(to_io_err)?;
+        let mut network_params =
+            NetworkParams::new(Network::from_str(&network)?, &signet_challenge)?;
+        network_params.set_coinbase_maturity(Config::parse_or(
+            values,
+            "coinbase_maturity",
+            COINBASE_MATURITY,
+        ))?;
+        if !genesis_hash.is_empty()
+            && Self::hash_from_string(&genesis_hash)? != network_params.genesis_header().hash
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Genesis hash doesn't match the genesis block of the network",
+            ));
+        }
+        Ok(network_params)
+    }
+
     fn hash_from_string(string: &str) -> io::Result<HashId> {
         if string.len() != 64 {
             return Err(io::Error::new(
@@ -156,3 +816,21 @@ impl Config {
         Ok(HashId::new(hash))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_blocks_in_flight_still_asks_for_one() -> io::Result<()> {
+        let log_file = "test_blocks_in_flight.log";
+        let values = HashMap::from([
+            ("log_file".to_string(), log_file.to_string()),
+            ("max_blocks_in_flight_per_peer".to_string(), "0".to_string()),
+        ]);
+        let config = Config::from_hashmap(values);
+        std::fs::remove_file(log_file)?;
+        assert_eq!(config?.get_max_blocks_in_flight_per_peer(), 1);
+        Ok(())
+    }
+}
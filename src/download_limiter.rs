@@ -0,0 +1,74 @@
+use std::time::Instant;
+
+/// Caps the download rate by holding back block requests, with a token bucket refilled at the cap and
+/// holding at most a second of it: a request is only issued while the bytes downloaded since the last check
+/// don't exceed the bucket, so an idle period doesn't let a burst of requests through afterwards.
+#[derive(Debug, Clone)]
+pub struct DownloadLimiter {
+    bytes_per_second: u64,
+    tokens: f64, // bytes that can still be downloaded, negative if more were downloaded than allowed
+    last_refill: Instant,
+    last_downloaded: u64, // bytes downloaded when the bucket was last updated
+}
+
+impl DownloadLimiter {
+    /// Creates a limiter of the given kilobits per second, starting with an empty bucket
+    pub fn new(max_kbps: u64, now: Instant) -> Self {
+        Self {
+            bytes_per_second: max_kbps * 1000 / 8,
+            tokens: 0.0,
+            last_refill: now,
+            last_downloaded: 0,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed and takes the bytes downloaded since the last call out of it,
+    /// returning true if another request can be issued
+    pub fn allows_request(&mut self, downloaded: u64, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        let capacity = self.bytes_per_second as f64;
+        self.tokens = (self.tokens + capacity * elapsed).min(capacity);
+        self.tokens -= downloaded.saturating_sub(self.last_downloaded) as f64;
+        self.last_refill = now;
+        self.last_downloaded = downloaded;
+        self.tokens >= 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_low_cap_throttles_requests_below_rate() {
+        // 8 kbps is 1000 bytes per second, and every request downloads 500 bytes right away
+        let start = Instant::now();
+        let mut limiter = DownloadLimiter::new(8, start);
+        let (mut downloaded, mut requests) = (0, 0);
+        for tick in 1..=100 {
+            let now = start + Duration::from_millis(tick * 100);
+            while limiter.allows_request(downloaded, now) {
+                downloaded += 500;
+                requests += 1;
+            }
+        }
+        // after 10 seconds at most 10000 bytes are allowed, plus the request that crossed the cap
+        assert!(downloaded <= 10500);
+        assert!(requests <= 21);
+        assert!(requests >= 20);
+    }
+
+    #[test]
+    fn test_idle_period_doesnt_allow_a_burst() {
+        let start = Instant::now();
+        let mut limiter = DownloadLimiter::new(8, start);
+        // nothing was downloaded for a minute, only a second worth of requests goes through at once
+        let now = start + Duration::from_secs(60);
+        let mut downloaded = 0;
+        while limiter.allows_request(downloaded, now) {
+            downloaded += 500;
+        }
+        assert_eq!(downloaded, 1500);
+    }
+}
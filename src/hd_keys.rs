@@ -0,0 +1,89 @@
+use crate::utility::to_io_err;
+use bitcoin_hashes::{hmac, sha512, Hash, HashEngine};
+use secp256k1::{Scalar, Secp256k1, SecretKey, SignOnly};
+use std::io;
+use std::sync::OnceLock;
+
+/// Key of the HMAC deriving the master key from a seed (BIP32)
+const MASTER_HMAC_KEY: &[u8] = b"Bitcoin seed";
+
+/// Indexes from this one on derive hardened children, which can't be derived from the parent public key
+pub const HARDENED: u32 = 1 << 31;
+
+/// Context public keys of parents are computed with, created once since it's expensive
+fn signer() -> &'static Secp256k1<SignOnly> {
+    static SIGNER: OnceLock<Secp256k1<SignOnly>> = OnceLock::new();
+    SIGNER.get_or_init(Secp256k1::signing_only)
+}
+
+/// A private key along with the chain code its children are derived with (BIP32)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedKey {
+    pub secret_key: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// Returns the master key of the given seed
+    pub fn from_seed(seed: &[u8]) -> io::Result<Self> {
+        Self::from_hmac(MASTER_HMAC_KEY, seed)
+    }
+
+    /// Returns the key of the given path of child indexes below this one, like m/44'/1'/0'/0/0 for BIP44
+    pub fn derive_path(&self, path: &[u32]) -> io::Result<Self> {
+        path.iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Returns the child key of the given index, hardened from `HARDENED` on
+    fn derive_child(&self, index: u32) -> io::Result<Self> {
+        let mut data = match index >= HARDENED {
+            true => [&[0x00][..], &self.secret_key.secret_bytes()].concat(),
+            false => self.secret_key.public_key(signer()).serialize().to_vec(),
+        };
+        data.extend(index.to_be_bytes());
+        let child = Self::from_hmac(&self.chain_code, &data)?;
+        let tweak = Scalar::from_be_bytes(self.secret_key.secret_bytes()).map_err(to_io_err)?;
+        Ok(Self {
+            secret_key: child.secret_key.add_tweak(&tweak).map_err(to_io_err)?,
+            chain_code: child.chain_code,
+        })
+    }
+
+    /// Splits the HMAC-SHA512 of the data into a key, the left half, and a chain code, the right one
+    fn from_hmac(key: &[u8], data: &[u8]) -> io::Result<Self> {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(key);
+        engine.input(data);
+        let hash = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+        let (secret_key, chain_code) = hash.split_at(32);
+        Ok(Self {
+            secret_key: SecretKey::from_slice(secret_key).map_err(to_io_err)?,
+            chain_code: chain_code.try_into().map_err(to_io_err)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::decode_hex;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_derivation_matches_the_bip32_test_vector() -> io::Result<()> {
+        let seed = decode_hex("000102030405060708090a0b0c0d0e0f").map_err(to_io_err)?;
+        let master = ExtendedKey::from_seed(&seed)?;
+        let expected = |hex: &str| SecretKey::from_str(hex).map_err(to_io_err);
+        assert_eq!(
+            master.secret_key,
+            expected("e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35")?
+        );
+        // m/0'/1
+        let child = master.derive_path(&[HARDENED, 1])?;
+        assert_eq!(
+            child.secret_key,
+            expected("3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368")?
+        );
+        Ok(())
+    }
+}
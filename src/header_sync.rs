@@ -0,0 +1,292 @@
+use crate::messages::block_header::Checkpoints;
+use crate::messages::{BlockHeader, HashId, Hashable};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Most headers held ahead of the chain, the ones arriving once it's full are downloaded again by the sync peer
+const MAX_DETACHED_HEADERS: usize = 50_000;
+
+/// Tracks the peer headers were last requested from, so the request can be re-issued
+/// to a different peer if that one stops sending headers.
+#[derive(Debug, Default)]
+pub struct HeaderSync {
+    request: Option<(SocketAddr, Instant)>, // peer syncing headers and the last time it sent some
+}
+
+impl HeaderSync {
+    /// Records a getheaders sent to the given peer
+    pub fn requested(&mut self, peer: SocketAddr, now: Instant) {
+        self.request = Some((peer, now));
+    }
+
+    /// Records a headers message from the given peer. A message that isn't paginated means we caught up,
+    /// so there's nothing left to wait for.
+    pub fn received(&mut self, peer: SocketAddr, paginated: bool, now: Instant) {
+        if let Some((sync_peer, last_header_received)) = &mut self.request {
+            if *sync_peer == peer {
+                *last_header_received = now;
+                if !paginated {
+                    self.request = None;
+                }
+            }
+        }
+    }
+
+    /// Returns the peer headers are being synced from, None if sync caught up
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.request.map(|(peer, _last_header_received)| peer)
+    }
+
+    /// Returns the peer to re-request headers from if the sync peer sent none within the timeout:
+    /// the first of the given peers that isn't the stalled one, None if there's no stall or no other peer.
+    pub fn retry_peer(
+        &self,
+        peers: &[SocketAddr],
+        timeout: Duration,
+        now: Instant,
+    ) -> Option<SocketAddr> {
+        let (stalled_peer, last_header_received) = self.request?;
+        if now.duration_since(last_header_received) < timeout {
+            return None;
+        }
+        peers.iter().copied().find(|peer| *peer != stalled_peer)
+    }
+}
+
+/// Headers of ranges requested ahead of the best chain, held until the headers they build on arrive
+/// from another peer. Only headers of ranges requested in parallel are held, up to `MAX_DETACHED_HEADERS`.
+/// They're kept by hash so headers of competing branches building on the same parent are all held.
+#[derive(Debug, Default)]
+pub struct DetachedHeaders {
+    enabled: bool,
+    held: HashMap<HashId, BlockHeader>,
+    children: HashMap<HashId, Vec<HashId>>, // hashes of the held headers building on each header
+}
+
+impl DetachedHeaders {
+    /// Creates the held headers, which are only kept if header ranges are downloaded in parallel
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            held: HashMap::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    /// Returns the headers ready to be connected, each after its parent: none if the header is known or its
+    /// parent isn't (in which case it's held), otherwise the header followed by the held ones building on it
+    pub fn arrange(
+        &mut self,
+        header: BlockHeader,
+        is_known: impl Fn(&HashId) -> bool,
+    ) -> Vec<BlockHeader> {
+        let hash = header.hash();
+        if is_known(&hash) || self.held.contains_key(&hash) {
+            return vec![];
+        }
+        if !is_known(&header.prev_block_hash) {
+            if self.enabled && self.held.len() < MAX_DETACHED_HEADERS {
+                self.children
+                    .entry(header.prev_block_hash)
+                    .or_default()
+                    .push(hash);
+                self.held.insert(hash, header);
+            }
+            return vec![];
+        }
+        let mut ready = vec![header];
+        let mut next = 0;
+        while let Some(parent) = ready.get(next).map(BlockHeader::hash) {
+            for child in self.children.remove(&parent).unwrap_or_default() {
+                ready.extend(self.held.remove(&child));
+            }
+            next += 1;
+        }
+        ready
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+}
+
+/// Returns the hashes of the checkpoints above the given height to anchor the header ranges requested
+/// from other peers, evenly spread so the given number of peers share the download
+pub fn range_anchors(checkpoints: &Checkpoints, height: usize, peers: usize) -> Vec<HashId> {
+    let mut heights: Vec<usize> = checkpoints
+        .keys()
+        .copied()
+        .filter(|h| *h > height)
+        .collect();
+    heights.sort_unstable();
+    let mut anchors: Vec<HashId> = (1..peers)
+        .filter_map(|range| heights.get((range - 1) * heights.len() / (peers - 1)))
+        .map(|height| checkpoints[height])
+        .collect();
+    anchors.dedup();
+    anchors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::block_header::HeaderSet;
+    use crate::utility::to_io_err;
+    use std::io;
+
+    #[test]
+    fn test_header_stall_retries_with_another_peer() -> io::Result<()> {
+        let stalled: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let other: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let peers = [stalled, other];
+        let timeout = Duration::from_secs(30);
+        let start = Instant::now();
+
+        let mut header_sync = HeaderSync::default();
+        assert_eq!(header_sync.retry_peer(&peers, timeout, start), None);
+
+        header_sync.requested(stalled, start);
+        assert_eq!(
+            header_sync.retry_peer(&peers, timeout, start + timeout / 2),
+            None
+        );
+        let stalled_at = start + timeout;
+        assert_eq!(
+            header_sync.retry_peer(&peers, timeout, stalled_at),
+            Some(other)
+        );
+        assert_eq!(
+            header_sync.retry_peer(&[stalled], timeout, stalled_at),
+            None
+        );
+
+        // headers from another peer don't count as progress of the sync peer
+        header_sync.received(other, true, stalled_at);
+        assert_eq!(
+            header_sync.retry_peer(&peers, timeout, stalled_at),
+            Some(other)
+        );
+
+        header_sync.received(stalled, true, stalled_at);
+        assert_eq!(header_sync.retry_peer(&peers, timeout, stalled_at), None);
+        header_sync.received(stalled, false, stalled_at);
+        assert_eq!(
+            header_sync.retry_peer(&peers, timeout, stalled_at + timeout),
+            None
+        );
+        Ok(())
+    }
+
+    fn header_chain(length: u32) -> Vec<BlockHeader> {
+        let mut chain = vec![BlockHeader::new(
+            0,
+            HashId::default(),
+            None,
+            HashId::default(),
+            0,
+            0,
+            0,
+        )];
+        for nonce in 1..length {
+            let prev_hash = chain[chain.len() - 1].hash();
+            chain.push(BlockHeader::new(
+                0x20000000,
+                prev_hash,
+                None,
+                HashId::default(),
+                0,
+                0,
+                nonce,
+            ));
+        }
+        chain
+    }
+
+    #[test]
+    fn test_overlapping_header_ranges_merge_without_duplicates() {
+        let chain = header_chain(10);
+        let mut headers = HeaderSet::with(chain[0].hash(), chain[0]);
+        let mut detached = DetachedHeaders::new(true);
+        let mut stored = vec![];
+
+        // the peer anchored ahead answers first, then the one syncing from the tip overlaps its range
+        let ahead_peer = chain[4..10].to_vec();
+        let tip_peer = chain[1..7].to_vec();
+        for header in ahead_peer.into_iter().chain(tip_peer) {
+            for ready in detached.arrange(header, |hash| headers.contains_key(hash)) {
+                headers.insert(ready.hash(), ready);
+                stored.push(ready.hash());
+            }
+        }
+
+        let expected: Vec<HashId> = chain[1..].iter().map(|header| header.hash()).collect();
+        assert_eq!(stored, expected);
+        assert_eq!(headers.len(), chain.len());
+        assert_eq!(detached.len(), 0);
+    }
+
+    #[test]
+    fn test_held_competing_branches_all_connect() {
+        let chain = header_chain(3);
+        let fork = BlockHeader::new(
+            0x20000000,
+            chain[1].hash(),
+            None,
+            HashId::default(),
+            0,
+            0,
+            9,
+        );
+        let mut detached = DetachedHeaders::new(true);
+        let is_known = |hash: &HashId| *hash == chain[0].hash();
+
+        assert!(detached.arrange(chain[2], is_known).is_empty());
+        assert!(detached.arrange(fork, is_known).is_empty());
+        assert_eq!(detached.len(), 2);
+        let ready: Vec<HashId> = detached
+            .arrange(chain[1], is_known)
+            .iter()
+            .map(BlockHeader::hash)
+            .collect();
+        assert_eq!(ready[0], chain[1].hash());
+        assert!(ready.contains(&chain[2].hash()) && ready.contains(&fork.hash()));
+        assert_eq!(detached.len(), 0);
+    }
+
+    #[test]
+    fn test_held_headers_are_capped() {
+        let mut detached = DetachedHeaders::new(true);
+        let unknown_parent = HashId::new([1; 32]);
+        for nonce in 0..=MAX_DETACHED_HEADERS as u32 {
+            let header = BlockHeader::new(
+                0x20000000,
+                unknown_parent,
+                None,
+                HashId::default(),
+                0,
+                0,
+                nonce,
+            );
+            detached.arrange(header, |_| false);
+        }
+        assert_eq!(detached.len(), MAX_DETACHED_HEADERS);
+    }
+
+    #[test]
+    fn test_range_anchors_spread_over_checkpoints_ahead() {
+        let checkpoints: Checkpoints = (1..=4)
+            .map(|i| (i * 1000, HashId::new([i as u8; 32])))
+            .collect();
+        assert_eq!(range_anchors(&checkpoints, 0, 1), vec![]);
+        assert_eq!(
+            range_anchors(&checkpoints, 1500, 2),
+            vec![HashId::new([2; 32])]
+        );
+        assert_eq!(
+            range_anchors(&checkpoints, 0, 3),
+            vec![HashId::new([1; 32]), HashId::new([3; 32])]
+        );
+        assert_eq!(range_anchors(&checkpoints, 4000, 3), vec![]);
+    }
+}
@@ -0,0 +1,58 @@
+/// Tracks the initial block download, complete once the tallest block catches up to within `max_blocks_behind`
+/// of the tallest header and that header is recent. Completion is reported once: falling behind again later,
+/// like during a stall, doesn't take the node back to initial block download.
+#[derive(Debug, Clone)]
+pub struct InitialBlockDownload {
+    max_blocks_behind: usize,
+    complete: bool,
+}
+
+impl InitialBlockDownload {
+    pub fn new(max_blocks_behind: usize) -> Self {
+        Self {
+            max_blocks_behind,
+            complete: false,
+        }
+    }
+
+    /// Returns true once the blocks caught up to the headers
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Records the current tallest header and block heights, and whether the tallest header is recent.
+    /// Returns true only the first time they show the download complete.
+    pub fn tip_changed(
+        &mut self,
+        (header_height, block_height): (usize, usize),
+        recent: bool,
+    ) -> bool {
+        if self.complete || !recent || block_height + self.max_blocks_behind < header_height {
+            return false;
+        }
+        self.complete = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reaching_the_header_tip_completes_once() {
+        let mut ibd = InitialBlockDownload::new(1);
+
+        assert!(!ibd.tip_changed((2000, 10), true));
+        // caught up to headers that are too old to be the tip of the network
+        assert!(!ibd.tip_changed((2000, 2000), false));
+        assert!(!ibd.tip_changed((2001, 1999), true));
+        assert!(!ibd.is_complete());
+
+        assert!(ibd.tip_changed((2001, 2000), true));
+        assert!(ibd.is_complete());
+        assert!(!ibd.tip_changed((2001, 2001), true));
+        assert!(!ibd.tip_changed((2010, 2001), true));
+        assert!(ibd.is_complete());
+    }
+}
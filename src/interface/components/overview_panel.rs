@@ -53,12 +53,9 @@ fn get_transaction_widget(transaction: TransactionDisplayInfo) -> Result<gtk::Wi
         .ok_or("Could not find origin image")?;
 
     match transaction.origin {
-        TransactionOrigin::Block => {
-            origin_img.set_file(Some("./src/interface/res/mined.png"));
-        }
-        TransactionOrigin::Pending => {
-            origin_img.set_file(Some("./src/interface/res/pending.png"));
-        }
+        TransactionOrigin::Block => origin_img.set_file(Some("./src/interface/res/mined.png")),
+        TransactionOrigin::Pending => origin_img.set_file(Some("./src/interface/res/pending.png")),
+        TransactionOrigin::Abandoned => origin_img.clear(),
     }
 
     Ok(transaction_widget)
@@ -60,6 +60,18 @@ fn get_recipients(builder: gtk::Builder) -> io::Result<Vec<RecipientDetails>> {
     Ok(recipients_details)
 }
 
+fn transaction_info_from_builder(builder: gtk::Builder) -> io::Result<TransactionInfo> {
+    let recipients = get_recipients(builder.clone())?;
+    let fee: u64 = match builder.object::<gtk::Entry>("transaction_fee_entry") {
+        Some(f) => {
+            let float_value = f.text().parse::<f64>().unwrap_or(0.0);
+            (float_value * 100000000.0) as u64
+        }
+        None => 0,
+    };
+    Ok(TransactionInfo { recipients, fee })
+}
+
 fn connect_send_btn(builder: gtk::Builder, sender: Sender<ModelRequest>) -> io::Result<()> {
     let transaction_send_btn: gtk::Button =
         builder.object("transaction_send_btn").ok_or_else(|| {
@@ -68,27 +80,46 @@ fn connect_send_btn(builder: gtk::Builder, sender: Sender<ModelRequest>) -> io::
                 "could not find transaction send btn",
             )
         })?;
-    transaction_send_btn.connect_clicked(move |_| match get_recipients(builder.clone()) {
-        Ok(recipients) => {
-            let fee: u64 = match builder.object::<gtk::Entry>("transaction_fee_entry") {
-                Some(f) => {
-                    let float_value = f.text().parse::<f64>().unwrap_or(0.0);
-                    (float_value * 100000000.0) as u64
+    transaction_send_btn.connect_clicked(move |_| {
+        match transaction_info_from_builder(builder.clone()) {
+            Ok(transaction_info) => {
+                if sender
+                    .send(ModelRequest::GenerateTransaction(transaction_info))
+                    .is_err()
+                {
+                    println!("could not send transaction details to model");
                 }
-                None => 0,
-            };
+            }
+            Err(e) => {
+                println!("could not get recipients: {}", e);
+            }
+        }
+    });
 
-            let transaction_info = TransactionInfo { recipients, fee };
+    Ok(())
+}
 
-            if sender
-                .send(ModelRequest::GenerateTransaction(transaction_info))
-                .is_err()
-            {
-                println!("could not send transaction details to model");
+fn connect_preview_btn(builder: gtk::Builder, sender: Sender<ModelRequest>) -> io::Result<()> {
+    let transaction_preview_btn: gtk::Button =
+        builder.object("transaction_preview_btn").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "could not find transaction preview btn",
+            )
+        })?;
+    transaction_preview_btn.connect_clicked(move |_| {
+        match transaction_info_from_builder(builder.clone()) {
+            Ok(transaction_info) => {
+                if sender
+                    .send(ModelRequest::PreviewTransaction(transaction_info))
+                    .is_err()
+                {
+                    println!("could not send transaction details to model");
+                }
+            }
+            Err(e) => {
+                println!("could not get recipients: {}", e);
             }
-        }
-        Err(e) => {
-            println!("could not get recipients: {}", e);
         }
     });
 
@@ -175,7 +206,8 @@ fn connect_append_btn(builder: gtk::Builder) -> io::Result<()> {
 
 /// Initialize send panel components
 pub fn init(builder: gtk::Builder, sender: Sender<ModelRequest>) -> io::Result<()> {
-    connect_send_btn(builder.clone(), sender)?;
+    connect_send_btn(builder.clone(), sender.clone())?;
+    connect_preview_btn(builder.clone(), sender)?;
     connect_clear_all_btn(builder.clone())?;
     connect_append_btn(builder)?;
 
@@ -3,7 +3,9 @@ use std::io;
 use std::sync::mpsc::Sender;
 
 use gtk::{
-    prelude::{BuilderExtManual, ComboBoxExt, ComboBoxExtManual, ComboBoxTextExt},
+    prelude::{
+        BuilderExtManual, ButtonExt, ComboBoxExt, ComboBoxExtManual, ComboBoxTextExt, EntryExt,
+    },
     ComboBoxText,
 };
 
@@ -19,6 +21,83 @@ fn register_wallet_entries_change_listener(
     });
 }
 
+/// Sends the address to import along with the height to rescan from, the whole chain if it's empty
+fn connect_import_address_btn(
+    builder: gtk::Builder,
+    sender: Sender<ModelRequest>,
+) -> io::Result<()> {
+    let import_btn: gtk::Button = builder.object("import_address_btn").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "could not find import address btn")
+    })?;
+    let address_entry: gtk::Entry = builder.object("import_address_entry").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find import address entry",
+        )
+    })?;
+    let height_entry: gtk::Entry = builder.object("import_height_entry").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find import height entry",
+        )
+    })?;
+
+    import_btn.connect_clicked(move |_| {
+        let address = address_entry.text().trim().to_string();
+        let from_height = height_entry.text().trim().parse().unwrap_or(0);
+        _ = sender.send(ModelRequest::ImportAddress(address, from_height));
+    });
+    Ok(())
+}
+
+/// Sends the active wallet to have its history rebuilt from the chain
+fn connect_rebuild_history_btn(
+    builder: gtk::Builder,
+    sender: Sender<ModelRequest>,
+) -> io::Result<()> {
+    let rebuild_btn: gtk::Button = builder.object("rebuild_history_btn").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find rebuild history btn",
+        )
+    })?;
+    let wallet_entries: ComboBoxText = builder
+        .object("wallet_entries")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "could not find wallet entries"))?;
+
+    rebuild_btn.connect_clicked(move |_| {
+        if let Some(active_wallet) = wallet_entries.active_text() {
+            _ = sender.send(ModelRequest::RebuildHistory(active_wallet.to_string()));
+        }
+    });
+    Ok(())
+}
+
+/// Sends the active wallet to sign the transaction saved for it to sign offline
+fn connect_sign_partial_tx_btn(
+    builder: gtk::Builder,
+    sender: Sender<ModelRequest>,
+) -> io::Result<()> {
+    let sign_btn: gtk::Button = builder.object("sign_partial_tx_btn").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find sign partial tx btn",
+        )
+    })?;
+    let wallet_entries: ComboBoxText = builder
+        .object("wallet_entries")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "could not find wallet entries"))?;
+
+    sign_btn.connect_clicked(move |_| {
+        if let Some(active_wallet) = wallet_entries.active_text() {
+            _ = sender.send(ModelRequest::SignPartialTransaction(
+                active_wallet.to_string(),
+            ));
+        }
+    });
+    Ok(())
+}
+
 pub fn append_wallet(builder: gtk::Builder, wallet: String, is_main_wallet: bool) {
     if let Some(wallet_entries) = builder.object::<gtk::ComboBoxText>("wallet_entries") {
         if is_main_wallet {
@@ -33,7 +112,9 @@ pub fn append_wallet(builder: gtk::Builder, wallet: String, is_main_wallet: bool
 /// Initializes the wallet switcher component of the interface.
 pub fn init(builder: gtk::Builder, sender: Sender<ModelRequest>) -> io::Result<()> {
     if let Some(wallet_entries) = builder.object::<gtk::ComboBoxText>("wallet_entries") {
-        register_wallet_entries_change_listener(wallet_entries, sender);
+        register_wallet_entries_change_listener(wallet_entries, sender.clone());
     }
-    Ok(())
+    connect_rebuild_history_btn(builder.clone(), sender.clone())?;
+    connect_sign_partial_tx_btn(builder.clone(), sender.clone())?;
+    connect_import_address_btn(builder, sender)
 }
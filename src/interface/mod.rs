@@ -8,6 +8,7 @@ use gtk::glib::{Receiver as GtkReceiver, SyncSender as GtkSender};
 use gtk::prelude::*;
 use std::io;
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 
 use self::components::table::table_append_data;
 use self::components::table::{GtkTable, GtkTableData};
@@ -16,8 +17,8 @@ pub mod components;
 
 /// Enum with messages from the model to the interface
 pub enum GtkMessage {
-    /// label, text
-    UpdateBalance((u64, u64)),
+    /// available, pending, immature, spendable if enabled
+    UpdateBalance((u64, u64, u64, Option<u64>)),
     UpdateOverviewTransactions(Vec<TransactionDisplayInfo>),
     /// type, notification title, notification message
     CreateNotification((gtk::MessageType, String, String)),
@@ -35,38 +36,145 @@ pub type RecipientDetails = (String, String, u64); // (address, label, value)
 /// Enum with requests from the interface to the model
 pub enum ModelRequest {
     GenerateTransaction(TransactionInfo),
+    /// builds the transaction without signing nor broadcasting it
+    PreviewTransaction(TransactionInfo),
     ChangeActiveWallet(String), // wallet address
     /// block hash, transaction hash
     GetPoi(String, String),
+    /// address to watch, height its history is rescanned from
+    ImportAddress(String, usize),
+    /// address of the wallet whose history is rebuilt from the chain
+    RebuildHistory(String),
+    /// address of the wallet signing the transaction its watch-only counterpart saved for offline signing
+    SignPartialTransaction(String),
 }
 
-/// called from the model, to update the status bar in the ui
-pub fn update_ui_progress_bar(
-    sender: &GtkSender<GtkMessage>,
-    new_status: Option<&str>,
-    mut fraction: f64,
-) -> io::Result<()> {
-    if fraction > 1.0 {
-        fraction = 1.0;
-    }
-    if let Some(new_status) = new_status {
-        sender
-            .send(GtkMessage::UpdateProgressBar((
-                Some(new_status.to_string()),
-                fraction,
-            )))
+/// Kind of a notification, telling how it's shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationKind {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Receives the updates from the model, so the model doesn't depend on GTK and can run without an interface.
+/// Every update does nothing unless overridden, for sinks that only care about some of them.
+pub trait UiNotifier: Send + Sync {
+    /// available, pending and immature balances, spendable one if enabled
+    fn update_balance(
+        &self,
+        _balances: (u64, u64, u64),
+        _spendable: Option<u64>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn notify(&self, _kind: NotificationKind, _title: &str, _message: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn update_table(&self, _table: GtkTable, _data: GtkTableData) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// optional new status, fraction
+    fn update_progress(&self, _status: Option<&str>, _fraction: f64) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn update_overview_transactions(&self, _txs: Vec<TransactionDisplayInfo>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn add_wallet_entry(&self, _address: &str, _is_main_wallet: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn update_poi_result(&self, _result: String) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Handle to the interface shared by the threads of the model
+pub type UiSender = Arc<dyn UiNotifier>;
+
+/// The GTK interface, which gets the updates through the channel attached to its main loop
+impl UiNotifier for GtkSender<GtkMessage> {
+    fn update_balance(&self, balances: (u64, u64, u64), spendable: Option<u64>) -> io::Result<()> {
+        let (available, pending, immature) = balances;
+        let message = GtkMessage::UpdateBalance((available, pending, immature, spendable));
+        self.send(message).map_err(to_io_err)
+    }
+
+    fn notify(&self, kind: NotificationKind, title: &str, message: &str) -> io::Result<()> {
+        let message_type = match kind {
+            NotificationKind::Info => gtk::MessageType::Info,
+            NotificationKind::Warning => gtk::MessageType::Warning,
+            NotificationKind::Error => gtk::MessageType::Error,
+        };
+        let notification = (message_type, title.to_string(), message.to_string());
+        self.send(GtkMessage::CreateNotification(notification))
+            .map_err(to_io_err)
+    }
+
+    fn update_table(&self, table: GtkTable, data: GtkTableData) -> io::Result<()> {
+        self.send(GtkMessage::UpdateTable((table, data)))
+            .map_err(to_io_err)
+    }
+
+    fn update_progress(&self, status: Option<&str>, fraction: f64) -> io::Result<()> {
+        let status = status.map(str::to_string);
+        self.send(GtkMessage::UpdateProgressBar((status, fraction.min(1.0))))
             .map_err(to_io_err)
-    } else {
-        sender
-            .send(GtkMessage::UpdateProgressBar((None, fraction)))
+    }
+
+    fn update_overview_transactions(&self, txs: Vec<TransactionDisplayInfo>) -> io::Result<()> {
+        self.send(GtkMessage::UpdateOverviewTransactions(txs))
+            .map_err(to_io_err)
+    }
+
+    fn add_wallet_entry(&self, address: &str, is_main_wallet: bool) -> io::Result<()> {
+        self.send(GtkMessage::AddWalletEntry(
+            address.to_string(),
+            is_main_wallet,
+        ))
+        .map_err(to_io_err)
+    }
+
+    fn update_poi_result(&self, result: String) -> io::Result<()> {
+        self.send(GtkMessage::UpdatePoiResult(result))
             .map_err(to_io_err)
     }
 }
 
-fn update_balance(builder: gtk::Builder, balance: u64, pending: u64) {
+/// Sink of a node running headless (without a display for GTK): notifications are printed, the rest of
+/// the updates are dropped
+pub struct HeadlessUi;
+
+impl UiNotifier for HeadlessUi {
+    fn notify(&self, _kind: NotificationKind, title: &str, message: &str) -> io::Result<()> {
+        println!("{}: {}", title, message);
+        Ok(())
+    }
+}
+
+/// Shows the immature coinbase outputs, only while there are some
+fn update_immature_balance(builder: &gtk::Builder, immature: f64) {
+    if let (Some(overview_immature_box), Some(balance_immature_val)) = (
+        builder.object::<gtk::Box>("overview_immature_box"),
+        builder.object::<gtk::Label>("balance_immature_val"),
+    ) {
+        balance_immature_val.set_text(format!("{:.8}", immature).as_str());
+        overview_immature_box.set_visible(immature > 0.0);
+    }
+}
+
+fn update_balance(builder: gtk::Builder, balances: (u64, u64, u64), spendable: Option<u64>) {
     // Format balances as (balance / 100000000.0)
+    let (balance, pending, immature) = balances;
     let balance = balance as f64 / 100000000.0;
     let pending = pending as f64 / 100000000.0;
+    let immature = immature as f64 / 100000000.0;
 
     // Get balances labels and update them
     if let Some(balance_available_val) = builder.object::<gtk::Label>("balance_available_val") {
@@ -84,7 +192,19 @@ fn update_balance(builder: gtk::Builder, balance: u64, pending: u64) {
     }
 
     if let Some(balance_total_val) = builder.object::<gtk::Label>("balance_total_val") {
-        balance_total_val.set_text(format!("{:.8}", balance + pending).as_str());
+        balance_total_val.set_text(format!("{:.8}", balance + pending + immature).as_str());
+    }
+
+    update_immature_balance(&builder, immature);
+
+    if let (Some(spendable), Some(overview_spendable_box), Some(balance_spendable_val)) = (
+        spendable,
+        builder.object::<gtk::Box>("overview_spendable_box"),
+        builder.object::<gtk::Label>("balance_spendable_val"),
+    ) {
+        let spendable = spendable as f64 / 100000000.0;
+        balance_spendable_val.set_text(format!("{:.8}", spendable).as_str());
+        overview_spendable_box.set_visible(true);
     }
 }
 
@@ -110,8 +230,8 @@ fn attach_rcv(receiver: GtkReceiver<GtkMessage>, builder: gtk::Builder) {
     receiver.attach(None, move |msg| {
         let builder_aux = builder.clone();
         match msg {
-            GtkMessage::UpdateBalance((balance, pending)) => {
-                update_balance(builder_aux, balance, pending);
+            GtkMessage::UpdateBalance((balance, pending, immature, spendable)) => {
+                update_balance(builder_aux, (balance, pending, immature), spendable);
             }
             GtkMessage::UpdateOverviewTransactions(transactions) => {
                 _ = update_overview_transactions(builder_aux, transactions);
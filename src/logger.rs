@@ -1,6 +1,9 @@
 use crate::messages::constants::config::VERBOSE;
+use crate::messages::constants::header_constants::{HEADER_SIZE, START_STRING_SIZE};
+use crate::messages::MessageHeader;
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
@@ -8,11 +11,13 @@ use std::thread;
 #[derive(Clone)]
 pub struct Logger {
     pub log_sender: Sender<Log>,
+    pub verbose: bool,
 }
 
 pub enum Log {
     Verbose(String),
     Quiet(String),
+    Trace(String), // only sent when message tracing is on, so it's always written
 }
 
 impl Logger {
@@ -24,17 +29,60 @@ impl Logger {
             .expect("Failed to open log file");
 
         let (sender, receiver): (Sender<Log>, Receiver<Log>) = mpsc::channel();
+        let verbose = log_level == VERBOSE;
         thread::spawn(move || {
-            let verbose = log_level == VERBOSE;
             for content in receiver.iter() {
                 let _ = match content {
                     Log::Verbose(content) if verbose => writeln!(file, "{}", &content),
-                    Log::Quiet(content) => writeln!(file, "{}", &content),
+                    Log::Quiet(content) | Log::Trace(content) => writeln!(file, "{}", &content),
                     _ => Ok(()),
                 };
             }
         });
 
-        Logger { log_sender: sender }
+        Logger {
+            log_sender: sender,
+            verbose,
+        }
+    }
+}
+
+/// Logs the command, size and direction of every message exchanged with peers, for protocol debugging.
+/// It does nothing unless created with a log sender.
+#[derive(Debug, Clone, Default)]
+pub struct MessageTracer {
+    log_sender: Option<Sender<Log>>,
+}
+
+impl MessageTracer {
+    pub fn new(log_sender: Sender<Log>) -> Self {
+        Self {
+            log_sender: Some(log_sender),
+        }
+    }
+
+    fn trace(&self, direction: &str, peer: SocketAddr, command: &str, size: usize) {
+        if let Some(log_sender) = &self.log_sender {
+            let command = command.trim_end_matches('\0');
+            let _ = log_sender.send(Log::Trace(format!(
+                "[trace] {} {} {} ({} bytes)",
+                direction, command, peer, size
+            )));
+        }
+    }
+
+    /// Traces a message received from the peer, its size includes the message header
+    pub fn received(&self, peer: SocketAddr, command: &str, size: usize) {
+        self.trace("received", peer, command, size);
+    }
+
+    /// Traces a serialized message (header and payload) sent to the peer
+    pub fn sent(&self, peer: SocketAddr, message: &[u8]) {
+        if self.log_sender.is_none() || message.len() < HEADER_SIZE {
+            return;
+        }
+        if let Ok(header) = MessageHeader::from_bytes(&message[START_STRING_SIZE..HEADER_SIZE]) {
+            self.trace("sent", peer, &header.command_name, message.len());
+        }
     }
 }
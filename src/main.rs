@@ -1,36 +1,84 @@
-use crate::messages::constants::config::VERBOSE;
+use crate::args_parser::Command;
+use crate::config::Config;
+use crate::interface::{HeadlessUi, ModelRequest, UiSender};
+use crate::messages::constants::config::{QUIET, VERBOSE};
 use gtk::glib;
 use std::io;
 
 mod args_parser;
+mod backup_check;
+mod block_requests;
+mod block_stats;
+mod chain_tip;
 mod config;
+mod download_limiter;
+mod hd_keys;
+mod header_sync;
+mod ibd;
 mod interface;
 mod logger;
+mod mempool;
 mod messages;
 mod network_controller;
+mod network_params;
 mod node;
 mod node_controller;
+mod non_final_txs;
+mod orphan_txs;
+mod peer_info;
 mod raw_transaction;
+mod rpc;
+mod socks5;
+mod sync_watchdog;
+#[cfg(test)]
+mod test_fixtures;
+mod tx_events;
+mod tx_index;
+mod tx_pool;
+mod tx_requests;
+mod upload_target;
 mod utility;
 mod utxo;
 mod wallet;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
 
-/// Main function that starts the program spawning the UI thread and the network thread and starting the sync
+/// Main function that starts the program spawning the UI thread and the network thread and starting the sync,
+/// unless it was only asked to verify a backup file. Without a display for the UI, the node runs headless.
 fn main() -> io::Result<()> {
-    let (ui_sender, receiver) = glib::MainContext::sync_channel(glib::PRIORITY_HIGH, 100);
+    let config_file = match args_parser::get_args() {
+        Command::Run(config_file) => config_file,
+        Command::VerifyBackup(backup_file, network) => {
+            return backup_check::run(&backup_file, network)
+        }
+    };
+    let config = Config::from_file(config_file)?;
     let (sender_aux, receiver_aux) = mpsc::channel();
-    let (writer_end, node_receiver) = mpsc::sync_channel(100);
-    let config_file = args_parser::get_args();
-    let config = config::Config::from_file(config_file)?;
-    thread::spawn(move || -> io::Result<()> {
-        let outer_controller =
-            network_controller::OuterNetworkController::new(ui_sender, writer_end, config.clone())?;
-        config.log("Connected to network, starting sync", VERBOSE);
-        outer_controller.start_sync(node_receiver, receiver_aux, config)
-    });
+    if config.get_headless() || gtk::init().is_err() {
+        config.log("Running headless, without the interface", QUIET);
+        start_network(Arc::new(HeadlessUi), receiver_aux, config)?;
+        // the sync runs on its own threads, the model requests channel must stay open meanwhile
+        let _sender_aux = sender_aux;
+        loop {
+            thread::park();
+        }
+    }
+    let (ui_sender, receiver) = glib::MainContext::sync_channel(glib::PRIORITY_HIGH, 100);
+    thread::spawn(move || start_network(Arc::new(ui_sender), receiver_aux, config));
 
     interface::init(receiver, sender_aux)?;
     Ok(())
 }
+
+/// Connects to the network and starts the sync, sending the updates for the interface to the given sink
+fn start_network(
+    ui_sender: UiSender,
+    ui_receiver: mpsc::Receiver<ModelRequest>,
+    config: Config,
+) -> io::Result<()> {
+    let (writer_end, node_receiver) = mpsc::sync_channel(100);
+    let outer_controller =
+        network_controller::OuterNetworkController::new(ui_sender, writer_end, config.clone())?;
+    config.log("Connected to network, starting sync", VERBOSE);
+    outer_controller.start_sync(node_receiver, ui_receiver, config)
+}
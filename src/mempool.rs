@@ -0,0 +1,103 @@
+use crate::messages::utility::{read_from_varint, to_compact_size_bytes, StreamRead};
+use crate::raw_transaction::RawTransaction;
+use std::fs;
+use std::io::{self, Cursor};
+
+/// A pending transaction saved to the mempool file, along with the unix time it was received and whether
+/// we broadcast it, so it's rebroadcast after a restart
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    pub tx: RawTransaction,
+    pub received_at: u32,
+    pub sent: bool,
+}
+
+/// The pending transactions kept across restarts, like the mempool.dat of Bitcoin Core. They're read back
+/// as if just received, so the ones that no longer fit the chain are left out then.
+#[derive(Debug, Default, PartialEq)]
+pub struct Mempool {
+    pub entries: Vec<MempoolEntry>,
+}
+
+impl Mempool {
+    pub fn new(entries: Vec<MempoolEntry>) -> Self {
+        Self { entries }
+    }
+
+    /// Saves the entries to the file, replacing it once they're fully written so a crash keeps the previous one
+    pub fn save_to_file(&self, file_name: &str) -> io::Result<()> {
+        let mut bytes = to_compact_size_bytes(self.entries.len() as u64);
+        for entry in &self.entries {
+            bytes.extend(entry.received_at.to_le_bytes());
+            bytes.push(entry.sent as u8);
+            bytes.extend(entry.tx.serialize_with_witness());
+        }
+        let temp_file_name = format!("{}.tmp", file_name);
+        fs::write(&temp_file_name, bytes)?;
+        fs::rename(temp_file_name, file_name)
+    }
+
+    /// Reads the entries saved to the file, dropping the ones received more than `expiry` seconds before now.
+    /// A missing file is an empty mempool.
+    pub fn from_file(file_name: &str, expiry: u32, now: u32) -> io::Result<Self> {
+        let bytes = match fs::read(file_name) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let mut cursor: Cursor<&[u8]> = Cursor::new(&bytes);
+        let count = read_from_varint(&mut cursor)?;
+        let mut entries = vec![];
+        for _ in 0..count {
+            let received_at = u32::from_le_stream(&mut cursor)?;
+            let sent = u8::from_le_stream(&mut cursor)? != 0;
+            let tx = RawTransaction::from_bytes(&mut cursor)?;
+            if now.saturating_sub(received_at) <= expiry {
+                entries.push(MempoolEntry {
+                    tx,
+                    received_at,
+                    sent,
+                });
+            }
+        }
+        Ok(Self::new(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{FUNDING_TX_HEX, PAYMENT_TX_HEX};
+    use crate::utility::{decode_hex, to_io_err};
+
+    #[test]
+    fn test_mempool_survives_saving_and_reloading() -> io::Result<()> {
+        let file_name = "test_mempool.dat";
+        let funding = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        let spending = decode_hex(PAYMENT_TX_HEX).map_err(to_io_err)?;
+        let entry = |bytes: &[u8], received_at, sent| -> io::Result<MempoolEntry> {
+            let tx = RawTransaction::from_bytes(&mut Cursor::new(bytes))?;
+            Ok(MempoolEntry {
+                tx,
+                received_at,
+                sent,
+            })
+        };
+        let pending = vec![entry(&funding, 1000, false)?, entry(&spending, 2000, true)?];
+        let mempool = Mempool::new(pending.clone());
+
+        mempool.save_to_file(file_name)?;
+        let reloaded = Mempool::from_file(file_name, 1000, 2000);
+        // the first one expires a second later
+        let expired = Mempool::from_file(file_name, 1000, 2001);
+        fs::remove_file(file_name)?;
+
+        assert_eq!(reloaded?, mempool);
+        assert_eq!(expired?.entries, pending[1..]);
+        assert_eq!(
+            Mempool::from_file(file_name, 1000, 2000)?,
+            Mempool::default()
+        );
+        Ok(())
+    }
+}
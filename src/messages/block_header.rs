@@ -1,8 +1,18 @@
 use crate::io::Cursor;
 use crate::messages::{utility::*, HashId, Hashable};
+use crate::network_params::Genesis;
 use crate::utility::{double_hash, to_io_err};
-use std::collections::HashMap;
-use std::io::{self, ErrorKind::InvalidData, Write};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{
+    self,
+    ErrorKind::{InvalidData, NotFound},
+    Read, Seek, SeekFrom, Write,
+};
+use std::ops::Range;
+
+/// Known (height, hash) pairs which every accepted header chain must go through
+pub type Checkpoints = HashMap<usize, HashId>;
 
 /// Block header struct as defined in the Bitcoin documentation.
 //https://developer.bitcoin.org/reference/block_chain.html#block-headers
@@ -54,19 +64,17 @@ impl BlockHeader {
         }
     }
 
-    pub fn genesis(hash: HashId) -> Self {
-        // return Genesis block header
-        Self {
-            version: 0_i32,
-            prev_block_hash: HashId::default(),
-            next_block_hash: None,
-            merkle_root_hash: HashId::default(),
-            timestamp: 0_u32,
-            nbits: 0_u32,
-            nonce: 0_u32,
-            hash,
-            height: 0,
-        }
+    /// Builds the genesis block header from its fields, which has no previous block and height 0
+    pub fn genesis(genesis: &Genesis) -> Self {
+        Self::new(
+            genesis.version,
+            HashId::default(),
+            None,
+            genesis.merkle_root,
+            genesis.timestamp,
+            genesis.nbits,
+            genesis.nonce,
+        )
     }
 
     /// Create a block header from a byte array (little endian).
@@ -96,29 +104,27 @@ impl BlockHeader {
         &self.merkle_root_hash
     }
 
-    fn compare_target_threshold_and_hash(target: &HashId, hash: &HashId) -> std::cmp::Ordering {
-        target.cmp(hash)
-    }
-
     pub fn validate_proof_of_work(&self) -> Result<(), std::io::Error> {
+        // the target must take from 3 to 31 bytes to be expanded, like every target of the networks with full proof of work
+        if !(3..=31).contains(&(self.nbits >> 24)) {
+            return Err(std::io::Error::new(InvalidData, "Invalid target in nbits"));
+        }
         let target_threshold: HashId = Self::nbits_to_target(self.nbits);
-        let block_header_hash: HashId = self.hash();
-        match Self::compare_target_threshold_and_hash(&target_threshold, &block_header_hash) {
-            std::cmp::Ordering::Less => {
-                // The block header hash is lower than the target threshold
-                // log("Proof of work is valid!",VERBOSE);
-            }
-            std::cmp::Ordering::Greater => {
-                // The block header hash is higher than the target threshold
-                // log("Proof of work is invalid!",QUIET);
-                return Err(std::io::Error::new(InvalidData, "Invalid Proof of Work"));
-            }
-            std::cmp::Ordering::Equal => {
-                // The block header hash is equal to the target threshold
-                // log("Proof of work is valid!",VERBOSE);
-            }
+        // the target is big endian while header hashes are stored in little endian
+        let mut block_header_hash: [u8; 32] = self.hash().hash;
+        block_header_hash.reverse();
+        match block_header_hash <= target_threshold.hash {
+            true => Ok(()),
+            false => Err(std::io::Error::new(InvalidData, "Invalid Proof of Work")),
+        }
+    }
+
+    /// Returns false if there is a checkpoint at the header's height with a different hash.
+    pub fn matches_checkpoint(&self, checkpoints: &Checkpoints) -> bool {
+        match checkpoints.get(&self.height) {
+            Some(hash) => *hash == self.hash,
+            None => true,
         }
-        Ok(())
     }
 
     fn nbits_to_target(nbits: u32) -> HashId {
@@ -177,9 +183,38 @@ impl Hashable for BlockHeader {
         self.hash
     }
 }
+/// Size of a header record in the backup file: the header followed by an empty transaction count
+pub const HEADER_RECORD_SIZE: u64 = 81;
+/// Blocks whose timestamps the median time past is taken from (BIP113)
+const MEDIAN_TIME_SPAN: usize = 11;
+/// Tips of forks this many blocks below a new header are forgotten, branches that deep being stale
+const MAX_FORK_TIP_DEPTH: usize = 144;
+
+/// Record of an evicted header in the backup file, along with the chain data only kept in memory
+#[derive(Debug, Clone)]
+struct EvictedHeader {
+    position: u64,
+    height: usize,
+    next_block_hash: Option<HashId>,
+}
+
+/// Limit on the saved headers kept in memory. Once there are twice as many, the ones more than
+/// `max_in_memory` blocks below the tallest are evicted, except the ones at checkpoint heights.
+#[derive(Debug, Clone)]
+struct HeaderOverflow {
+    file: String,
+    max_in_memory: usize,
+    pinned_heights: HashSet<usize>,
+}
+
 #[derive(Debug, Clone)]
 pub struct HeaderSet {
     headers: HashMap<HashId, BlockHeader>,
+    positions: HashMap<HashId, u64>, // record in the backup file of the saved headers still in memory
+    evicted: HashMap<HashId, EvictedHeader>,
+    overflow: Option<HeaderOverflow>,
+    tips: HashSet<HashId>,    // headers no other header builds on
+    invalid: HashSet<HashId>, // headers whose block was rejected
 }
 
 impl HeaderSet {
@@ -187,43 +222,182 @@ impl HeaderSet {
         let mut headers = HashMap::new();
         headers.insert(hash, header);
 
-        Self { headers }
+        Self {
+            headers,
+            positions: HashMap::new(),
+            evicted: HashMap::new(),
+            overflow: None,
+            tips: HashSet::from([hash]),
+            invalid: HashSet::new(),
+        }
+    }
+
+    /// Keeps at most `max_in_memory` saved headers below the tallest one in memory (plus the checkpoints),
+    /// reading the older ones back from the given backup file when needed
+    pub fn limit_memory(&mut self, file: &str, max_in_memory: usize, checkpoints: &Checkpoints) {
+        self.overflow = Some(HeaderOverflow {
+            file: file.to_string(),
+            max_in_memory,
+            pinned_heights: checkpoints.keys().copied().collect(),
+        });
     }
 
     pub fn contains_key(&self, hash: &HashId) -> bool {
-        self.headers.contains_key(hash)
+        self.headers.contains_key(hash) || self.evicted.contains_key(hash)
     }
 
+    /// Inserts a header that isn't in the backup file, so it's always kept in memory
     pub fn insert(&mut self, hash: HashId, header: BlockHeader) {
+        self.extend_branch(hash, &header);
         self.headers.insert(hash, header);
     }
 
-    pub fn entry(
-        &mut self,
-        hash: HashId,
-    ) -> std::collections::hash_map::Entry<'_, HashId, BlockHeader> {
-        self.headers.entry(hash)
+    /// Inserts a header saved at the given record of the backup file, which may be evicted from memory
+    pub fn insert_saved(&mut self, header: BlockHeader, position: u64) {
+        self.extend_branch(header.hash, &header);
+        self.headers.insert(header.hash, header);
+        if let Some(overflow) = &self.overflow {
+            self.positions.insert(header.hash, position);
+            if self.positions.len() > 2 * overflow.max_in_memory {
+                self.evict_old_headers();
+            }
+        }
+    }
+
+    /// Makes a new header a tip in place of its parent
+    fn extend_branch(&mut self, hash: HashId, header: &BlockHeader) {
+        if self.contains_key(&hash) {
+            return;
+        }
+        self.tips.remove(&header.prev_block_hash);
+        self.tips.insert(hash);
+        self.prune_tips(header.height);
     }
 
-    pub fn get(&self, hash: &HashId) -> Option<&BlockHeader> {
-        self.headers.get(hash)
+    /// Forgets the tips of the forks buried too deep below the given height
+    fn prune_tips(&mut self, height: usize) {
+        let min_height = height.saturating_sub(MAX_FORK_TIP_DEPTH);
+        let (headers, evicted) = (&self.headers, &self.evicted);
+        self.tips.retain(|hash| {
+            let tip_height = match headers.get(hash) {
+                Some(header) => Some(header.height),
+                None => evicted.get(hash).map(|evicted| evicted.height),
+            };
+            tip_height.is_none_or(|tip_height| tip_height >= min_height)
+        });
     }
 
-    pub fn get_mut(&mut self, hash: &HashId) -> Option<&mut BlockHeader> {
-        self.headers.get_mut(hash)
+    /// Returns the hashes of the headers no other header builds on, the tips of the best chain and of its forks
+    pub fn tips(&self) -> impl Iterator<Item = &HashId> {
+        self.tips.iter()
     }
 
-    pub fn get_next_header(&self, hash: &HashId) -> Option<&BlockHeader> {
-        if let Some(header) = self.headers.get(hash) {
-            if let Some(next_hash) = header.next_block_hash {
-                return self.headers.get(&next_hash);
+    /// Records that the block of the header was rejected, which makes its branch invalid
+    pub fn mark_invalid(&mut self, hash: HashId) {
+        self.invalid.insert(hash);
+    }
+
+    pub fn is_invalid(&self, hash: &HashId) -> bool {
+        self.invalid.contains(hash)
+    }
+
+    fn evict_old_headers(&mut self) {
+        let overflow = match &self.overflow {
+            Some(overflow) => overflow,
+            None => return,
+        };
+        let tallest = self.headers.values().map(|h| h.height).max().unwrap_or(0);
+        let cutoff = tallest.saturating_sub(overflow.max_in_memory);
+        let headers = &mut self.headers;
+        self.positions.retain(|hash, position| {
+            let header = match headers.get(hash) {
+                Some(header) if header.height < cutoff => *header,
+                _ => return true,
+            };
+            if overflow.pinned_heights.contains(&header.height) {
+                return true;
             }
+            headers.remove(hash);
+            let evicted = EvictedHeader {
+                position: *position,
+                height: header.height,
+                next_block_hash: header.next_block_hash,
+            };
+            self.evicted.insert(*hash, evicted);
+            false
+        });
+    }
+
+    /// Reads an evicted header back from the backup file
+    fn read_evicted(&self, evicted: &EvictedHeader) -> io::Result<BlockHeader> {
+        let file = match &self.overflow {
+            Some(overflow) => &overflow.file,
+            None => return Err(io::Error::new(NotFound, "Headers aren't backed by a file")),
+        };
+        let mut file = File::open(file)?;
+        file.seek(SeekFrom::Start(evicted.position * HEADER_RECORD_SIZE))?;
+        let mut bytes = [0_u8; 80];
+        file.read_exact(&mut bytes)?;
+        let mut header = BlockHeader::from_bytes(&mut Cursor::new(&bytes[..]))?;
+        header.height = evicted.height;
+        header.next_block_hash = evicted.next_block_hash;
+        Ok(header)
+    }
+
+    /// Returns the header of the given hash, reading it from the backup file if it was evicted from memory
+    pub fn get(&self, hash: &HashId) -> Option<BlockHeader> {
+        match self.headers.get(hash) {
+            Some(header) => Some(*header),
+            None => self.read_evicted(self.evicted.get(hash)?).ok(),
         }
-        None
+    }
+
+    /// Sets the header following the given one in the best chain
+    pub fn set_next_block_hash(&mut self, hash: &HashId, next_block_hash: HashId) {
+        if let Some(header) = self.headers.get_mut(hash) {
+            header.next_block_hash = Some(next_block_hash);
+        } else if let Some(evicted) = self.evicted.get_mut(hash) {
+            evicted.next_block_hash = Some(next_block_hash);
+        }
+    }
+
+    pub fn get_next_header(&self, hash: &HashId) -> Option<BlockHeader> {
+        let next_hash = self.get(hash)?.next_block_hash?;
+        self.get(&next_hash)
     }
 
     pub fn len(&self) -> usize {
-        self.headers.len()
+        self.headers.len() + self.evicted.len()
+    }
+
+    /// Returns the headers of the chain ending at the given tip whose heights are in the range, lowest first
+    pub fn best_chain_range(&self, tip: &BlockHeader, heights: Range<usize>) -> Vec<BlockHeader> {
+        let mut headers = vec![];
+        let mut current_header = Some(*tip);
+        while let Some(header) = current_header {
+            if header.height < heights.start {
+                break;
+            }
+            if heights.contains(&header.height) {
+                headers.push(header);
+            }
+            current_header = self.get(&header.prev_block_hash);
+        }
+        headers.reverse();
+        headers
+    }
+
+    /// Returns the median timestamp of the tip and the blocks before it, 11 at most (BIP113). Lock times are
+    /// compared against it instead of the tip's timestamp, which a miner can set ahead at will.
+    pub fn median_time_past(&self, tip: &BlockHeader) -> u32 {
+        let start = (tip.height + 1).saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times: Vec<u32> = self
+            .best_chain_range(tip, start..tip.height + 1)
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        times.sort_unstable();
+        times.get(times.len() / 2).copied().unwrap_or(tip.timestamp)
     }
 }
 
@@ -292,6 +466,30 @@ mod tests {
         fs::remove_file(file_name).unwrap();
     }
 
+    #[test]
+    fn test_header_at_checkpoint_height() {
+        let mut header = BlockHeader::new(
+            0x20000000,
+            HashId::default(),
+            None,
+            HashId::default(),
+            1681095679,
+            422120062,
+            1823431201,
+        );
+        header.height = 10;
+
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.insert(10, header.hash);
+        assert!(header.matches_checkpoint(&checkpoints));
+
+        checkpoints.insert(10, HashId::new([1u8; 32]));
+        assert!(!header.matches_checkpoint(&checkpoints));
+
+        header.height = 11;
+        assert!(header.matches_checkpoint(&checkpoints));
+    }
+
     #[test]
     fn test_nbits_to_target() {
         let nbits: u32 = 0x181bc330;
@@ -334,11 +532,12 @@ mod tests {
         assert_eq!(block_header.timestamp, 1681095679);
         assert_eq!(block_header.nbits, 422120062);
         assert_eq!(block_header.nonce, 1823431201);
+        assert!(block_header.validate_proof_of_work().is_ok());
     }
 
     #[test]
     fn test_push_headers_to_headerset() {
-        let child_header = BlockHeader::genesis(HashId::default());
+        let child_header = BlockHeader::new(0, HashId::default(), None, HashId::default(), 0, 0, 0);
         let mut headerset = HeaderSet::with(child_header.hash, child_header);
 
         let parent_header = BlockHeader {
@@ -367,4 +566,120 @@ mod tests {
             Some(child_header.hash)
         );
     }
+
+    #[test]
+    fn test_best_chain_range_getdata() {
+        let genesis = BlockHeader::new(0, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let mut headerset = HeaderSet::with(genesis.hash, genesis);
+        let mut tip = genesis;
+        for nonce in 1..=5 {
+            let mut header =
+                BlockHeader::new(0x20000000, tip.hash, None, HashId::default(), 0, 0, nonce);
+            header.height = tip.height + 1;
+            headerset.insert(header.hash, header);
+            tip = header;
+        }
+
+        let headers = headerset.best_chain_range(&tip, 2..4);
+        let heights: Vec<usize> = headers.iter().map(|header| header.height).collect();
+        assert_eq!(heights, vec![2, 3]);
+
+        let get_data = crate::messages::GetData::from_inv(
+            headers.clone(),
+            crate::messages::InvType::MSGWitnessBlock,
+        );
+        let hashes: Vec<HashId> = get_data
+            .inventory
+            .items
+            .iter()
+            .map(|inventory| inventory.hash)
+            .collect();
+        assert_eq!(hashes, vec![headers[0].hash, headers[1].hash]);
+    }
+
+    #[test]
+    fn test_median_time_past_is_the_median_of_the_last_eleven_blocks() {
+        let genesis = BlockHeader::new(0, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let mut headerset = HeaderSet::with(genesis.hash, genesis);
+        let mut tip = genesis;
+        assert_eq!(headerset.median_time_past(&tip), 0);
+        // timestamps going up by 10 seconds, except the tip's, which is far ahead
+        for height in 1..=20 {
+            let time = match height {
+                20 => 1_000_000,
+                height => height * 10,
+            };
+            let mut header =
+                BlockHeader::new(0x20000000, tip.hash, None, HashId::default(), time, 0, 0);
+            header.height = height as usize;
+            headerset.insert(header.hash, header);
+            tip = header;
+        }
+        // blocks 10 to 20, the median being block 15
+        assert_eq!(headerset.median_time_past(&tip), 150);
+    }
+
+    #[test]
+    fn test_fork_tips_buried_too_deep_are_forgotten() {
+        let genesis = BlockHeader::new(0, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let mut headerset = HeaderSet::with(genesis.hash, genesis);
+        let mut fork = BlockHeader::new(0x20000000, genesis.hash, None, HashId::default(), 1, 0, 0);
+        fork.height = 1;
+        headerset.insert(fork.hash, fork);
+        let mut tip = genesis;
+        for height in 1..=MAX_FORK_TIP_DEPTH + 2 {
+            let mut header =
+                BlockHeader::new(0x20000000, tip.hash, None, HashId::default(), 2, 0, 0);
+            header.height = height;
+            headerset.insert(header.hash, header);
+            tip = header;
+            let tips: HashSet<HashId> = headerset.tips().copied().collect();
+            match height <= MAX_FORK_TIP_DEPTH + 1 {
+                true => assert_eq!(tips, HashSet::from([fork.hash, tip.hash])),
+                false => assert_eq!(tips, HashSet::from([tip.hash])),
+            }
+        }
+    }
+
+    #[test]
+    fn test_evicted_headers_are_read_from_backup_file() -> io::Result<()> {
+        let file_name = "test_evicted_headers.dat";
+        _ = fs::remove_file(file_name);
+        let genesis = BlockHeader::new(0, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let mut headerset = HeaderSet::with(genesis.hash, genesis);
+        headerset.limit_memory(file_name, 2, &Checkpoints::new());
+        let mut chain = vec![genesis];
+        for nonce in 1..=8 {
+            let tip = chain[chain.len() - 1];
+            let mut header =
+                BlockHeader::new(0x20000000, tip.hash, None, HashId::default(), 0, 0, nonce);
+            header.height = tip.height + 1;
+            header.save_to_file(file_name)?;
+            headerset.insert_saved(header, nonce as u64 - 1);
+            headerset.set_next_block_hash(&tip.hash, header.hash);
+            chain.push(header);
+        }
+
+        let old_header = chain[2];
+        assert!(!headerset.headers.contains_key(&old_header.hash));
+        assert!(headerset.contains_key(&old_header.hash));
+        assert_eq!(headerset.len(), chain.len());
+        let read_header = headerset.get(&old_header.hash);
+        assert_eq!(read_header.map(|header| header.hash), Some(old_header.hash));
+        assert_eq!(read_header.map(|header| header.height), Some(2));
+        assert_eq!(
+            headerset
+                .get_next_header(&old_header.hash)
+                .map(|header| header.hash),
+            Some(chain[3].hash)
+        );
+
+        let heights: Vec<usize> = headerset
+            .best_chain_range(&chain[8], 1..9)
+            .iter()
+            .map(|header| header.height)
+            .collect();
+        assert_eq!(heights, (1..9).collect::<Vec<usize>>());
+        fs::remove_file(file_name)
+    }
 }
@@ -1,22 +1,35 @@
+use super::signet;
 use super::Message;
-use crate::interface::GtkMessage;
+use crate::interface::UiNotifier;
 use crate::io::{self, Cursor};
 use crate::messages::constants::commands::BLOCK;
+use crate::messages::constants::messages::{
+    MAX_BLOCK_BASE_SIZE, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT,
+};
+use crate::messages::witness_commitment::WitnessCommitment;
 use crate::messages::MerkleTree;
 use crate::messages::{utility::*, BlockHeader, HashId, Hashable, Serialize};
-use crate::raw_transaction::{RawTransaction, TransactionOrigin};
+use crate::network_params::{NetworkParams, PowPolicy, SoftForks};
+use crate::raw_transaction::interpreter::TxChecker;
+use crate::raw_transaction::{tx_input::TxInputType, RawTransaction, TransactionOrigin};
 use crate::utility::double_hash;
 use crate::utility::to_io_err;
-use crate::utxo::UtxoSet;
+use crate::utxo::{OutPoint, UtxoSet};
 use crate::wallet::Wallet;
 use bitcoin_hashes::{sha256, Hash};
-use gtk::glib::SyncSender;
-use std::collections::HashMap;
-use std::fs::OpenOptions;
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
 use std::io::{Read, Write};
 
 pub type BlockSet = HashMap<HashId, Block>;
 
+/// First bytes of a gzip member. A plain backup record can't start with them, since they'd declare a block of
+/// 31 bytes, smaller than its header.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
 /// A struct that represents a block with a header and  a list of transactions.
 #[derive(Debug, Clone)]
 pub struct Block {
@@ -40,6 +53,16 @@ impl Block {
         Ok(message)
     }
 
+    /// Builds the block message with the witnesses of its transactions, for peers asking for witness blocks
+    pub fn serialize_witness_message(&self) -> io::Result<Vec<u8>> {
+        let mut payload = self.header.serialize();
+        payload.extend(to_compact_size_bytes(self.txn_count as u64));
+        for txn in self.txns.iter() {
+            payload.extend(txn.serialize_with_witness());
+        }
+        self.build_message(BLOCK, Some(payload))
+    }
+
     pub fn hash_transactions(&self) -> Vec<sha256::Hash> {
         let mut txn_hashes: Vec<sha256::Hash> = vec![];
         self.txns.iter().for_each(|txn| {
@@ -52,52 +75,312 @@ impl Block {
 
     fn validate_merkle_root(&self) -> io::Result<()> {
         // hash all transactions in the block
-        let txn_hashes = self.hash_transactions();
-
-        // build merkle tree from transaction hashes
-        let merkle_tree = MerkleTree::generate_from_hashes(txn_hashes); // clone txn_hashes if merkle proofing
-        let root_hash = merkle_tree.get_root();
+        let root_hash = merkle_root(self.hash_transactions());
 
-        match self.header.merkle_root_hash == HashId::new(root_hash.to_byte_array()) {
+        match self.header.merkle_root_hash == root_hash {
             true => Ok(()),
             false => {
                 eprintln!("\x1b[93mMerkle root is invalid!\x1b[0m");
                 Err(io::Error::new(
                     io::ErrorKind::InvalidData,
-                    "Merkle root hash mismatch",
+                    format!(
+                        "Merkle root hash mismatch: computed {}, expected {}. {}",
+                        root_hash,
+                        self.header.merkle_root_hash,
+                        self.merkle_root_diagnostic()
+                    ),
                 ))
             }
         }
     }
 
-    /// Validates the block by checking the proof of work, merkle root.
-    pub fn validate(&self) -> io::Result<()> {
-        self.header.validate_proof_of_work()?;
+    /// Explains a merkle root mismatch as far as it can be told apart: a transaction count that doesn't match
+    /// the parsed transactions, transactions hashed along with their witnesses, or one that doesn't read back
+    /// the same from its own serialization (a parsing bug)
+    fn merkle_root_diagnostic(&self) -> String {
+        if self.txn_count != self.txns.len() {
+            return format!(
+                "The block declares {} transactions but {} were parsed",
+                self.txn_count,
+                self.txns.len()
+            );
+        }
+        if self.witness_merkle_root() == self.header.merkle_root_hash {
+            return "The root matches when transactions are hashed with their witnesses, segwit transactions \
+                    must be hashed without them"
+                .to_string();
+        }
+        match self
+            .txns
+            .iter()
+            .enumerate()
+            .find(|(index, txn)| !reads_back(txn, *index == 0))
+        {
+            Some((index, txn)) => format!(
+                "Transaction {} ({}) doesn't read back the same from its serialization",
+                index,
+                txn.txid()
+            ),
+            None => "No transaction could be singled out, one differs from the block committed to"
+                .to_string(),
+        }
+    }
+
+    /// Returns the merkle root of the transactions hashed along with their witnesses
+    fn witness_merkle_root(&self) -> HashId {
+        let wtxid_hashes = self
+            .txns
+            .iter()
+            .map(|txn| double_hash(&txn.serialize_with_witness()))
+            .collect();
+        merkle_root(wtxid_hashes)
+    }
+
+    /// Validates the block by checking the proof of work (as required by the network), the size and
+    /// signature operation limits, the coinbase, that no transaction spends an outpoint twice or pushes an oversized script element
+    /// and the merkle root.
+    pub fn validate(&self, network_params: &NetworkParams) -> io::Result<()> {
+        match &network_params.pow_policy {
+            PowPolicy::Full => self.header.validate_proof_of_work()?,
+            PowPolicy::Trivial => {}
+            PowPolicy::Signet(challenge) => self.validate_signet(challenge)?,
+        }
+        self.validate_size()?;
+        self.validate_sigops()?;
+        self.validate_coinbase()?;
+        for txn in &self.txns {
+            txn.check_no_duplicate_inputs()?;
+            txn.check_script_element_sizes()?;
+        }
         self.validate_merkle_root()?;
         Ok(())
     }
 
+    /// Checks the coinbase commits to the witnesses of the transactions (BIP141) once segwit is active: its
+    /// commitment must be well formed and hold the hash of their witness merkle root and the witness reserved value
+    /// of the coinbase. Blocks without one, like every block before segwit, can't have witness data. Blocks
+    /// downloaded without witnesses can't be checked.
+    pub fn validate_witness_commitment(&self, segwit: bool) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let Some(coinbase) = self.txns.first() else {
+            return Err(invalid("Block without coinbase"));
+        };
+        match WitnessCommitment::from_coinbase(coinbase).filter(|_| segwit) {
+            Some(commitment) => commitment.validate(&self.witness_commitment_root()),
+            None if self.txns.iter().all(|txn| txn.witnesses.is_empty()) => Ok(()),
+            None => Err(invalid("Block has witness data but no witness commitment")),
+        }
+    }
+
+    /// Returns the merkle root of the wtxids the witness commitment is made of, where the coinbase counts as zero
+    fn witness_commitment_root(&self) -> sha256::Hash {
+        let mut wtxid_hashes: Vec<sha256::Hash> = self
+            .txns
+            .iter()
+            .map(|txn| double_hash(&txn.serialize_with_witness()))
+            .collect();
+        if let Some(coinbase) = wtxid_hashes.first_mut() {
+            *coinbase = sha256::Hash::from_byte_array([0; 32]);
+        }
+        MerkleTree::generate_from_hashes(wtxid_hashes).get_root()
+    }
+
+    /// Returns the size in bytes of the header and transaction count, which have no witness data
+    fn overhead(&self) -> usize {
+        80 + to_varint(self.txns.len() as u64).len()
+    }
+
+    /// Returns the weight of the block (BIP 141), where the header and transaction count weigh 4 units per byte
+    pub fn weight(&self) -> usize {
+        4 * self.overhead() + self.txns.iter().map(RawTransaction::weight).sum::<usize>()
+    }
+
+    /// Returns the size in bytes of the block serialized with the witnesses of its transactions
+    pub fn size(&self) -> usize {
+        self.overhead()
+            + self
+                .txns
+                .iter()
+                .map(|txn| txn.serialize_with_witness().len())
+                .sum::<usize>()
+    }
+
+    /// Checks the block doesn't exceed the consensus limits on its weight and on its size without witnesses
+    fn validate_size(&self) -> io::Result<()> {
+        let base_size = self.overhead()
+            + self
+                .txns
+                .iter()
+                .map(|txn| txn.serialize().len())
+                .sum::<usize>();
+        let weight = self.weight();
+        if weight > MAX_BLOCK_WEIGHT || base_size > MAX_BLOCK_BASE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Block of weight {} and size {} exceeds the limits",
+                    weight, base_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the signature operations in the scripts of the block don't exceed the consensus limit on their cost.
+    /// Spending validation adds the ones of the redeem scripts and witnesses, which need the outputs spent.
+    fn validate_sigops(&self) -> io::Result<()> {
+        check_sigops_cost(self.legacy_sigops_cost())
+    }
+
+    /// Returns the cost of the signature operations in the scripts of the block, 4 each by their legacy count
+    fn legacy_sigops_cost(&self) -> usize {
+        4 * self
+            .txns
+            .iter()
+            .map(|txn| txn.sigop_count(false))
+            .sum::<usize>()
+    }
+
+    /// Checks the first transaction is a coinbase spending the null outpoint, and that it's the only one
+    fn validate_coinbase(&self) -> io::Result<()> {
+        let first_is_coinbase = match self.txns.first().map(|txn| &txn.tx_in) {
+            Some(TxInputType::CoinBaseInput(coinbase)) => coinbase.has_null_prevout(),
+            _ => false,
+        };
+        let other_coinbase = self.txns.iter().skip(1).any(|txn| match &txn.tx_in {
+            TxInputType::CoinBaseInput(_) => true,
+            TxInputType::TxInput(inputs) => {
+                inputs.iter().any(|input| input.previous_output.is_null())
+            }
+        });
+        if !first_is_coinbase || other_coinbase {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Block must have exactly one coinbase, as its first transaction",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the transactions against the outputs they spend, which full validation requires on top of `validate`:
+    /// every input spends a confirmed unspent output or one created earlier in the block, no output is spent
+    /// twice, P2PKH outputs are unlocked by a valid signature, no transaction pays out more than its inputs
+    /// and the coinbase claims at most the block subsidy plus the fees. The lock times and encodings checked
+    /// depend on the soft forks active at the block's height. Relative lock times are checked against the
+    /// height of the block confirming each output spent, found with the given function for those buried
+    /// deeper than the utxo set tracks.
+    pub fn validate_spends(
+        &self,
+        utxo_set: &UtxoSet,
+        confirmation_height: impl Fn(&HashId) -> Option<usize>,
+        network_params: &NetworkParams,
+    ) -> io::Result<()> {
+        let depth = |txid: &HashId| match utxo_set.confirmations(txid) {
+            usize::MAX => confirmation_height(txid).map_or(usize::MAX, |height| {
+                self.header.height.saturating_sub(height)
+            }),
+            confirmations => confirmations.saturating_add(1),
+        };
+        let mut block_outputs: HashMap<OutPoint, (u64, Vec<u8>)> = HashMap::new();
+        let mut spent: HashSet<OutPoint> = HashSet::new();
+        let (mut fees, mut sigops_cost) = (0u64, self.legacy_sigops_cost());
+        let soft_forks = network_params.soft_forks(self.header.height);
+        for txn in self.txns.iter().skip(1) {
+            let (fee, cost) = spend_outputs(
+                txn,
+                (utxo_set, &depth),
+                &mut block_outputs,
+                &mut spent,
+                soft_forks,
+            )?;
+            fees = fees
+                .checked_add(fee)
+                .ok_or_else(|| invalid_spend(txn, "overflows the fees"))?;
+            sigops_cost += cost;
+        }
+        check_sigops_cost(sigops_cost)?;
+        let allowed = network_params
+            .block_subsidy(self.header.height)
+            .saturating_add(fees);
+        let claimed = self.txns.first().and_then(total_output_value);
+        match claimed {
+            Some(claimed) if claimed <= allowed => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Coinbase claims more than the {} satoshis allowed", allowed),
+            )),
+        }
+    }
+
+    /// Returns the height encoded in the coinbase (BIP34), None if the block has no coinbase
+    pub fn coinbase_height(&self) -> Option<u32> {
+        match &self.txns.first()?.tx_in {
+            TxInputType::CoinBaseInput(coinbase) => Some(coinbase.height),
+            TxInputType::TxInput(_) => None,
+        }
+    }
+
+    /// Checks the height encoded in the coinbase matches the height of the block in the chain, once BIP34 is active
+    pub fn validate_coinbase_height(
+        &self,
+        height: usize,
+        network_params: &NetworkParams,
+    ) -> io::Result<()> {
+        if !network_params.soft_forks(height).coinbase_height {
+            return Ok(());
+        }
+        match self.coinbase_height() {
+            Some(coinbase_height) if coinbase_height as usize == height => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Coinbase height doesn't match block height {}", height),
+            )),
+        }
+    }
+
     fn update_wallets(
         &self,
         utxo_set: &mut UtxoSet,
         txn: &RawTransaction,
         wallets: &mut HashMap<String, Wallet>,
     ) -> io::Result<()> {
-        for wallet in wallets.values_mut() {
-            if txn.address_is_involved(&wallet.address) {
-                let txn_info =
-                    txn.transaction_info_for(&wallet.address, self.header.timestamp, utxo_set);
-                wallet.update_history(txn_info);
-            }
+        let involved = txn.involved_addresses(utxo_set.address_format());
+        for wallet in wallets
+            .values_mut()
+            .filter(|wallet| wallet.is_involved(&involved))
+        {
+            let txn_info = wallet.transaction_info(|address| {
+                txn.transaction_info_for(address, self.header.timestamp, utxo_set)
+            });
+            wallet.update_history(txn_info);
         }
         Ok(())
     }
 
+    /// Validates the block signature found in the coinbase witness commitment against the signet challenge (BIP325)
+    pub fn validate_signet(&self, challenge: &[u8]) -> io::Result<()> {
+        let coinbase = self.txns.first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Signet block without coinbase")
+        })?;
+        let (modified_coinbase, solution) = signet::extract_solution(coinbase)?;
+
+        let mut txn_hashes = self.hash_transactions();
+        txn_hashes[0] = double_hash(&modified_coinbase.serialize());
+        let signet_merkle_root = MerkleTree::generate_from_hashes(txn_hashes).get_root();
+
+        let spending = signet::spending_transaction(
+            &self.header,
+            HashId::new(signet_merkle_root.to_byte_array()),
+            challenge,
+        );
+        signet::verify_solution(&spending, challenge, &solution)
+    }
+
     /// Adds to the utxo set
     pub fn expand_utxo(
         &self,
         utxo_set: &mut UtxoSet,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
+        ui_sender: Option<&dyn UiNotifier>,
         wallets: &mut HashMap<String, Wallet>,
         active_addr: Option<&str>,
     ) -> io::Result<()> {
@@ -108,11 +391,23 @@ impl Block {
         Ok(())
     }
 
+    /// Adds the transactions of the block to the history of the wallets, for blocks whose utxos are already in the set
+    pub fn update_wallets_history(
+        &self,
+        utxo_set: &mut UtxoSet,
+        wallets: &mut HashMap<String, Wallet>,
+    ) -> io::Result<()> {
+        for txn in self.txns.iter() {
+            self.update_wallets(utxo_set, txn, wallets)?;
+        }
+        Ok(())
+    }
+
     /// Reads all transactions in the file and returns them in a BlockSet.
     pub fn all_from_file(file_name: &str) -> io::Result<BlockSet> {
         let mut block_set: BlockSet = HashMap::new();
 
-        match std::fs::read(file_name) {
+        match std::fs::read(file_name).and_then(|bytes| Self::decompress_backup(&bytes)) {
             Ok(bytes) => {
                 // create cursor to read bytes
                 let mut cursor: Cursor<&[u8]> = Cursor::new(&bytes);
@@ -137,8 +432,79 @@ impl Block {
         Ok(block_set)
     }
 
-    /// Serialize the block and then save it to the given file.
-    pub fn save_to_file(&self, file_name: &str) -> io::Result<()> {
+    /// Returns the plain records of a backup file, decompressing the ones saved as gzip members. Each record is
+    /// told apart by its first bytes, so backups saved before compression was enabled still load.
+    pub fn decompress_backup(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut cursor: Cursor<&[u8]> = Cursor::new(bytes);
+        let mut plain = Vec::with_capacity(bytes.len());
+        while (cursor.position() as usize) < bytes.len() {
+            let start = cursor.position() as usize;
+            if bytes[start..].starts_with(&GZIP_MAGIC) {
+                GzDecoder::new(&mut cursor).read_to_end(&mut plain)?;
+                continue;
+            }
+            let end = read_record_end(&mut cursor)?;
+            plain.extend(&bytes[start..end]);
+            cursor.set_position(end as u64);
+        }
+        Ok(plain)
+    }
+
+    /// Checks the blocks of a backup file are valid and chain to each other. Blocks are saved as they arrive, so
+    /// they're in no particular order, but only the first block whose parent isn't in the backup may lack it
+    /// (the one after the genesis or the pruned blocks). Returns how many blocks were checked, or the first
+    /// error along with the offset of its block.
+    pub fn verify_backup(bytes: &[u8], network_params: &NetworkParams) -> io::Result<usize> {
+        let mut cursor: Cursor<&[u8]> = Cursor::new(bytes);
+        let mut blocks: Vec<(u64, HashId, HashId)> = vec![];
+        while (cursor.position() as usize) < bytes.len() {
+            let offset = cursor.position();
+            let block = Self::read_backup_record(&mut cursor).map_err(|e| {
+                io::Error::new(e.kind(), format!("Block at offset {}: {}", offset, e))
+            })?;
+            block.validate(network_params).map_err(|e| {
+                let msg = format!("Block {} at offset {}: {}", block.hash(), offset, e);
+                io::Error::new(e.kind(), msg)
+            })?;
+            blocks.push((offset, block.hash(), block.header.prev_block_hash));
+        }
+        let hashes: HashSet<HashId> = blocks.iter().map(|(_, hash, _)| *hash).collect();
+        let mut unchained = blocks.iter().filter(|(_, _, prev)| !hashes.contains(prev));
+        match unchained.nth(1) {
+            Some((offset, hash, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Block {} at offset {}: parent isn't in the backup",
+                    hash, offset
+                ),
+            )),
+            None => Ok(blocks.len()),
+        }
+    }
+
+    /// Reads a block saved to a backup file, preceded by its size
+    fn read_backup_record(cursor: &mut Cursor<&[u8]>) -> io::Result<Block> {
+        let block_size = read_from_varint(cursor)?;
+        let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+        if block_size > remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Declared size {} exceeds the rest of the backup",
+                    block_size
+                ),
+            ));
+        }
+        let mut block_bytes = vec![0; block_size as usize];
+        cursor.read_exact(&mut block_bytes)?;
+        match Block::deserialize(&block_bytes)? {
+            Message::Block(block) => Ok(block),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Not a block")),
+        }
+    }
+
+    /// Serialize the block and then save it to the given file, compressed as a gzip member if asked to.
+    pub fn save_to_file(&self, file_name: &str, compress: bool) -> io::Result<()> {
         let mut file = OpenOptions::new()
             .write(true)
             .append(true)
@@ -150,9 +516,171 @@ impl Block {
         let msg_len = to_compact_size_bytes(bytes.len() as u64);
         let data = [msg_len, bytes].concat();
 
-        file.write_all(&data)?;
+        file.write_all(&compressed_if(data, compress)?)?;
         Ok(())
     }
+
+    /// Removes the given blocks from the backup file, keeping the rest in order. Returns how many were removed.
+    /// The file is rewritten to a temporary file which is then renamed, so a crash keeps the previous backup.
+    pub fn prune_file(
+        file_name: &str,
+        pruned: &HashSet<HashId>,
+        compress: bool,
+    ) -> io::Result<usize> {
+        let bytes = Self::decompress_backup(&fs::read(file_name)?)?;
+        let mut cursor: Cursor<&[u8]> = Cursor::new(&bytes);
+        let mut kept = Vec::with_capacity(bytes.len());
+        let mut removed = 0;
+        while (cursor.position() as usize) < bytes.len() {
+            let start = cursor.position() as usize;
+            let end = read_record_end(&mut cursor)?;
+            let header = BlockHeader::from_bytes(&mut cursor)?;
+            cursor.set_position(end as u64);
+            match pruned.contains(&header.hash()) {
+                true => removed += 1,
+                false => kept.extend(&bytes[start..end]),
+            }
+        }
+        let temp_file_name = format!("{}.tmp", file_name);
+        fs::write(&temp_file_name, compressed_if(kept, compress)?)?;
+        fs::rename(temp_file_name, file_name)?;
+        Ok(removed)
+    }
+}
+
+/// Reads the size of a backup record, returning where the record ends, which must be within the backup
+fn read_record_end(cursor: &mut Cursor<&[u8]>) -> io::Result<usize> {
+    let block_size = read_from_varint(cursor)?;
+    usize::try_from(block_size)
+        .ok()
+        .and_then(|block_size| (cursor.position() as usize).checked_add(block_size))
+        .filter(|end| *end <= cursor.get_ref().len())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated block backup"))
+}
+
+/// Returns the bytes as a gzip member if compress is set, unchanged otherwise
+fn compressed_if(bytes: Vec<u8>, compress: bool) -> io::Result<Vec<u8>> {
+    if !compress {
+        return Ok(bytes);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&bytes)?;
+    encoder.finish()
+}
+
+/// Spends the outputs the transaction's inputs point to, checking they're unspent and unlocked, and adds its
+/// own outputs to the ones created in the block. Returns the fee the transaction pays and the cost of the
+/// signature operations its inputs run besides the ones in its scripts.
+fn spend_outputs(
+    txn: &RawTransaction,
+    (utxo_set, depth): (&UtxoSet, &impl Fn(&HashId) -> usize),
+    block_outputs: &mut HashMap<OutPoint, (u64, Vec<u8>)>,
+    spent: &mut HashSet<OutPoint>,
+    soft_forks: SoftForks,
+) -> io::Result<(u64, usize)> {
+    let checker = TxChecker::new(txn).map_err(|_| invalid_spend(txn, "is a coinbase"))?;
+    let (mut input_value, mut sigops_cost) = (0u64, 0);
+    for (index, input) in checker.inputs.iter().enumerate() {
+        let outpoint = (input.previous_output.hash, input.previous_output.index);
+        let (value, cost) = spend_input(
+            &checker,
+            (index, outpoint),
+            (utxo_set, depth),
+            block_outputs,
+            spent,
+            soft_forks,
+        )?;
+        input_value = input_value
+            .checked_add(value)
+            .ok_or_else(|| invalid_spend(txn, "overflows its input value"))?;
+        sigops_cost += cost;
+    }
+    let fee = total_output_value(txn)
+        .and_then(|output_value| input_value.checked_sub(output_value))
+        .ok_or_else(|| invalid_spend(txn, "pays out more than its inputs"))?;
+    let txid = txn.txid();
+    for (index, output) in txn.tx_out.iter().enumerate() {
+        block_outputs.insert(
+            (txid, index as u32),
+            (output.value, output.pk_script.clone()),
+        );
+    }
+    Ok((fee, sigops_cost))
+}
+
+/// Spends the output the input at the given index points to, returning its value and the cost of the signature
+/// operations of its redeem script and witness. Coinbase outputs can only be spent once they're mature.
+/// The depth of the block over the one confirming each output is given by the depth function.
+fn spend_input(
+    checker: &TxChecker,
+    (index, outpoint): (usize, OutPoint),
+    (utxo_set, depth): (&UtxoSet, &impl Fn(&HashId) -> usize),
+    block_outputs: &HashMap<OutPoint, (u64, Vec<u8>)>,
+    spent: &mut HashSet<OutPoint>,
+    soft_forks: SoftForks,
+) -> io::Result<(u64, usize)> {
+    let txn = checker.tx;
+    if !spent.insert(outpoint) {
+        return Err(invalid_spend(
+            txn,
+            "spends an output already spent in the block",
+        ));
+    }
+    if utxo_set.is_immature_coinbase(&outpoint) {
+        return Err(invalid_spend(txn, "spends an immature coinbase output"));
+    }
+    // outputs created in the block are as deep as the transaction spending them
+    let ((value, lock), depth) = match block_outputs.get(&outpoint) {
+        Some(prevout) => (prevout, 0),
+        None => utxo_set
+            .resolve_prevout(&outpoint)
+            .map(|prevout| (prevout, depth(&outpoint.0)))
+            .ok_or_else(|| invalid_spend(txn, "spends an unknown or spent output"))?,
+    };
+    check_unlock(checker, (index, depth), (*value, lock), soft_forks)
+        .map_err(|e| invalid_spend(txn, &e.to_string()))?;
+    Ok((*value, checker.sigops_cost(index, lock, soft_forks)))
+}
+
+/// Checks the input at the given index unlocks the output of the given value and script it spends at the given
+/// depth: its scripts must succeed, checking the lock times they require once the soft forks enforcing them are
+/// active, and it must meet its own relative lock time once BIP68 is
+fn check_unlock(
+    checker: &TxChecker,
+    (index, depth): (usize, usize),
+    (value, lock): (u64, &[u8]),
+    soft_forks: SoftForks,
+) -> io::Result<()> {
+    checker.verify_input(index, (value, lock), soft_forks)?;
+    if soft_forks.sequence_locks {
+        checker.tx.check_sequence_lock(index, depth)?;
+    }
+    Ok(())
+}
+
+/// Checks the cost of the signature operations of a block is within the consensus limit
+fn check_sigops_cost(cost: usize) -> io::Result<()> {
+    match cost > MAX_BLOCK_SIGOPS_COST {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Block signature operations cost {}, over the limit", cost),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Returns the sum of the transaction's outputs, None if it overflows
+fn total_output_value(txn: &RawTransaction) -> Option<u64> {
+    txn.tx_out
+        .iter()
+        .try_fold(0u64, |total, output| total.checked_add(output.value))
+}
+
+fn invalid_spend(txn: &RawTransaction, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Transaction {} {}", txn.txid(), reason),
+    )
 }
 
 // https://developer.bitcoin.org/reference/block_chain.html#serialized-blocks
@@ -175,10 +703,13 @@ impl Serialize for Block {
         let header = BlockHeader::from_bytes(&mut cursor)?;
         let txn_count = read_from_varint(&mut cursor)? as usize;
         let mut txns = vec![];
-        let coinbase_transaction = RawTransaction::coinbase_from_bytes(&mut cursor)?;
-        txns.push(coinbase_transaction);
-        let other_txns = RawTransaction::vec_from_bytes(&mut cursor, txn_count)?;
-        txns.extend(other_txns);
+        // a header-only block, like the pseudo genesis ones, has no coinbase to read
+        if txn_count > 0 {
+            let coinbase_transaction = RawTransaction::coinbase_from_bytes(&mut cursor)?;
+            txns.push(coinbase_transaction);
+            let other_txns = RawTransaction::vec_from_bytes(&mut cursor, txn_count)?;
+            txns.extend(other_txns);
+        }
         let block = Block {
             header,
             txn_count,
@@ -194,11 +725,41 @@ impl Hashable for Block {
     }
 }
 
+/// Returns the root of the merkle tree of the given transaction hashes
+fn merkle_root(txn_hashes: Vec<sha256::Hash>) -> HashId {
+    let merkle_tree = MerkleTree::generate_from_hashes(txn_hashes);
+    HashId::new(merkle_tree.get_root().to_byte_array())
+}
+
+/// Returns true if reading the serialization of the transaction gives back the same transaction
+fn reads_back(txn: &RawTransaction, coinbase: bool) -> bool {
+    let bytes = txn.serialize_with_witness();
+    let mut cursor = Cursor::new(&bytes[..]);
+    let read = match coinbase {
+        true => RawTransaction::coinbase_from_bytes(&mut cursor),
+        false => RawTransaction::from_bytes(&mut cursor),
+    };
+    read.is_ok_and(|read| read == *txn)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::signet::WITNESS_COMMITMENT_HEADER;
     use crate::messages::Block;
-    use std::fs;
+    use crate::messages::Headers;
+    use crate::network_params::Network;
+    use crate::raw_transaction::script::TESTNET_ADDRESSES;
+    use crate::raw_transaction::script::{
+        push_data, OP_1, OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160, OP_RETURN,
+    };
+    use crate::raw_transaction::tx_input::CoinBaseInput;
+    use crate::raw_transaction::tx_output::TxOutput;
+    use crate::test_fixtures::{
+        tx_from_hex, utxo, FUNDING_TX_HEX, PAYMENT_TX_HEX, SPENDING_TX_HEX,
+    };
+    use crate::utility::decode_hex;
+    use crate::utxo::{lock_address, WalletUtxo};
 
     #[test]
     fn test_read_serialized_block_from_bytes() -> io::Result<()> {
@@ -214,11 +775,454 @@ mod tests {
         if !bytes.is_empty() {
             let message = Block::deserialize(&bytes).unwrap();
             if let Message::Block(block) = message {
-                block.validate()?;
+                block.validate(&NetworkParams::new(Network::Testnet, &[])?)?;
                 assert_eq!(block.txn_count, block.txns.len());
             };
         }
 
         Ok(())
     }
+
+    fn regular_txn() -> io::Result<RawTransaction> {
+        let bytes = decode_hex(PAYMENT_TX_HEX).map_err(to_io_err)?;
+        RawTransaction::from_bytes(&mut Cursor::new(&bytes))
+    }
+
+    #[test]
+    fn test_minimal_work_block_only_accepted_on_regtest() -> io::Result<()> {
+        let txn = coinbase_block(HashId::default(), 1).txns.remove(0);
+        let header = BlockHeader::new(
+            0x20000000,
+            HashId::default(),
+            None,
+            txn.txid(),
+            1681095679,
+            0x1d00ffff, // testnet minimum difficulty
+            0,
+        );
+        let block = Block::new(header, 1, vec![txn]);
+
+        block.validate(&NetworkParams::new(Network::Regtest, &[])?)?;
+        assert!(block
+            .validate(&NetworkParams::new(Network::Testnet, &[])?)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_only_block_has_no_transactions() -> io::Result<()> {
+        let header = BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, 0);
+        // the header followed by a transaction count of zero
+        let bytes = [header.serialize(), vec![0x00]].concat();
+        let Message::Block(block) = Block::deserialize(&bytes)? else {
+            return Err(io::Error::other("Not a block"));
+        };
+        assert_eq!((block.txn_count, block.txns.len()), (0, 0));
+        assert_eq!(block.hash(), header.hash());
+        assert!(block
+            .validate(&NetworkParams::new(Network::Regtest, &[])?)
+            .is_err());
+        Ok(())
+    }
+
+    fn coinbase_block(prev_block_hash: HashId, height: u32) -> Block {
+        let coinbase_input = CoinBaseInput {
+            _hash: HashId::default(),
+            _index: u32::MAX,
+            _script_bytes: 4,
+            height,
+            _coinbase_script: vec![],
+            _sequence: u32::MAX,
+        };
+        let coinbase = RawTransaction {
+            version: 1,
+            tx_in_count: 1,
+            tx_in: TxInputType::CoinBaseInput(coinbase_input),
+            tx_out_count: 0,
+            tx_out: vec![],
+            lock_time: 0,
+            witnesses: vec![],
+        };
+        let header = BlockHeader::new(1, prev_block_hash, None, coinbase.txid(), 0, 0, 0);
+        Block::new(header, 1, vec![coinbase])
+    }
+
+    #[test]
+    fn test_coinbase_height_must_match_chain_height() -> io::Result<()> {
+        let block = coinbase_block(HashId::default(), 25000);
+        assert_eq!(block.coinbase_height(), Some(25000));
+
+        let testnet = NetworkParams::new(Network::Testnet, &[])?;
+        block.validate_coinbase_height(25000, &testnet)?;
+        assert!(block.validate_coinbase_height(25001, &testnet).is_err());
+        // heights before BIP34 activation aren't checked
+        block.validate_coinbase_height(100, &testnet)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_root_mismatch_names_the_altered_transaction() -> io::Result<()> {
+        let txn = tx_from_hex(SPENDING_TX_HEX)?;
+        let mut block = coinbase_block(HashId::default(), 1);
+        block.txns.push(txn);
+        block.txn_count = 2;
+        block.header.merkle_root_hash = merkle_root(block.hash_transactions());
+        block.validate_merkle_root()?;
+
+        // a parsing bug reading one input more than the transaction has
+        block.txns[1].tx_in_count = 2;
+        let message = block.validate_merkle_root().unwrap_err().to_string();
+        assert!(message.contains("Merkle root hash mismatch"));
+        assert!(message.contains(&format!(
+            "Transaction 1 ({}) doesn't read back the same",
+            block.txns[1].txid()
+        )));
+
+        block.txn_count = 3;
+        let message = block.validate_merkle_root().unwrap_err().to_string();
+        assert!(message.contains("declares 3 transactions but 2 were parsed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_backup_reports_corrupted_block_and_offset() -> io::Result<()> {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let mut blocks = vec![coinbase_block(HashId::default(), 0)];
+        for height in 1..3 {
+            blocks.push(coinbase_block(blocks[height - 1].hash(), height as u32));
+        }
+        let records = blocks
+            .iter()
+            .map(|block| {
+                let bytes = block.serialize()?;
+                Ok([to_compact_size_bytes(bytes.len() as u64), bytes].concat())
+            })
+            .collect::<io::Result<Vec<Vec<u8>>>>()?;
+        assert_eq!(Block::verify_backup(&records.concat(), &regtest)?, 3);
+
+        // a flipped bit in the coinbase of the second block no longer matches its merkle root
+        let offset = records[0].len();
+        let mut corrupted = records.concat();
+        let last_byte = offset + records[1].len() - 1;
+        corrupted[last_byte] ^= 1;
+        let error = Block::verify_backup(&corrupted, &regtest).map(|_| ());
+        let message = error.map_err(|e| e.to_string()).unwrap_err();
+        assert!(message.contains(&format!("Block {} at offset {}", blocks[1].hash(), offset)));
+        assert!(message.contains("Merkle root hash mismatch"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_removes_blocks_but_keeps_headers() -> io::Result<()> {
+        let (blocks_file, headers_file) = ("test_prune_blocks.dat", "test_prune_headers.dat");
+        let mut blocks = vec![coinbase_block(HashId::default(), 0)];
+        for height in 1..3 {
+            blocks.push(coinbase_block(blocks[height - 1].hash(), height as u32));
+        }
+        for block in &blocks {
+            block.save_to_file(blocks_file, false)?;
+            block.header.save_to_file(headers_file)?;
+        }
+
+        let pruned: HashSet<HashId> = blocks[..2].iter().map(Block::hash).collect();
+        let removed = Block::prune_file(blocks_file, &pruned, false);
+        let remaining = Block::all_from_file(blocks_file);
+        let headers = Headers::from_file(headers_file);
+        fs::remove_file(blocks_file)?;
+        fs::remove_file(headers_file)?;
+
+        assert_eq!(removed?, 2);
+        let remaining: Vec<HashId> = remaining?.into_keys().collect();
+        assert_eq!(remaining, vec![blocks[2].hash()]);
+        assert_eq!(headers?.0.count, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_blocks_reload_identically() -> io::Result<()> {
+        let blocks_file = "test_compressed_blocks.dat";
+        let mut blocks = vec![coinbase_block(HashId::default(), 0)];
+        for height in 1..3 {
+            blocks.push(coinbase_block(blocks[height - 1].hash(), height as u32));
+        }
+        // the first block was saved before compression was enabled
+        for (height, block) in blocks.iter().enumerate() {
+            block.save_to_file(blocks_file, height > 0)?;
+        }
+        let bytes = fs::read(blocks_file)?;
+        let reloaded = Block::all_from_file(blocks_file);
+        let pruned = HashSet::from([blocks[0].hash()]);
+        let removed = Block::prune_file(blocks_file, &pruned, true);
+        let remaining = Block::all_from_file(blocks_file);
+        fs::remove_file(blocks_file)?;
+
+        let reloaded = reloaded?;
+        assert_eq!(reloaded.len(), 3);
+        for block in &blocks {
+            assert_eq!(reloaded[&block.hash()].serialize()?, block.serialize()?);
+        }
+        let plain = Block::decompress_backup(&bytes)?;
+        assert_ne!(plain, bytes);
+        assert_eq!(Block::decompress_backup(&plain)?, plain);
+        assert_eq!(removed?, 1);
+        assert_eq!(remaining?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_block_is_rejected() -> io::Result<()> {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let mut block = coinbase_block(HashId::default(), 1);
+        let output = TxOutput {
+            value: 0,
+            pk_script_bytes: 999_000,
+            pk_script: vec![OP_RETURN; 999_000],
+        };
+        block.txns[0].tx_out.push(output);
+        block.txns[0].tx_out_count = 1;
+        block.header.merkle_root_hash = block.txns[0].txid();
+        block.validate(&regtest)?;
+
+        // a thousand bytes more go over the 1MB limit on the size without witnesses
+        block.txns[0].tx_out[0].pk_script.extend([OP_RETURN; 1000]);
+        block.txns[0].tx_out[0].pk_script_bytes += 1000;
+        block.header.merkle_root_hash = block.txns[0].txid();
+        let err = block.validate(&regtest).unwrap_err();
+        assert!(err.to_string().contains("exceeds the limits"));
+        Ok(())
+    }
+
+    /// Returns a block of the given height confirming the transaction, along with a UTXO set holding the
+    /// P2PKH output of the key its first input is signed with, worth the given value
+    fn block_spending(
+        txn: &RawTransaction,
+        height: usize,
+        prevout_value: u64,
+    ) -> io::Result<(Block, UtxoSet)> {
+        let mut block = coinbase_block(HashId::default(), height as u32);
+        block.txns.push(txn.clone());
+        block.txn_count = 2;
+        let merkle_root = MerkleTree::generate_from_hashes(block.hash_transactions()).get_root();
+        block.header.merkle_root_hash = HashId::new(merkle_root.to_byte_array());
+        block.header.height = height;
+
+        let (prevout, public_key) = match &txn.tx_in {
+            TxInputType::TxInput(inputs) => {
+                let script_sig = &inputs[0].script_sig;
+                let public_key = script_sig[script_sig.len() - 33..].to_vec();
+                let prevout = &inputs[0].previous_output;
+                ((prevout.hash, prevout.index), public_key)
+            }
+            TxInputType::CoinBaseInput(_) => return Err(io::Error::other("Not a spend")),
+        };
+        let pubkey_hash = bitcoin_hashes::hash160::Hash::hash(&public_key).to_byte_array();
+        let lock = [
+            &[OP_DUP, OP_HASH160, 0x14],
+            &pubkey_hash[..],
+            &[OP_EQUALVERIFY, OP_CHECKSIG],
+        ]
+        .concat();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.index_output("", prevout, prevout_value, &lock);
+        Ok((block, utxo_set))
+    }
+
+    #[test]
+    fn test_full_validation_rejects_a_tx_paying_out_more_than_its_inputs() -> io::Result<()> {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let txn = regular_txn()?;
+        // the outputs add up to 1100000 satoshis
+        let (block, utxo_set) = block_spending(&txn, 1, 1100000)?;
+        block.validate(&regtest)?;
+        block.validate_spends(&utxo_set, |_| None, &regtest)?;
+
+        let (block, utxo_set) = block_spending(&txn, 1, 1099999)?;
+        block.validate(&regtest)?;
+        let err = block
+            .validate_spends(&utxo_set, |_| None, &regtest)
+            .unwrap_err();
+        assert!(err.to_string().contains("pays out more than its inputs"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_output_is_only_spent_once_mature() -> io::Result<()> {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let txn = regular_txn()?;
+        let (block, mut utxo_set) = block_spending(&txn, 1, 1100000)?;
+        let prevout = match &txn.tx_in {
+            TxInputType::TxInput(inputs) => (
+                inputs[0].previous_output.hash,
+                inputs[0].previous_output.index,
+            ),
+            TxInputType::CoinBaseInput(_) => return Err(io::Error::other("Not a spend")),
+        };
+        let lock = utxo_set
+            .get_txout(&prevout.0, prevout.1)
+            .map(|(_value, lock, _coinbase)| lock)
+            .ok_or_else(|| io::Error::other("Unknown prevout"))?;
+        let mut wallet = WalletUtxo::new();
+        wallet.utxos.insert(prevout, utxo(prevout.1, 1100000, true));
+        utxo_set
+            .set
+            .insert(lock_address(&lock, &TESTNET_ADDRESSES), wallet);
+
+        // a coinbase at the tip can be spent 100 blocks later, in the block on top of 99 others
+        utxo_set.block_connected(vec![prevout.0], 0);
+        utxo_set.block_connected(vec![], 98);
+        let err = block
+            .validate_spends(&utxo_set, |_| None, &regtest)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("spends an immature coinbase output"));
+        utxo_set.block_connected(vec![], 99);
+        block.validate_spends(&utxo_set, |_| None, &regtest)
+    }
+
+    #[test]
+    fn test_relative_lock_time_counts_from_outputs_buried_past_the_tracked_blocks() -> io::Result<()>
+    {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let mut txn = regular_txn()?;
+        txn.version = 2;
+        let prevout = match &mut txn.tx_in {
+            TxInputType::TxInput(inputs) => {
+                inputs[0].sequence = 200;
+                (
+                    inputs[0].previous_output.hash,
+                    inputs[0].previous_output.index,
+                )
+            }
+            TxInputType::CoinBaseInput(_) => return Err(io::Error::other("Not a spend")),
+        };
+        // anyone can spend the output, so only its relative lock time is checked
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.index_output("", prevout, 1100000, &[OP_1]);
+        utxo_set.block_connected(vec![prevout.0], 0);
+        utxo_set.block_connected(vec![], 149);
+        assert_eq!(utxo_set.confirmations(&prevout.0), usize::MAX);
+
+        let confirmation_height = |txid: &HashId| (*txid == prevout.0).then_some(0);
+        let (block, _utxo_set) = block_spending(&txn, 150, 1100000)?;
+        let err = block
+            .validate_spends(&utxo_set, confirmation_height, &regtest)
+            .unwrap_err();
+        assert!(err.to_string().contains("locked for 200 blocks"));
+        let (block, _utxo_set) = block_spending(&txn, 200, 1100000)?;
+        block.validate_spends(&utxo_set, confirmation_height, &regtest)
+    }
+
+    #[test]
+    fn test_non_strict_der_signature_only_rejected_once_bip66_is_active() -> io::Result<()> {
+        let testnet = NetworkParams::new(Network::Testnet, &[])?;
+        let mut txn = regular_txn()?;
+        let (block, utxo_set) = block_spending(&txn, testnet.bip66_height, 1100000)?;
+        block.validate_spends(&utxo_set, |_| None, &testnet)?;
+        // pads R with a zero byte, which strict DER doesn't allow
+        if let TxInputType::TxInput(inputs) = &mut txn.tx_in {
+            let script_sig = &inputs[0].script_sig;
+            let (signature, public_key) = (
+                &script_sig[1..script_sig[0] as usize + 1],
+                &script_sig[script_sig.len() - 34..],
+            );
+            let r_len = signature[3];
+            let padded = [
+                &[0x30, signature[1] + 1, 0x02, r_len + 1, 0x00],
+                &signature[4..],
+            ]
+            .concat();
+            inputs[0].script_sig = [&push_data(&padded)[..], public_key].concat();
+            inputs[0].script_bytes = inputs[0].script_sig.len() as u64;
+        }
+
+        let (block, utxo_set) = block_spending(&txn, testnet.bip66_height - 1, 1100000)?;
+        block.validate_spends(&utxo_set, |_| None, &testnet)?;
+        let (block, utxo_set) = block_spending(&txn, testnet.bip66_height, 1100000)?;
+        let err = block
+            .validate_spends(&utxo_set, |_| None, &testnet)
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't strictly DER encoded"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_witness_commitment_must_match_the_witnesses() -> io::Result<()> {
+        let bytes = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        let mut block = coinbase_block(HashId::default(), 1);
+        block
+            .txns
+            .push(RawTransaction::from_bytes(&mut Cursor::new(&bytes))?);
+        block.txn_count = 2;
+        let err = block.validate_witness_commitment(true).unwrap_err();
+        assert!(err.to_string().contains("no witness commitment"));
+
+        let reserved_value = [0u8; 32];
+        let root = block.witness_commitment_root();
+        let committed = double_hash(&[&root.to_byte_array()[..], &reserved_value].concat());
+        let commitment = [&WITNESS_COMMITMENT_HEADER[..], &committed.to_byte_array()].concat();
+        block.txns[0].tx_out.push(TxOutput {
+            value: 0,
+            pk_script_bytes: commitment.len() as u64,
+            pk_script: commitment,
+        });
+        block.txns[0].tx_out_count = 1;
+        block.txns[0].witnesses = vec![vec![reserved_value.to_vec()]];
+        block.validate_witness_commitment(true)?;
+
+        // the commitment no longer matches once a witness changes
+        block.txns[1].witnesses[0][0][4] ^= 1;
+        assert!(block.validate_witness_commitment(true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_witness_block_message_keeps_the_witnesses() -> io::Result<()> {
+        let mut block = coinbase_block(HashId::default(), 1);
+        block.txns.push(tx_from_hex(FUNDING_TX_HEX)?);
+        block.txn_count = 2;
+
+        let read = |message: Vec<u8>| match Block::deserialize(&message[24..])? {
+            Message::Block(read) => Ok(read.txns),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
+        };
+        assert_eq!(read(block.serialize_witness_message()?)?, block.txns);
+        let stripped = read(block.serialize_message()?)?;
+        assert!(stripped[1].witnesses.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_must_start_with_its_only_coinbase() -> io::Result<()> {
+        let regtest = NetworkParams::new(Network::Regtest, &[])?;
+        let block = coinbase_block(HashId::default(), 1);
+        block.validate(&regtest)?;
+
+        let txn = regular_txn()?;
+        let header = BlockHeader::new(1, HashId::default(), None, txn.txid(), 0, 0, 0);
+        let non_coinbase_first = Block::new(header, 1, vec![txn]);
+        assert!(non_coinbase_first.validate(&regtest).is_err());
+
+        let mut second_coinbase = regular_txn()?;
+        if let TxInputType::TxInput(inputs) = &mut second_coinbase.tx_in {
+            inputs[0].previous_output.hash = HashId::default();
+            inputs[0].previous_output.index = u32::MAX;
+        }
+        let mut two_coinbases = coinbase_block(HashId::default(), 1);
+        two_coinbases.txns.push(second_coinbase);
+        two_coinbases.txn_count = 2;
+        let err = two_coinbases.validate(&regtest).unwrap_err();
+        assert!(err.to_string().contains("exactly one coinbase"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_record_sizes_past_the_end_are_rejected() {
+        let oversized = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let truncated = [0x50, 0x01, 0x00];
+        for backup in [&oversized[..], &truncated[..]] {
+            let err = Block::decompress_backup(backup).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
 }
@@ -0,0 +1,351 @@
+use crate::messages::constants::commands::{BLOCKTXN, CMPCTBLOCK, GETBLOCKTXN};
+use crate::messages::utility::{read_from_varint, read_hash, to_compact_size_bytes, StreamRead};
+use crate::messages::{Block, BlockHeader, HashId, Message, Serialize};
+use crate::raw_transaction::RawTransaction;
+use bitcoin_hashes::{sha256, siphash24, Hash};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+
+/// Bytes of the short transaction ids of compact blocks
+const SHORT_ID_SIZE: usize = 6;
+/// Transaction indexes of compact blocks must fit in 16 bits
+const MAX_INDEXES: u64 = u16::MAX as u64 + 1;
+/// Smallest serialized transaction (version, one empty input and output counts and lock time)
+const MIN_TX_SIZE: u64 = 10;
+
+/// Reads the count of the items that follow, failing if it's over the indexes a compact block can have
+/// or the rest of the buffer can't hold that many items of at least `min_size` bytes each
+fn read_index_count(cursor: &mut Cursor<&[u8]>, min_size: u64) -> io::Result<usize> {
+    let count = read_from_varint(cursor)?;
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if count > MAX_INDEXES || count * min_size > remaining {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Declared count {} doesn't fit in the remaining bytes",
+                count
+            ),
+        ));
+    }
+    Ok(count as usize)
+}
+
+/// Decodes a differentially encoded index: the first one is sent as is, the rest as the gap after the previous one
+fn read_index(cursor: &mut Cursor<&[u8]>, previous: Option<usize>) -> io::Result<usize> {
+    let index = read_from_varint(cursor)?.saturating_add(previous.map_or(0, |p| p as u64 + 1));
+    if index >= MAX_INDEXES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Transaction index doesn't fit in 16 bits",
+        ));
+    }
+    Ok(index as usize)
+}
+
+/// Encodes the ascending indexes differentially, each one as the gap after the previous one
+fn index_gaps(indexes: impl IntoIterator<Item = usize>) -> Vec<u64> {
+    let mut previous: Option<usize> = None;
+    indexes
+        .into_iter()
+        .map(|index| {
+            let gap = index.saturating_sub(previous.map_or(0, |p| p + 1));
+            previous = Some(index);
+            gap as u64
+        })
+        .collect()
+}
+
+/// Returns the short id of a transaction: the SipHash-2-4 of its txid with the block keys, truncated to 6 bytes
+fn short_id((k0, k1): (u64, u64), txid: &HashId) -> u64 {
+    siphash24::Hash::hash_to_u64_with_keys(k0, k1, &txid.hash) & 0xffff_ffff_ffff
+}
+
+/// Block announced by its header, the short ids of the transactions the receiver likely has and the
+/// prefilled ones it likely doesn't (at least the coinbase), each at its index in the block (BIP152)
+#[derive(Debug, Clone)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub nonce: u64,
+    pub short_ids: Vec<u64>,
+    pub prefilled: Vec<(usize, RawTransaction)>,
+}
+
+impl CompactBlock {
+    /// Returns the compact block of the given one with the given nonce, prefilling only the coinbase
+    pub fn from_block(block: &Block, nonce: u64) -> Self {
+        let mut compact_block = Self {
+            header: block.header,
+            nonce,
+            short_ids: vec![],
+            prefilled: block
+                .txns
+                .first()
+                .cloned()
+                .map(|coinbase| (0, coinbase))
+                .into_iter()
+                .collect(),
+        };
+        let keys = compact_block.short_id_keys();
+        compact_block.short_ids = block
+            .txns
+            .iter()
+            .skip(1)
+            .map(|tx| short_id(keys, &tx.txid()))
+            .collect();
+        compact_block
+    }
+
+    /// Returns the SipHash keys of the short ids, taken from the hash of the header and nonce
+    fn short_id_keys(&self) -> (u64, u64) {
+        let data = [&self.header.serialize()[..], &self.nonce.to_le_bytes()].concat();
+        let hash = sha256::Hash::hash(&data).to_byte_array();
+        let (mut k0, mut k1) = ([0u8; 8], [0u8; 8]);
+        k0.copy_from_slice(&hash[..8]);
+        k1.copy_from_slice(&hash[8..16]);
+        (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+    }
+
+    /// Places the prefilled transactions and the given pending ones matching a short id in the block,
+    /// leaving the slots of the transactions we don't have empty
+    pub fn reconstruct<'a>(
+        &self,
+        pending: impl IntoIterator<Item = &'a RawTransaction>,
+    ) -> io::Result<PartialBlock> {
+        let mut txns: Vec<Option<RawTransaction>> =
+            vec![None; self.short_ids.len() + self.prefilled.len()];
+        for (index, tx) in &self.prefilled {
+            match txns.get_mut(*index) {
+                Some(slot @ None) => *slot = Some(tx.clone()),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Prefilled transaction index out of the block",
+                    ))
+                }
+            }
+        }
+        // the short ids fill the slots left by the prefilled transactions in order
+        let empty_slots = (0..txns.len()).filter(|index| txns[*index].is_none());
+        let mut slots: HashMap<u64, usize> =
+            self.short_ids.iter().copied().zip(empty_slots).collect();
+        let keys = self.short_id_keys();
+        for tx in pending {
+            if let Some(index) = slots.remove(&short_id(keys, &tx.txid())) {
+                txns[index] = Some(tx.clone());
+            }
+        }
+        Ok(PartialBlock {
+            header: self.header,
+            txns,
+        })
+    }
+
+    fn read_short_id(cursor: &mut Cursor<&[u8]>) -> io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        cursor.read_exact(&mut bytes[..SHORT_ID_SIZE])?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_prefilled(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<(usize, RawTransaction)>> {
+        let count = read_index_count(cursor, 1 + MIN_TX_SIZE)?;
+        let mut prefilled: Vec<(usize, RawTransaction)> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let index = read_index(cursor, prefilled.last().map(|(index, _tx)| *index))?;
+            prefilled.push((index, RawTransaction::from_bytes(cursor)?));
+        }
+        Ok(prefilled)
+    }
+}
+
+impl Serialize for CompactBlock {
+    fn serialize(&self) -> io::Result<Vec<u8>> {
+        let mut payload = self.header.serialize();
+        payload.extend(self.nonce.to_le_bytes());
+        payload.extend(to_compact_size_bytes(self.short_ids.len() as u64));
+        for short_id in &self.short_ids {
+            payload.extend(&short_id.to_le_bytes()[..SHORT_ID_SIZE]);
+        }
+        payload.extend(to_compact_size_bytes(self.prefilled.len() as u64));
+        let gaps = index_gaps(self.prefilled.iter().map(|(index, _tx)| *index));
+        for (gap, (_index, tx)) in gaps.into_iter().zip(&self.prefilled) {
+            payload.extend(to_compact_size_bytes(gap));
+            payload.extend(tx.serialize());
+        }
+        self.build_message(CMPCTBLOCK, Some(payload))
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let header = BlockHeader::from_bytes(&mut cursor)?;
+        let nonce = u64::from_le_stream(&mut cursor)?;
+        let short_id_count = read_index_count(&mut cursor, SHORT_ID_SIZE as u64)?;
+        let short_ids = (0..short_id_count)
+            .map(|_| Self::read_short_id(&mut cursor))
+            .collect::<io::Result<Vec<u64>>>()?;
+        let prefilled = Self::read_prefilled(&mut cursor)?;
+        Ok(Message::CompactBlock(Self {
+            header,
+            nonce,
+            short_ids,
+            prefilled,
+        }))
+    }
+}
+
+/// Block being reconstructed from a compact block, with the slots of the transactions still missing empty
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txns: Vec<Option<RawTransaction>>,
+}
+
+impl PartialBlock {
+    /// Returns the indexes of the transactions we don't have yet
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.txns.len())
+            .filter(|index| self.txns[*index].is_none())
+            .collect()
+    }
+
+    /// Fills the empty slots in order with the given transactions, which must be exactly the missing ones
+    pub fn complete(self, missing_txns: Vec<RawTransaction>) -> io::Result<Block> {
+        if missing_txns.len() != self.missing().len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing transactions don't match the requested ones",
+            ));
+        }
+        let mut missing_txns = missing_txns.into_iter();
+        let txns: Vec<RawTransaction> = self
+            .txns
+            .into_iter()
+            .filter_map(|tx| tx.or_else(|| missing_txns.next()))
+            .collect();
+        Ok(Block::new(self.header, txns.len(), txns))
+    }
+}
+
+/// Request of the transactions of a block at the given indexes, missing after reconstructing its compact block
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetBlockTxn {
+    pub block_hash: HashId,
+    pub indexes: Vec<usize>,
+}
+
+impl GetBlockTxn {
+    pub fn new(block_hash: HashId, indexes: Vec<usize>) -> Self {
+        Self {
+            block_hash,
+            indexes,
+        }
+    }
+}
+
+impl Serialize for GetBlockTxn {
+    fn serialize(&self) -> io::Result<Vec<u8>> {
+        let mut payload: Vec<u8> = self.block_hash.iter().copied().collect();
+        payload.extend(to_compact_size_bytes(self.indexes.len() as u64));
+        for gap in index_gaps(self.indexes.iter().copied()) {
+            payload.extend(to_compact_size_bytes(gap));
+        }
+        self.build_message(GETBLOCKTXN, Some(payload))
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let block_hash = read_hash(&mut cursor)?;
+        let count = read_index_count(&mut cursor, 1)?;
+        let mut indexes: Vec<usize> = Vec::with_capacity(count);
+        for _ in 0..count {
+            indexes.push(read_index(&mut cursor, indexes.last().copied())?);
+        }
+        Ok(Message::GetBlockTxn(Self::new(block_hash, indexes)))
+    }
+}
+
+/// Reply to a getblocktxn message with the requested transactions of the block, in the requested order
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTxn {
+    pub block_hash: HashId,
+    pub txns: Vec<RawTransaction>,
+}
+
+impl BlockTxn {
+    pub fn new(block_hash: HashId, txns: Vec<RawTransaction>) -> Self {
+        Self { block_hash, txns }
+    }
+}
+
+impl Serialize for BlockTxn {
+    fn serialize(&self) -> io::Result<Vec<u8>> {
+        let mut payload: Vec<u8> = self.block_hash.iter().copied().collect();
+        payload.extend(to_compact_size_bytes(self.txns.len() as u64));
+        for tx in &self.txns {
+            payload.extend(tx.serialize());
+        }
+        self.build_message(BLOCKTXN, Some(payload))
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let block_hash = read_hash(&mut cursor)?;
+        let count = read_index_count(&mut cursor, MIN_TX_SIZE)?;
+        let txns = (0..count)
+            .map(|_| RawTransaction::from_bytes(&mut cursor))
+            .collect::<io::Result<Vec<RawTransaction>>>()?;
+        Ok(Message::BlockTxn(Self::new(block_hash, txns)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{Hashable, MerkleTree};
+    use crate::test_fixtures::transaction;
+
+    /// Returns the payload of a serialized message, skipping its 24 bytes header
+    fn payload(message: io::Result<Vec<u8>>) -> io::Result<Vec<u8>> {
+        Ok(message?[24..].to_vec())
+    }
+
+    #[test]
+    fn test_missing_txns_are_requested_and_complete_the_block() -> io::Result<()> {
+        let txns = (0..3).map(transaction).collect::<io::Result<Vec<_>>>()?;
+        let merkle_root = |block: &Block| {
+            let root = MerkleTree::generate_from_hashes(block.hash_transactions()).get_root();
+            HashId::new(root.to_byte_array())
+        };
+        let header =
+            |merkle_root| BlockHeader::new(1, HashId::default(), None, merkle_root, 0, 0, 0);
+        let mut block = Block::new(header(HashId::default()), txns.len(), txns.clone());
+        block.header = header(merkle_root(&block));
+        let compact_block = CompactBlock::from_block(&block, 7);
+        let compact_block = match CompactBlock::deserialize(&payload(compact_block.serialize())?)? {
+            Message::CompactBlock(compact_block) => compact_block,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Not a compact block")),
+        };
+
+        // only the first transaction after the coinbase is pending
+        let partial_block = compact_block.reconstruct([&txns[1]])?;
+        assert_eq!(partial_block.missing(), vec![2]);
+
+        let getblocktxn = GetBlockTxn::new(block.hash(), vec![1, 3, 4]);
+        assert_eq!(index_gaps(getblocktxn.indexes.clone()), vec![1, 1, 0]);
+        match GetBlockTxn::deserialize(&payload(getblocktxn.serialize())?)? {
+            Message::GetBlockTxn(decoded) => assert_eq!(decoded, getblocktxn),
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Not a getblocktxn")),
+        }
+
+        let blocktxn = BlockTxn::new(block.hash(), vec![txns[2].clone()]);
+        let blocktxn = match BlockTxn::deserialize(&payload(blocktxn.serialize())?)? {
+            Message::BlockTxn(blocktxn) => blocktxn,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Not a blocktxn")),
+        };
+        assert!(partial_block.clone().complete(vec![]).is_err());
+        // the header is the same either way, it's the transactions that must match its merkle root
+        let completed = partial_block.complete(blocktxn.txns)?;
+        assert_eq!(merkle_root(&completed), block.header.merkle_root_hash);
+        assert_eq!(completed.txns, txns);
+        Ok(())
+    }
+}
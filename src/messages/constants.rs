@@ -13,13 +13,22 @@ pub mod header_constants {
 pub mod messages {
     pub const _MAX_INV_SIZE: usize = 50000;
     pub const MAX_PAYLOAD_SIZE: u32 = 500 * 1024 * 1024; // 500 MB
+    pub const MISBEHAVIOR_PENALTY: u32 = 20; // added for every malformed payload of a known command
+    pub const MAX_MISBEHAVIOR_SCORE: u32 = 100; // peers reaching this score are disconnected
+    pub const MAX_BLOCK_WEIGHT: usize = 4_000_000; // weight units, witness bytes count 1 and the rest 4
+    pub const MAX_BLOCK_BASE_SIZE: usize = 1_000_000; // bytes of the block serialized without witnesses
+    pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000; // units, each signature operation outside witnesses costs 4
 }
 
 /// Constants with all valid commands in the bitcoin protocol in str format
 pub mod commands {
     pub const ADDR: &str = "addr\0\0\0\0\0\0\0\0";
+    pub const ALERT: &str = "alert\0\0\0\0\0\0\0";
     pub const BLOCK: &str = "block\0\0\0\0\0\0\0";
+    pub const BLOCKTXN: &str = "blocktxn\0\0\0\0";
+    pub const CMPCTBLOCK: &str = "cmpctblock\0\0";
     pub const FEEFILTER: &str = "feefilter\0\0\0";
+    pub const GETBLOCKTXN: &str = "getblocktxn\0";
     pub const GETDATA: &str = "getdata\0\0\0\0\0";
     pub const GETHEADERS: &str = "getheaders\0\0";
     pub const HEADERS: &str = "headers\0\0\0\0\0";
@@ -33,11 +42,15 @@ pub mod commands {
     pub const VERACK: &str = "verack\0\0\0\0\0\0";
     pub const VERSION: &str = "version\0\0\0\0\0";
     pub const NOTFOUND: &str = "notfound\0\0\0\0";
+    // known commands that are ignored by the node
+    pub const UNIMPLEMENTED: [&str; 4] = [ADDR, ALERT, VERACK, VERSION];
 }
 
 /// Constants with accepted version which is latest version
 pub mod version_constants {
     pub const LATEST_VERSION: i32 = 70015;
+    pub const CMPCT_VERSION: u64 = 1;
+    pub const USER_AGENT: &str = "/nodo-rustico:0.1.0/";
 }
 
 /// Constants with all config parameters
@@ -49,7 +62,21 @@ pub mod config {
     pub const LOG_FILE: &str = "tmp/node.log";
     pub const HEADERS_FILE: &str = "tmp/headers_backup.dat";
     pub const BLOCKS_FILE: &str = "tmp/blocks_backup.dat";
+    pub const UTXO_SNAPSHOT_FILE: &str = "tmp/utxo_snapshot.dat";
+    pub const WATCH_ADDRESSES_FILE: &str = "tmp/watch_addresses.txt";
     pub const TCP_TIMEOUT: u64 = 30;
+    pub const HEADER_STALL_TIMEOUT: u64 = 60;
+    pub const SYNC_STALL_TIMEOUT: u64 = 300;
+    pub const BROADCAST_PROPAGATION_TIMEOUT: u64 = 30;
+    pub const ABANDON_PENDING_TX_TIMEOUT: u64 = 86400;
+    pub const MAX_ORPHAN_TX_BYTES: usize = 5_000_000;
+    pub const MAX_NON_FINAL_TX_BYTES: usize = 5_000_000;
+    pub const MAX_TX_SIGOPS: usize = 4000;
+    pub const MAX_FEE: u64 = 1_000_000;
+    pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+    pub const BLOCK_REQUEST_TIMEOUT: u64 = 60;
+    pub const OVERVIEW_TX_COUNT: usize = 20;
+    pub const MEMPOOL_EXPIRY_HOURS: u32 = 336;
     pub const START_TIMESTAMP: u32 = 1681095600;
     pub const PORT: u16 = 18333;
     pub const MAGIC: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
@@ -0,0 +1,53 @@
+use crate::messages::constants::commands::FEEFILTER;
+use crate::messages::{utility::StreamRead, Message, Serialize};
+use std::io::{self, Cursor};
+
+/// Struct that represents the FeeFilter message, asking not to announce transactions below a fee rate (BIP133)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeFilter {
+    pub fee_rate: u64, // satoshis per kilo virtual byte
+}
+
+impl FeeFilter {
+    /// Creates a new `FeeFilter` message with the given minimum fee rate.
+    pub fn new(fee_rate: u64) -> Self {
+        Self { fee_rate }
+    }
+}
+
+impl Serialize for FeeFilter {
+    fn serialize(&self) -> io::Result<Vec<u8>> {
+        let payload = self.fee_rate.to_le_bytes().to_vec();
+        self.build_message(FEEFILTER, Some(payload))
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let fee_rate = u64::from_le_stream(&mut cursor)?;
+        Ok(Message::FeeFilter(Self::new(fee_rate)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::constants::header_constants::HEADER_SIZE;
+
+    #[test]
+    fn test_feefilter_roundtrip() -> io::Result<()> {
+        let message = FeeFilter::new(1000).serialize()?;
+        assert_eq!(message.len(), HEADER_SIZE + 8);
+
+        match FeeFilter::deserialize(&message[HEADER_SIZE..])? {
+            Message::FeeFilter(feefilter) => assert_eq!(feefilter, FeeFilter::new(1000)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Expected feefilter",
+                ))
+            }
+        }
+        assert!(FeeFilter::deserialize(&[0x01, 0x02]).is_err());
+        Ok(())
+    }
+}
@@ -16,14 +16,24 @@ impl GetData {
         Self { inventory }
     }
 
-    /// Create a new getdata message from a list of BlockHeaders using its hashes
-    pub fn from_inv(block_headers: Vec<BlockHeader>) -> Self {
+    /// Create a new getdata message from a list of BlockHeaders using its hashes, asking for the blocks as the
+    /// given type, which decides whether they come with their witness data
+    pub fn from_inv(block_headers: Vec<BlockHeader>, inv_type: InvType) -> Self {
         let mut inventory_vector: Vec<Inventory> = Vec::new();
         for block_header in block_headers {
-            inventory_vector.push(Inventory::new(InvType::MSGBlock, block_header.hash()));
+            inventory_vector.push(Inventory::new(inv_type.clone(), block_header.hash()));
         }
         Self::new(InventoryVector::new(inventory_vector))
     }
+
+    /// Create a new getdata message asking for each of the announced inventories as the given type
+    pub fn typed(inv_type: InvType, inventories: Vec<Inventory>) -> Self {
+        let items = inventories
+            .into_iter()
+            .map(|inventory| Inventory::new(inv_type.clone(), inventory.hash))
+            .collect();
+        Self::new(InventoryVector::new(items))
+    }
 }
 
 impl Serialize for GetData {
@@ -43,3 +53,49 @@ impl Serialize for GetData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::HashId;
+
+    #[test]
+    fn test_block_download_requests_witness_blocks() -> io::Result<()> {
+        let header = BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let getdata = GetData::from_inv(vec![header], InvType::MSGWitnessBlock);
+        assert_eq!(
+            getdata.inventory.items[0].inv_type,
+            InvType::MSGWitnessBlock
+        );
+        assert_eq!(getdata.inventory.items[0].hash, header.hash());
+
+        let bytes = getdata.serialize()?;
+        assert_eq!(bytes[25..29], [0x02, 0x00, 0x00, 0x40]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_witness_block_getdata_from_inv() -> io::Result<()> {
+        let (block_hash, txid) = (HashId::new([1; 32]), HashId::new([2; 32]));
+        let inv = InventoryVector::new(vec![
+            Inventory::new(InvType::MSGTx, txid),
+            Inventory::new(InvType::_MSGError, HashId::default()),
+            Inventory::new(InvType::MSGBlock, block_hash),
+        ]);
+        let (blocks, txs) = inv.split_blocks_and_txs();
+        assert_eq!(txs.len(), 1);
+
+        let getdata = GetData::typed(InvType::MSGWitnessBlock, blocks);
+        let bytes = getdata.serialize()?;
+        // the payload follows the 24 bytes of the message header
+        let items = match GetData::deserialize(&bytes[24..])? {
+            Message::GetData(getdata) => getdata.inventory.items,
+            _ => vec![],
+        };
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].inv_type, InvType::MSGWitnessBlock);
+        assert_eq!(items[0].hash, block_hash);
+        assert_eq!(bytes[25..29], 0x40000002u32.to_le_bytes());
+        Ok(())
+    }
+}
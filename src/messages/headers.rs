@@ -94,10 +94,15 @@ impl MessageHeader {
             HEADERS,
             NO_COMMAND,
             SENDCMPCT,
+            CMPCTBLOCK,
+            GETBLOCKTXN,
+            BLOCKTXN,
             SENDHEADERS,
             PING,
             FEEFILTER,
             ADDR,
+            ALERT,
+            PONG,
             INV,
             TX,
             NOTFOUND,
@@ -1,7 +1,9 @@
 use crate::messages::constants::commands::HEADERS;
 use crate::messages::constants::header_constants::MAX_HEADER;
-use crate::messages::utility::{read_from_varint, to_varint};
+use crate::messages::utility::{read_from_varint, to_varint, StreamRead};
 use crate::messages::{BlockHeader, HashId, Hashable, Message, Serialize};
+use crate::network_params::{NetworkParams, PowPolicy};
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Cursor};
 /// Struct that contains a list of block headers and the number of headers
@@ -42,28 +44,94 @@ impl Headers {
         self.count == MAX_HEADER
     }
 
+    /// Returns true if the message declared more headers than it held, so the rest must be requested again
+    pub fn is_truncated(&self) -> bool {
+        self.block_headers.len() < self.count
+    }
+
     /// Doesn't check headers size, only use if you know the headers' block_headers is not empty.
     pub fn last_header_hash_unchecked(&self) -> HashId {
         self.block_headers[self.block_headers.len() - 1].hash()
     }
 
-    /// Returns a Headers struct with all the headers contained in the file
-    pub fn from_file(file_name: &str) -> io::Result<Headers> {
+    /// Returns the headers of the file up to the first truncated or corrupt one, whose record doesn't end with an
+    /// empty transaction count or whose parent isn't an earlier header, along with the error found there if any.
+    /// A crash while appending to the file so loses only the headers after the corruption.
+    pub fn from_file(file_name: &str) -> io::Result<(Headers, Option<io::Error>)> {
         let bytes = fs::read(file_name)?;
-        let file_size = bytes.len() as u64;
         let mut cursor: Cursor<&[u8]> = Cursor::new(&bytes);
         let mut headers = Headers::default();
-        // while cursor has more data
-        while cursor.position() < file_size {
-            // deserialize block_header
-            let block_header = BlockHeader::deserialize(&mut cursor)?;
+        let mut known: HashSet<HashId> = HashSet::new();
+        while (cursor.position() as usize) < bytes.len() {
+            let block_header = match read_backup_record(&mut cursor, &known) {
+                Ok(block_header) => block_header,
+                Err(e) => return Ok((headers, Some(e))),
+            };
+            if known.is_empty() {
+                known.insert(block_header.prev_block_hash);
+            }
+            known.insert(block_header.hash());
             headers.count += 1;
             headers.block_headers.push(block_header);
         }
-        Ok(headers)
+        Ok((headers, None))
+    }
+
+    /// Checks the proof of work of the headers in a backup file (unless the network doesn't require it) and that
+    /// each one chains to an earlier one, except the first. Returns how many headers were checked, or the first
+    /// error along with the offset of its header.
+    pub fn verify_backup(bytes: &[u8], network_params: &NetworkParams) -> io::Result<usize> {
+        let mut cursor: Cursor<&[u8]> = Cursor::new(bytes);
+        let mut known: HashSet<HashId> = HashSet::new();
+        let mut count = 0;
+        while (cursor.position() as usize) < bytes.len() {
+            let offset = cursor.position();
+            let header = BlockHeader::deserialize(&mut cursor).map_err(|e| {
+                io::Error::new(e.kind(), format!("Header at offset {}: {}", offset, e))
+            })?;
+            if known.is_empty() {
+                known.insert(header.prev_block_hash);
+            }
+            let checked = match network_params.pow_policy {
+                PowPolicy::Trivial => Ok(()),
+                PowPolicy::Full | PowPolicy::Signet(_) => header.validate_proof_of_work(),
+            }
+            .and_then(|_| match known.contains(&header.prev_block_hash) {
+                true => Ok(()),
+                false => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "parent isn't an earlier header",
+                )),
+            });
+            checked.map_err(|e| {
+                let msg = format!("Header {} at offset {}: {}", header.hash(), offset, e);
+                io::Error::new(e.kind(), msg)
+            })?;
+            known.insert(header.hash());
+            count += 1;
+        }
+        Ok(count)
     }
 }
 
+/// Reads a header record of the backup file, checking it ends with an empty transaction count and, unless it's the
+/// first one, that its parent is one of the known headers
+fn read_backup_record(
+    cursor: &mut Cursor<&[u8]>,
+    known: &HashSet<HashId>,
+) -> io::Result<BlockHeader> {
+    let offset = cursor.position();
+    let block_header = BlockHeader::from_bytes(cursor)?;
+    let empty_tx = u8::from_le_stream(cursor)?;
+    if empty_tx != 0 || !(known.is_empty() || known.contains(&block_header.prev_block_hash)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupt header record at offset {}", offset),
+        ));
+    }
+    Ok(block_header)
+}
+
 impl Serialize for Headers {
     fn serialize(&self) -> std::io::Result<Vec<u8>> {
         let mut payload = Vec::new();
@@ -77,14 +145,105 @@ impl Serialize for Headers {
         Ok(message)
     }
 
+    /// Keeps the headers read before the payload ended if it holds fewer than declared, failing only if it holds none
     fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
         let mut cursor = Cursor::new(bytes);
         let count = read_from_varint(&mut cursor)? as usize;
         let mut block_headers: Vec<BlockHeader> = vec![];
         for _block_num in 0..count {
-            block_headers.push(BlockHeader::deserialize(&mut cursor)?);
+            match BlockHeader::deserialize(&mut cursor) {
+                Ok(header) => block_headers.push(header),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !block_headers.is_empty() => {
+                    break
+                }
+                Err(e) => return Err(e),
+            }
         }
         let headers = Self::new(count, block_headers);
         Ok(Message::Headers(headers))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_payload_keeps_parsed_headers() -> io::Result<()> {
+        let header_bytes: [u8; 80] = [
+            0, 0, 160, 32, 51, 180, 220, 237, 64, 63, 94, 99, 227, 55, 166, 166, 187, 194, 136,
+            175, 122, 209, 45, 188, 74, 201, 99, 234, 23, 0, 0, 0, 0, 0, 0, 0, 219, 236, 86, 82,
+            205, 174, 207, 171, 185, 174, 211, 50, 34, 116, 178, 242, 43, 7, 42, 179, 16, 189, 22,
+            176, 239, 148, 154, 195, 174, 188, 14, 245, 255, 123, 51, 100, 126, 10, 41, 25, 33, 90,
+            175, 108,
+        ];
+        // declares 5 headers but holds 2 and half of the third one
+        let mut payload = to_varint(5);
+        for _ in 0..2 {
+            payload.extend(header_bytes);
+            payload.push(0);
+        }
+        payload.extend(&header_bytes[..40]);
+
+        let headers = match Headers::deserialize(&payload)? {
+            Message::Headers(headers) => headers,
+            _ => panic!("Expected a headers message"),
+        };
+        assert_eq!(headers.block_headers.len(), 2);
+        assert!(headers.is_truncated());
+
+        // with no header to keep, the payload is rejected
+        assert!(Headers::deserialize(&payload[..41]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_tail_of_backup_keeps_the_valid_prefix() -> io::Result<()> {
+        let file_name = "test_corrupt_headers.dat";
+        let mut headers = vec![BlockHeader::new(
+            1,
+            HashId::default(),
+            None,
+            HashId::default(),
+            0,
+            0,
+            0,
+        )];
+        for nonce in 1..3 {
+            let parent = headers[headers.len() - 1].hash();
+            headers.push(BlockHeader::new(
+                1,
+                parent,
+                None,
+                HashId::default(),
+                0,
+                0,
+                nonce,
+            ));
+        }
+        for header in &headers {
+            header.save_to_file(file_name)?;
+        }
+        // a header that doesn't follow the others, then half of one cut by a crash
+        let stray = BlockHeader::new(1, HashId::new([7; 32]), None, HashId::default(), 0, 0, 9);
+        let mut bytes = fs::read(file_name)?;
+        bytes.extend(stray.serialize());
+        bytes.push(0);
+        let complete = bytes.clone();
+        bytes.extend(&headers[0].serialize()[..40]);
+        fs::write(file_name, &bytes)?;
+        let truncated = Headers::from_file(file_name);
+        fs::write(file_name, &complete)?;
+        let corrupt = Headers::from_file(file_name);
+        fs::remove_file(file_name)?;
+
+        for result in [truncated, corrupt] {
+            let (loaded, corruption) = result?;
+            let hashes: Vec<HashId> = loaded.block_headers.iter().map(|h| h.hash()).collect();
+            assert_eq!(hashes, headers.iter().map(|h| h.hash()).collect::<Vec<_>>());
+            assert_eq!(loaded.count, 3);
+            assert!(corruption.is_some());
+        }
+        Ok(())
+    }
+}
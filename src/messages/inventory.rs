@@ -23,6 +23,26 @@ impl InventoryVector {
         }
         Ok(payload)
     }
+
+    /// Builds the notfound message answering a getdata for these inventories
+    pub fn serialize_notfound(&self) -> io::Result<Vec<u8>> {
+        let payload = self.build_payload()?;
+        self.build_message(constants::commands::NOTFOUND, Some(payload))
+    }
+
+    /// Splits the inventories into the ones announcing blocks and the ones announcing transactions, dropping
+    /// any other kind
+    pub fn split_blocks_and_txs(self) -> (Vec<Inventory>, Vec<Inventory>) {
+        let (blocks, others): (Vec<_>, Vec<_>) = self
+            .items
+            .into_iter()
+            .partition(|inventory| inventory.inv_type.is_block());
+        let txs = others
+            .into_iter()
+            .filter(|inventory| inventory.inv_type.is_tx())
+            .collect();
+        (blocks, txs)
+    }
 }
 
 impl Serialize for InventoryVector {
@@ -76,25 +96,41 @@ pub enum InvType {
     _MSGError = 0,
     MSGTx = 1,
     MSGBlock = 2,
-    _MSGFilteredBlock = 3,
-    _MSGCompactBlock = 4,
-    _MSGWitnessTx = 0x40000001,
-    _MSGWitnessBlock = 0x40000002,
-    _MSGFilteredWitnessBlock = 0x40000003,
+    MSGFilteredBlock = 3,
+    MSGCompactBlock = 4,
+    MSGWitnessTx = 0x40000001,
+    MSGWitnessBlock = 0x40000002,
+    MSGFilteredWitnessBlock = 0x40000003,
 }
 
 impl InvType {
+    /// Returns true if the inventory refers to a block, with or without its witness data or filtered
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            InvType::MSGBlock
+                | InvType::MSGFilteredBlock
+                | InvType::MSGWitnessBlock
+                | InvType::MSGFilteredWitnessBlock
+        )
+    }
+
+    /// Returns true if the inventory refers to a transaction, with or without its witness data
+    pub fn is_tx(&self) -> bool {
+        matches!(self, InvType::MSGTx | InvType::MSGWitnessTx)
+    }
+
     /// Convert the inventory type to a u32 (e.g used for serialization)
     pub fn to_u32(&self) -> u32 {
         match self {
             InvType::_MSGError => 0,
             InvType::MSGTx => 1,
             InvType::MSGBlock => 2,
-            InvType::_MSGFilteredBlock => 3,
-            InvType::_MSGCompactBlock => 4,
-            InvType::_MSGWitnessTx => 0x40000001,
-            InvType::_MSGWitnessBlock => 0x40000002,
-            InvType::_MSGFilteredWitnessBlock => 0x40000003,
+            InvType::MSGFilteredBlock => 3,
+            InvType::MSGCompactBlock => 4,
+            InvType::MSGWitnessTx => 0x40000001,
+            InvType::MSGWitnessBlock => 0x40000002,
+            InvType::MSGFilteredWitnessBlock => 0x40000003,
         }
     }
 
@@ -104,11 +140,11 @@ impl InvType {
             0 => Ok(InvType::_MSGError),
             1 => Ok(InvType::MSGTx),
             2 => Ok(InvType::MSGBlock),
-            3 => Ok(InvType::_MSGFilteredBlock),
-            4 => Ok(InvType::_MSGCompactBlock),
-            0x40000001 => Ok(InvType::_MSGWitnessTx),
-            0x40000002 => Ok(InvType::_MSGWitnessBlock),
-            0x40000003 => Ok(InvType::_MSGFilteredWitnessBlock),
+            3 => Ok(InvType::MSGFilteredBlock),
+            4 => Ok(InvType::MSGCompactBlock),
+            0x40000001 => Ok(InvType::MSGWitnessTx),
+            0x40000002 => Ok(InvType::MSGWitnessBlock),
+            0x40000003 => Ok(InvType::MSGFilteredWitnessBlock),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Invalid inventory type",
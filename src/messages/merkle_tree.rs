@@ -1,6 +1,7 @@
 use crate::utility::double_hash;
 use bitcoin_hashes::sha256;
-use std::io::Error;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
 
 /// Enum that represents the direction of a node in a merkle tree
 #[derive(Clone, Debug)]
@@ -54,23 +55,6 @@ pub struct MerkleTree {
 // Doc: https://developer.bitcoin.org/reference/block_chain.html#merkle-trees
 // Reference: https://medium.com/coinmonks/merkle-tree-a-simple-explanation-and-implementation-48903442bc08
 impl MerkleTree {
-    fn get_leaf_node_direction(&self, hash_leaf: sha256::Hash) -> Direction {
-        let mut hash_index = 0;
-        for hash in self.tree[0].iter() {
-            if hash == &hash_leaf {
-                break;
-            }
-            hash_index += 1;
-        }
-
-        if hash_index % 2 == 0 {
-            return Direction::Left;
-        }
-        Direction::Right
-        // this function does not contemplate the chance that the hash is not in the tree
-        // yet it is not needed as the generated proof will fail later on
-    }
-
     fn ensure_even(hashes: Vec<sha256::Hash>) -> Vec<sha256::Hash> {
         if hashes.len() % 2 != 0 {
             let mut new_hashes = hashes.clone();
@@ -144,10 +128,7 @@ impl MerkleTree {
     }
 
     /// Generates a Merkle proof for a given hash
-    pub fn generate_proof(&self, hash: sha256::Hash) -> Result<MerkleProof, Error> {
-        let mut proof: Vec<(sha256::Hash, Direction)> = Vec::new();
-        proof.push((hash, self.get_leaf_node_direction(hash)));
-
+    pub fn _generate_proof(&self, hash: sha256::Hash) -> Result<MerkleProof, Error> {
         let mut hash_index = 0;
         for h in self.tree[0].iter() {
             if h == &hash {
@@ -155,33 +136,57 @@ impl MerkleTree {
             }
             hash_index += 1;
         }
+        // a hash not in the tree gets a proof that fails to generate the root
+        Ok(self.proof_at(hash, hash_index))
+    }
 
+    /// Generates the Merkle proofs of the given hashes building the leaf index once,
+    /// failing if any of them is not a leaf of the tree
+    pub fn generate_proofs(&self, hashes: &[sha256::Hash]) -> Result<Vec<MerkleProof>, Error> {
+        let leaves: HashMap<&sha256::Hash, usize> = match self.tree.first() {
+            Some(leaves) => leaves
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, h)| (h, i))
+                .collect(),
+            None => HashMap::new(),
+        };
+        hashes
+            .iter()
+            .map(|hash| match leaves.get(hash) {
+                Some(index) => Ok(self.proof_at(*hash, *index)),
+                None => Err(Error::new(
+                    ErrorKind::NotFound,
+                    "Hash is not a leaf of the merkle tree",
+                )),
+            })
+            .collect()
+    }
+
+    /// Builds the proof of the given hash as the leaf at the given index
+    fn proof_at(&self, hash: sha256::Hash, leaf_index: usize) -> MerkleProof {
+        let leaf_direction = if leaf_index % 2 == 0 {
+            Direction::Left
+        } else {
+            Direction::Right
+        };
+        let mut proof: Vec<(sha256::Hash, Direction)> = vec![(hash, leaf_direction)];
+        let mut hash_index = leaf_index;
         for level in 0..(self.tree.len() - 1) {
             let is_left_child = hash_index % 2 == 0;
-            let sibling_direction = if is_left_child {
-                Direction::Right
-            } else {
-                Direction::Left
-            };
-
-            let mut sibling_index = if is_left_child {
-                hash_index + 1
+            let (sibling_index, sibling_direction) = if is_left_child {
+                (hash_index + 1, Direction::Right)
             } else {
-                hash_index - 1
+                (hash_index - 1, Direction::Left)
             };
 
             // This means that the hash is the last one in the tree
-            if sibling_index >= self.tree[level].len() {
-                sibling_index = self.tree[level].len() - 1;
-            }
-
-            let sibling_node: (sha256::Hash, Direction) =
-                (self.tree[level][sibling_index], sibling_direction);
-            proof.push(sibling_node);
+            let sibling_index = sibling_index.min(self.tree[level].len() - 1);
+            proof.push((self.tree[level][sibling_index], sibling_direction));
             hash_index /= 2 // shouldn't need to floor as it's a usize
         }
-
-        Ok(MerkleProof { proof })
+        MerkleProof { proof }
     }
 }
 
@@ -427,18 +432,38 @@ mod tests {
 
         // iterate all elements in the tree and validate their proof
         for transaction in txid_hashes {
-            let proof = actual_tree.generate_proof(transaction).unwrap();
+            let proof = actual_tree._generate_proof(transaction).unwrap();
             let merkle_root = proof.generate_merkle_root();
             assert_eq!(merkle_root, abcd_hash);
         }
 
         // alien transaction should fail to generate correct proof
         let alien_transaction = double_hash(b"alien");
-        let alien_proof = actual_tree.generate_proof(alien_transaction).unwrap();
+        let alien_proof = actual_tree._generate_proof(alien_transaction).unwrap();
         let bad_merkle_root = alien_proof.generate_merkle_root();
         assert_ne!(bad_merkle_root, abcd_hash);
     }
 
+    #[test]
+    fn test_batch_proofs_verify_to_the_same_root() {
+        let hashes: Vec<sha256::Hash> = [b"a", b"b", b"c", b"d", b"e"]
+            .iter()
+            .map(|tx| double_hash(*tx))
+            .collect();
+        let tree = MerkleTree::generate_from_hashes(hashes.clone());
+
+        let requested = [hashes[4], hashes[1], hashes[2]];
+        let proofs = tree.generate_proofs(&requested).unwrap();
+        assert_eq!(proofs.len(), requested.len());
+        for (proof, hash) in proofs.iter().zip(requested) {
+            assert_eq!(proof.proof[0].0, hash);
+            assert_eq!(proof.generate_merkle_root(), tree.get_root());
+        }
+
+        let alien = double_hash(b"alien");
+        assert!(tree.generate_proofs(&[hashes[0], alien]).is_err());
+    }
+
     #[test]
     fn test_merkle_tree_from_raw_transactions() {
         let tx1_bytes = decode_hex("020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff2303aba925044428c1644d65726d6169646572204654572101000023f5cb010000000000ffffffff02ce80250000000000160014c035e789d9efffa10aa92e93f48f29b8cfb224c20000000000000000266a24aa21a9ed8e2fa0dcf35a1c3853030613ab9fe45ff77255df36484815fd2899d8675e3d180120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap();
@@ -497,14 +522,14 @@ mod tests {
         let tx1_hash_str =
             reverse_hex_str("3412733ebdff59c8b28fed2b18b2a4fd60332fb08a5ca4b2ecfaea4e241fc081");
         let tx1_hash = tx1_hash_str.parse().unwrap();
-        let proof_from_tx1 = merkle_tree.generate_proof(tx1_hash).unwrap();
+        let proof_from_tx1 = merkle_tree._generate_proof(tx1_hash).unwrap();
         let merkle_root_from_tx1 = proof_from_tx1.generate_merkle_root();
         assert_eq!(expected_sha256, merkle_root_from_tx1);
 
         let tx2_hash_str =
             reverse_hex_str("3412733ebdff59c8b28fed2b18b2a4fd60332fb08a5ca4b2ecfaea4e241fc081");
         let tx2_hash = tx2_hash_str.parse().unwrap();
-        let proof_from_tx2 = merkle_tree.generate_proof(tx2_hash).unwrap();
+        let proof_from_tx2 = merkle_tree._generate_proof(tx2_hash).unwrap();
         let merkle_root_from_tx2 = proof_from_tx2.generate_merkle_root();
         assert_eq!(expected_sha256, merkle_root_from_tx2);
     }
@@ -4,7 +4,9 @@ use bitcoin_hashes::Hash;
 use std::io;
 pub(crate) mod block_header;
 mod block_message;
+mod compact_block;
 pub(crate) mod constants;
+mod feefilter_message;
 mod getdata_message;
 mod getheader_message;
 mod headers;
@@ -12,14 +14,19 @@ mod headers_message;
 pub(crate) mod inventory;
 pub mod merkle_tree;
 mod ping_message;
+mod sendcmpct_message;
 mod sendheaders;
+mod signet;
 pub mod utility;
 mod verack_message;
 pub(crate) mod version_message;
+mod witness_commitment;
 
 pub use block_header::BlockHeader;
 pub use block_message::Block;
 pub use block_message::BlockSet;
+pub use compact_block::{BlockTxn, CompactBlock, GetBlockTxn, PartialBlock};
+pub use feefilter_message::FeeFilter;
 pub use getdata_message::GetData;
 pub use getheader_message::GetHeader;
 pub use headers::MessageHeader;
@@ -27,6 +34,7 @@ pub use headers_message::Headers;
 pub use inventory::{InvType, Inventory, InventoryVector};
 pub use merkle_tree::MerkleTree;
 pub use ping_message::Ping;
+pub use sendcmpct_message::SendCmpct;
 pub use sendheaders::SendHeaders;
 pub use verack_message::VerAck;
 pub use version_message::Version;
@@ -127,11 +135,16 @@ impl Services {
         }
     }
 
+    /// Returns the advertised services as the bitmap they're encoded in
+    pub fn bitmap(self) -> u64 {
+        self.bitmap
+    }
+
     pub fn _is_unnamed(self) -> bool {
         self.bitmap == 0
     }
 
-    pub fn _is_node_network(self) -> bool {
+    pub fn is_node_network(self) -> bool {
         self.bitmap & 1 != 0
     }
 
@@ -143,7 +156,7 @@ impl Services {
         self.bitmap & 4 != 0
     }
 
-    pub fn _is_node_witness(self) -> bool {
+    pub fn is_node_witness(self) -> bool {
         self.bitmap & 8 != 0
     }
 
@@ -177,11 +190,28 @@ pub enum Message {
     _VerAck(VerAck),
     Version(Version),
     Inv(InventoryVector),
+    NotFound(InventoryVector),
     Transaction(RawTransaction),
     Ping(Ping),
+    SendCmpct(SendCmpct),
+    SendHeaders(SendHeaders),
+    FeeFilter(FeeFilter),
+    CompactBlock(CompactBlock),
+    GetBlockTxn(GetBlockTxn),
+    BlockTxn(BlockTxn),
     Ignore,
 }
 
+impl Message {
+    /// Returns true for the messages exchanged to relay a block as a compact block (BIP152)
+    pub fn is_compact_block_relay(&self) -> bool {
+        matches!(
+            self,
+            Message::CompactBlock(_) | Message::GetBlockTxn(_) | Message::BlockTxn(_)
+        )
+    }
+}
+
 pub trait Hashable {
     fn hash(&self) -> HashId;
 }
@@ -235,10 +265,10 @@ mod tests {
     #[test]
     fn test_single_service_from_bytes() -> Result<(), io::Error> {
         assert!(Services::from(0x00_u64.to_le_bytes())._is_unnamed());
-        assert!(Services::from(0x01_u64.to_le_bytes())._is_node_network());
+        assert!(Services::from(0x01_u64.to_le_bytes()).is_node_network());
         assert!(Services::from(0x02_u64.to_le_bytes())._is_node_get_utxo());
         assert!(Services::from(0x04_u64.to_le_bytes())._is_node_bloom());
-        assert!(Services::from(0x08_u64.to_le_bytes())._is_node_witness());
+        assert!(Services::from(0x08_u64.to_le_bytes()).is_node_witness());
         assert!(Services::from(0x10_u64.to_le_bytes())._is_node_xthin());
         assert!(Services::from(0x0400_u64.to_le_bytes())._is_node_network_limited());
         Ok(())
@@ -248,10 +278,10 @@ mod tests {
     fn test_multiple_services_from_empty_bytes() -> Result<(), io::Error> {
         let services = Services::from(0x00_u64.to_le_bytes());
         assert!(services._is_unnamed());
-        assert!(!services._is_node_network());
+        assert!(!services.is_node_network());
         assert!(!services._is_node_get_utxo());
         assert!(!services._is_node_bloom());
-        assert!(!services._is_node_witness());
+        assert!(!services.is_node_witness());
         assert!(!services._is_node_xthin());
         assert!(!services._is_node_network_limited());
         Ok(())
@@ -261,10 +291,10 @@ mod tests {
     fn test_multiple_services_from_valid_bytes() -> Result<(), io::Error> {
         let services = Services::from(0x0401_u64.to_le_bytes());
         assert!(!services._is_unnamed());
-        assert!(services._is_node_network());
+        assert!(services.is_node_network());
         assert!(!services._is_node_get_utxo());
         assert!(!services._is_node_bloom());
-        assert!(!services._is_node_witness());
+        assert!(!services.is_node_witness());
         assert!(!services._is_node_xthin());
         assert!(services._is_node_network_limited());
         Ok(())
@@ -1,5 +1,8 @@
 use crate::messages::{
-    constants::{commands::PONG, config::MAGIC},
+    constants::{
+        commands::{PING, PONG},
+        config::MAGIC,
+    },
     utility::StreamRead,
     Message, MessageHeader, Serialize,
 };
@@ -12,10 +15,15 @@ pub struct Ping {
 }
 
 impl Ping {
-    fn new(nonce: u64) -> Self {
+    pub fn new(nonce: u64) -> Self {
         Self { nonce }
     }
 
+    /// Builds the ping message, with header, to be sent to a peer
+    pub fn build_ping(&self) -> io::Result<Vec<u8>> {
+        self.build_message(PING, Some(self.serialize()?))
+    }
+
     pub fn pong(bytes: &[u8]) -> io::Result<Vec<u8>> {
         let hash = double_hash(bytes);
         let checksum: [u8; 4] = [hash[0], hash[1], hash[2], hash[3]];
@@ -0,0 +1,77 @@
+use crate::messages::constants::commands::SENDCMPCT;
+use crate::messages::{utility::StreamRead, Message, Serialize};
+use std::io::{self, Cursor};
+
+/// Struct that represents the SendCmpct message used to negotiate compact block relay (BIP152)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendCmpct {
+    pub announce: bool,
+    pub version: u64,
+}
+
+impl SendCmpct {
+    /// Creates a new `SendCmpct` message with the given announce mode and compact block version.
+    pub fn new(announce: bool, version: u64) -> Self {
+        Self { announce, version }
+    }
+
+    fn build_payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.announce as u8];
+        payload.extend(self.version.to_le_bytes());
+        payload
+    }
+}
+
+impl Serialize for SendCmpct {
+    fn serialize(&self) -> io::Result<Vec<u8>> {
+        let message = self.build_message(SENDCMPCT, Some(self.build_payload()))?;
+        Ok(message)
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Message, io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let announce = match u8::from_le_stream(&mut cursor)? {
+            0 => false,
+            1 => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid announce flag in sendcmpct message",
+                ))
+            }
+        };
+        let version = u64::from_le_stream(&mut cursor)?;
+        Ok(Message::SendCmpct(Self::new(announce, version)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::constants::header_constants::HEADER_SIZE;
+
+    #[test]
+    fn test_sendcmpct_roundtrip() -> io::Result<()> {
+        let message = SendCmpct::new(true, 1).serialize()?;
+        assert_eq!(message.len(), HEADER_SIZE + 9);
+
+        let sendcmpct = match SendCmpct::deserialize(&message[HEADER_SIZE..])? {
+            Message::SendCmpct(sendcmpct) => sendcmpct,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Expected sendcmpct",
+                ))
+            }
+        };
+        assert_eq!(sendcmpct, SendCmpct::new(true, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sendcmpct_invalid_announce_flag() {
+        let mut payload = vec![0x02];
+        payload.extend(1u64.to_le_bytes());
+        assert!(SendCmpct::deserialize(&payload).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::messages::constants::commands::SENDHEADERS;
-use crate::messages::{MessageHeader, Serialize};
+use crate::messages::{Message, MessageHeader, Serialize};
+use std::io;
 
 /// Struct that represents the SendHeaders message
 #[derive(Debug, Clone)]
@@ -21,4 +22,9 @@ impl Serialize for SendHeaders {
         let message = self.build_message(SENDHEADERS, None)?;
         Ok(message)
     }
+
+    /// The message has no payload, it only asks for new blocks to be announced with headers
+    fn deserialize(_bytes: &[u8]) -> Result<Message, io::Error> {
+        Ok(Message::SendHeaders(SendHeaders::new()))
+    }
 }
@@ -0,0 +1,376 @@
+use crate::messages::utility::read_from_varint;
+use crate::messages::{BlockHeader, HashId};
+use crate::raw_transaction::script::{
+    parse_script, push_data, ScriptOp, OP_0, OP_1, OP_16, OP_CHECKMULTISIG, OP_CHECKSIG, OP_RETURN,
+};
+use crate::raw_transaction::tx_input::{Outpoint, TxInput, TxInputType};
+use crate::raw_transaction::tx_output::TxOutput;
+use crate::raw_transaction::RawTransaction;
+use crate::utility::to_io_err;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use std::io::{self, Cursor, Read};
+
+/// Prefix of the coinbase output committing to the witness data (BIP141)
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+/// Prefix of the push in the witness commitment that carries the signet solution (BIP325)
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+const SIGHASH_ALL: u8 = 0x01;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unsupported_challenge() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Only OP_TRUE, pay to pubkey and bare multisig signet challenges are supported",
+    )
+}
+
+/// Returns the coinbase without the signet solution (needed for the signet merkle root) and the solution.
+/// The solution push is left with only its header, as the signer saw it.
+pub fn extract_solution(coinbase: &RawTransaction) -> io::Result<(RawTransaction, Vec<u8>)> {
+    let mut modified_coinbase = coinbase.clone();
+    let commitment = modified_coinbase
+        .tx_out
+        .iter_mut()
+        .rev()
+        .find(|tx_out| tx_out.pk_script.starts_with(&WITNESS_COMMITMENT_HEADER))
+        .ok_or_else(|| invalid_data("Signet block without witness commitment"))?;
+
+    let mut solution = Vec::new();
+    let mut script = Vec::new();
+    for (range, op) in parse_script(&commitment.pk_script)? {
+        match op {
+            ScriptOp::Push(data)
+                if solution.is_empty()
+                    && data.len() > SIGNET_HEADER.len()
+                    && data.starts_with(&SIGNET_HEADER) =>
+            {
+                solution = data[SIGNET_HEADER.len()..].to_vec();
+                script.extend(push_data(&SIGNET_HEADER));
+            }
+            _ => script.extend(&commitment.pk_script[range]),
+        }
+    }
+    commitment.pk_script_bytes = script.len() as u64;
+    commitment.pk_script = script;
+    Ok((modified_coinbase, solution))
+}
+
+fn single_input_transaction(
+    previous_output: Outpoint,
+    script_sig: Vec<u8>,
+    pk_script: Vec<u8>,
+) -> RawTransaction {
+    RawTransaction {
+        version: 0,
+        tx_in_count: 1,
+        tx_in: TxInputType::TxInput(vec![TxInput {
+            previous_output,
+            script_bytes: script_sig.len() as u64,
+            script_sig,
+            sequence: 0,
+        }]),
+        tx_out_count: 1,
+        tx_out: vec![TxOutput {
+            value: 0,
+            pk_script_bytes: pk_script.len() as u64,
+            pk_script,
+        }],
+        lock_time: 0,
+        witnesses: vec![],
+    }
+}
+
+/// Builds the virtual transaction whose signature the signet solution must provide.
+/// It spends a transaction paying to the challenge that commits to the block (without its nonce).
+pub fn spending_transaction(
+    header: &BlockHeader,
+    signet_merkle_root: HashId,
+    challenge: &[u8],
+) -> RawTransaction {
+    let header_bytes = header.serialize();
+    let mut block_data = header_bytes[..36].to_vec(); // version and previous block hash
+    block_data.extend(signet_merkle_root.iter());
+    block_data.extend(&header_bytes[68..72]); // timestamp
+
+    let script_sig = [&[OP_0][..], &push_data(&block_data)].concat();
+    let null_outpoint = Outpoint {
+        hash: HashId::default(),
+        index: u32::MAX,
+    };
+    let to_spend = single_input_transaction(null_outpoint, script_sig, challenge.to_vec());
+
+    let outpoint = Outpoint {
+        hash: to_spend.txid(),
+        index: 0,
+    };
+    single_input_transaction(outpoint, vec![], vec![OP_RETURN])
+}
+
+/// Checks the DER signature (followed by its sighash type) of the spending transaction against the public key
+fn check_signature(
+    spending: &RawTransaction,
+    challenge: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> io::Result<bool> {
+    let (sighash_type, der) = match signature.split_last() {
+        Some((sighash_type, der)) => (*sighash_type, der),
+        None => return Ok(false),
+    };
+    if sighash_type != SIGHASH_ALL {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only SIGHASH_ALL signet signatures are supported",
+        ));
+    }
+    let (mut signature, public_key) =
+        match (Signature::from_der(der), PublicKey::from_slice(public_key)) {
+            (Ok(signature), Ok(public_key)) => (signature, public_key),
+            _ => return Ok(false),
+        };
+    signature.normalize_s();
+    let z = spending.sig_hash(challenge.to_vec(), 0)?;
+    let message = Message::from_slice(&z).map_err(to_io_err)?;
+    Ok(Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature, &public_key)
+        .is_ok())
+}
+
+/// Runs an m-of-n OP_CHECKMULTISIG over the stack: signatures must match the public keys in order
+fn check_multisig(
+    spending: &RawTransaction,
+    challenge: &[u8],
+    stack: &[Vec<u8>],
+    (m, keys, n): (u8, &[ScriptOp], u8),
+) -> io::Result<bool> {
+    let public_keys = keys
+        .iter()
+        .map(|op| match op {
+            ScriptOp::Push(public_key) => Ok(public_key),
+            ScriptOp::Op(_) => Err(unsupported_challenge()),
+        })
+        .collect::<io::Result<Vec<&Vec<u8>>>>()?;
+    let required = (m - OP_1 + 1) as usize;
+    if public_keys.len() != (n - OP_1 + 1) as usize || required > public_keys.len() {
+        return Err(unsupported_challenge());
+    }
+    // the extra element consumed by OP_CHECKMULTISIG must be empty
+    let signatures = match stack.split_first() {
+        Some((dummy, signatures)) if dummy.is_empty() && signatures.len() == required => signatures,
+        _ => return Ok(false),
+    };
+
+    let mut public_keys = public_keys.into_iter();
+    for signature in signatures {
+        loop {
+            match public_keys.next() {
+                Some(public_key) => {
+                    if check_signature(spending, challenge, signature, public_key)? {
+                        break;
+                    }
+                }
+                None => return Ok(false),
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Returns the pushed data of a script made only of pushes
+fn pushes(script: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    parse_script(script)?
+        .into_iter()
+        .map(|(_, op)| match op {
+            ScriptOp::Push(data) => Ok(data),
+            ScriptOp::Op(OP_0) => Ok(vec![]),
+            ScriptOp::Op(_) => Err(invalid_data("Signet solution script sig must be push only")),
+        })
+        .collect()
+}
+
+/// Executes the solution's script sig against the challenge, for the supported challenge templates
+fn check_script(
+    spending: &RawTransaction,
+    challenge: &[u8],
+    script_sig: &[u8],
+) -> io::Result<bool> {
+    let stack = pushes(script_sig)?;
+    let challenge_ops: Vec<ScriptOp> = parse_script(challenge)?
+        .into_iter()
+        .map(|(_, op)| op)
+        .collect();
+    match &challenge_ops[..] {
+        [ScriptOp::Op(OP_1)] => Ok(true),
+        [ScriptOp::Push(public_key), ScriptOp::Op(OP_CHECKSIG)] => match &stack[..] {
+            [signature] => check_signature(spending, challenge, signature, public_key),
+            _ => Ok(false),
+        },
+        [ScriptOp::Op(m @ OP_1..=OP_16), keys @ .., ScriptOp::Op(n @ OP_1..=OP_16), ScriptOp::Op(OP_CHECKMULTISIG)] => {
+            check_multisig(spending, challenge, &stack, (*m, keys, *n))
+        }
+        _ => Err(unsupported_challenge()),
+    }
+}
+
+/// Verifies the serialized solution (script sig and witness stack) satisfies the challenge
+pub fn verify_solution(
+    spending: &RawTransaction,
+    challenge: &[u8],
+    solution: &[u8],
+) -> io::Result<()> {
+    let mut cursor = Cursor::new(solution);
+    let script_sig_len = match solution.is_empty() {
+        true => 0,
+        false => read_from_varint(&mut cursor)?,
+    };
+    let remaining = solution.len() as u64 - cursor.position();
+    if script_sig_len > remaining {
+        return Err(invalid_data(
+            "Signet solution script sig is longer than the solution",
+        ));
+    }
+    let mut script_sig = vec![0u8; script_sig_len as usize];
+    cursor.read_exact(&mut script_sig)?;
+    if (cursor.position() as usize) < solution.len() && read_from_varint(&mut cursor)? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Witness signet solutions are not supported",
+        ));
+    }
+    if (cursor.position() as usize) != solution.len() {
+        return Err(invalid_data("Signet solution has trailing data"));
+    }
+
+    match check_script(spending, challenge, &script_sig)? {
+        true => Ok(()),
+        false => Err(invalid_data("Invalid signet block signature")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Block;
+    use crate::network_params::{Network, NetworkParams};
+    use crate::raw_transaction::tx_input::CoinBaseInput;
+    use secp256k1::SecretKey;
+
+    fn coinbase_with_commitment(commitment: Vec<u8>) -> RawTransaction {
+        let coinbase_input = CoinBaseInput {
+            _hash: HashId::default(),
+            _index: u32::MAX,
+            _script_bytes: 4,
+            height: 100,
+            _coinbase_script: vec![],
+            _sequence: u32::MAX,
+        };
+        RawTransaction {
+            version: 1,
+            tx_in_count: 1,
+            tx_in: TxInputType::CoinBaseInput(coinbase_input),
+            tx_out_count: 2,
+            tx_out: vec![
+                TxOutput {
+                    value: 5_000_000_000,
+                    pk_script_bytes: 1,
+                    pk_script: vec![OP_1],
+                },
+                TxOutput {
+                    value: 0,
+                    pk_script_bytes: commitment.len() as u64,
+                    pk_script: commitment,
+                },
+            ],
+            lock_time: 0,
+            witnesses: vec![],
+        }
+    }
+
+    fn multisig_challenge(secret_key: &SecretKey) -> Vec<u8> {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), secret_key).serialize();
+        [
+            &[OP_1][..],
+            &push_data(&public_key),
+            &[OP_1, OP_CHECKMULTISIG],
+        ]
+        .concat()
+    }
+
+    /// Builds a block signed by the given key the way a signet miner does
+    fn signed_block(secret_key: &SecretKey, challenge: &[u8]) -> io::Result<Block> {
+        let commitment = [&WITNESS_COMMITMENT_HEADER[..], &[0u8; 32]].concat();
+        let unsigned =
+            coinbase_with_commitment([&commitment[..], &push_data(&SIGNET_HEADER)].concat());
+        let header = |merkle_root| {
+            BlockHeader::new(
+                0x20000000,
+                HashId::default(),
+                None,
+                merkle_root,
+                1681095679,
+                0x1e0377ae,
+                0,
+            )
+        };
+
+        let spending = spending_transaction(&header(unsigned.txid()), unsigned.txid(), challenge);
+        let z = spending.sig_hash(challenge.to_vec(), 0)?;
+        let message = Message::from_slice(&z).map_err(to_io_err)?;
+        let mut signature = Secp256k1::new()
+            .sign_ecdsa(&message, secret_key)
+            .serialize_der()
+            .to_vec();
+        signature.push(SIGHASH_ALL);
+
+        let script_sig = [&[OP_0][..], &push_data(&signature)].concat();
+        let solution = [&[script_sig.len() as u8][..], &script_sig, &[0x00]].concat();
+        let signet_push = push_data(&[&SIGNET_HEADER[..], &solution].concat());
+        let signed = coinbase_with_commitment([&commitment[..], &signet_push].concat());
+        Ok(Block::new(header(signed.txid()), 1, vec![signed]))
+    }
+
+    #[test]
+    fn test_signet_block_signature() -> io::Result<()> {
+        let secret_key = SecretKey::from_slice(&[0x11; 32]).map_err(to_io_err)?;
+        let challenge = multisig_challenge(&secret_key);
+        let block = signed_block(&secret_key, &challenge)?;
+        block.validate_signet(&challenge)?;
+        block.validate(&NetworkParams::new(Network::Signet, &challenge)?)?;
+
+        let other_key = SecretKey::from_slice(&[0x22; 32]).map_err(to_io_err)?;
+        assert!(block
+            .validate_signet(&multisig_challenge(&other_key))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_solution_keeps_signet_header() -> io::Result<()> {
+        let commitment = [&WITNESS_COMMITMENT_HEADER[..], &[0u8; 32]].concat();
+        let signet_push = push_data(&[&SIGNET_HEADER[..], &[0x01, 0x51, 0x00]].concat());
+        let coinbase = coinbase_with_commitment([&commitment[..], &signet_push].concat());
+
+        let (modified, solution) = extract_solution(&coinbase)?;
+        assert_eq!(solution, vec![0x01, 0x51, 0x00]);
+        assert_eq!(
+            modified.tx_out[1].pk_script,
+            [&commitment[..], &push_data(&SIGNET_HEADER)].concat()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_solution_with_a_script_sig_past_its_end_is_rejected() {
+        let spending = coinbase_with_commitment(vec![]);
+        let oversized = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let truncated = [0x05, OP_1];
+        for solution in [&oversized[..], &truncated[..]] {
+            let err = verify_solution(&spending, &[OP_1], solution).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -139,6 +139,63 @@ mod tests {
         assert_eq!(value, 67305985);
     }
 
+    fn read_both<T: StreamRead>(bytes: &[u8]) -> io::Result<(T, T)> {
+        let le = T::from_le_stream(&mut Cursor::new(bytes))?;
+        let be = T::from_be_stream(&mut Cursor::new(bytes))?;
+        Ok((le, be))
+    }
+
+    #[test]
+    fn test_endian_read_unsigned_matrix() -> io::Result<()> {
+        assert_eq!(read_both::<u8>(&[0xab])?, (0xab, 0xab));
+        assert_eq!(read_both::<u16>(&[0x01, 0x02])?, (0x0201, 0x0102));
+        assert_eq!(
+            read_both::<u32>(&[0x01, 0x02, 0x03, 0x04])?,
+            (0x04030201, 0x01020304)
+        );
+        assert_eq!(
+            read_both::<u64>(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])?,
+            (0x0807060504030201, 0x0102030405060708)
+        );
+        let bytes: Vec<u8> = (0x01..=0x10).collect();
+        assert_eq!(
+            read_both::<u128>(&bytes)?,
+            (
+                0x100f0e0d0c0b0a090807060504030201,
+                0x0102030405060708090a0b0c0d0e0f10
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_endian_read_signed_matrix() -> io::Result<()> {
+        assert_eq!(
+            read_both::<i32>(&[0xfe, 0xff, 0xff, 0xff])?,
+            (-2, -16777217)
+        );
+        assert_eq!(
+            read_both::<i32>(&[0x00, 0x00, 0x00, 0x80])?,
+            (i32::MIN, 128)
+        );
+        assert_eq!(
+            read_both::<i64>(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f])?,
+            (i64::MAX, -129)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_endian_read_advances_cursor_and_fails_when_short() -> io::Result<()> {
+        let bytes = [0x01, 0x00, 0x00, 0x02, 0x03];
+        let mut cursor = Cursor::new(&bytes[..]);
+        assert_eq!(u16::from_le_stream(&mut cursor)?, 1);
+        assert_eq!(u16::from_be_stream(&mut cursor)?, 2);
+        assert_eq!(cursor.position(), 4);
+        assert!(u16::from_le_stream(&mut cursor).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_read_hash() {
         let data: [u8; 32] = [
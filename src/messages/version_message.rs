@@ -1,4 +1,7 @@
-use crate::messages::constants::{commands::VERSION, version_constants::LATEST_VERSION};
+use crate::messages::constants::{
+    commands::VERSION,
+    version_constants::{LATEST_VERSION, USER_AGENT},
+};
 use crate::messages::utility::{read_from_varint, to_varint, StreamRead};
 use crate::messages::{Message, Serialize, Services};
 use crate::utility::actual_timestamp_or_default;
@@ -17,12 +20,12 @@ pub struct Version {
     addr_recv_services: u64,
     addr_recv_ip: Ipv6Addr,
     addr_recv_port: u16,
-    _addr_trans_services: u64,
+    addr_trans_services: u64,
     addr_trans_ip: Ipv6Addr,
     addr_trans_port: u16,
     nonce: u64,
-    _user_agent: String,
-    _start_height: i32,
+    user_agent: String,
+    start_height: i32,
     relay: bool,
 }
 
@@ -34,12 +37,12 @@ impl Default for Version {
         let addr_recv_services = 0;
         let addr_recv_ip = Ipv6Addr::LOCALHOST;
         let addr_recv_port = constants::config::PORT;
-        let _addr_trans_services = 0;
+        let addr_trans_services = 0;
         let addr_trans_ip = Ipv6Addr::LOCALHOST;
         let addr_trans_port = constants::config::PORT;
         let nonce = 0;
-        let _user_agent = "".to_string();
-        let _start_height = 0;
+        let user_agent = USER_AGENT.to_string();
+        let start_height = 0;
         let relay = true;
         Version::new(
             // message_header,
@@ -49,12 +52,12 @@ impl Default for Version {
             addr_recv_services,
             addr_recv_ip,
             addr_recv_port,
-            _addr_trans_services,
+            addr_trans_services,
             addr_trans_ip,
             addr_trans_port,
             nonce,
-            _user_agent,
-            _start_height,
+            user_agent,
+            start_height,
             relay,
         )
     }
@@ -69,12 +72,12 @@ impl Version {
         addr_recv_services: u64,
         addr_recv_ip: Ipv6Addr,
         addr_recv_port: u16,
-        _addr_trans_services: u64,
+        addr_trans_services: u64,
         addr_trans_ip: Ipv6Addr,
         addr_trans_port: u16,
         nonce: u64,
-        _user_agent: String,
-        _start_height: i32,
+        user_agent: String,
+        start_height: i32,
         relay: bool,
     ) -> Self {
         Self {
@@ -84,12 +87,12 @@ impl Version {
             addr_recv_services,
             addr_recv_ip,
             addr_recv_port,
-            _addr_trans_services,
+            addr_trans_services,
             addr_trans_ip,
             addr_trans_port,
             nonce,
-            _user_agent,
-            _start_height,
+            user_agent,
+            start_height,
             relay,
         }
     }
@@ -98,7 +101,7 @@ impl Version {
     pub fn default_for_trans_addr(address: SocketAddr) -> Self {
         Version {
             addr_trans_ip: match address.ip() {
-                IpAddr::V4(ip4) => ip4.to_ipv6_compatible(),
+                IpAddr::V4(ip4) => ip4.to_ipv6_mapped(),
                 IpAddr::V6(ip6) => ip6,
             },
             addr_trans_port: address.port(),
@@ -106,6 +109,11 @@ impl Version {
         }
     }
 
+    /// Returns the version message with the given relay flag, false asking the peer not to send transactions
+    pub fn with_relay(self, relay: bool) -> Self {
+        Self { relay, ..self }
+    }
+
     pub fn build_payload(&self) -> io::Result<Vec<u8>> {
         let mut payload = Vec::new();
         payload.extend(&self.version.to_le_bytes());
@@ -115,22 +123,43 @@ impl Version {
         payload.extend(&self.addr_recv_services.to_le_bytes());
         payload.extend(&self.addr_recv_ip.octets());
         payload.extend(&self.addr_recv_port.to_be_bytes());
-        payload.extend(&self.addr_recv_services.to_le_bytes());
+        payload.extend(&self.addr_trans_services.to_le_bytes());
         payload.extend(&self.addr_trans_ip.octets());
         payload.extend(&self.addr_trans_port.to_be_bytes());
-        payload.extend(to_varint(self.nonce.to_le_bytes().len() as u64));
         payload.extend(&self.nonce.to_le_bytes());
-        payload.extend(self._user_agent.as_bytes());
-        payload.extend(&self._start_height.to_le_bytes());
+        payload.extend(to_varint(self.user_agent.len() as u64));
+        payload.extend(self.user_agent.as_bytes());
+        payload.extend(&self.start_height.to_le_bytes());
         payload.extend(&(self.relay as u8).to_le_bytes());
 
         Ok(payload)
     }
 
     /// Returns true if the version is accepted by the other version (the other version is newer)
-    pub fn accepts(&self, another_version: Version) -> bool {
+    pub fn accepts(&self, another_version: &Version) -> bool {
         self.version <= another_version.version
     }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn services(&self) -> Services {
+        self.services
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    pub fn start_height(&self) -> i32 {
+        self.start_height
+    }
+
+    /// Returns false if the peer asked not to be sent transaction invs until it sends a filter
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
 }
 
 impl Serialize for Version {
@@ -150,7 +179,7 @@ impl Serialize for Version {
             u64::from_le_stream(&mut cursor)?,
             Ipv6Addr::from(u128::from_be_stream(&mut cursor)?),
             u16::from_be_stream(&mut cursor)?,
-            u64::from_le_stream(&mut cursor)?, // not used
+            u64::from_le_stream(&mut cursor)?,
             Ipv6Addr::from(u128::from_be_stream(&mut cursor)?),
             u16::from_be_stream(&mut cursor)?,
             u64::from_le_stream(&mut cursor)?,
@@ -212,12 +241,67 @@ mod tests {
                 .expect("Invalid IPv6 address");
             assert_eq!(data.addr_recv_ip.to_string(), expected_addr.to_string());
             assert_eq!(data.addr_recv_port, 53032);
-            assert_eq!(data._addr_trans_services, 1037);
+            assert_eq!(data.addr_trans_services, 1037);
             assert_eq!(data.addr_trans_port, 0);
             assert_eq!(data.nonce, 7085675175729411284);
-            assert_eq!(data._user_agent, "/Satoshi:0.16.3/".to_string());
-            assert_eq!(data._start_height, 2434713);
+            assert_eq!(data.user_agent, "/Satoshi:0.16.3/".to_string());
+            assert_eq!(data.start_height, 2434713);
             assert_eq!(data.relay, true);
         }
     }
+
+    #[test]
+    fn test_version_roundtrip() -> io::Result<()> {
+        let address: SocketAddr = "127.0.0.1:18333"
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid socket address"))?;
+        let version = Version::default_for_trans_addr(address);
+        let bytes = version.build_payload()?;
+
+        match Version::deserialize(&bytes)? {
+            Message::Version(data) => {
+                assert_eq!(data.version(), LATEST_VERSION);
+                assert_eq!(data.user_agent(), USER_AGENT);
+                assert_eq!(data.start_height(), 0);
+                assert_eq!(data.addr_trans_port, 18333);
+            }
+            _ => panic!("Expected version message"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_payload_address_byte_order() -> io::Result<()> {
+        let address: SocketAddr = "200.105.43.35:18333"
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid socket address"))?;
+        let version = Version {
+            addr_recv_services: 0,
+            addr_trans_services: 1037,
+            ..Version::default_for_trans_addr(address)
+        };
+        let bytes = version.build_payload()?;
+
+        // version (4) + services (8) + timestamp (8) + recv address (26)
+        let trans = &bytes[46..72];
+        assert_eq!(trans[..8], 1037u64.to_le_bytes());
+        assert_eq!(
+            trans[8..24],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 200, 105, 43, 35]
+        );
+        assert_eq!(trans[24..], [0x47, 0x9d]);
+
+        match Version::deserialize(&bytes)? {
+            Message::Version(data) => {
+                assert_eq!(data.addr_trans_services, 1037);
+                assert_eq!(
+                    data.addr_trans_ip.to_ipv4_mapped(),
+                    Some([200, 105, 43, 35].into())
+                );
+                assert_eq!(data.addr_trans_port, 18333);
+            }
+            _ => panic!("Expected version message"),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,95 @@
+use super::signet::WITNESS_COMMITMENT_HEADER;
+use crate::raw_transaction::RawTransaction;
+use crate::utility::double_hash;
+use bitcoin_hashes::{sha256, Hash};
+use std::io;
+
+/// Size of the commitment output script: the header followed by the 32 bytes commitment
+const COMMITMENT_SCRIPT_SIZE: usize = WITNESS_COMMITMENT_HEADER.len() + 32;
+
+/// The commitment of a coinbase to the witnesses of its block (BIP141): the hash in its last output starting
+/// with the commitment header, and the witness reserved value the coinbase input carries, None if its witness
+/// isn't a single 32 bytes item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WitnessCommitment {
+    pub commitment: [u8; 32],
+    pub reserved_value: Option<[u8; 32]>,
+}
+
+impl WitnessCommitment {
+    /// Parses the commitment of the coinbase, None if it has no output committing to the witnesses
+    pub fn from_coinbase(coinbase: &RawTransaction) -> Option<Self> {
+        let output = coinbase.tx_out.iter().rev().find(|tx_out| {
+            tx_out.pk_script.len() >= COMMITMENT_SCRIPT_SIZE
+                && tx_out.pk_script.starts_with(&WITNESS_COMMITMENT_HEADER)
+        })?;
+        let reserved_value = match coinbase.witnesses.as_slice() {
+            [witness] if witness.len() == 1 => witness[0].as_slice().try_into().ok(),
+            _ => None,
+        };
+        Some(Self {
+            commitment: output.pk_script[WITNESS_COMMITMENT_HEADER.len()..COMMITMENT_SCRIPT_SIZE]
+                .try_into()
+                .ok()?,
+            reserved_value,
+        })
+    }
+
+    /// Checks the commitment is the hash of the given witness merkle root and the reserved value, failing if
+    /// the coinbase has no valid reserved value
+    pub fn validate(&self, witness_root: &sha256::Hash) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let Some(reserved_value) = self.reserved_value else {
+            return Err(invalid("Coinbase witness isn't a 32 bytes reserved value"));
+        };
+        let committed = double_hash(&[&witness_root.to_byte_array()[..], &reserved_value].concat());
+        match committed.to_byte_array() == self.commitment {
+            true => Ok(()),
+            false => Err(invalid("Witness commitment doesn't match the transactions")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MerkleTree;
+    use crate::utility::{decode_hex, to_io_err};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_commitment_of_a_testnet_coinbase_is_valid() -> io::Result<()> {
+        // the only two transactions of a testnet block
+        let coinbase = decode_hex("020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff2303aba925044428c1644d65726d6169646572204654572101000023f5cb010000000000ffffffff02ce80250000000000160014c035e789d9efffa10aa92e93f48f29b8cfb224c20000000000000000266a24aa21a9ed8e2fa0dcf35a1c3853030613ab9fe45ff77255df36484815fd2899d8675e3d180120000000000000000000000000000000000000000000000000000000000000000000000000").map_err(to_io_err)?;
+        let coinbase = RawTransaction::from_bytes(&mut Cursor::new(&coinbase))?;
+        let payment = decode_hex("02000000000101fad25ca83a41395a00dec1a6bc20ee52ec413984358157d697fc09d53091c2e50100000017160014038e5730357e5631b6a5626df15a244ab0a7d9e8fdffffff0260b0d7c50e0000001600143c898dff9dd73d780d846a61a65a7cbfa871a81d30420500000000001600144cf6537ae378d52ab13c4fe5a0d52808dbfc75ef02473044022011fc8d6b5b350ae40b44093e4ca7aa0e19a60fb835362da365c86636df6d1e3902205278495b8c7cf237bf12561665b6858715c57e1bdb65a0525c018ee054d3960d012103cc957cab76d1677ae3547e7654096f392d3b3784acb29075830fdd72d1361a0baaa92500").map_err(to_io_err)?;
+        let payment = RawTransaction::from_bytes(&mut Cursor::new(&payment))?;
+
+        let parsed = WitnessCommitment::from_coinbase(&coinbase);
+        let commitment =
+            decode_hex("8e2fa0dcf35a1c3853030613ab9fe45ff77255df36484815fd2899d8675e3d18")
+                .map_err(to_io_err)?;
+        let expected = WitnessCommitment {
+            commitment: commitment
+                .try_into()
+                .map_err(|_| io::ErrorKind::InvalidData)?,
+            reserved_value: Some([0; 32]),
+        };
+        assert_eq!(parsed, Some(expected.clone()));
+
+        let wtxids = vec![
+            sha256::Hash::from_byte_array([0; 32]),
+            double_hash(&payment.serialize_with_witness()),
+        ];
+        let root = MerkleTree::generate_from_hashes(wtxids).get_root();
+        expected.validate(&root)?;
+        assert!(expected.validate(&double_hash(b"other")).is_err());
+
+        // a coinbase without a reserved value has a malformed commitment
+        let mut malformed = coinbase;
+        malformed.witnesses = vec![];
+        let parsed = WitnessCommitment::from_coinbase(&malformed).map(|c| c.validate(&root));
+        assert!(matches!(parsed, Some(Err(_))));
+        Ok(())
+    }
+}
@@ -1,90 +1,237 @@
+use crate::block_requests::BlockRequests;
+use crate::block_stats::BlockStats;
+use crate::chain_tip::{ChainTip, ChainTipInfo, TipStatus};
 use crate::config::Config;
+use crate::download_limiter::DownloadLimiter;
+use crate::header_sync::{range_anchors, DetachedHeaders, HeaderSync};
+use crate::ibd::InitialBlockDownload;
 use crate::interface::components::overview_panel::TransactionDisplayInfo;
-use crate::interface::{GtkMessage, ModelRequest};
-use crate::messages::block_header::HeaderSet;
+use crate::interface::{ModelRequest, NotificationKind, UiNotifier, UiSender};
+use crate::mempool::{Mempool, MempoolEntry};
+use crate::messages::block_header::{HeaderSet, HEADER_RECORD_SIZE};
 use crate::messages::constants::config::{QUIET, VERBOSE};
-use crate::messages::merkle_tree::MerkleProof;
 use crate::messages::{
-    Block, BlockHeader, GetData, GetHeader, HashId, Hashable, Headers, InvType, Inventory,
-    InventoryVector, MerkleTree, Message, Serialize,
+    Block, BlockHeader, BlockTxn, CompactBlock, GetBlockTxn, GetData, GetHeader, HashId, Hashable,
+    Headers, InvType, Inventory, InventoryVector, MerkleTree, Message, PartialBlock, Serialize,
 };
+use crate::sync_watchdog::SyncWatchdog;
 
 use crate::node_controller::NodeController;
-use crate::raw_transaction::{RawTransaction, TransactionOrigin};
-use crate::utility::{decode_hex, double_hash, reverse_hex_str, to_io_err};
-use crate::utxo::UtxoSet;
-use crate::wallet::Wallet;
+use crate::non_final_txs::NonFinalTxs;
+use crate::orphan_txs::OrphanTxs;
+use crate::raw_transaction::interpreter::TxChecker;
+use crate::raw_transaction::script::{decode_script, AddressFormat, ScriptType};
+use crate::raw_transaction::tx_input::TxInputType;
+use crate::raw_transaction::{partial::PartialTransaction, RawTransaction, TransactionOrigin};
+use crate::rpc;
+use crate::tx_events::{SentTransactions, TxConfirmations, TxConfirmed};
+use crate::tx_index::TxIndex;
+use crate::tx_requests::TxRequests;
+use crate::utility::{
+    actual_timestamp_or_default, decode_hex, double_hash, reverse_hex_str, to_io_err,
+};
+use crate::utxo::{read_utxo_snapshot, write_utxo_snapshot, UtxoSet};
+use crate::wallet::{check_recipient_networks, Wallet};
 use bitcoin_hashes::{sha256, Hash};
 use chrono::Utc;
-use gtk::glib::SyncSender;
-use std::collections::{hash_map::Entry::Occupied, hash_map::Entry::Vacant, HashMap};
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry::Occupied, hash_map::Entry::Vacant, HashMap, HashSet};
+use std::fs;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::Path;
 use std::sync::{
     mpsc::{self, Receiver},
-    Arc, RwLock, RwLockReadGuard,
+    Arc, Mutex, RwLock, RwLockReadGuard,
 };
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use crate::interface::components::send_panel::TransactionInfo;
 use crate::interface::components::table::{
     table_data_from_blocks, table_data_from_headers, table_data_from_tx, GtkTable, GtkTableData,
 };
-use crate::interface::{components::send_panel::TransactionInfo, update_ui_progress_bar};
 use crate::node::Node;
 
 pub type BlockSet = HashMap<HashId, Block>;
 
+/// Blocks closer than this to the tip are never pruned, so short reorgs and proofs of inclusion still work
+const MIN_BLOCKS_TO_KEEP: usize = 288;
+/// Blocks the tallest block can be behind the tallest header once initial block download is complete
+const IBD_MAX_BLOCKS_BEHIND: usize = 1;
+/// Compact blocks waiting for their missing transactions at once, more are left to be downloaded in full
+const MAX_PARTIAL_BLOCKS: usize = 16;
+/// Seconds a compact block waits for its missing transactions before it's dropped
+const PARTIAL_BLOCK_TIMEOUT: u64 = 60;
+/// Blocks this deep below the tip are served in full when asked for as compact blocks, like BIP152 suggests
+const MAX_COMPACT_BLOCK_DEPTH: usize = 10;
+/// Blocks older than this many seconds below the tip are historical, not served once the upload target is reached
+const HISTORICAL_BLOCK_AGE: u32 = 7 * 24 * 60 * 60;
+
 /// Structs of the network controller (main controller of the program)
 pub struct NetworkController {
     headers: HeaderSet,
     tallest_header: BlockHeader,
     tallest_block: BlockHeader,
-    valid_blocks: BlockSet,   // valid blocks downloaded so far
+    chain_tip: ChainTip, // tallest block, shared with the threads waiting for a height
+    valid_blocks: BlockSet, // valid blocks downloaded so far
     blocks_on_hold: BlockSet, // downloaded blocks for which we don't have the previous block
     pending_blocks: HashMap<HashId, Vec<HashId>>, // blocks which haven't arrived, and the blocks which come immediately after them
+    partial_blocks: HashMap<HashId, (PartialBlock, SocketAddr, Instant)>, // compact blocks waiting for the transactions requested with getblocktxn, with the peer asked and when
     utxo_set: UtxoSet,
     nodes: NodeController,
-    ui_sender: SyncSender<GtkMessage>,
+    ui_sender: UiSender,
     active_wallet: String,
     wallets: HashMap<String, Wallet>, // key is address of the wallet
-    tx_read: HashMap<HashId, ()>,
+    spendable_fee_rate: Option<u64>,  // shows the spendable balance if set
+    overview_tx_count: usize, // latest transactions of the active wallet shown on the overview
+    utxo_snapshot_height: Option<usize>, // blocks up to this height are already in the utxo set
+    header_sync: HeaderSync,
+    detached_headers: DetachedHeaders, // headers of ranges requested ahead, waiting for their parent
+    header_records: u64,               // headers saved to the headers backup file
+    download_limiter: Option<DownloadLimiter>, // holds back block requests if the download rate is capped
+    block_requests: BlockRequests, // blocks waiting for a peer with room or for the download limiter
+    tx_confirmations: TxConfirmations, // wallet transactions waiting to be included in a block
+    sent_txs: SentTransactions,    // transactions we broadcast and the peers that relayed them back
+    orphan_txs: Option<OrphanTxs>, // transactions whose parents we haven't seen, if they're held back from relay
+    non_final_txs: Option<NonFinalTxs>, // transactions whose lock time hasn't passed, if they're held
+    tx_requests: TxRequests, // transactions asked from each peer, the only ones accepted with relay off
+    tx_index: TxIndex, // where the known transactions are, also telling which were already read
+    ibd: InitialBlockDownload, // new blocks are announced and transactions relayed once it's complete
+    blocks_file_lock: Arc<Mutex<()>>, // held while appending to the blocks backup or rewriting it
 }
 
 impl NetworkController {
+    /// Creates the header set holding the genesis, limited to the configured headers in memory
+    fn new_header_set(genesis_header: BlockHeader, config: &Config) -> HeaderSet {
+        let mut headers = HeaderSet::with(genesis_header.hash, genesis_header);
+        if let Some(max) = config.get_max_headers_in_memory() {
+            headers.limit_memory(config.get_headers_file(), max, config.get_checkpoints());
+        }
+        headers
+    }
+
+    /// Creates the limiter of the block download rate, if it's capped
+    fn new_download_limiter(config: &Config) -> Option<DownloadLimiter> {
+        config
+            .get_max_download_kbps()
+            .map(|kbps| DownloadLimiter::new(kbps, Instant::now()))
+    }
+
     /// Creates a new network controller from the given sender and writer
     pub fn new(
-        ui_sender: SyncSender<GtkMessage>,
+        ui_sender: UiSender,
         writer_end: mpsc::SyncSender<(SocketAddr, Message)>,
         config: Config,
     ) -> Result<Self, io::Error> {
-        let genesis_header = BlockHeader::genesis(config.get_genesis());
-        let (active_wallet, wallets) = Wallet::init_all(&config, Some(&ui_sender))?;
+        let genesis_header = config.get_network_params().genesis_header();
+        let (active_wallet, wallets) = Self::load_wallets(&config, ui_sender.as_ref())?;
         Ok(Self {
-            headers: HeaderSet::with(genesis_header.hash, genesis_header),
+            headers: Self::new_header_set(genesis_header, &config),
             tallest_header: genesis_header,
             tallest_block: genesis_header,
+            chain_tip: ChainTip::new(genesis_header.height, genesis_header.hash),
             valid_blocks: BlockSet::new(),
             blocks_on_hold: BlockSet::new(),
             pending_blocks: HashMap::new(),
+            partial_blocks: HashMap::new(),
             utxo_set: UtxoSet::new(),
+            spendable_fee_rate: config.get_spendable_fee_rate(),
+            overview_tx_count: config.get_overview_tx_count(),
+            download_limiter: Self::new_download_limiter(&config),
+            detached_headers: DetachedHeaders::new(config.get_header_download_peers().is_some()),
+            orphan_txs: config.get_max_orphan_tx_bytes().map(OrphanTxs::new),
+            non_final_txs: config
+                .get_hold_non_final_txs()
+                .then(|| NonFinalTxs::new(config.get_max_non_final_tx_bytes())),
+            tx_requests: TxRequests::default(),
+            block_requests: BlockRequests::new(config.get_max_blocks_in_flight_per_peer()),
             nodes: NodeController::connect_to_peers(writer_end, ui_sender.clone(), config)?,
             active_wallet,
             wallets,
             ui_sender,
-            tx_read: HashMap::new(),
+            utxo_snapshot_height: None,
+            header_sync: HeaderSync::default(),
+            header_records: 0,
+            tx_confirmations: TxConfirmations::default(),
+            sent_txs: SentTransactions::default(),
+            tx_index: TxIndex::default(),
+            ibd: InitialBlockDownload::new(IBD_MAX_BLOCKS_BEHIND),
+            blocks_file_lock: Arc::new(Mutex::new(())),
         })
     }
 
-    fn update_ui_poi_result(&self, proof: MerkleProof, root_from_proof: sha256::Hash) {
-        let root_from_proof_str = format!("{:?}", root_from_proof);
+    /// Loads the wallets and the watch-only ones
+    fn load_wallets(
+        config: &Config,
+        ui_sender: &dyn UiNotifier,
+    ) -> io::Result<(String, HashMap<String, Wallet>)> {
+        let (active_wallet, mut wallets) = Wallet::init_all(config, Some(ui_sender))?;
+        Self::load_watch_addresses(config, ui_sender, &mut wallets);
+        Ok((active_wallet, wallets))
+    }
 
-        let result_str = format!(
-            "{:?}\n\nMerkle root generated from poi: {:?}",
-            proof,
-            &reverse_hex_str(&root_from_proof_str)[..root_from_proof_str.len() - 2]
-        );
+    /// Adds a watch-only wallet for every valid address in the configured watch addresses file
+    fn load_watch_addresses(
+        config: &Config,
+        ui_sender: &dyn UiNotifier,
+        wallets: &mut HashMap<String, Wallet>,
+    ) {
+        let path = config.get_watch_addresses_file();
+        if path.is_empty() {
+            return;
+        }
+        let (watched, invalid) = match Wallet::watch_only_from_file(path) {
+            Ok(result) => result,
+            // nothing was imported yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                config.log(
+                    &format!("Could not read watch addresses file: {}", e),
+                    QUIET,
+                );
+                return;
+            }
+        };
+        for address in invalid {
+            config.log(
+                &format!("Skipping invalid watch address: {}", address),
+                QUIET,
+            );
+        }
+        for wallet in watched {
+            if let Vacant(entry) = wallets.entry(wallet.address.clone()) {
+                Wallet::update_ui_wallet_entry(ui_sender, &wallet.address, false);
+                entry.insert(wallet);
+            }
+        }
+    }
 
-        _ = self.ui_sender.send(GtkMessage::UpdatePoiResult(result_str));
+    /// Sends the proofs of inclusion of the comma separated transactions in the block to the UI
+    fn update_ui_poi_result(&self, block: &Block, tx_hashes: &str) -> io::Result<()> {
+        let merkle_tree = MerkleTree::generate_from_hashes(block.hash_transactions());
+        let proofs = match merkle_tree.generate_proofs(&parse_tx_hashes(tx_hashes)?) {
+            Ok(proofs) => proofs,
+            Err(_) => {
+                return self.notify_ui_message(
+                    NotificationKind::Error,
+                    "Transaction not found",
+                    "Transaction not found in block.",
+                )
+            }
+        };
+        let mut results = vec![];
+        for proof in proofs {
+            let root_from_proof_str = format!("{:?}", proof.generate_merkle_root());
+            results.push(format!(
+                "{:?}\n\nMerkle root generated from poi: {:?}",
+                proof,
+                &reverse_hex_str(&root_from_proof_str)[..root_from_proof_str.len() - 2]
+            ));
+        }
+        _ = self.ui_sender.update_poi_result(results.join("\n\n"));
+        Ok(())
     }
 
     fn handle_getheaders_message(&self, getheaders_message: GetHeader) -> Option<Headers> {
@@ -94,14 +241,11 @@ impl NetworkController {
         };
 
         let max_blocks = 2000;
-        let mut next_block_header = match self.headers.get_next_header(&last_known_hash) {
-            Some(header) => *header,
-            None => return None,
-        };
+        let mut next_block_header = self.headers.get_next_header(&last_known_hash)?;
         let mut headers: Vec<BlockHeader> = vec![next_block_header];
         for _ in 1..max_blocks {
             next_block_header = match self.headers.get_next_header(&next_block_header.hash) {
-                Some(header) => *header,
+                Some(header) => header,
                 None => break,
             };
             headers.push(next_block_header);
@@ -113,46 +257,64 @@ impl NetworkController {
     }
 
     fn update_ui_progress(&self, msg: Option<&str>, progress: f64) {
-        _ = update_ui_progress_bar(&self.ui_sender, msg, progress);
+        _ = self.ui_sender.update_progress(msg, progress);
     }
 
     fn update_ui_table(&self, table: GtkTable, data: GtkTableData) -> io::Result<()> {
-        self.ui_sender
-            .send(GtkMessage::UpdateTable((table, data)))
-            .map_err(to_io_err)?;
-
-        Ok(())
+        self.ui_sender.update_table(table, data)
     }
 
     fn update_ui_balance(&self) -> io::Result<()> {
-        let (balance, pending) = self.read_active_wallet_balance()?;
+        let (balance, pending, immature) = self.read_active_wallet_balance()?;
+        let spendable = self.spendable_fee_rate.map(|fee_rate| {
+            self.active_wallet_addresses()
+                .into_iter()
+                .map(|address| {
+                    self.utxo_set
+                        .get_wallet_spendable_balance(address, fee_rate)
+                })
+                .sum()
+        });
         self.ui_sender
-            .send(GtkMessage::UpdateBalance((balance, pending)))
-            .map_err(to_io_err)
+            .update_balance((balance, pending, immature), spendable)
     }
 
-    fn notify_ui_message(&self, t: gtk::MessageType, title: &str, msg: &str) -> io::Result<()> {
-        self.ui_sender
-            .send(GtkMessage::CreateNotification((
-                t,
-                title.to_string(),
-                msg.to_string(),
-            )))
-            .map_err(to_io_err)
+    fn notify_ui_message(&self, kind: NotificationKind, title: &str, msg: &str) -> io::Result<()> {
+        self.ui_sender.notify(kind, title, msg)
+    }
+
+    /// Returns the latest transactions of the active wallet shown on the overview, None if there's no active wallet
+    fn overview_transactions(&self) -> Option<Vec<TransactionDisplayInfo>> {
+        let wallet = self.wallets.get(&self.active_wallet)?;
+        Some(wallet.get_last_n_transactions(self.overview_tx_count))
+    }
+
+    /// Returns the addresses whose coins the active wallet holds
+    fn active_wallet_addresses(&self) -> Vec<&str> {
+        match self.wallets.get(&self.active_wallet) {
+            Some(wallet) => wallet.addresses(),
+            None => vec![self.active_wallet.as_str()],
+        }
     }
 
-    fn read_active_wallet_balance(&self) -> io::Result<(u64, u64)> {
-        let balance = self.utxo_set.get_wallet_balance(&self.active_wallet);
-        let pending_balance = self
-            .utxo_set
-            .get_pending_wallet_balance(&self.active_wallet);
+    fn read_active_wallet_balance(&self) -> io::Result<(u64, u64, u64)> {
+        let addresses = self.active_wallet_addresses();
+        let sum = |balance_of: fn(&UtxoSet, &str) -> u64| {
+            addresses
+                .iter()
+                .map(|address| balance_of(&self.utxo_set, address))
+                .sum()
+        };
+        let balance = sum(UtxoSet::get_wallet_balance);
+        let pending_balance = sum(UtxoSet::get_pending_wallet_balance);
+        let immature_balance = sum(UtxoSet::get_immature_wallet_balance);
 
-        Ok((balance, pending_balance))
+        Ok((balance, pending_balance, immature_balance))
     }
 
-    fn get_best_headers(&self, amount: usize) -> Vec<&BlockHeader> {
+    fn get_best_headers(&self, amount: usize) -> Vec<BlockHeader> {
         let mut best_headers = vec![];
-        let mut current_header = &self.tallest_header;
+        let mut current_header = self.tallest_header;
         for _ in 0..amount {
             best_headers.push(current_header);
             current_header = match self.headers.get(&current_header.prev_block_hash) {
@@ -194,33 +356,135 @@ impl NetworkController {
         }
     }
 
-    fn _add_to_valid_blocks(&mut self, mut block: Block, config: &Config) {
-        _ = block.expand_utxo(
-            &mut self.utxo_set,
-            Some(&self.ui_sender),
-            &mut self.wallets,
-            Some(&self.active_wallet),
-        );
-
-        _ = self.update_ui_balance();
-
+    /// Connects the block to the chain, returns false if it was rejected
+    fn _add_to_valid_blocks(&mut self, mut block: Block, config: &Config) -> bool {
         // get real height of the block
         block.header.height = match self.valid_blocks.get(&block.header.prev_block_hash) {
             Some(prev_block) => prev_block.header.height + 1,
             _ => 0, // this will never happen
         };
+        if let Err(e) = self.validate_connection(&block, config) {
+            config.log(&format!("Rejected block {}: {}", block.hash(), e), QUIET);
+            self.headers.mark_invalid(block.hash());
+            return false;
+        }
+
+        self.expand_utxo(&block);
+        _ = self.update_ui_balance();
 
         // update progress bar
         let pseudo_genesis_timestamp = config.get_start_timestamp();
         let progress = (block.header.timestamp - pseudo_genesis_timestamp) as f64
             / (Utc::now().timestamp() - pseudo_genesis_timestamp as i64) as f64;
         let msg = format!("Received block {}", block.header.height);
-        _ = update_ui_progress_bar(&self.ui_sender, Some(&msg), progress);
+        _ = self.ui_sender.update_progress(Some(&msg), progress);
 
         if block.header.height > self.tallest_block.height {
-            self.tallest_block = block.header;
+            self.tip_changed(&block, config);
         }
+        self.tx_confirmations.block_connected(&block);
+        self.sent_txs.block_connected(&block);
         self.valid_blocks.insert(block.hash(), block);
+        true
+    }
+
+    /// Checks the consensus rules that depend on where the block connects: the height in its coinbase and,
+    /// with full validation, the outputs it spends
+    fn validate_connection(&self, block: &Block, config: &Config) -> io::Result<()> {
+        let network_params = config.get_network_params();
+        block.validate_coinbase_height(block.header.height, network_params)?;
+        if config.get_full_validation() {
+            let confirmation_height = |txid: &HashId| {
+                let block_hash = self.tx_index.block_hash(txid)?;
+                self.headers.get(&block_hash).map(|header| header.height)
+            };
+            block.validate_spends(&self.utxo_set, confirmation_height, network_params)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the block the tallest one, moving the txid index to its chain. If stale blocks are dropped,
+    /// they're looked for every `stale_block_depth` blocks, as it takes going over every block in memory.
+    fn tip_changed(&mut self, block: &Block, config: &Config) {
+        self.tx_index
+            .tip_changed(&self.valid_blocks, self.tallest_block.hash(), block);
+        if let Some(non_final_txs) = &mut self.non_final_txs {
+            for tx in &block.txns {
+                non_final_txs.remove(&tx.txid());
+            }
+        }
+        self.tallest_block = block.header;
+        _ = self
+            .chain_tip
+            .block_connected(block.header.height, block.hash());
+        self.check_ibd_complete(config);
+        if let Some(depth) = config.get_stale_block_depth() {
+            if block.header.height % depth == 0 {
+                self.drop_stale_blocks(depth, config);
+            }
+        }
+    }
+
+    /// Drops the blocks off the best chain buried `depth` blocks below the tip from memory,
+    /// they're downloaded again if a reorg needs them
+    fn drop_stale_blocks(&mut self, depth: usize, config: &Config) {
+        let stale = stale_blocks(&self.valid_blocks, &self.tallest_block, depth);
+        if stale.is_empty() {
+            return;
+        }
+        self.valid_blocks.retain(|hash, _| !stale.contains(hash));
+        config.log(
+            &format!("Dropped {} stale blocks from memory", stale.len()),
+            VERBOSE,
+        );
+    }
+
+    fn expand_utxo(&mut self, block: &Block) {
+        let txids = block.txns.iter().map(RawTransaction::txid);
+        self.utxo_set.block_connected(txids, block.header.height);
+        match self.utxo_snapshot_height {
+            Some(height) if block.header.height <= height => {
+                _ = block.update_wallets_history(&mut self.utxo_set, &mut self.wallets);
+            }
+            _ => {
+                _ = block.expand_utxo(
+                    &mut self.utxo_set,
+                    Some(self.ui_sender.as_ref()),
+                    &mut self.wallets,
+                    Some(&self.active_wallet),
+                );
+            }
+        }
+    }
+
+    /// Restores the utxo set from the last snapshot if its block is on the best header chain,
+    /// so the backup blocks up to it only need to update the wallets history. Full validation rebuilds the utxo set
+    /// from the blocks instead.
+    fn read_utxo_snapshot(&mut self, config: &Config) {
+        if config.get_full_validation() {
+            return;
+        }
+        let (tip, utxo_set) = match read_utxo_snapshot(config.get_utxo_snapshot_file()) {
+            Ok(snapshot) => snapshot,
+            Err(..) => return,
+        };
+        let height = match self.headers.get(&tip) {
+            Some(header) => header.height,
+            None => return,
+        };
+        let best_chain = self
+            .headers
+            .best_chain_range(&self.tallest_header, height..height + 1);
+        if best_chain.first().map(|header| header.hash()) != Some(tip) {
+            config.log("Ignoring utxo snapshot of a stale block", QUIET);
+            return;
+        }
+        self.utxo_set = utxo_set;
+        self.utxo_snapshot_height = Some(height);
+        config.log(
+            &format!("Restored utxo snapshot at block {}", height),
+            QUIET,
+        );
     }
 
     fn add_to_valid_blocks(&mut self, block_id: HashId, config: &Config) {
@@ -228,7 +492,9 @@ impl NetworkController {
         let mut blocks_not_on_hold: Vec<HashId> = vec![block_id];
         while let Some(block_id) = blocks_not_on_hold.pop() {
             if let Some(block) = self.blocks_on_hold.remove(&block_id) {
-                self._add_to_valid_blocks(block, config);
+                if !self._add_to_valid_blocks(block, config) {
+                    continue;
+                }
                 if let Some(mut unblocked_blocks) = self.pending_blocks.remove(&block_id) {
                     blocks_not_on_hold.append(&mut unblocked_blocks);
                 }
@@ -236,6 +502,85 @@ impl NetworkController {
         }
     }
 
+    /// Connects a block received from `source` if its parent is valid, holding it otherwise.
+    /// Once synced, a block that becomes the new tip is announced to the other peers.
+    fn accept_block(
+        &mut self,
+        mut block: Block,
+        source: SocketAddr,
+        config: &Config,
+    ) -> io::Result<()> {
+        block.header.height = match self.valid_blocks.get(&block.header.prev_block_hash) {
+            Some(previous_block) => previous_block.header.height + 1,
+            None => {
+                self.put_block_on_hold(block);
+                return Ok(());
+            }
+        };
+        if !self.headers.contains_key(&block.hash()) {
+            self.headers.insert(block.hash(), block.header);
+            if block.header.height > self.tallest_header.height {
+                self.tallest_header = block.header;
+                self.update_best_header_chain();
+            }
+        }
+        let (block_hash, tip) = (block.hash(), self.tallest_block.hash());
+        // add to on-hold and then validate as many on-hold blocks as possible
+        self.blocks_on_hold.insert(block_hash, block);
+        self.add_to_valid_blocks(block_hash, config);
+        if self.tallest_block.hash() != tip {
+            self.tip_advanced(source, config)?;
+        } else {
+            self.log_chain_tips(config);
+        }
+        Ok(())
+    }
+
+    /// Logs the known chain tips, after a block that didn't advance the tip shows there's a fork. Finding them
+    /// walks every branch, so it's only done when verbose logs are written.
+    fn log_chain_tips(&self, config: &Config) {
+        if !config.is_verbose() {
+            return;
+        }
+        for tip in self.get_chain_tips() {
+            config.log(&format!("Chain tip: {}", tip), VERBOSE);
+        }
+    }
+
+    /// Relays the held transactions the new tip made final and, once synced, announces the tip to the peers
+    /// other than `source`
+    fn tip_advanced(&mut self, source: SocketAddr, config: &Config) -> io::Result<()> {
+        let height = self.tallest_block.height + 1;
+        let time = self.headers.median_time_past(&self.tallest_block);
+        let now_final = self
+            .non_final_txs
+            .as_mut()
+            .map(|non_final_txs| non_final_txs.take_final(height, time));
+        for (tx, tx_source) in now_final.unwrap_or_default() {
+            config.log(
+                &format!("Held transaction {} is now final", tx.txid()),
+                VERBOSE,
+            );
+            self.accept_transaction(tx, tx_source, config)?;
+        }
+        if let Some(stats) = self.block_stats(&self.tallest_block.hash(), config) {
+            config.log(&format!("Block stats: {}", stats), VERBOSE);
+        }
+        if self.ibd.is_complete() {
+            let compact_block = self
+                .valid_blocks
+                .get(&self.tallest_block.hash())
+                .map(|block| CompactBlock::from_block(block, rand::random()));
+            self.nodes.announce_block(
+                self.tallest_block,
+                compact_block.as_ref(),
+                source,
+                config,
+            )?;
+        }
+        Ok(())
+    }
+
     fn put_block_on_hold(&mut self, block: Block) {
         // add to pending blocks the previous block, mark this block as blocked by the previous one
         match self.pending_blocks.entry(block.header.prev_block_hash) {
@@ -248,15 +593,83 @@ impl NetworkController {
     }
 
     fn request_blocks_evenly(&mut self, headers: &mut Headers, config: &Config) -> io::Result<()> {
-        let chunks = headers.block_headers.chunks(20); // request 20 blocks at a time
-        for chunk in chunks {
-            let get_data = GetData::from_inv(chunk.to_vec());
-            self.nodes.send_to_all(&get_data.serialize()?, config)?;
+        self.block_requests.queue(&headers.block_headers);
+        self.send_queued_block_requests(config)
+    }
+
+    /// Requests the queued blocks from the sync peers with room for them, 20 blocks per request at most, stopping
+    /// once every peer is at its limit or the download limiter holds the requests back. The blocks asked from
+    /// peers that disconnected, that didn't deliver them in time or that the request couldn't be sent to are
+    /// requested again first.
+    fn send_queued_block_requests(&mut self, config: &Config) -> io::Result<()> {
+        let mut peers = self.nodes.sync_peers(config);
+        self.block_requests.requeue_gone_peers(&peers);
+        if let Some(timeout) = config.get_block_request_timeout() {
+            let timeout = Duration::from_secs(timeout);
+            for peer in self
+                .block_requests
+                .requeue_timed_out(timeout, Instant::now())
+            {
+                config.log(
+                    &format!(
+                        "Peer {} is slow to deliver blocks, requesting them again",
+                        peer
+                    ),
+                    VERBOSE,
+                );
+            }
+        }
+        let mut sent = false;
+        loop {
+            let downloaded = self.nodes.bytes_received();
+            if let Some(limiter) = &mut self.download_limiter {
+                if !limiter.allows_request(downloaded, Instant::now()) {
+                    break;
+                }
+            }
+            let Some((peer, headers)) =
+                self.block_requests.next_request(&peers, 20, Instant::now())
+            else {
+                break;
+            };
+            let get_data = GetData::from_inv(headers, self.block_request_type(&peer, config));
+            let serialized = get_data.serialize()?;
+            if self
+                .nodes
+                .send_to_specific(&peer, &serialized, config)
+                .is_err()
+            {
+                self.block_requests.requeue_peer(&peer);
+                peers.retain(|sync_peer| *sync_peer != peer);
+                continue;
+            }
+            sent = true;
+        }
+        if sent {
+            config.log("Requesting blocks, sent GetData message.", VERBOSE);
         }
-        config.log("Requesting blocks, sent GetData message.", VERBOSE);
         Ok(())
     }
 
+    /// Returns the type blocks are requested from the peer as: compact blocks once synced, if the peer supports
+    /// them and blocks aren't downloaded with their witnesses, since those would be reconstructed stripped
+    fn block_request_type(&self, peer: &SocketAddr, config: &Config) -> InvType {
+        match block_inv_type(config) {
+            InvType::MSGBlock
+                if self.ibd.is_complete() && self.nodes.supports_compact_blocks(peer) =>
+            {
+                InvType::MSGCompactBlock
+            }
+            inv_type => inv_type,
+        }
+    }
+
+    /// Records the block arrived, requesting the queued blocks the room it leaves at its peer allows
+    fn block_delivered(&mut self, hash: &HashId, config: &Config) -> io::Result<()> {
+        self.block_requests.delivered(hash);
+        self.send_queued_block_requests(config)
+    }
+
     /// requests block for headers after given timestamp
     fn request_blocks(&mut self, mut headers: Headers, config: &Config) -> io::Result<()> {
         if headers.count == 0 {
@@ -266,6 +679,29 @@ impl NetworkController {
         self.request_blocks_evenly(&mut headers, config)
     }
 
+    /// Requests the blocks of the best chain at the given heights which haven't been downloaded yet,
+    /// to fill gaps left by blocks that never arrived
+    pub fn request_missing_blocks(
+        &mut self,
+        heights: Range<usize>,
+        config: &Config,
+    ) -> io::Result<()> {
+        let missing_blocks: Vec<BlockHeader> = self
+            .headers
+            .best_chain_range(&self.tallest_header, heights)
+            .into_iter()
+            .filter(|header| {
+                !self.valid_blocks.contains_key(&header.hash())
+                    && !self.blocks_on_hold.contains_key(&header.hash())
+            })
+            .collect();
+        config.log(
+            &format!("Requesting {} missing blocks", missing_blocks.len()),
+            VERBOSE,
+        );
+        self.request_blocks(Headers::new(missing_blocks.len(), missing_blocks), config)
+    }
+
     fn get_downloadable_bck_headers(&mut self, headers: Headers) -> Headers {
         // since every block needs to come after a valid block, create a "pseudo genesis" validated block
         if headers.block_headers.is_empty() {
@@ -277,24 +713,39 @@ impl NetworkController {
         if let Some(previous_header) = self.headers.get(&first_downloadable_header.prev_block_hash)
         {
             // this never fails
-            let pseudo_genesis_block = Block::new(*previous_header, 0, vec![]);
+            let pseudo_genesis_block = Block::new(previous_header, 0, vec![]);
             self.valid_blocks
                 .insert(pseudo_genesis_block.hash(), pseudo_genesis_block);
         }
         headers
     }
 
+    /// Sets the height of the header from its parent header.
+    /// Returns false if the parent is unknown or the header doesn't match the checkpoint at its height.
+    fn connect_header(&self, header: &mut BlockHeader, config: &Config) -> bool {
+        match self.headers.get(&header.prev_block_hash) {
+            Some(parent_header) => header.height = parent_header.height + 1,
+            None => return false, // ignore header if prev_header is unknown
+        }
+        if !header.matches_checkpoint(config.get_checkpoints()) {
+            config.log(
+                &format!("Rejected header {} not matching checkpoint", header.hash),
+                QUIET,
+            );
+            return false;
+        }
+        true
+    }
+
     fn read_backup_headers(&mut self, mut headers: Headers, config: &Config) -> Headers {
         // save new headers to hashmap and backup file
         let mut new_headers = vec![];
-        for mut header in headers.block_headers {
-            match self.headers.get(&header.prev_block_hash) {
-                Some(parent_header) => {
-                    header.height = parent_header.height + 1;
-                }
-                None => continue, // ignore header if prev_header is unknown
+        self.header_records = headers.block_headers.len() as u64;
+        for (position, mut header) in headers.block_headers.into_iter().enumerate() {
+            if !self.connect_header(&mut header, config) {
+                continue;
             }
-            self.headers.insert(header.hash(), header);
+            self.headers.insert_saved(header, position as u64);
             //self.set_next_block_hash_for_blockheaders();
             new_headers.push(header);
             if header.height > self.tallest_header.height {
@@ -332,94 +783,756 @@ impl NetworkController {
         if let Some(previous_header) = self.headers.get(&first_downloadable_header.prev_block_hash)
         {
             if let Vacant(entry) = self.valid_blocks.entry(previous_header.hash()) {
-                let pseudo_genesis_block = Block::new(*previous_header, 0, vec![]);
+                let pseudo_genesis_block = Block::new(previous_header, 0, vec![]);
                 entry.insert(pseudo_genesis_block);
             }
         }
         self.request_blocks(headers, config)
     }
 
+    /// Saves a connected header to the header set and backup file
+    fn store_header(&mut self, header: BlockHeader, config: &Config) -> io::Result<()> {
+        header.save_to_file(config.get_headers_file())?;
+        self.headers.insert_saved(header, self.header_records);
+        self.header_records += 1;
+        if header.height > self.tallest_header.height {
+            self.tallest_header = header
+        }
+        Ok(())
+    }
+
+    /// Records that the peer sent headers, so its header sync isn't considered stalled.
+    /// A truncated message has the rest re-requested by the listener, so sync isn't caught up yet.
+    fn headers_received(&mut self, peer: SocketAddr, headers: &Headers) {
+        let more_expected = headers.is_paginated() || headers.is_truncated();
+        self.header_sync
+            .received(peer, more_expected, Instant::now());
+    }
+
+    /// Requests headers from the first sync peer that can be reached, which is tracked to detect stalls,
+    /// and the ranges ahead from the following ones if headers are downloaded in parallel
     fn request_headers(&mut self, header_hash: HashId, config: &Config) -> io::Result<()> {
+        let peers = self.nodes.sync_peers(config);
+        for (i, peer) in peers.iter().enumerate() {
+            if self
+                .request_headers_from(*peer, header_hash, config)
+                .is_ok()
+            {
+                return self.request_header_ranges(&peers[i + 1..], config);
+            }
+        }
+        let message = match config.get_trusted_peer() {
+            Some(trusted_peer) => format!("Trusted peer {} disconnected", trusted_peer),
+            None => "No peer to request headers from".to_string(),
+        };
+        Err(io::Error::new(io::ErrorKind::NotConnected, message))
+    }
+
+    fn request_headers_from(
+        &mut self,
+        peer: SocketAddr,
+        header_hash: HashId,
+        config: &Config,
+    ) -> io::Result<()> {
         let getheader_message = GetHeader::from_last_header(header_hash);
         self.nodes
-            .send_to_all(&getheader_message.serialize()?, config)?;
+            .send_to_specific(&peer, &getheader_message.serialize()?, config)?;
+        self.header_sync.requested(peer, Instant::now());
         Ok(())
     }
 
-    fn read_pending_tx(&mut self, transaction: RawTransaction) -> io::Result<()> {
-        let tx_hash: HashId = transaction.get_hash();
-        if self.tx_read.contains_key(&tx_hash) {
+    /// Requests from each of the given peers the headers after a checkpoint ahead of the tallest header
+    fn request_header_ranges(&mut self, peers: &[SocketAddr], config: &Config) -> io::Result<()> {
+        let parallel_peers = match config.get_header_download_peers() {
+            Some(parallel_peers) => parallel_peers,
+            None => return Ok(()),
+        };
+        let anchors = range_anchors(
+            config.get_checkpoints(),
+            self.tallest_header.height,
+            parallel_peers,
+        );
+        for (peer, anchor) in peers.iter().zip(anchors) {
+            let getheader_message = GetHeader::from_last_header(anchor).serialize()?;
+            if self
+                .nodes
+                .send_to_specific(peer, &getheader_message, config)
+                .is_ok()
+            {
+                config.log(
+                    &format!(
+                        "Requesting headers after checkpoint {} from {}",
+                        anchor, peer
+                    ),
+                    VERBOSE,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the headers ready to be connected after receiving the given one, holding it if it
+    /// belongs to a range ahead of the chain
+    fn arrange_header(&mut self, header: BlockHeader) -> Vec<BlockHeader> {
+        let headers = &self.headers;
+        self.detached_headers
+            .arrange(header, |hash| headers.contains_key(hash))
+    }
+
+    /// Requests the headers after the tallest one from the sync peer once a range held ahead connected,
+    /// so it skips the headers of the range instead of downloading them again
+    fn continue_header_sync(&mut self, config: &Config) -> io::Result<()> {
+        match self.header_sync.peer() {
+            Some(peer) => self.request_headers_from(peer, self.tallest_header.hash(), config),
+            None => Ok(()),
+        }
+    }
+
+    /// Re-requests headers from another peer if the sync peer sent none within the timeout
+    fn retry_stalled_headers(&mut self, timeout: Duration, config: &Config) -> io::Result<()> {
+        let peers = self.nodes.sync_peers(config);
+        if let Some(peer) = self.header_sync.retry_peer(&peers, timeout, Instant::now()) {
+            config.log(
+                &format!("Header sync stalled, requesting headers from {}", peer),
+                QUIET,
+            );
+            self.request_headers_from(peer, self.tallest_header.hash(), config)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the tallest header and block heights, and whether the node is still syncing: the blocks are behind
+    /// the headers. An old tallest header alone isn't syncing, as idle regtest and signet chains stop growing.
+    fn sync_progress(&self) -> ((usize, usize), bool) {
+        let heights = (self.tallest_header.height, self.tallest_block.height);
+        (heights, heights.1 < heights.0)
+    }
+
+    /// Returns true if the tallest header is at most a day old, so it's likely the tip of the network
+    fn is_tallest_header_recent(&self) -> bool {
+        Utc::now().timestamp() - self.tallest_header.timestamp as i64 <= 24 * 60 * 60
+    }
+
+    /// Tells the user once the blocks catch up to the headers, from then on new blocks are announced
+    /// and transactions relayed, and the sync peers are asked to send new blocks as compact blocks
+    fn check_ibd_complete(&mut self, config: &Config) {
+        let heights = (self.tallest_header.height, self.tallest_block.height);
+        if !self
+            .ibd
+            .tip_changed(heights, self.is_tallest_header_recent())
+        {
+            return;
+        }
+        let msg = format!("Blocks caught up to the network at height {}.", heights.1);
+        config.log(&format!("Initial block download complete: {}", msg), QUIET);
+        let sync_peers = self.nodes.sync_peers(config);
+        self.nodes
+            .request_compact_block_announcements(&sync_peers, config);
+        _ = self.ui_sender.update_progress(Some("Synced."), 1.0);
+        _ = self.notify_ui_message(NotificationKind::Info, "Sync complete", &msg);
+    }
+
+    /// Tells the user sync stalled, instead of leaving the progress bar frozen
+    fn report_sync_stall(&self, timeout: u64, config: &Config) {
+        let (heights, _) = self.sync_progress();
+        let msg = format!(
+            "No new headers or blocks in {} seconds (headers at height {}, blocks at height {}).",
+            timeout, heights.0, heights.1
+        );
+        config.log(&format!("Sync stalled: {}", msg), QUIET);
+        _ = self.notify_ui_message(NotificationKind::Error, "Sync stalled", &msg);
+    }
+
+    /// Adds the newly connected peers and requests the headers and blocks still missing from them
+    fn resume_sync(
+        &mut self,
+        new_nodes: Vec<(SocketAddr, Node)>,
+        config: &Config,
+    ) -> io::Result<()> {
+        config.log(
+            &format!("Connected to {} new peers", new_nodes.len()),
+            QUIET,
+        );
+        for (_, node) in new_nodes {
+            self.nodes.add_node(node);
+        }
+        let heights = self.tallest_block.height + 1..self.tallest_header.height + 1;
+        self.request_missing_blocks(heights, config)?;
+        self.request_headers(self.tallest_header.hash(), config)
+    }
+
+    /// Returns the pending transactions to save to the mempool file
+    fn mempool(&self) -> Mempool {
+        let now = actual_timestamp_or_default() as u32;
+        let entries = self
+            .tx_index
+            .pending()
+            .map(|tx| {
+                let txid = tx.txid();
+                MempoolEntry {
+                    tx: tx.clone(),
+                    received_at: self.tx_index.received_at(&txid).unwrap_or(now),
+                    sent: self.sent_txs.is_unconfirmed(&txid),
+                }
+            })
+            .collect();
+        Mempool::new(entries)
+    }
+
+    /// Reads back the pending transactions saved to the mempool file as if just received, rebroadcasting ours
+    fn read_mempool(&mut self, config: &Config) -> io::Result<()> {
+        let Some(file_name) = config.get_mempool_file() else {
+            return Ok(());
+        };
+        let now = actual_timestamp_or_default() as u32;
+        let mut unread = Mempool::from_file(file_name, config.get_mempool_expiry(), now)?.entries;
+        let saved = unread.len();
+        // children may be saved before their parents, so the entries left out are retried while others are read
+        loop {
+            let retried = unread.len();
+            let mut left_out = vec![];
+            for entry in unread {
+                if !self.read_mempool_entry(&entry, config)? {
+                    left_out.push(entry);
+                }
+            }
+            unread = left_out;
+            if unread.len() == retried {
+                break;
+            }
+        }
+        config.log(
+            &format!(
+                "Read {} pending transactions from {}, left out {}",
+                saved - unread.len(),
+                file_name,
+                unread.len()
+            ),
+            VERBOSE,
+        );
+        Ok(())
+    }
+
+    /// Reads back a saved pending transaction if it's still valid against the outputs it spends, rebroadcasting
+    /// it if it's ours. Returns false if it was left out.
+    fn read_mempool_entry(&mut self, entry: &MempoolEntry, config: &Config) -> io::Result<bool> {
+        let txid = entry.tx.txid();
+        if entry.tx.check_no_duplicate_inputs().is_err()
+            || self.check_spends(&entry.tx, config).is_err()
+            || !self.read_pending_tx(entry.tx.clone())?
+        {
+            return Ok(false);
+        }
+        self.tx_index.set_received_at(txid, entry.received_at);
+        if entry.sent {
+            self.sent_txs.sent(txid, Instant::now());
+            self.nodes
+                .send_to_tx_relay_peers(&entry.tx.build_message()?, config)?;
+        }
+        Ok(true)
+    }
+
+    /// Checks the transaction could be included in the next block: it spends mature outputs, confirmed or of
+    /// pending transactions, unlocking them and paying out at most their value. The utxo set only holds the
+    /// outputs since the start timestamp unless it's 0, so the check is skipped otherwise.
+    fn check_spends(&self, tx: &RawTransaction, config: &Config) -> io::Result<()> {
+        if config.get_start_timestamp() > 0 {
             return Ok(());
         }
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let checker = TxChecker::new(tx)?;
+        let soft_forks = config
+            .get_network_params()
+            .soft_forks(self.tallest_block.height + 1);
+        for (index, input) in checker.inputs.iter().enumerate() {
+            let outpoint = (input.previous_output.hash, input.previous_output.index);
+            if self.utxo_set.is_immature_coinbase(&outpoint) {
+                return Err(invalid("Spends an immature coinbase output"));
+            }
+            let prevout = self.tx_index.resolve_prevout(&outpoint, &self.utxo_set);
+            let prevout = prevout.ok_or_else(|| invalid("Spends an unknown or spent output"))?;
+            checker.verify_input(index, prevout, soft_forks)?;
+        }
+        match tx.fee(|outpoint| {
+            self.tx_index
+                .resolve_prevout(outpoint, &self.utxo_set)
+                .map(|(value, _)| value)
+        }) {
+            Some(_fee) => Ok(()),
+            None => Err(invalid("Pays out more than its inputs")),
+        }
+    }
+
+    /// Stores a pending transaction, returns false if it was already known
+    fn read_pending_tx(&mut self, transaction: RawTransaction) -> io::Result<bool> {
+        let tx_hash: HashId = transaction.get_hash();
+        if self.tx_index.contains(&tx_hash) {
+            return Ok(false);
+        }
 
         transaction.generate_utxo(
             &mut self.utxo_set,
             TransactionOrigin::Pending,
-            Some(&self.ui_sender),
+            Some(self.ui_sender.as_ref()),
             Some(&self.active_wallet),
         )?;
 
         let data = table_data_from_tx(&transaction);
         self.update_ui_table(GtkTable::Transactions, data)?;
 
-        // have to check for each wallet separately
-        for w in self.wallets.iter_mut() {
-            let (address, wallet) = w;
-            if transaction.address_is_involved(address) {
-                let tx_info = transaction.transaction_info_for_pending(
+        self.tx_index.add_pending(transaction.clone());
+        let involved = transaction.involved_addresses(self.utxo_set.address_format());
+        if !self
+            .wallets
+            .values()
+            .any(|wallet| wallet.is_involved(&involved))
+        {
+            return Ok(true);
+        }
+
+        self.tx_confirmations.track(tx_hash);
+        for wallet in self
+            .wallets
+            .values_mut()
+            .filter(|wallet| wallet.is_involved(&involved))
+        {
+            let tx_info = wallet.transaction_info(|address| {
+                transaction.transaction_info_for_pending(
                     address,
                     Utc::now().timestamp() as u32,
                     &mut self.utxo_set,
-                );
-                wallet.update_history(tx_info);
-            }
+                )
+            });
+            wallet.update_history(tx_info);
         }
 
-        self.tx_read.insert(tx_hash, ());
-        Ok(())
+        Ok(true)
     }
 
-    /// Generates a transaction and broadcasts it to all peers given the transaction details
-    pub fn generate_transaction(
+    /// Returns false if the transaction can't be included in the next block yet, holding it until it can
+    /// or rejecting it if non-final transactions aren't held
+    fn is_final_or_hold(
         &mut self,
-        details: TransactionInfo,
+        tx: &RawTransaction,
+        source: SocketAddr,
         config: &Config,
-    ) -> io::Result<()> {
-        let wallet = match self.wallets.get_mut(&self.active_wallet) {
-            Some(w) => w,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "Wallet not found")),
+    ) -> bool {
+        let height = self.tallest_block.height + 1;
+        let time = self.headers.median_time_past(&self.tallest_block);
+        if tx.is_final(height, time) {
+            return true;
+        }
+        let action = match &mut self.non_final_txs {
+            Some(non_final_txs) => match non_final_txs.hold(tx.clone(), source) {
+                true => "Holding",
+                false => return false,
+            },
+            None => "Rejected",
         };
+        config.log(
+            &format!(
+                "{} non-final transaction {}, locked until {}",
+                action,
+                tx.txid(),
+                tx.lock_time
+            ),
+            VERBOSE,
+        );
+        false
+    }
+
+    /// Returns true if relay is off and the peer sent the transaction without us requesting it from that peer,
+    /// in which case it's ignored.
+    fn is_unsolicited(&mut self, txid: &HashId, peer: SocketAddr, config: &Config) -> bool {
+        if config.get_relay() || self.tx_requests.take(*txid, peer, Instant::now()) {
+            return false;
+        }
+        config.log(
+            &format!("Ignored unsolicited transaction {} from {}", txid, peer),
+            VERBOSE,
+        );
+        true
+    }
+
+    /// Returns true if the transaction is one we broadcast being relayed back by the peer,
+    /// recording that the peer already has it. It's reported as accepted by the first peer relaying it
+    /// back, unless it was already reported as not propagated.
+    fn is_own_relayed_back(&mut self, txid: &HashId, peer: SocketAddr, config: &Config) -> bool {
+        let awaited = self.sent_txs.is_awaiting_propagation(txid);
+        match self.sent_txs.relayed_back(txid, peer) {
+            Some(peers) => {
+                config.log(
+                    &format!(
+                        "Peer {} relayed back our transaction {}, now known by {} peers",
+                        peer, txid, peers
+                    ),
+                    VERBOSE,
+                );
+                if awaited {
+                    _ = self.notify_ui_message(
+                        NotificationKind::Info,
+                        "Transaction accepted by network",
+                        &format!("Transaction hash: {}", txid),
+                    );
+                }
+                true
+            }
+            None => false,
+        }
+    }
 
-        let tx = wallet.generate_transaction(&mut self.utxo_set, details);
+    /// Warns about the broadcasted transactions no peer relayed back within the propagation timeout
+    fn report_unpropagated_txs(&mut self, timeout: Duration) -> io::Result<()> {
+        for txid in self.sent_txs.take_unpropagated(timeout, Instant::now()) {
+            self.notify_ui_message(
+                NotificationKind::Warning,
+                "Transaction not propagated",
+                &format!(
+                    "No peer announced transaction {} within {} seconds",
+                    txid,
+                    timeout.as_secs()
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Abandons the transactions we broadcast that neither confirmed nor were relayed back within the timeout,
+    /// making the coins they spent available again. The pending transactions spending from them can't confirm
+    /// either, so they're abandoned too.
+    fn abandon_stale_txs(&mut self, timeout: Duration) -> io::Result<()> {
+        let stale = self.sent_txs.take_stale(timeout, Instant::now());
+        if stale.is_empty() {
+            return Ok(());
+        }
+        for txid in stale {
+            for tx in self.tx_index.remove_with_descendants(&txid) {
+                let abandoned = tx.txid();
+                let reason = match abandoned == txid {
+                    true => "wasn't confirmed nor relayed by any peer".to_string(),
+                    false => format!("spends from the abandoned transaction {}", txid),
+                };
+                self.abandon_tx(&tx, &reason)?;
+            }
+        }
+        self.update_ui_balance()
+    }
 
-        match tx {
-            Ok(tx) => {
-                let tx_hash = double_hash(&tx.serialize());
-                let bytes = tx.build_message()?;
-                self.nodes.send_to_all(&bytes, config)?;
+    /// Undoes an abandoned pending transaction in the utxo set and the wallets' history
+    fn abandon_tx(&mut self, tx: &RawTransaction, reason: &str) -> io::Result<()> {
+        let txid = tx.txid();
+        self.utxo_set.abandon_pending(tx);
+        self.tx_confirmations.untrack(&txid);
+        self.sent_txs.forget(&txid);
+        for wallet in self.wallets.values_mut() {
+            wallet.abandon(&txid);
+        }
+        self.notify_ui_message(
+            NotificationKind::Warning,
+            "Transaction abandoned",
+            &format!(
+                "Transaction {} {}, its coins are available again",
+                txid, reason
+            ),
+        )
+    }
 
-                self.read_pending_tx(tx)?;
+    /// Returns true if an input spends an output of a transaction that is neither confirmed nor pending.
+    /// Outputs of blocks older than the start timestamp aren't indexed, so they can only be told apart from
+    /// unknown ones if the utxo set was built from the genesis block.
+    fn is_orphan(&self, tx: &RawTransaction, config: &Config) -> bool {
+        if config.get_start_timestamp() > 0 {
+            return false;
+        }
+        match &tx.tx_in {
+            TxInputType::TxInput(inputs) => inputs.iter().any(|input| {
+                let prevout = (input.previous_output.hash, input.previous_output.index);
+                self.utxo_set.resolve_prevout(&prevout).is_none()
+                    && !self.tx_index.contains(&prevout.0)
+            }),
+            TxInputType::CoinBaseInput(_) => false,
+        }
+    }
+
+    /// Returns the fee rate peers' fee filters are checked against: the package fee rate of the transaction,
+    /// which a high-fee child of a pending transaction raises above the transaction's own rate
+    fn relay_fee_rate(&self, tx: &RawTransaction, config: &Config) -> Option<u64> {
+        let own_rate = tx.fee_rate(&self.utxo_set);
+        let package_rate = self.tx_index.package_fee_rate(&tx.txid(), &self.utxo_set);
+        if package_rate > own_rate {
+            config.log(
+                &format!(
+                    "Relaying transaction {} at its package fee rate of {:?} sat/kvB, its own is {:?}",
+                    tx.txid(),
+                    package_rate,
+                    own_rate
+                ),
+                VERBOSE,
+            );
+        }
+        package_rate.max(own_rate)
+    }
+
+    /// Adds the transaction received from the peer to the pending ones and, once synced, announces it to
+    /// the others along with the held orphans spending it. An orphan itself is held back until its parent
+    /// arrives instead, if the orphan pool is enabled.
+    fn accept_transaction(
+        &mut self,
+        tx: RawTransaction,
+        source: SocketAddr,
+        config: &Config,
+    ) -> io::Result<()> {
+        let txid = tx.txid();
+        // the utxo set is behind during IBD, so orphans can't be told apart then
+        if self.ibd.is_complete() && self.is_orphan(&tx, config) {
+            if let Some(orphan_txs) = &mut self.orphan_txs {
+                let evicted = orphan_txs.add(tx, source);
+                config.log(
+                    &format!(
+                        "Holding orphan transaction {}, evicted {} orphans",
+                        txid, evicted
+                    ),
+                    VERBOSE,
+                );
+                return Ok(());
+            }
+        }
+        // transactions can't be checked against the utxo set while it's behind, so they're only relayed after IBD
+        if !self.read_pending_tx(tx.clone())? || !self.ibd.is_complete() {
+            return Ok(());
+        }
+        let fee_rate = self.relay_fee_rate(&tx, config);
+        self.nodes
+            .announce_transaction(txid, fee_rate, source, config)?;
+        for (child, child_source) in self.take_orphan_children(&tx) {
+            self.accept_transaction(child, child_source, config)?;
+        }
+        Ok(())
+    }
+
+    /// Removes and returns the orphans spending outputs of the transaction, the ones paying the most
+    /// for their package first
+    fn take_orphan_children(&mut self, tx: &RawTransaction) -> Vec<(RawTransaction, SocketAddr)> {
+        let mut children = match &mut self.orphan_txs {
+            Some(orphan_txs) => orphan_txs.children_of(tx),
+            None => vec![],
+        };
+        children.sort_by_cached_key(|(child, _source)| {
+            Reverse(
+                self.tx_index
+                    .ancestor_fee_rate(&child.txid(), &self.utxo_set),
+            )
+        });
+        children
+    }
+
+    /// Generates a transaction and broadcasts it to all peers given the transaction details
+    pub fn generate_transaction(
+        &mut self,
+        details: TransactionInfo,
+        config: &Config,
+    ) -> io::Result<()> {
+        let watch_only = match self.wallets.get(&self.active_wallet) {
+            Some(w) => w.is_watch_only(),
+            None => return Err(io::Error::new(io::ErrorKind::Other, "Wallet not found")),
+        };
+        if let Err(e) = check_recipient_networks(&details, config.get_network_params()) {
+            return self.notify_ui_message(
+                NotificationKind::Error,
+                "Invalid recipient",
+                &format!("{}", e),
+            );
+        }
+        if config.get_warn_address_reuse() {
+            self.warn_address_reuse(&details)?;
+        }
+        match watch_only {
+            true => self.send_partial_transaction(details, config),
+            false => self.send_signed_transaction(details, config),
+        }
+    }
+
+    /// Signs the transaction with the active wallet and broadcasts it
+    fn send_signed_transaction(
+        &mut self,
+        details: TransactionInfo,
+        config: &Config,
+    ) -> io::Result<()> {
+        let generated = match self.wallets.get_mut(&self.active_wallet) {
+            Some(wallet) => {
+                wallet.generate_transaction(&mut self.utxo_set, details, config.get_max_fee())
+            }
+            None => Err(io::Error::new(io::ErrorKind::Other, "Wallet not found")),
+        };
+        match generated {
+            Ok(tx) => self.broadcast_transaction(tx, config),
+            Err(e) => self.notify_ui_message(
+                NotificationKind::Error,
+                "Failed broadcasting transaction",
+                &format!("{}", e),
+            ),
+        }
+    }
+
+    /// Warns about the recipients that already received coins, since reusing an address links its payments
+    fn warn_address_reuse(&self, details: &TransactionInfo) -> io::Result<()> {
+        for (address, _label, _value) in &details.recipients {
+            if self.utxo_set.has_received(address) {
                 self.notify_ui_message(
-                    gtk::MessageType::Info,
-                    "Transaction broadcasted",
-                    &format!("Transaction hash: {}", HashId::from_hash(tx_hash)),
-                )
+                    NotificationKind::Warning,
+                    "Address reuse",
+                    &format!(
+                        "Address {} already received coins, paying it again links both payments",
+                        address
+                    ),
+                )?;
             }
+        }
+        Ok(())
+    }
+
+    fn broadcast_transaction(&mut self, tx: RawTransaction, config: &Config) -> io::Result<()> {
+        let tx_hash = double_hash(&tx.serialize());
+        let bytes = tx.build_message()?;
+        self.nodes.send_to_tx_relay_peers(&bytes, config)?;
+        self.sent_txs
+            .sent(HashId::from_hash(tx_hash), Instant::now());
+        if config.get_broadcast_propagation_timeout().is_some() {
+            self.sent_txs.await_propagation(HashId::from_hash(tx_hash));
+        }
+
+        self.read_pending_tx(tx)?;
+        self.notify_ui_message(
+            NotificationKind::Info,
+            "Transaction broadcasted",
+            &format!("Transaction hash: {}", HashId::from_hash(tx_hash)),
+        )
+    }
+
+    /// Watch-only wallets exchange their transactions with an offline signer through the partial transactions directory.
+    /// The first send saves the unsigned transaction, once its signed copy is found the transaction is broadcasted.
+    fn send_partial_transaction(
+        &mut self,
+        details: TransactionInfo,
+        config: &Config,
+    ) -> io::Result<()> {
+        let dir = config.get_partial_tx_dir();
+        if dir.is_empty() {
+            return self.notify_ui_message(
+                NotificationKind::Error,
+                "Failed broadcasting transaction",
+                "Active wallet is watch-only, it can't sign transactions",
+            );
+        }
+        let (unsigned_path, signed_path) = partial_tx_paths(dir, &self.active_wallet);
+        if !Path::new(&signed_path).exists() {
+            return self.save_partial_transaction(details, &unsigned_path, config.get_max_fee());
+        }
+        match finalize_partial_transaction(&unsigned_path, &signed_path) {
+            Ok(tx) => self.broadcast_transaction(tx, config),
             Err(e) => self.notify_ui_message(
-                gtk::MessageType::Error,
+                NotificationKind::Error,
                 "Failed broadcasting transaction",
                 &format!("{}", e),
             ),
         }
     }
 
-    /// Gets the proof of inclusion for a transaction given the block hash and transaction hash
+    fn save_partial_transaction(
+        &mut self,
+        details: TransactionInfo,
+        path: &str,
+        max_fee: Option<u64>,
+    ) -> io::Result<()> {
+        let saved = match self.wallets.get_mut(&self.active_wallet) {
+            Some(wallet) => wallet
+                .build_partial(&self.utxo_set, details, max_fee)
+                .and_then(|partial| partial.save(path)),
+            None => Err(io::Error::new(io::ErrorKind::Other, "Wallet not found")),
+        };
+        match saved {
+            Ok(()) => self.notify_ui_message(
+                NotificationKind::Info,
+                "Transaction saved for offline signing",
+                &format!(
+                    "Unsigned transaction saved to {}\nSend again once it's signed to broadcast it",
+                    path
+                ),
+            ),
+            Err(e) => self.notify_ui_message(
+                NotificationKind::Error,
+                "Failed building transaction",
+                &format!("{}", e),
+            ),
+        }
+    }
+
+    /// Signs the transaction saved for offline signing by the watch-only counterpart of the given wallet,
+    /// notifying the ui of the result
+    pub fn sign_partial_transaction(&self, address: &str, config: &Config) -> io::Result<()> {
+        let dir = config.get_partial_tx_dir();
+        let wallet = match self.wallets.get(address) {
+            Some(wallet) if !dir.is_empty() && !wallet.is_watch_only() => wallet,
+            _ => {
+                return self.notify_ui_message(
+                    NotificationKind::Error,
+                    "Signing failed",
+                    "Offline signing needs a wallet with its secret key and a partial transactions directory.",
+                )
+            }
+        };
+        let (unsigned_path, signed_path) = partial_tx_paths(dir, address);
+        match sign_partial_transaction(
+            wallet,
+            &unsigned_path,
+            &signed_path,
+            self.utxo_set.address_format(),
+        ) {
+            Ok(signed) => {
+                let msg = format!("Signed {} inputs of {}", signed, unsigned_path);
+                config.log(&msg, QUIET);
+                self.notify_ui_message(NotificationKind::Info, "Transaction signed", &msg)
+            }
+            Err(e) => self.notify_ui_message(
+                NotificationKind::Error,
+                "Signing failed",
+                &format!("Could not sign {}: {}", unsigned_path, e),
+            ),
+        }
+    }
+
+    /// Builds a transaction from the active wallet without signing nor broadcasting it, and notifies the ui
+    /// of the fee it would pay, its estimated size and the coins it spends, warning if dust change raised the fee
+    pub fn preview_transaction(&self, details: TransactionInfo) -> io::Result<()> {
+        let wallet = match self.wallets.get(&self.active_wallet) {
+            Some(w) => w,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "Wallet not found")),
+        };
+
+        let requested_fee = details.fee;
+        let inputs = wallet
+            .preview_inputs(&self.utxo_set, &details)
+            .unwrap_or_default();
+        match wallet.build_unsigned(&self.utxo_set, details) {
+            Ok((_tx, fee, size)) => {
+                let (kind, msg) = preview_message((fee, requested_fee), size, &inputs);
+                self.notify_ui_message(kind, "Transaction preview", &msg)
+            }
+            Err(e) => self.notify_ui_message(
+                NotificationKind::Error,
+                "Failed building transaction",
+                &format!("{}", e),
+            ),
+        }
+    }
+
+    /// Gets the proofs of inclusion for the transactions given the block hash and comma separated transaction hashes
     pub fn get_proof_of_inclusion(&self, block_hash: String, tx_hash: String) -> io::Result<()> {
         let block_hashid: HashId = match block_hash.parse() {
             Ok(hash) => hash,
             Err(_) => {
                 return self.notify_ui_message(
-                    gtk::MessageType::Error,
+                    NotificationKind::Error,
                     "Invalid block hash",
                     "Invalid block hash.",
                 )
@@ -429,100 +1542,660 @@ impl NetworkController {
             Some(block) => block,
             None => {
                 return self.notify_ui_message(
-                    gtk::MessageType::Error,
+                    NotificationKind::Error,
                     "Block not found",
                     "Block not found in blockchain.",
                 )
             }
         };
+        self.update_ui_poi_result(block, &tx_hash)
+    }
 
-        let block_tx_hashes = block.hash_transactions();
-        let merkle_tree = MerkleTree::generate_from_hashes(block_tx_hashes);
-        let dhx = decode_hex(&reverse_hex_str(&tx_hash)).map_err(to_io_err)?;
-        let tx_hashed = sha256::Hash::from_slice(&dhx).map_err(to_io_err)?;
-        let proof = merkle_tree.generate_proof(tx_hashed)?;
-        let root_from_proof = proof.generate_merkle_root();
+    /// Finds a transaction by its id among the valid blocks and the pending transactions,
+    /// along with the hash of the block holding it (None if it's still pending)
+    pub fn find_transaction(&self, txid: &HashId) -> Option<(RawTransaction, Option<HashId>)> {
+        self.tx_index.find(txid, &self.valid_blocks)
+    }
 
-        self.update_ui_poi_result(proof, root_from_proof);
+    /// Returns true if the item is a valid block older than `HISTORICAL_BLOCK_AGE` below the tallest block
+    fn is_historical_block(&self, inventory: &Inventory) -> bool {
+        match self.valid_blocks.get(&inventory.hash) {
+            Some(block) if inventory.inv_type.is_block() => {
+                self.tallest_block
+                    .timestamp
+                    .saturating_sub(block.header.timestamp)
+                    > HISTORICAL_BLOCK_AGE
+            }
+            _ => false,
+        }
+    }
 
+    /// Returns the message serving an item a peer asked for: a valid block, as a compact block if it was asked
+    /// for like that and is recent, or a pending transaction, like the ones we announced, with its witnesses
+    /// only if they were asked for. Confirmed transactions aren't served, like other nodes do.
+    fn getdata_reply(&self, inventory: &Inventory) -> Option<io::Result<Vec<u8>>> {
+        match inventory.inv_type {
+            InvType::MSGBlock => self
+                .valid_blocks
+                .get(&inventory.hash)
+                .map(Block::serialize_message),
+            InvType::MSGWitnessBlock => self
+                .valid_blocks
+                .get(&inventory.hash)
+                .map(Block::serialize_witness_message),
+            InvType::MSGCompactBlock => {
+                let block = self.valid_blocks.get(&inventory.hash)?;
+                match self
+                    .tallest_block
+                    .height
+                    .saturating_sub(block.header.height)
+                {
+                    depth if depth > MAX_COMPACT_BLOCK_DEPTH => Some(block.serialize_message()),
+                    _ => Some(CompactBlock::from_block(block, rand::random()).serialize()),
+                }
+            }
+            InvType::MSGTx => match self.find_transaction(&inventory.hash)? {
+                (tx, None) => Some(tx.build_stripped_message()),
+                _ => None,
+            },
+            InvType::MSGWitnessTx => match self.find_transaction(&inventory.hash)? {
+                (tx, None) => Some(tx.build_message()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the stats of a valid block, None if it isn't known or an output it spends can't be found anymore
+    pub fn block_stats(&self, hash: &HashId, config: &Config) -> Option<BlockStats> {
+        let block = self.valid_blocks.get(hash)?;
+        let subsidy = config
+            .get_network_params()
+            .block_subsidy(block.header.height);
+        BlockStats::new(block, subsidy, |(txid, index)| {
+            let (tx, _block_hash) = self.find_transaction(txid)?;
+            tx.tx_out.get(*index as usize).map(|output| output.value)
+        })
+    }
+
+    /// Watches the address as a new wallet, rescanning the blocks from the given height for its history
+    pub fn import_address(
+        &mut self,
+        address: String,
+        from_height: usize,
+        config: &Config,
+    ) -> io::Result<()> {
+        let wallet = match Wallet::watch_only(&address) {
+            Ok(wallet) if !self.wallets.contains_key(&address) => wallet,
+            Ok(_) => {
+                return self.notify_ui_message(
+                    NotificationKind::Error,
+                    "Import failed",
+                    "Address already imported.",
+                )
+            }
+            Err(e) => {
+                return self.notify_ui_message(
+                    NotificationKind::Error,
+                    "Import failed",
+                    &e.to_string(),
+                )
+            }
+        };
+        self.save_watch_address(&address, config)?;
+        Wallet::update_ui_wallet_entry(self.ui_sender.as_ref(), &address, false);
+        self.wallets.insert(address.clone(), wallet);
+        let found = self.rescan(&address, from_height);
+        let msg = format!(
+            "Found {} transactions of {} from height {}",
+            found, address, from_height
+        );
+        config.log(&msg, VERBOSE);
+        self.notify_ui_message(NotificationKind::Info, "Address imported", &msg)
+    }
+
+    /// Adds the imported address to the watch addresses file, so it's still watched after a restart
+    fn save_watch_address(&self, address: &str, config: &Config) -> io::Result<()> {
+        let path = config.get_watch_addresses_file();
+        if path.is_empty() {
+            return Ok(());
+        }
+        match Wallet::save_watch_address(path, address) {
+            Ok(()) => Ok(()),
+            Err(e) => self.notify_ui_message(
+                NotificationKind::Warning,
+                "Import not saved",
+                &format!(
+                    "Could not save {} to the watch addresses file: {}",
+                    address, e
+                ),
+            ),
+        }
+    }
+
+    /// Adds the transactions of the best chain blocks from the given height to the history of the wallet.
+    /// Its balance needs no rescan, the utxo set tracks the outputs of every address.
+    /// Returns the number of transactions found.
+    pub fn rescan(&mut self, address: &str, from_height: usize) -> usize {
+        let Some(wallet) = self.wallets.get_mut(address) else {
+            return 0;
+        };
+        let blocks = best_chain_blocks(&self.valid_blocks, self.tallest_block.hash, from_height);
+        wallet.rescan(blocks, &mut self.utxo_set)
+    }
+
+    /// Rebuilds the history of the wallet from the whole best chain, then adds back its pending transactions
+    pub fn rebuild_history(&mut self, address: &str, config: &Config) -> io::Result<()> {
+        let Some(wallet) = self.wallets.get_mut(address) else {
+            return self.notify_ui_message(
+                NotificationKind::Error,
+                "Rebuild failed",
+                "Unknown wallet.",
+            );
+        };
+        let blocks = best_chain_blocks(&self.valid_blocks, self.tallest_block.hash, 0);
+        let found = wallet.rebuild_history(blocks, &mut self.utxo_set);
+        for tx in self.tx_index.pending() {
+            if tx.address_is_involved(&wallet.addresses(), self.utxo_set.address_format()) {
+                let received_at = self.tx_index.received_at(&tx.txid()).unwrap_or_default();
+                let tx_info = wallet.transaction_info(|address| {
+                    tx.transaction_info_for_pending(address, received_at, &mut self.utxo_set)
+                });
+                wallet.update_history(tx_info);
+            }
+        }
+        let msg = format!(
+            "Rebuilt the history of {} from {} transactions",
+            address, found
+        );
+        config.log(&msg, VERBOSE);
+        self.notify_ui_message(NotificationKind::Info, "History rebuilt", &msg)
+    }
+
+    /// Reconstructs the compact block from the pending transactions (BIP152). Returns the block if none
+    /// is missing, otherwise requests the missing ones from the peer and keeps the block until they arrive.
+    fn reconstruct_compact_block(
+        &mut self,
+        compact_block: CompactBlock,
+        peer_addr: SocketAddr,
+        config: &Config,
+    ) -> io::Result<Option<Block>> {
+        let hash = compact_block.header.hash();
+        let known =
+            self.valid_blocks.contains_key(&hash) || self.blocks_on_hold.contains_key(&hash);
+        let partial_block = match compact_block.reconstruct(self.tx_index.pending()) {
+            Ok(partial_block) if !known => partial_block,
+            _ => return Ok(None),
+        };
+        let missing = partial_block.missing();
+        if missing.is_empty() {
+            return Ok(partial_block.complete(vec![]).ok());
+        }
+        self.make_room_for_partial_block();
+        config.log(
+            &format!(
+                "Requesting {} missing transactions of compact block {}",
+                missing.len(),
+                hash
+            ),
+            VERBOSE,
+        );
+        let getblocktxn = GetBlockTxn::new(hash, missing).serialize()?;
+        // a peer that can't be reached won't send the transactions, so the block isn't kept for it
+        if self
+            .nodes
+            .send_to_specific(&peer_addr, &getblocktxn, config)
+            .is_ok()
+        {
+            self.partial_blocks
+                .insert(hash, (partial_block, peer_addr, Instant::now()));
+        }
+        Ok(None)
+    }
+
+    /// Drops the compact blocks that waited too long for their transactions and, if there are still too
+    /// many, the one waiting the longest
+    fn make_room_for_partial_block(&mut self) {
+        let timeout = Duration::from_secs(PARTIAL_BLOCK_TIMEOUT);
+        self.partial_blocks
+            .retain(|_, (_, _, requested)| requested.elapsed() < timeout);
+        if self.partial_blocks.len() >= MAX_PARTIAL_BLOCKS {
+            let oldest = self
+                .partial_blocks
+                .iter()
+                .min_by_key(|(_, (_, _, requested))| *requested)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                self.partial_blocks.remove(&oldest);
+            }
+        }
+    }
+
+    /// Completes the compact block the transactions were requested for, None if they don't match the request
+    /// or weren't requested from the peer that sent them
+    fn complete_compact_block(
+        &mut self,
+        blocktxn: BlockTxn,
+        peer_addr: SocketAddr,
+    ) -> Option<Block> {
+        match self.partial_blocks.get(&blocktxn.block_hash) {
+            Some((_, requested_from, _)) if *requested_from == peer_addr => {}
+            _ => return None,
+        }
+        let (partial_block, _, _) = self.partial_blocks.remove(&blocktxn.block_hash)?;
+        partial_block.complete(blocktxn.txns).ok()
+    }
+
+    /// Sends the peer the transactions of the block it requested, if we have the block and all of them
+    fn send_block_txns(
+        &mut self,
+        getblocktxn: GetBlockTxn,
+        peer_addr: SocketAddr,
+        config: &Config,
+    ) -> io::Result<()> {
+        let Some(block) = self.valid_blocks.get(&getblocktxn.block_hash) else {
+            return Ok(());
+        };
+        let txns: Option<Vec<RawTransaction>> = getblocktxn
+            .indexes
+            .iter()
+            .map(|index| block.txns.get(*index).cloned())
+            .collect();
+        let Some(txns) = txns else {
+            return Ok(());
+        };
+        let blocktxn = BlockTxn::new(getblocktxn.block_hash, txns).serialize()?;
+        _ = self.nodes.send_to_specific(&peer_addr, &blocktxn, config);
         Ok(())
     }
 
+    /// Returns a receiver of the confirmations of wallet transactions seen while pending
+    pub fn subscribe_tx_confirmed(&mut self) -> Receiver<TxConfirmed> {
+        self.tx_confirmations.subscribe()
+    }
+
+    /// Returns every known chain tip: the tallest block, the tips of the branches off its chain and the
+    /// headers downloaded ahead of it, tallest first
+    pub fn get_chain_tips(&self) -> Vec<ChainTipInfo> {
+        let active_hash = self.tallest_block.hash();
+        let mut tips: Vec<ChainTipInfo> = self
+            .headers
+            .tips()
+            .filter(|hash| **hash != active_hash)
+            .filter_map(|hash| self.headers.get(hash))
+            .map(|tip| self.chain_tip_info(tip))
+            .collect();
+        tips.push(ChainTipInfo {
+            hash: active_hash,
+            height: self.tallest_block.height,
+            branch_len: 0,
+            status: TipStatus::Active,
+        });
+        tips.sort_by_key(|tip| Reverse(tip.height));
+        tips
+    }
+
+    /// Walks the branch of the tip down to the chain of the tallest block, telling its length and status
+    fn chain_tip_info(&self, tip: BlockHeader) -> ChainTipInfo {
+        let (mut branch, mut active) = (tip, self.tallest_block);
+        let mut invalid = false;
+        while branch.hash() != active.hash() {
+            let taller = match branch.height >= active.height {
+                true => {
+                    invalid |= self.headers.is_invalid(&branch.hash());
+                    &mut branch
+                }
+                false => &mut active,
+            };
+            match self.headers.get(&taller.prev_block_hash) {
+                Some(parent) => *taller = parent,
+                None => break,
+            }
+        }
+        let status = if invalid {
+            TipStatus::Invalid
+        } else if self.valid_blocks.contains_key(&tip.hash()) {
+            TipStatus::ValidFork
+        } else {
+            TipStatus::HeadersOnly
+        };
+        ChainTipInfo {
+            hash: tip.hash(),
+            height: tip.height,
+            branch_len: tip.height - branch.height,
+            status,
+        }
+    }
+
     fn update_best_header_chain(&mut self) {
         let mut current_header_hash = self.tallest_header.hash;
         let mut prev_header_hash = self.tallest_header.prev_block_hash;
         loop {
-            let previous_header = match self.headers.get_mut(&prev_header_hash) {
+            let previous_header = match self.headers.get(&prev_header_hash) {
                 Some(previous_header) => previous_header,
                 None => return, // this will only happen when the current header is the genesis
             };
 
             // update previous in loop, until the previous' next is the current
-            match previous_header.next_block_hash {
-                Some(next_hash) if next_hash == current_header_hash => break,
-                _ => previous_header.next_block_hash = Some(current_header_hash),
+            if previous_header.next_block_hash == Some(current_header_hash) {
+                break;
             }
+            self.headers
+                .set_next_block_hash(&prev_header_hash, current_header_hash);
 
             // set values for next iteration
             current_header_hash = prev_header_hash;
-            let current_header = match self.headers.get(&prev_header_hash) {
-                Some(header) => header,
-                None => return, // will never happen
-            };
-            prev_header_hash = current_header.prev_block_hash;
+            prev_header_hash = previous_header.prev_block_hash;
+        }
+    }
+
+    /// Logs the diagnostic information of the connected peers
+    fn log_peer_info(&self, config: &Config) {
+        for peer_info in self.nodes.peer_info() {
+            config.log(&format!("Peer: {}", peer_info), VERBOSE);
+        }
+    }
+
+    /// Reads the blocks from the backup file, returns the height blocks weren't pruned from (0 if none were)
+    fn read_backup_blocks(&mut self, config: &Config) -> io::Result<usize> {
+        let blocks = match Block::all_from_file(config.get_blocks_file()) {
+            Ok(blocks) => blocks,
+            Err(..) => return Ok(0),
+        };
+        self.update_ui_progress(Some("Found blocks backup file, reading blocks..."), 0.0);
+        let first_unpruned_height = match config.get_prune_below_height() {
+            Some(..) => self.connect_pruned_backup(&blocks).unwrap_or(0),
+            None => 0,
+        };
+        for (_, block) in blocks.into_iter() {
+            self.read_backup_block(block, config);
+        }
+        self.ui_sender
+            .update_progress(Some("Read blocks backup finished."), 1.0)?;
+        Ok(first_unpruned_height)
+    }
+
+    /// When the oldest backup blocks were pruned, connects the first remaining one to a pseudo genesis block,
+    /// as long as the utxo snapshot already holds the pruned blocks. Returns the height of the first remaining block.
+    fn connect_pruned_backup(&mut self, blocks: &BlockSet) -> Option<usize> {
+        let snapshot_height = self.utxo_snapshot_height?;
+        let first_header = blocks
+            .keys()
+            .filter_map(|hash| self.headers.get(hash))
+            .min_by_key(|header| header.height)?;
+        let parent = self.headers.get(&first_header.prev_block_hash)?;
+        if parent.height > snapshot_height || self.valid_blocks.contains_key(&parent.hash()) {
+            return None;
+        }
+        self.valid_blocks
+            .insert(parent.hash(), Block::new(parent, 0, vec![]));
+        Some(parent.height + 1)
+    }
+
+    /// Returns the tallest block with the serialized utxo set at it and the value its outputs hold
+    fn utxo_snapshot(&self) -> (BlockHeader, Vec<u8>, u64) {
+        let utxo_set = &self.utxo_set;
+        (
+            self.tallest_block,
+            utxo_set.to_bytes(),
+            utxo_set.total_value(),
+        )
+    }
+
+    /// Removes the blocks buried below the prune height from memory, returning them to be removed from the backup
+    /// file once the controller is unlocked. Only called once a utxo snapshot at the given height holds their effects,
+    /// and blocks within `MIN_BLOCKS_TO_KEEP` of it are kept so recent blocks can still be served and proven.
+    fn prune_blocks(&mut self, snapshot_height: usize, config: &Config) -> HashSet<HashId> {
+        let Some(prune_height) = config
+            .get_prune_below_height()
+            .map(|height| height.min(snapshot_height.saturating_sub(MIN_BLOCKS_TO_KEEP)))
+        else {
+            return HashSet::new();
+        };
+        let pruned: HashSet<HashId> = self
+            .valid_blocks
+            .values()
+            .filter(|block| block.header.height < prune_height)
+            .map(Block::hash)
+            .collect();
+        self.valid_blocks.retain(|hash, _| !pruned.contains(hash));
+        self.tx_index.blocks_pruned(&pruned);
+        pruned
+    }
+
+    /// Reads the headers backup file. If its tail is corrupt, the file is cut back to the valid headers before it,
+    /// so the ones appended from then on follow them.
+    fn read_headers_file(config: &Config) -> io::Result<Headers> {
+        let file_name = config.get_headers_file();
+        let (headers, corruption) = Headers::from_file(file_name)?;
+        if let Some(e) = corruption {
+            config.log(
+                &format!(
+                    "Headers backup is corrupt, keeping the {} headers before it: {}",
+                    headers.count, e
+                ),
+                QUIET,
+            );
+            fs::OpenOptions::new()
+                .write(true)
+                .open(file_name)?
+                .set_len(headers.count as u64 * HEADER_RECORD_SIZE)?;
         }
+        Ok(headers)
     }
 
     /// Starts the sync process by requesting headers from all peers from the last known header (or genesis block) to the current time
     /// If a backup file is found, it will read the blocks and headers from the backup file
     pub fn start_sync(&mut self, config: &Config) -> io::Result<()> {
+        self.log_peer_info(config);
         let mut downloadable_headers = Headers::default();
         // attempt to read headers from backup file
         self.update_ui_progress(Some("Reading backup files..."), 0.0);
-        if let Ok(headers) = Headers::from_file(config.get_headers_file()) {
+        if let Ok(headers) = Self::read_headers_file(config) {
             self.update_ui_progress(Some("Reading headers from backup file..."), 0.0);
             downloadable_headers = self.read_backup_headers(headers, config);
-            update_ui_progress_bar(
-                &self.ui_sender,
-                Some("Finished reading headers from backup file."),
-                1.0,
-            )?;
+            self.ui_sender
+                .update_progress(Some("Finished reading headers from backup file."), 1.0)?;
+        }
+
+        self.read_utxo_snapshot(config);
+        self.utxo_set
+            .set_min_confirmations(config.get_min_confirmations());
+        self.utxo_set
+            .set_spend_own_change(config.get_spend_own_change());
+        self.utxo_set
+            .set_coinbase_maturity(config.get_network_params().coinbase_maturity);
+        self.utxo_set
+            .set_address_format(config.get_network_params().address_format);
+        let first_unpruned_height = self.read_backup_blocks(config)?;
+        if let Err(e) = self.read_mempool(config) {
+            config.log(&format!("Could not read mempool file: {}", e), QUIET);
         }
 
-        // attempt to read blocks from backup file
-        if let Ok(blocks) = Block::all_from_file(config.get_blocks_file()) {
-            self.update_ui_progress(Some("Found blocks backup file, reading blocks..."), 0.0);
-            for (_, block) in blocks.into_iter() {
-                self.read_backup_block(block, config);
-            }
-            update_ui_progress_bar(&self.ui_sender, Some("Read blocks backup finished."), 1.0)?;
-        }
-        // Finally, catch up to blockchain doing IBD
-        let mut missing_blocks: Vec<BlockHeader> = vec![];
-        for header in downloadable_headers.block_headers {
-            if !self.valid_blocks.contains_key(&header.hash())
-                && !self.blocks_on_hold.contains_key(&header.hash())
-            {
-                missing_blocks.push(header);
-            }
+        // Finally, catch up to blockchain doing IBD
+        if let Some(first_header) = downloadable_headers.block_headers.first() {
+            let heights =
+                first_header.height.max(first_unpruned_height)..self.tallest_header.height + 1;
+            self.request_missing_blocks(heights, config)?;
+        }
+        self.request_headers(self.tallest_header.hash(), config)?;
+        Ok(())
+    }
+}
+
+/// Returns the blocks of the chain ending at the tip from the given height up, lowest first.
+/// Stops at the first block missing from the set, like a pruned one.
+fn best_chain_blocks(blocks: &BlockSet, tip: HashId, from_height: usize) -> Vec<&Block> {
+    let mut chain = vec![];
+    let mut hash = tip;
+    while let Some(block) = blocks.get(&hash) {
+        if block.header.height < from_height {
+            break;
+        }
+        chain.push(block);
+        hash = block.header.prev_block_hash;
+    }
+    chain.reverse();
+    chain
+}
+
+/// Returns the blocks buried `depth` or more blocks below the tip that aren't on its chain.
+/// The tip itself may not be in the set yet.
+fn stale_blocks(blocks: &BlockSet, tip: &BlockHeader, depth: usize) -> HashSet<HashId> {
+    let Some(max_height) = tip.height.checked_sub(depth) else {
+        return HashSet::new();
+    };
+    let buried: Vec<&Block> = blocks
+        .values()
+        .filter(|block| block.header.height <= max_height)
+        .collect();
+    let Some(min_height) = buried.iter().map(|block| block.header.height).min() else {
+        return HashSet::new();
+    };
+    let best_chain: HashSet<HashId> = best_chain_blocks(blocks, tip.prev_block_hash, min_height)
+        .into_iter()
+        .map(Block::hash)
+        .collect();
+    buried
+        .into_iter()
+        .map(Block::hash)
+        .filter(|hash| !best_chain.contains(hash))
+        .collect()
+}
+
+/// Parses comma separated transaction hashes into the hashes used as merkle tree leaves
+fn parse_tx_hashes(tx_hashes: &str) -> io::Result<Vec<sha256::Hash>> {
+    let mut hashes = vec![];
+    for tx_hash in tx_hashes.split(',').map(str::trim) {
+        let dhx = decode_hex(&reverse_hex_str(tx_hash)).map_err(to_io_err)?;
+        hashes.push(sha256::Hash::from_slice(&dhx).map_err(to_io_err)?);
+    }
+    Ok(hashes)
+}
+
+/// Returns the type blocks are requested as, with their witness data unless configured to download them stripped
+fn block_inv_type(config: &Config) -> InvType {
+    match config.get_download_witness() {
+        true => InvType::MSGWitnessBlock,
+        false => InvType::MSGBlock,
+    }
+}
+
+/// Describes the fee a previewed transaction pays, its estimated size and the coins it spends. It's a warning
+/// if the fee is over the requested one, because the change was too small to keep.
+fn preview_message(
+    (fee, requested_fee): (u64, u64),
+    size: usize,
+    inputs: &[(HashId, u32, u64)],
+) -> (NotificationKind, String) {
+    let (kind, raised) = match fee > requested_fee {
+        true => (
+            NotificationKind::Warning,
+            format!(
+                ", {} more than requested as the change is too small to keep",
+                fee - requested_fee
+            ),
+        ),
+        false => (NotificationKind::Info, String::new()),
+    };
+    let spends: String = inputs
+        .iter()
+        .map(|(txid, index, value)| format!("\n{}:{} ({} satoshis)", txid, index, value))
+        .collect();
+    let msg = format!(
+        "Fee: {} satoshis{}\nEstimated size: {} bytes\nSpends:{}",
+        fee, raised, size, spends
+    );
+    (kind, msg)
+}
+
+/// Binds the port incoming connections are accepted on, None if the node only connects to peers
+fn bind_listener(listen: bool, port: u16) -> io::Result<Option<TcpListener>> {
+    if !listen {
+        return Ok(None);
+    }
+    TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).map(Some)
+}
+
+/// Logs the decoded scripts of the outputs of a transaction that don't follow a standard template
+fn log_nonstandard_outputs(tx: &RawTransaction, config: &Config) {
+    for (index, output) in tx.tx_out.iter().enumerate() {
+        let script = decode_script(
+            &output.pk_script,
+            &config.get_network_params().address_format,
+        );
+        if script.script_type == ScriptType::NonStandard {
+            config.log(
+                &format!("Nonstandard output {} of {}: {}", index, tx.txid(), script),
+                VERBOSE,
+            );
         }
-        self.request_blocks(Headers::new(missing_blocks.len(), missing_blocks), config)?;
-        self.request_headers(self.tallest_header.hash(), config)?;
-        Ok(())
     }
 }
 
+/// Returns the paths of the unsigned and signed partial transactions of the given address
+fn partial_tx_paths(dir: &str, address: &str) -> (String, String) {
+    (
+        format!("{}/{}.ptx", dir, address),
+        format!("{}/{}.signed.ptx", dir, address),
+    )
+}
+
+fn sign_partial_transaction(
+    wallet: &Wallet,
+    unsigned_path: &str,
+    signed_path: &str,
+    format: &AddressFormat,
+) -> io::Result<usize> {
+    let mut partial = PartialTransaction::load(unsigned_path)?;
+    let signed = wallet.sign_partial(&mut partial, format)?;
+    partial.save(signed_path)?;
+    Ok(signed)
+}
+
+/// Removes the pruned blocks from the backup file, holding its lock so no block is appended while it's rewritten
+fn prune_blocks_file(
+    file_lock: &Mutex<()>,
+    pruned: &HashSet<HashId>,
+    config: &Config,
+) -> io::Result<()> {
+    if pruned.is_empty() {
+        return Ok(());
+    }
+    let _file_guard = file_lock.lock().map_err(to_io_err)?;
+    let removed = Block::prune_file(
+        config.get_blocks_file(),
+        pruned,
+        config.get_compress_backups(),
+    )?;
+    config.log(&format!("Pruned {} blocks", removed), VERBOSE);
+    Ok(())
+}
+
+/// Merges the offline signatures into the unsigned transaction, removing both files once it's complete
+fn finalize_partial_transaction(
+    unsigned_path: &str,
+    signed_path: &str,
+) -> io::Result<RawTransaction> {
+    let mut partial = PartialTransaction::load(unsigned_path)?;
+    partial.combine(&PartialTransaction::load(signed_path)?)?;
+    let tx = partial.finalize()?;
+    fs::remove_file(unsigned_path)?;
+    fs::remove_file(signed_path)?;
+    Ok(tx)
+}
+
 /// OuterNetworkController is a wrapper around the inner NetworkController in order to allow for safe multithreading
 pub struct OuterNetworkController {
     inner: Arc<RwLock<NetworkController>>,
-    ui_sender: SyncSender<GtkMessage>,
+    ui_sender: UiSender,
     writer_chanel: mpsc::SyncSender<(SocketAddr, Message)>,
 }
 
 impl OuterNetworkController {
     /// Creates a new OuterNetworkController given a ui_sender and a writer
     pub fn new(
-        ui_sender: SyncSender<GtkMessage>,
+        ui_sender: UiSender,
         writer_end: mpsc::SyncSender<(SocketAddr, Message)>,
         config: Config,
     ) -> Result<Self, io::Error> {
@@ -540,23 +2213,21 @@ impl OuterNetworkController {
 
     fn update_ui_headers_periodically(
         inner: &RwLockReadGuard<'_, NetworkController>,
-        ui_sender: &SyncSender<GtkMessage>,
+        ui_sender: &dyn UiNotifier,
         tallest_header_hash: &mut HashId,
         amount: usize,
     ) {
-        let headers: Vec<&BlockHeader> = inner.get_best_headers(amount);
+        let headers: Vec<BlockHeader> = inner.get_best_headers(amount);
         if inner.tallest_header.hash() != *tallest_header_hash {
             *tallest_header_hash = inner.tallest_header.hash();
-            let data = table_data_from_headers(headers.clone());
-            _ = ui_sender
-                .send(GtkMessage::UpdateTable((GtkTable::Headers, data)))
-                .map_err(to_io_err);
+            let data = table_data_from_headers(headers.iter().collect());
+            _ = ui_sender.update_table(GtkTable::Headers, data);
         }
     }
 
     fn update_ui_blocks_periodically(
         inner: &RwLockReadGuard<'_, NetworkController>,
-        ui_sender: &SyncSender<GtkMessage>,
+        ui_sender: &dyn UiNotifier,
         tallest_block_hash: &mut HashId,
         amount: usize,
     ) {
@@ -564,29 +2235,27 @@ impl OuterNetworkController {
             *tallest_block_hash = inner.tallest_block.hash;
             let blocks = inner.get_best_blocks(amount);
             let data = table_data_from_blocks(blocks);
-            _ = ui_sender.send(GtkMessage::UpdateTable((GtkTable::Blocks, data)));
+            _ = ui_sender.update_table(GtkTable::Blocks, data);
         }
     }
 
     fn update_ui_overview_tx_periodically(
         inner: &RwLockReadGuard<'_, NetworkController>,
-        ui_sender: &SyncSender<GtkMessage>,
+        ui_sender: &dyn UiNotifier,
         txs_on_overview: &mut Vec<TransactionDisplayInfo>,
     ) {
-        let curr_active_wallet = inner.active_wallet.clone();
         _ = inner.update_ui_balance();
-        if let Some(wallet) = inner.wallets.get(&curr_active_wallet) {
-            let transactions = wallet.get_last_n_transactions(20);
+        if let Some(transactions) = inner.overview_transactions() {
             if transactions != *txs_on_overview {
                 *txs_on_overview = transactions.clone();
-                _ = ui_sender.send(GtkMessage::UpdateOverviewTransactions(transactions));
+                _ = ui_sender.update_overview_transactions(transactions);
             }
         }
     }
 
     fn update_ui_data_periodically(&self) -> io::Result<()> {
         let inner = self.inner.clone();
-        let ui_sender: SyncSender<GtkMessage> = self.ui_sender.clone();
+        let ui_sender: UiSender = self.ui_sender.clone();
         thread::spawn(move || -> io::Result<()> {
             let mut tallest_header_hash = HashId::default();
             let mut tallest_block_hash = HashId::default();
@@ -597,17 +2266,336 @@ impl OuterNetworkController {
                     inner.read().map_err(to_io_err)?;
                 Self::update_ui_headers_periodically(
                     &inner,
-                    &ui_sender,
+                    ui_sender.as_ref(),
                     &mut tallest_header_hash,
                     100,
                 );
                 Self::update_ui_blocks_periodically(
                     &inner,
-                    &ui_sender,
+                    ui_sender.as_ref(),
                     &mut tallest_block_hash,
                     100,
                 );
-                Self::update_ui_overview_tx_periodically(&inner, &ui_sender, &mut txs_on_overview)
+                Self::update_ui_overview_tx_periodically(
+                    &inner,
+                    ui_sender.as_ref(),
+                    &mut txs_on_overview,
+                )
+            }
+        });
+        Ok(())
+    }
+
+    /// Writes a snapshot of the utxo set to disk and returns the tallest block it holds. The set is serialized
+    /// under the read lock and written to disk once it's released.
+    fn flush_utxo_snapshot(
+        inner: &Arc<RwLock<NetworkController>>,
+        config: &Config,
+    ) -> io::Result<BlockHeader> {
+        let (tip, utxo_set_bytes, total_value) = inner.read().map_err(to_io_err)?.utxo_snapshot();
+        write_utxo_snapshot(config.get_utxo_snapshot_file(), tip.hash(), &utxo_set_bytes)?;
+        let flushed = format!("Flushed utxo snapshot at height {}", tip.height);
+        config.log(
+            &format!("{}, holding {} satoshis", flushed, total_value),
+            VERBOSE,
+        );
+        Ok(tip)
+    }
+
+    /// Saves the pending transactions to the mempool file, if there's one
+    fn save_mempool(inner: &Arc<RwLock<NetworkController>>, config: &Config) -> io::Result<()> {
+        let Some(file_name) = config.get_mempool_file() else {
+            return Ok(());
+        };
+        let mempool = inner.read().map_err(to_io_err)?.mempool();
+        mempool.save_to_file(file_name)
+    }
+
+    /// Flushes the utxo snapshot, if they're taken, and saves the mempool before the node exits,
+    /// so the next run resumes from where it stopped
+    fn persist_before_exit(inner: &Arc<RwLock<NetworkController>>, config: &Config) {
+        if config.get_utxo_flush_interval().is_some() {
+            if let Err(e) = Self::flush_utxo_snapshot(inner, config) {
+                config.log(&format!("Could not flush utxo snapshot: {}", e), QUIET);
+            }
+        }
+        if let Err(e) = Self::save_mempool(inner, config) {
+            config.log(&format!("Could not save mempool file: {}", e), QUIET);
+        }
+    }
+
+    /// Persists the state like `persist_before_exit` when the node is interrupted or terminated, then exits
+    fn persist_on_interrupt(&self, config: Config) {
+        let inner = self.inner.clone();
+        let handler_config = config.clone();
+        let handled = ctrlc::set_handler(move || {
+            Self::persist_before_exit(&inner, &handler_config);
+            std::process::exit(0);
+        });
+        if let Err(e) = handled {
+            config.log(&format!("Could not handle interruptions: {}", e), QUIET);
+        }
+    }
+
+    /// Persists a snapshot of the utxo set every `utxo_flush_interval` seconds, so a restart resumes from it.
+    /// The set is serialized under the read lock and written to disk once it's released, then the blocks
+    /// the snapshot makes unnecessary are pruned.
+    fn flush_utxo_periodically(&self, config: Config) {
+        let interval = match config.get_utxo_flush_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(std::time::Duration::from_secs(interval));
+                let tip = match Self::flush_utxo_snapshot(&inner, &config) {
+                    Ok(tip) => tip,
+                    Err(e) => {
+                        config.log(&format!("Could not flush utxo snapshot: {}", e), QUIET);
+                        continue;
+                    }
+                };
+                let mut inner_write = inner.write().map_err(to_io_err)?;
+                let pruned = inner_write.prune_blocks(tip.height, &config);
+                let file_lock = inner_write.blocks_file_lock.clone();
+                drop(inner_write);
+                if let Err(e) = prune_blocks_file(&file_lock, &pruned, &config) {
+                    config.log(&format!("Could not prune blocks: {}", e), QUIET);
+                }
+            }
+        });
+    }
+
+    /// Checks every `header_stall_timeout_seconds` whether header sync stalled, re-requesting headers from another peer if so.
+    fn detect_header_stalls_periodically(&self, config: Config) {
+        let timeout = match config.get_header_stall_timeout() {
+            Some(timeout) => Duration::from_secs(timeout),
+            None => return,
+        };
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(timeout);
+                let mut inner = inner.write().map_err(to_io_err)?;
+                if let Err(e) = inner.retry_stalled_headers(timeout, &config) {
+                    config.log(&format!("Could not re-request headers: {}", e), QUIET);
+                }
+            }
+        });
+    }
+
+    /// Checks every second for broadcasted transactions that weren't relayed back within the propagation timeout,
+    /// only locking the controller for writing when there's one to report.
+    fn report_unpropagated_txs_periodically(&self, config: Config) {
+        let timeout = match config.get_broadcast_propagation_timeout() {
+            Some(timeout) => Duration::from_secs(timeout),
+            None => return,
+        };
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let now = Instant::now();
+                if !inner
+                    .read()
+                    .map_err(to_io_err)?
+                    .sent_txs
+                    .has_unpropagated(timeout, now)
+                {
+                    continue;
+                }
+                let mut inner = inner.write().map_err(to_io_err)?;
+                if let Err(e) = inner.report_unpropagated_txs(timeout) {
+                    config.log(
+                        &format!("Could not report unpropagated transactions: {}", e),
+                        QUIET,
+                    );
+                }
+            }
+        });
+    }
+
+    /// Checks every minute for broadcasted transactions that have been pending for longer than the abandon timeout.
+    fn abandon_stale_txs_periodically(&self, config: Config) {
+        let timeout = match config.get_abandon_pending_tx_timeout() {
+            Some(timeout) => Duration::from_secs(timeout),
+            None => return,
+        };
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(Duration::from_secs(60));
+                let mut inner = inner.write().map_err(to_io_err)?;
+                if let Err(e) = inner.abandon_stale_txs(timeout) {
+                    config.log(
+                        &format!("Could not abandon stale transactions: {}", e),
+                        QUIET,
+                    );
+                }
+            }
+        });
+    }
+
+    /// Saves the pending transactions to the mempool file every minute, so a restart reads them back
+    fn save_mempool_periodically(&self, config: Config) {
+        if config.get_mempool_file().is_none() {
+            return;
+        }
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(Duration::from_secs(60));
+                if let Err(e) = Self::save_mempool(&inner, &config) {
+                    config.log(&format!("Could not save mempool file: {}", e), QUIET);
+                }
+            }
+        });
+    }
+
+    /// Sends the queued block requests once the download rate allows it or a peer has room for them, which it
+    /// also gets when a peer at its limit disconnects.
+    fn send_queued_block_requests_periodically(&self, config: Config) {
+        let inner = self.inner.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let mut inner = inner.write().map_err(to_io_err)?;
+                if let Err(e) = inner.send_queued_block_requests(&config) {
+                    config.log(&format!("Could not request blocks: {}", e), QUIET);
+                }
+            }
+        });
+    }
+
+    /// Checks the sync progress, notifying the user when neither headers nor blocks advance for
+    /// `sync_stall_timeout_seconds` and connecting to new peers if `sync_stall_reconnect` is set.
+    fn watch_sync_progress(&self, config: Config) {
+        let timeout = match config.get_sync_stall_timeout() {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let inner = self.inner.clone();
+        let senders = (self.writer_chanel.clone(), self.ui_sender.clone());
+        let mut watchdog = SyncWatchdog::new(Duration::from_secs(timeout), Instant::now());
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                thread::sleep(Duration::from_secs((timeout / 4).max(1)));
+                let inner_read = inner.read().map_err(to_io_err)?;
+                let (heights, syncing) = inner_read.sync_progress();
+                if !watchdog.stalled(heights, syncing, Instant::now()) {
+                    continue;
+                }
+                inner_read.report_sync_stall(timeout, &config);
+                drop(inner_read);
+                if !config.get_sync_stall_reconnect() {
+                    continue;
+                }
+                if let Err(e) = Self::resume_sync_with_new_peers(&inner, senders.clone(), &config) {
+                    config.log(&format!("Could not resume sync: {}", e), QUIET);
+                }
+            }
+        });
+    }
+
+    /// Connects to the peers found through the seed that aren't connected yet, and resumes sync with them
+    fn resume_sync_with_new_peers(
+        t_inner: &Arc<RwLock<NetworkController>>,
+        senders: (mpsc::SyncSender<(SocketAddr, Message)>, UiSender),
+        config: &Config,
+    ) -> io::Result<()> {
+        let (writer_end, ui_sender) = senders;
+        let (known_peers, block_relay_only, ibd_complete) = {
+            let inner = t_inner.read().map_err(to_io_err)?;
+            let block_relay_connections = config.get_block_relay_connections();
+            (
+                inner.nodes.peers(),
+                inner
+                    .nodes
+                    .missing_block_relay_only(block_relay_connections),
+                inner.ibd.is_complete(),
+            )
+        };
+        let new_nodes = NodeController::connect_to_new_peers(
+            &known_peers,
+            block_relay_only,
+            ibd_complete,
+            writer_end,
+            ui_sender,
+            config,
+        )?;
+        t_inner
+            .write()
+            .map_err(to_io_err)?
+            .resume_sync(new_nodes, config)
+    }
+
+    /// Blocks until the tallest block reaches the given height and returns its hash,
+    /// failing with `TimedOut` if it doesn't within the timeout. The controller isn't locked meanwhile.
+    pub fn wait_for_height(
+        t_inner: &Arc<RwLock<NetworkController>>,
+        height: usize,
+        timeout: Duration,
+    ) -> io::Result<HashId> {
+        let chain_tip = t_inner.read().map_err(to_io_err)?.chain_tip.clone();
+        chain_tip.wait_for_height(height, timeout)
+    }
+
+    /// Answers the RPC requests on `rpc_port` of localhost, each connection on its own thread since requests
+    /// block until a block arrives. Waiting only holds the chain tip, the controller is only read for peer info.
+    fn serve_rpc(&self, config: &Config) -> io::Result<()> {
+        let Some(port) = config.get_rpc_port() else {
+            return Ok(());
+        };
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+        let chain_tip = self.inner.read().map_err(to_io_err)?.chain_tip.clone();
+        let inner = self.inner.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let chain_tip = chain_tip.clone();
+                let inner = inner.clone();
+                let peer_info = move || Ok(inner.read().map_err(to_io_err)?.nodes.peer_info());
+                thread::spawn(move || rpc::serve_connection(stream, &chain_tip, peer_info));
+            }
+        });
+        Ok(())
+    }
+
+    /// Exits the node once the tallest block reaches `stop_at_height`, persisting its state first.
+    fn stop_at_height(&self, config: Config) {
+        let height = match config.get_stop_at_height() {
+            Some(height) => height,
+            None => return,
+        };
+        let inner = self.inner.clone();
+        thread::spawn(move || loop {
+            if let Ok(hash) = Self::wait_for_height(&inner, height, Duration::from_secs(60)) {
+                config.log(
+                    &format!("Reached block {} at height {}, stopping", hash, height),
+                    QUIET,
+                );
+                Self::persist_before_exit(&inner, &config);
+                std::process::exit(0);
+            }
+        });
+    }
+
+    /// Logs the confirmations of wallet transactions as blocks including them arrive.
+    fn log_tx_confirmations(&self, config: Config) -> io::Result<()> {
+        let confirmations = self
+            .inner
+            .write()
+            .map_err(to_io_err)?
+            .subscribe_tx_confirmed();
+        thread::spawn(move || {
+            for event in confirmations {
+                config.log(
+                    &format!(
+                        "Transaction {} confirmed in block {} at height {}",
+                        event.txid, event.block_hash, event.height
+                    ),
+                    QUIET,
+                );
             }
         });
         Ok(())
@@ -620,14 +2608,12 @@ impl OuterNetworkController {
         let mut inner_lock = t_inner.write().map_err(to_io_err)?;
         inner_lock.active_wallet = wallet;
 
-        let curr_active_wallet = inner_lock.active_wallet.clone();
         inner_lock.update_ui_balance()?;
 
-        if let Some(wallet) = inner_lock.wallets.get(&curr_active_wallet) {
-            let transactions = wallet.get_last_n_transactions(20);
+        if let Some(transactions) = inner_lock.overview_transactions() {
             _ = inner_lock
                 .ui_sender
-                .send(GtkMessage::UpdateOverviewTransactions(transactions));
+                .update_overview_transactions(transactions);
         }
 
         Ok(())
@@ -642,6 +2628,14 @@ impl OuterNetworkController {
         inner_lock.generate_transaction(transaction_info, &config)
     }
 
+    fn handle_ui_preview_transaction(
+        t_inner: Arc<RwLock<NetworkController>>,
+        transaction_info: TransactionInfo,
+    ) -> io::Result<()> {
+        let inner_lock = t_inner.read().map_err(to_io_err)?;
+        inner_lock.preview_transaction(transaction_info)
+    }
+
     fn handle_ui_get_poi(
         t_inner: Arc<RwLock<NetworkController>>,
         block_hash: String,
@@ -651,6 +2645,40 @@ impl OuterNetworkController {
         inner_lock.get_proof_of_inclusion(block_hash, tx_hash)
     }
 
+    fn handle_ui_request(
+        t_inner: Arc<RwLock<NetworkController>>,
+        request: ModelRequest,
+        config: &Config,
+    ) -> io::Result<()> {
+        match request {
+            ModelRequest::GenerateTransaction(transaction_info) => {
+                Self::handle_ui_generate_transaction(t_inner, transaction_info, config.clone())
+            }
+            ModelRequest::PreviewTransaction(transaction_info) => {
+                Self::handle_ui_preview_transaction(t_inner, transaction_info)
+            }
+            ModelRequest::ChangeActiveWallet(wallet) => {
+                Self::handle_ui_change_active_wallet(t_inner, wallet)
+            }
+            ModelRequest::GetPoi(block_hash, tx_hash) => {
+                _ = Self::handle_ui_get_poi(t_inner, block_hash, tx_hash);
+                Ok(())
+            }
+            ModelRequest::ImportAddress(address, from_height) => t_inner
+                .write()
+                .map_err(to_io_err)?
+                .import_address(address, from_height, config),
+            ModelRequest::RebuildHistory(address) => t_inner
+                .write()
+                .map_err(to_io_err)?
+                .rebuild_history(&address, config),
+            ModelRequest::SignPartialTransaction(address) => t_inner
+                .read()
+                .map_err(to_io_err)?
+                .sign_partial_transaction(&address, config),
+        }
+    }
+
     fn recv_ui_messages(
         &self,
         ui_receiver: Receiver<ModelRequest>,
@@ -659,23 +2687,8 @@ impl OuterNetworkController {
         let inner = self.inner.clone();
         thread::spawn(move || -> io::Result<()> {
             loop {
-                let t_inner: Arc<RwLock<NetworkController>> = inner.clone();
-                match ui_receiver.recv().map_err(to_io_err)? {
-                    ModelRequest::GenerateTransaction(transaction_info) => {
-                        Self::handle_ui_generate_transaction(
-                            t_inner,
-                            transaction_info,
-                            config.clone(),
-                        )
-                    }
-                    ModelRequest::ChangeActiveWallet(wallet) => {
-                        Self::handle_ui_change_active_wallet(t_inner, wallet)
-                    }
-                    ModelRequest::GetPoi(block_hash, tx_hash) => {
-                        _ = Self::handle_ui_get_poi(t_inner, block_hash, tx_hash);
-                        Ok(())
-                    }
-                }?;
+                let request = ui_receiver.recv().map_err(to_io_err)?;
+                Self::handle_ui_request(inner.clone(), request, &config)?;
             }
         });
         Ok(())
@@ -683,50 +2696,78 @@ impl OuterNetworkController {
 
     fn handle_node_block_message(
         t_inner: Arc<RwLock<NetworkController>>,
-        mut block: Block,
+        peer_addr: SocketAddr,
+        block: Block,
         config: &Config,
     ) -> io::Result<()> {
+        t_inner
+            .write()
+            .map_err(to_io_err)?
+            .block_delivered(&block.hash(), config)?;
         let inner_read = t_inner.read().map_err(to_io_err)?;
         if inner_read.valid_blocks.contains_key(&block.hash())
             || inner_read.blocks_on_hold.contains_key(&block.hash())
         {
             return Ok(());
         }
-        if block.validate().is_err() {
+        if block.validate(config.get_network_params()).is_err() {
+            return Ok(());
+        }
+        // the height of a block is only known from its header, and segwit is active on the tip otherwise
+        let segwit = inner_read
+            .headers
+            .get(&block.hash())
+            .is_none_or(|header| config.get_network_params().soft_forks(header.height).segwit);
+        if config.get_download_witness() && block.validate_witness_commitment(segwit).is_err() {
             return Ok(());
         }
-        block.save_to_file(config.get_blocks_file())?;
+        let file_lock = inner_read.blocks_file_lock.lock().map_err(to_io_err)?;
+        block.save_to_file(config.get_blocks_file(), config.get_compress_backups())?;
+        drop(file_lock);
         drop(inner_read);
 
-        let mut inner_write = t_inner.write().map_err(to_io_err)?;
-        if let Some(previous_block) = inner_write.valid_blocks.get(&block.header.prev_block_hash) {
-            block.header.height = previous_block.header.height + 1;
-            if let Vacant(entry) = inner_write.headers.entry(block.hash()) {
-                entry.insert(block.header);
-                if block.header.height > inner_write.tallest_header.height {
-                    inner_write.tallest_header = block.header;
-                    inner_write.update_best_header_chain();
-                }
+        t_inner
+            .write()
+            .map_err(to_io_err)?
+            .accept_block(block, peer_addr, config)
+    }
+
+    /// Handles the compact block relay messages, passing on the blocks they complete like full blocks
+    fn handle_node_compact_block_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        message: Message,
+        config: &Config,
+    ) -> io::Result<()> {
+        let mut inner = t_inner.write().map_err(to_io_err)?;
+        let block = match message {
+            Message::CompactBlock(compact_block) => {
+                inner.reconstruct_compact_block(compact_block, peer_addr, config)?
             }
-            let block_hash = block.hash();
-            // add to on-hold and then validate as many on-hold blocks as possible
-            inner_write.blocks_on_hold.insert(block_hash, block);
-            inner_write.add_to_valid_blocks(block_hash, config);
-        } else {
-            inner_write.put_block_on_hold(block);
+            Message::BlockTxn(blocktxn) => inner.complete_compact_block(blocktxn, peer_addr),
+            Message::GetBlockTxn(getblocktxn) => {
+                inner.send_block_txns(getblocktxn, peer_addr, config)?;
+                None
+            }
+            _ => None,
+        };
+        drop(inner);
+        match block {
+            Some(block) => Self::handle_node_block_message(t_inner, peer_addr, block, config),
+            None => Ok(()),
         }
-        Ok(())
     }
 
     fn handle_headers_message_info(
         config: &Config,
         inner_read: RwLockReadGuard<'_, NetworkController>,
-        ui_sender: &SyncSender<GtkMessage>,
+        ui_sender: &dyn UiNotifier,
     ) -> io::Result<()> {
         config.log(
             &format!(
-                "Read headers. New header count: {:?}",
-                inner_read.headers.len()
+                "Read headers. New header count: {:?}, held ahead of the chain: {}",
+                inner_read.headers.len(),
+                inner_read.detached_headers.len()
             ),
             VERBOSE,
         );
@@ -740,7 +2781,7 @@ impl OuterNetworkController {
         let genesis_block_timestamp = 1231006500; // 2009-01-03T18:15Z
         let progress = (most_recent_timestamp - genesis_block_timestamp) as f64
             / (Utc::now().timestamp() - genesis_block_timestamp) as f64;
-        _ = update_ui_progress_bar(ui_sender, Some(&msg), progress);
+        _ = ui_sender.update_progress(Some(&msg), progress);
         Ok(())
     }
 
@@ -754,42 +2795,65 @@ impl OuterNetworkController {
         inner_write.try_request_trimmed_blocks(headers, config)
     }
 
-    fn handle_node_headers_message(
-        t_inner: Arc<RwLock<NetworkController>>,
+    /// Saves the headers that connect to the chain to the header set and backup file, returns the new ones
+    fn store_new_headers(
+        t_inner: &Arc<RwLock<NetworkController>>,
         headers: Headers,
         config: &Config,
-        ui_sender: &SyncSender<GtkMessage>,
-    ) -> io::Result<()> {
-        let mut inner_read = t_inner.read().map_err(to_io_err)?;
-        let prev_header_count = inner_read.headers.len();
-        // save new headers to hashmap and backup file
+    ) -> io::Result<Vec<BlockHeader>> {
         let mut new_headers: Vec<BlockHeader> = vec![];
-        for mut header in headers.block_headers {
-            if inner_read.headers.contains_key(&header.hash()) {
-                continue;
-            }
-            match inner_read.headers.get(&header.prev_block_hash) {
-                Some(parent_header) => header.height = parent_header.height + 1,
-                None => continue, // ignore header if prev_header is unknown
-            }
-            drop(inner_read);
+        let mut range_connected = false;
+        for header in headers.block_headers {
             let mut inner_write = t_inner.write().map_err(to_io_err)?;
-            inner_write.headers.insert(header.hash(), header);
-            header.save_to_file(config.get_headers_file())?;
-            new_headers.push(header);
-            if header.height > inner_write.tallest_header.height {
-                inner_write.tallest_header = header
+            let ready_headers = inner_write.arrange_header(header);
+            range_connected |= ready_headers.len() > 1;
+            for mut ready_header in ready_headers {
+                if !inner_write.connect_header(&mut ready_header, config) {
+                    break;
+                }
+                inner_write.store_header(ready_header, config)?;
+                new_headers.push(ready_header);
             }
-            drop(inner_write);
-            inner_read = t_inner.read().map_err(to_io_err)?;
         }
-        if prev_header_count == inner_read.headers.len() {
+        if range_connected {
+            // the sync peer may have disconnected, the stall detection then picks another one
+            _ = t_inner
+                .write()
+                .map_err(to_io_err)?
+                .continue_header_sync(config);
+        }
+        Ok(new_headers)
+    }
+
+    fn handle_node_headers_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        headers: Headers,
+        config: &Config,
+        ui_sender: &dyn UiNotifier,
+    ) -> io::Result<()> {
+        if headers.is_truncated() {
+            let parsed = headers.block_headers.len();
+            let message = format!(
+                "Truncated headers from {}: read {} of {}, requesting the rest",
+                peer_addr, parsed, headers.count
+            );
+            config.log(&message, VERBOSE);
+        }
+        t_inner
+            .write()
+            .map_err(to_io_err)?
+            .headers_received(peer_addr, &headers);
+        let new_headers = Self::store_new_headers(&t_inner, headers, config)?;
+        if new_headers.is_empty() {
             return Ok(());
         }
+        let inner_read = t_inner.read().map_err(to_io_err)?;
         _ = Self::handle_headers_message_info(config, inner_read, ui_sender);
-        let mut inner_write = t_inner.write().map_err(to_io_err)?;
-        inner_write.update_best_header_chain();
-        drop(inner_write);
+        t_inner
+            .write()
+            .map_err(to_io_err)?
+            .update_best_header_chain();
 
         // request blocks mined after given date
         Self::try_request_trimmed_blocks(t_inner, new_headers, config)
@@ -801,29 +2865,93 @@ impl OuterNetworkController {
         inventories: InventoryVector,
         config: &Config,
     ) -> io::Result<()> {
-        let mut filtered_inv: Vec<Inventory> = Vec::new();
-        for inventory in inventories.items {
-            if inventory.inv_type == InvType::MSGTx || inventory.inv_type == InvType::MSGBlock {
-                filtered_inv.push(inventory);
-            }
+        let mut inner_write = t_inner.write().map_err(to_io_err)?;
+        let (mut blocks, mut txs) = inventories.split_blocks_and_txs();
+        if !config.is_sync_peer(&peer_addr) {
+            blocks.clear();
         }
-        if filtered_inv.is_empty() {
+        if inner_write.nodes.is_block_relay_only(&peer_addr) {
+            txs.clear();
+        }
+        txs.retain(|inventory| {
+            !inner_write.is_own_relayed_back(&inventory.hash, peer_addr, config)
+        });
+        if blocks.is_empty() && txs.is_empty() {
             return Ok(());
         }
 
-        let getdata_message = GetData::new(InventoryVector::new(filtered_inv));
-        let mut inner_write = t_inner.write().map_err(to_io_err)?;
+        if !config.get_relay() {
+            for inventory in &txs {
+                inner_write
+                    .tx_requests
+                    .requested(inventory.hash, peer_addr, Instant::now());
+            }
+        }
+        // witness data isn't sent unless asked for
+        let mut getdata_message = GetData::typed(block_inv_type(config), blocks);
+        let tx_requests = GetData::typed(InvType::MSGWitnessTx, txs);
+        getdata_message
+            .inventory
+            .items
+            .extend(tx_requests.inventory.items);
         _ = inner_write
             .nodes
             .send_to_specific(&peer_addr, &getdata_message.serialize()?, config);
         Ok(())
     }
 
+    /// Requests again, from another peer if one has room, the blocks the peer answered it doesn't have, and
+    /// forgets the requests of the transactions it doesn't have
+    fn handle_node_notfound_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        inventories: InventoryVector,
+        config: &Config,
+    ) -> io::Result<()> {
+        let mut inner_write = t_inner.write().map_err(to_io_err)?;
+        let (blocks, txs) = inventories.split_blocks_and_txs();
+        for inventory in txs {
+            inner_write.tx_requests.not_found(inventory.hash, peer_addr);
+        }
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let hashes: Vec<HashId> = blocks.iter().map(|inventory| inventory.hash).collect();
+        inner_write.block_requests.not_found(&peer_addr, &hashes);
+        inner_write.send_queued_block_requests(config)
+    }
+
+    /// Stores a transaction received from a peer and, if it's new, announces it to the other peers.
     fn handle_node_tx_message(
         t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
         tx: RawTransaction,
+        config: &Config,
     ) -> io::Result<()> {
-        t_inner.write().map_err(to_io_err)?.read_pending_tx(tx)
+        let mut inner = t_inner.write().map_err(to_io_err)?;
+        let txid = tx.txid();
+        if inner.is_own_relayed_back(&txid, peer_addr, config)
+            || inner.is_unsolicited(&txid, peer_addr, config)
+        {
+            return Ok(());
+        }
+        if let Err(e) = tx
+            .check_no_duplicate_inputs()
+            .and_then(|_| tx.check_push_only_inputs())
+            .and_then(|_| {
+                config
+                    .get_max_tx_sigops()
+                    .map_or(Ok(()), |max_sigops| tx.check_sigops(max_sigops))
+            })
+        {
+            config.log(&format!("Rejected transaction {}: {}", txid, e), VERBOSE);
+            return Ok(());
+        }
+        log_nonstandard_outputs(&tx, config);
+        if !inner.is_final_or_hold(&tx, peer_addr, config) {
+            return Ok(());
+        }
+        inner.accept_transaction(tx, peer_addr, config)
     }
 
     pub fn handle_getheaders_message(
@@ -847,39 +2975,134 @@ impl OuterNetworkController {
         Ok(())
     }
 
-    pub fn handle_node_getdata_message(
+    pub fn handle_node_getdata_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        getdata: GetData,
+        config: &Config,
+    ) -> io::Result<()> {
+        config.log(
+            &format!("GetData request: {:?} from peer {:?}", getdata, peer_addr),
+            VERBOSE,
+        );
+        let mut inner_write = t_inner.write().map_err(to_io_err)?;
+        let mut replies: Vec<Vec<u8>> = Vec::new();
+        let mut not_found = vec![];
+        for inventory in getdata.inventory.items {
+            if inner_write.is_historical_block(&inventory)
+                && inner_write.nodes.upload_target_reached()
+            {
+                not_found.push(inventory);
+            } else if let Some(reply) = inner_write.getdata_reply(&inventory) {
+                replies.push(reply?);
+            }
+        }
+        if !not_found.is_empty() {
+            replies.push(InventoryVector::new(not_found).serialize_notfound()?);
+        }
+
+        for reply in replies {
+            inner_write
+                .nodes
+                .send_to_specific(&peer_addr, &reply, config)?;
+        }
+        Ok(())
+    }
+
+    /// Records the preferences a peer sent on how transactions and blocks are announced to it
+    fn handle_node_preference_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        message: Message,
+    ) -> io::Result<()> {
+        let nodes = &mut t_inner.write().map_err(to_io_err)?.nodes;
+        match message {
+            Message::SendCmpct(sendcmpct) => nodes.update_compact_relay(&peer_addr, &sendcmpct),
+            Message::FeeFilter(feefilter) => nodes.update_fee_filter(&peer_addr, &feefilter),
+            Message::SendHeaders(_) => nodes.update_prefers_headers(&peer_addr),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// In whitelist mode, transactions and announcements are only accepted from whitelisted peers,
+    /// while headers and blocks from other peers are accepted only if they can be used for syncing,
+    /// which is only the trusted peer if there's one.
+    /// Logs the messages that are ignored.
+    fn is_relay_allowed((peer_addr, message): &(SocketAddr, Message), config: &Config) -> bool {
+        let allowed = match message {
+            Message::Transaction(_) | Message::Inv(_) => config.is_whitelisted(peer_addr),
+            Message::Headers(_)
+            | Message::Block(_)
+            | Message::CompactBlock(_)
+            | Message::BlockTxn(_) => config.is_sync_peer(peer_addr),
+            _ => true,
+        };
+        if !allowed {
+            config.log(
+                &format!(
+                    "Ignoring relayed message from non whitelisted or untrusted peer {}",
+                    peer_addr
+                ),
+                VERBOSE,
+            );
+        }
+        allowed
+    }
+
+    fn handle_node_message(
         t_inner: Arc<RwLock<NetworkController>>,
-        peer_addr: SocketAddr,
-        getdata: GetData,
+        message: (SocketAddr, Message),
         config: &Config,
+        ui_sender: &dyn UiNotifier,
     ) -> io::Result<()> {
-        config.log(
-            &format!("GetHeader request: {:?} from peer {:?}", getdata, peer_addr),
-            VERBOSE,
-        );
-        let mut inner_write = t_inner.write().map_err(to_io_err)?;
-        let mut blocks: Vec<Block> = Vec::new();
-        for inventory in getdata.inventory.items {
-            if inventory.inv_type == InvType::MSGBlock {
-                let block = match inner_write.valid_blocks.get(&inventory.hash) {
-                    Some(block) => block.clone(),
-                    None => continue,
-                };
-                blocks.push(block);
-            }
-        }
-
-        if blocks.is_empty() {
+        if !Self::is_relay_allowed(&message, config) {
             return Ok(());
         }
+        match message {
+            (peer_addr, Message::Headers(headers)) => {
+                Self::handle_node_headers_message(t_inner, peer_addr, headers, config, ui_sender)
+            }
+            (p_addr, Message::GetHeader(get_headers)) => {
+                Self::handle_getheaders_message(t_inner, p_addr, get_headers, config)
+            }
+            (peer_addr, Message::Block(block)) => {
+                Self::handle_node_block_message(t_inner, peer_addr, block, config)
+            }
+            (peer_addr, Message::GetData(get_data)) => {
+                Self::handle_node_getdata_message(t_inner, peer_addr, get_data, config)
+            }
+            (peer_addr, Message::Inv(inventories)) => {
+                Self::handle_node_inv_message(t_inner, peer_addr, inventories, config)
+            }
+            (peer_addr, Message::NotFound(inventories)) => {
+                Self::handle_node_notfound_message(t_inner, peer_addr, inventories, config)
+            }
+            (peer_addr, Message::Transaction(tx)) => {
+                Self::handle_node_tx_message(t_inner, peer_addr, tx, config)
+            }
+            (peer_addr, message) => {
+                Self::handle_node_other_message(t_inner, peer_addr, message, config)
+            }
+        }
+    }
 
-        for block in blocks {
-            let serialized_block = block.serialize_message()?;
-            inner_write
-                .nodes
-                .send_to_specific(&peer_addr, &serialized_block, config)?;
+    /// Handles the peer preferences and the compact block relay messages
+    fn handle_node_other_message(
+        t_inner: Arc<RwLock<NetworkController>>,
+        peer_addr: SocketAddr,
+        message: Message,
+        config: &Config,
+    ) -> io::Result<()> {
+        match message {
+            Message::SendCmpct(_) | Message::FeeFilter(_) | Message::SendHeaders(_) => {
+                Self::handle_node_preference_message(t_inner, peer_addr, message)
+            }
+            message if message.is_compact_block_relay() => {
+                Self::handle_node_compact_block_message(t_inner, peer_addr, message, config)
+            }
+            _ => Ok(()), // unexpected messages were already filtered by node listeners
         }
-        Ok(())
     }
 
     fn recv_node_messages(
@@ -892,25 +3115,10 @@ impl OuterNetworkController {
         let handle = thread::spawn(move || -> io::Result<()> {
             loop {
                 let t_inner: Arc<RwLock<NetworkController>> = inner.clone();
-                if let Err(result) = match node_receiver.recv().map_err(to_io_err)? {
-                    (_, Message::Headers(headers)) => {
-                        Self::handle_node_headers_message(t_inner, headers, &config, &ui_sender)
-                    }
-                    (p_addr, Message::GetHeader(get_headers)) => {
-                        Self::handle_getheaders_message(t_inner, p_addr, get_headers, &config)
-                    }
-                    (_, Message::Block(block)) => {
-                        Self::handle_node_block_message(t_inner, block, &config)
-                    }
-                    (peer_addr, Message::GetData(get_data)) => {
-                        Self::handle_node_getdata_message(t_inner, peer_addr, get_data, &config)
-                    }
-                    (peer_addr, Message::Inv(inventories)) => {
-                        Self::handle_node_inv_message(t_inner, peer_addr, inventories, &config)
-                    }
-                    (_, Message::Transaction(tx)) => Self::handle_node_tx_message(t_inner, tx),
-                    _ => Ok(()), // unexpected messages were already filtered by node listeners
-                } {
+                let message = node_receiver.recv().map_err(to_io_err)?;
+                if let Err(result) =
+                    Self::handle_node_message(t_inner, message, &config, ui_sender.as_ref())
+                {
                     config.log(&format!("Received unhandled error: {:?}", result), QUIET);
                     return Err(result);
                 }
@@ -919,33 +3127,62 @@ impl OuterNetworkController {
         Ok(handle)
     }
 
+    /// Answers the handshake of an incoming connection and adds it to the connected nodes
+    fn accept_node(
+        inner: &Arc<RwLock<NetworkController>>,
+        mut stream: TcpStream,
+        writer_channel: mpsc::SyncSender<(SocketAddr, Message)>,
+        ui_sender: UiSender,
+        config: Config,
+    ) -> io::Result<()> {
+        let peer_version = match Node::inverse_handshake(&mut stream, config.get_relay()) {
+            Ok(peer_version) => peer_version,
+            Err(..) => return Ok(()),
+        };
+        let address = stream.peer_addr()?;
+        let node = Node::spawn(
+            stream,
+            address,
+            peer_version,
+            writer_channel,
+            ui_sender,
+            config,
+        )?;
+        inner.write().map_err(to_io_err)?.nodes.add_node(node);
+        Ok(())
+    }
+
     fn listen_for_nodes(&self, config: Config) -> io::Result<()> {
         let inner = self.inner.clone();
-        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, config.get_listening_port());
-        let listener = TcpListener::bind(addr).unwrap_or_else(|e| {
-            eprintln!("Ignoring Error: {:?}", e);
-            std::process::exit(1);
-        });
+        let listener = match bind_listener(config.get_listen(), config.get_listening_port()) {
+            Ok(Some(listener)) => listener,
+            Ok(None) => {
+                config.log("Not listening for incoming connections", VERBOSE);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Ignoring Error: {:?}", e);
+                std::process::exit(1);
+            }
+        };
         let ui_sender = self.ui_sender.clone();
         let writer_channel = self.writer_chanel.clone();
         thread::spawn(move || -> io::Result<()> {
             for stream in listener.incoming() {
                 match stream {
-                    Ok(mut stream) => {
-                        let ui_sender = ui_sender.clone();
-                        if Node::inverse_handshake(&mut stream).is_err() {
-                            continue;
-                        }
-                        let node = Node::spawn(stream, writer_channel.clone(), ui_sender, config.clone())?;
-                        inner.write().map_err(to_io_err)?.nodes.add_node(node);
-                    }
+                    Ok(stream) => Self::accept_node(
+                        &inner,
+                        stream,
+                        writer_channel.clone(),
+                        ui_sender.clone(),
+                        config.clone(),
+                    )?,
                     Err(e) => println!("Error: {}", e),
                 }
             }
             Ok(())
         });
         Ok(())
-
     }
 
     fn sync(&self, config: Config) -> io::Result<()> {
@@ -969,6 +3206,17 @@ impl OuterNetworkController {
         self.recv_ui_messages(ui_receiver, config.clone())?;
         self.recv_node_messages(node_receiver, config.clone())?;
         self.update_ui_data_periodically()?;
+        self.flush_utxo_periodically(config.clone());
+        self.detect_header_stalls_periodically(config.clone());
+        self.send_queued_block_requests_periodically(config.clone());
+        self.report_unpropagated_txs_periodically(config.clone());
+        self.abandon_stale_txs_periodically(config.clone());
+        self.save_mempool_periodically(config.clone());
+        self.persist_on_interrupt(config.clone());
+        self.log_tx_confirmations(config.clone())?;
+        self.stop_at_height(config.clone());
+        self.serve_rpc(&config)?;
+        self.watch_sync_progress(config.clone());
         self.sync(config)
     }
 }
@@ -976,19 +3224,946 @@ impl OuterNetworkController {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interface::components::overview_panel::TransactionRole;
+    use crate::interface::HeadlessUi;
     use crate::messages::constants::config::PORT;
     use crate::messages::version_message::Version;
     use crate::messages::{MessageHeader, VerAck};
+    use crate::raw_transaction::tx_input::CoinBaseInput;
+    use crate::raw_transaction::tx_output::TxOutput;
+    use crate::test_fixtures::{tx_from_hex, FUNDING_TX_HEX, PAYMENT_TX_HEX, SPENDING_TX_HEX};
     use gtk::glib;
-    use std::io::Write;
+    use std::io::{Cursor, Write};
     use std::net::TcpStream;
     use std::path::PathBuf;
     use std::sync::mpsc::SyncSender;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_listen_disabled_binds_no_socket() -> io::Result<()> {
+        let port = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        assert!(bind_listener(false, port)?.is_none());
+        // the port is still free, so nothing was bound on it
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+        assert!(bind_listener(true, port).is_err());
+        drop(listener);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_block_beyond_depth_is_dropped() {
+        let block = |prev: Option<&Block>, nonce: u32| {
+            let prev_hash = prev.map_or(HashId::default(), Block::hash);
+            let mut header = BlockHeader::new(1, prev_hash, None, HashId::default(), 0, 0, nonce);
+            header.height = prev.map_or(0, |prev| prev.header.height + 1);
+            Block::new(header, 0, vec![])
+        };
+        let root = block(None, 0);
+        let best = block(Some(&root), 0);
+        let stale = block(Some(&root), 1);
+        let tip = block(Some(&best), 0);
+        let blocks: BlockSet = [&root, &best, &stale, &tip]
+            .into_iter()
+            .map(|block| (block.hash(), block.clone()))
+            .collect();
+        let new_tip = block(Some(&tip), 0).header;
+
+        // the blocks at height 1 are 2 blocks below the new tip, only the one off its chain is dropped
+        assert!(stale_blocks(&blocks, &new_tip, 3).is_empty());
+        assert_eq!(
+            stale_blocks(&blocks, &new_tip, 2),
+            HashSet::from([stale.hash()])
+        );
+    }
+
+    /// Available, pending and immature balances, spendable one if enabled
+    type BalanceUpdate = ((u64, u64, u64), Option<u64>);
+
+    /// Records the balance updates and the kind and title of the notifications sent to the interface
+    #[derive(Default)]
+    struct RecordingUi {
+        balances: Mutex<Vec<BalanceUpdate>>,
+        notifications: Mutex<Vec<(NotificationKind, String)>>,
+    }
+
+    impl UiNotifier for RecordingUi {
+        fn update_balance(
+            &self,
+            balances: (u64, u64, u64),
+            spendable: Option<u64>,
+        ) -> io::Result<()> {
+            self.balances
+                .lock()
+                .map_err(to_io_err)?
+                .push((balances, spendable));
+            Ok(())
+        }
+
+        fn notify(&self, kind: NotificationKind, title: &str, _msg: &str) -> io::Result<()> {
+            self.notifications
+                .lock()
+                .map_err(to_io_err)?
+                .push((kind, title.to_string()));
+            Ok(())
+        }
+    }
+
+    /// Runs the test with a regtest controller without peers, configured in the given directory, whose only block
+    /// is the genesis. The directory is removed afterwards.
+    fn with_regtest_controller(
+        dir: &str,
+        ui_sender: UiSender,
+        test: impl FnOnce(NetworkController, Config) -> io::Result<()>,
+    ) -> io::Result<()> {
+        with_regtest_controller_options(dir, &[], ui_sender, test)
+    }
+
+    /// Like `with_regtest_controller`, adding the given lines to the config
+    fn with_regtest_controller_options(
+        dir: &str,
+        options: &[&str],
+        ui_sender: UiSender,
+        test: impl FnOnce(NetworkController, Config) -> io::Result<()>,
+    ) -> io::Result<()> {
+        fs::create_dir_all(format!("{}/wallets", dir))?;
+        let config_file = format!("{}/node.conf", dir);
+        // nothing listens on the seed, so the controller starts without peers
+        let mut config = vec![
+            "seed=127.0.0.1:1".to_string(),
+            "network=regtest".to_string(),
+            "start_timestamp=0".to_string(),
+            format!("wallets_dir={}/wallets", dir),
+            format!("log_file={}/node.log", dir),
+        ];
+        config.extend(options.iter().map(|option| option.to_string()));
+        fs::write(&config_file, config.join("\n"))?;
+        let result = Config::from_file(config_file.into()).and_then(|config| {
+            let (writer_end, _node_receiver) = mpsc::sync_channel(100);
+            let mut controller = NetworkController::new(ui_sender, writer_end, config.clone())?;
+            let genesis = Block::new(controller.tallest_block, 0, vec![]);
+            controller.valid_blocks.insert(genesis.hash(), genesis);
+            test(controller, config)
+        });
+        fs::remove_dir_all(dir)?;
+        result
+    }
+
+    /// Builds the block on top of the given one, whose coinbase has the given outputs
+    fn child_block(parent: &BlockHeader, tx_out: Vec<TxOutput>) -> Block {
+        let coinbase_input = CoinBaseInput {
+            _hash: HashId::default(),
+            _index: u32::MAX,
+            _script_bytes: 4,
+            height: parent.height as u32 + 1,
+            _coinbase_script: vec![],
+            _sequence: u32::MAX,
+        };
+        let coinbase = RawTransaction {
+            version: 1,
+            tx_in_count: 1,
+            tx_in: TxInputType::CoinBaseInput(coinbase_input),
+            tx_out_count: tx_out.len() as u64,
+            tx_out,
+            lock_time: 0,
+            witnesses: vec![],
+        };
+        let header = BlockHeader::new(1, parent.hash(), None, coinbase.txid(), 0, 0, 0);
+        Block::new(header, 1, vec![coinbase])
+    }
+
+    #[test]
+    fn test_stripped_block_download_skips_the_witness_commitment() -> io::Result<()> {
+        let dir = "test_stripped_blocks_node";
+        let blocks_file = format!("blocks_file={}/blocks.dat", dir);
+        let options = ["download_witness=false", &blocks_file];
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller_options(dir, &options, ui_sender, |controller, config| {
+            let parent = controller.tallest_block;
+            let getdata = GetData::from_inv(vec![parent], block_inv_type(&config));
+            assert_eq!(getdata.inventory.items[0].inv_type, InvType::MSGBlock);
+
+            // a segwit transaction in a block without a witness commitment
+            let bytes = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+            let mut txns = child_block(&parent, vec![]).txns;
+            txns.push(RawTransaction::from_bytes(&mut Cursor::new(&bytes))?);
+            let mut block = Block::new(parent, 2, txns);
+            let root = MerkleTree::generate_from_hashes(block.hash_transactions()).get_root();
+            block.header = BlockHeader::new(
+                1,
+                parent.hash(),
+                None,
+                HashId::new(root.to_byte_array()),
+                0,
+                0,
+                0,
+            );
+            assert!(block.validate_witness_commitment(true).is_err());
+
+            let source = SocketAddr::from(([127, 0, 0, 1], 18444));
+            let controller = Arc::new(RwLock::new(controller));
+            OuterNetworkController::handle_node_block_message(
+                controller.clone(),
+                source,
+                block.clone(),
+                &config,
+            )?;
+            let inner = controller.read().map_err(to_io_err)?;
+            assert!(inner.valid_blocks.contains_key(&block.hash()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_headless_controller_connects_a_block() -> io::Result<()> {
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller("test_headless_node", ui_sender, |mut controller, config| {
+            let block = child_block(&controller.tallest_block, vec![]);
+            let source = SocketAddr::from(([127, 0, 0, 1], 18444));
+            controller.accept_block(block.clone(), source, &config)?;
+            assert_eq!(controller.tallest_block.hash(), block.hash());
+            assert_eq!(controller.tallest_block.height, 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_connected_coinbase_updates_the_immature_balance() -> io::Result<()> {
+        let ui = Arc::new(RecordingUi::default());
+        let ui_sender: UiSender = ui.clone();
+        with_regtest_controller("test_balance_node", ui_sender, |mut controller, config| {
+            // P2PKH script of the active wallet, from its version byte, pubkey hash and checksum
+            let address = bs58::decode(&controller.active_wallet)
+                .into_vec()
+                .map_err(to_io_err)?;
+            let pk_script = [&[0x76, 0xa9, 0x14], &address[1..21], &[0x88, 0xac]].concat();
+            let output = TxOutput {
+                value: 5_000_000_000,
+                pk_script_bytes: pk_script.len() as u64,
+                pk_script,
+            };
+            let block = child_block(&controller.tallest_block, vec![output]);
+            let source = SocketAddr::from(([127, 0, 0, 1], 18444));
+            controller.accept_block(block, source, &config)?;
+            Ok(())
+        })?;
+        let balances = ui.balances.lock().map_err(to_io_err)?;
+        assert_eq!(*balances, vec![((0, 0, 5_000_000_000), None)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_sent_tx_is_abandoned_and_its_inputs_spendable_again() -> io::Result<()> {
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller("test_abandon_node", ui_sender, |mut controller, config| {
+            let address = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
+            let funding = tx_from_hex(FUNDING_TX_HEX)?;
+            funding.generate_utxo(
+                &mut controller.utxo_set,
+                TransactionOrigin::Block,
+                None,
+                None,
+            )?;
+            // spends the output of the funding transaction paying the address
+            let spending = tx_from_hex(SPENDING_TX_HEX)?;
+            controller.broadcast_transaction(spending.clone(), &config)?;
+            assert!(controller
+                .utxo_set
+                .get_wallet_available_utxos(address)
+                .is_empty());
+            assert_eq!(
+                controller.utxo_set.get_pending_wallet_balance(address),
+                1705366
+            );
+
+            // a pending transaction spending its change can't confirm without it
+            let mut child = tx_from_hex(PAYMENT_TX_HEX)?;
+            if let TxInputType::TxInput(inputs) = &mut child.tx_in {
+                inputs[0].previous_output.hash = spending.txid();
+            }
+            controller.tx_index.add_pending(child.clone());
+
+            // not confirmed nor relayed back by any peer when the timeout runs out
+            controller.abandon_stale_txs(Duration::ZERO)?;
+            let available = controller.utxo_set.get_wallet_available_utxos(address);
+            assert_eq!(available.len(), 1);
+            assert_eq!(
+                (available[0].0, available[0].1.value),
+                (funding.txid(), 1815366)
+            );
+            assert_eq!(controller.utxo_set.get_pending_wallet_balance(address), 0);
+            assert!(!controller.tx_index.contains(&spending.txid()));
+            assert!(!controller.tx_index.contains(&child.txid()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_only_saved_txs_valid_against_the_utxo_set_are_read_back() -> io::Result<()> {
+        let ui_sender = Arc::new(HeadlessUi);
+        let dir = "test_read_mempool_node";
+        let options = [format!("mempool_file={}/mempool.dat", dir)];
+        let options: Vec<&str> = options.iter().map(String::as_str).collect();
+        with_regtest_controller_options(dir, &options, ui_sender, |mut controller, config| {
+            let funding = tx_from_hex(FUNDING_TX_HEX)?;
+            funding.generate_utxo(
+                &mut controller.utxo_set,
+                TransactionOrigin::Block,
+                None,
+                None,
+            )?;
+            // spends an unknown output, and the funding output with a signature no longer matching it
+            let unknown = tx_from_hex(PAYMENT_TX_HEX)?;
+            let mut tampered = tx_from_hex(SPENDING_TX_HEX)?;
+            tampered.tx_out[0].value += 1;
+            let spending = tx_from_hex(SPENDING_TX_HEX)?;
+            let entries = [&unknown, &tampered, &spending]
+                .into_iter()
+                .map(|tx| MempoolEntry {
+                    tx: tx.clone(),
+                    received_at: actual_timestamp_or_default() as u32,
+                    sent: false,
+                })
+                .collect();
+            Mempool::new(entries).save_to_file(&format!("{}/mempool.dat", dir))?;
+
+            controller.read_mempool(&config)?;
+            assert!(controller.tx_index.contains(&spending.txid()));
+            assert!(!controller.tx_index.contains(&unknown.txid()));
+            assert!(!controller.tx_index.contains(&tampered.txid()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_getdata_for_our_broadcast_tx_serves_it() -> io::Result<()> {
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller(
+            "test_getdata_tx_node",
+            ui_sender,
+            |mut controller, config| {
+                let tx = tx_from_hex(FUNDING_TX_HEX)?;
+                controller.broadcast_transaction(tx.clone(), &config)?;
+
+                let reply =
+                    |inv_type| controller.getdata_reply(&Inventory::new(inv_type, tx.txid()));
+                assert_eq!(
+                    reply(InvType::MSGWitnessTx).transpose()?,
+                    Some(tx.build_message()?)
+                );
+                // peers asking without the witness get the transaction stripped of it
+                let stripped = reply(InvType::MSGTx).transpose()?;
+                assert_eq!(stripped, Some(tx.build_stripped_message()?));
+                assert_ne!(stripped, Some(tx.build_message()?));
+                let unknown = Inventory::new(InvType::MSGTx, HashId::default());
+                assert!(controller.getdata_reply(&unknown).is_none());
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_overview_shows_the_configured_number_of_transactions() -> io::Result<()> {
+        let options = ["overview_tx_count=5"];
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller_options(
+            "test_overview_node",
+            &options,
+            ui_sender,
+            |mut controller, _| {
+                let active_wallet = controller.active_wallet.clone();
+                let wallet = controller
+                    .wallets
+                    .get_mut(&active_wallet)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No active wallet"))?;
+                let transactions: Vec<TransactionDisplayInfo> = (0..8)
+                    .map(|i| TransactionDisplayInfo {
+                        role: TransactionRole::Receiver,
+                        origin: TransactionOrigin::Block,
+                        date: "date".to_string(),
+                        amount: 10,
+                        hash: HashId::new([i; 32]),
+                    })
+                    .collect();
+                wallet.history = transactions[..3].to_vec();
+                // a history shorter than the count is shown whole
+                assert_eq!(
+                    controller.overview_transactions(),
+                    Some(transactions[..3].to_vec())
+                );
+
+                if let Some(wallet) = controller.wallets.get_mut(&active_wallet) {
+                    wallet.history = transactions.clone();
+                }
+                assert_eq!(
+                    controller.overview_transactions(),
+                    Some(transactions[3..].to_vec())
+                );
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_sending_to_a_used_address_warns_about_reuse() -> io::Result<()> {
+        let ui = Arc::new(RecordingUi::default());
+        let ui_sender: UiSender = ui.clone();
+        with_regtest_controller("test_reuse_node", ui_sender, |mut controller, _| {
+            let funding = tx_from_hex(FUNDING_TX_HEX)?;
+            funding.generate_utxo(
+                &mut controller.utxo_set,
+                TransactionOrigin::Block,
+                None,
+                None,
+            )?;
+
+            let fresh = TransactionInfo {
+                recipients: vec![(
+                    "mrhW6tcF2LDetj3kJvaDTvatrVxNK64NXk".to_string(),
+                    String::new(),
+                    1000,
+                )],
+                fee: 100,
+            };
+            // loading the wallets may have notified already
+            ui.notifications.lock().map_err(to_io_err)?.clear();
+            controller.warn_address_reuse(&fresh)?;
+            assert!(ui.notifications.lock().map_err(to_io_err)?.is_empty());
+
+            // the funding transaction paid this address
+            let used = TransactionInfo {
+                recipients: vec![(
+                    "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX".to_string(),
+                    String::new(),
+                    1000,
+                )],
+                fee: 100,
+            };
+            controller.warn_address_reuse(&used)?;
+            let notifications = ui.notifications.lock().map_err(to_io_err)?;
+            assert_eq!(
+                *notifications,
+                vec![(NotificationKind::Warning, "Address reuse".to_string())]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_competing_branches_are_reported_as_chain_tips() -> io::Result<()> {
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller(
+            "test_chain_tips_node",
+            ui_sender,
+            |mut controller, config| {
+                let source = SocketAddr::from(([127, 0, 0, 1], 18444));
+                let genesis = controller.tallest_block;
+                let mut active = vec![child_block(&genesis, vec![])];
+                let mut active_tip = active[0].header;
+                active_tip.height = 1;
+                active.push(child_block(&active_tip, vec![]));
+                // a different coinbase output tells apart the block competing with the first one
+                let output = TxOutput {
+                    value: 1,
+                    pk_script_bytes: 0,
+                    pk_script: vec![],
+                };
+                let fork = child_block(&genesis, vec![output]);
+                // the coinbase of a block on top of the first one claims height 1 instead of 2
+                let mut invalid = child_block(&genesis, vec![]);
+                invalid.header =
+                    BlockHeader::new(1, active[0].hash(), None, invalid.txns[0].txid(), 1, 0, 0);
+                for block in active.iter().chain([&fork, &invalid]) {
+                    controller.accept_block(block.clone(), source, &config)?;
+                }
+                // a header announced on top of the tallest block, whose block wasn't downloaded
+                let mut ahead = child_block(&controller.tallest_block, vec![]).header;
+                ahead.height = 3;
+                controller.headers.insert(ahead.hash(), ahead);
+
+                let tips = controller.get_chain_tips();
+                let expected = [
+                    (ahead.hash(), 3, 1, TipStatus::HeadersOnly),
+                    (active[1].hash(), 2, 0, TipStatus::Active),
+                    (invalid.hash(), 2, 1, TipStatus::Invalid),
+                    (fork.hash(), 1, 1, TipStatus::ValidFork),
+                ];
+                assert_eq!(tips.len(), expected.len());
+                for (hash, height, branch_len, status) in expected {
+                    let tip = ChainTipInfo {
+                        hash,
+                        height,
+                        branch_len,
+                        status,
+                    };
+                    assert!(tips.contains(&tip), "missing {:?}", tip);
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Returns the commands the peer received until nothing more arrives
+    fn received_commands(stream: &mut TcpStream) -> io::Result<Vec<String>> {
+        stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+        let mut commands = vec![];
+        while let Ok(header) = MessageHeader::from_stream(stream) {
+            header.read_payload(stream)?;
+            commands.push(header.command_name.trim_end_matches('\0').to_string());
+        }
+        Ok(commands)
+    }
+
+    /// Accepts the given number of connections on the listener, adding them to the controller as peers
+    fn add_peers(
+        controller: &mut NetworkController,
+        listener: &TcpListener,
+        count: usize,
+        ui_sender: UiSender,
+        config: &Config,
+    ) -> io::Result<()> {
+        let (writer_end, _node_receiver) = mpsc::sync_channel(100);
+        for _ in 0..count {
+            let (stream, peer) = listener.accept()?;
+            let version = Version::default_for_trans_addr(peer);
+            let node = Node::spawn(
+                stream,
+                peer,
+                version,
+                writer_end.clone(),
+                ui_sender.clone(),
+                config.clone(),
+            )?;
+            controller.nodes.add_node(node);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks_are_pruned_below_the_configured_height_or_the_kept_ones() -> io::Result<()> {
+        let dir = "test_prune_height_node";
+        let blocks_file = format!("blocks_file={}/blocks.dat", dir);
+        let options = [
+            "prune_below_height=100",
+            "utxo_flush_interval=1000",
+            &blocks_file,
+        ];
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller_options(dir, &options, ui_sender, |mut controller, config| {
+            let mut parent = controller.tallest_block;
+            for height in 1..=150 {
+                let mut block = child_block(&parent, vec![]);
+                block.header.height = height;
+                block.save_to_file(config.get_blocks_file(), false)?;
+                parent = block.header;
+                controller.valid_blocks.insert(block.hash(), block);
+            }
+            let file_lock = controller.blocks_file_lock.clone();
+            let lowest_kept = |controller: &NetworkController| {
+                controller
+                    .valid_blocks
+                    .values()
+                    .map(|block| block.header.height)
+                    .min()
+            };
+
+            // the snapshot is too close to the configured height to prune up to it
+            let pruned = controller.prune_blocks(MIN_BLOCKS_TO_KEEP + 40, &config);
+            prune_blocks_file(&file_lock, &pruned, &config)?;
+            assert_eq!(lowest_kept(&controller), Some(40));
+            assert_eq!(Block::all_from_file(config.get_blocks_file())?.len(), 111);
+
+            let pruned = controller.prune_blocks(MIN_BLOCKS_TO_KEEP + 140, &config);
+            prune_blocks_file(&file_lock, &pruned, &config)?;
+            assert_eq!(lowest_kept(&controller), Some(100));
+            assert_eq!(Block::all_from_file(config.get_blocks_file())?.len(), 51);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_pruning_without_utxo_flushes_is_rejected() {
+        let dir = "test_prune_without_flushes";
+        let ui_sender = Arc::new(HeadlessUi);
+        let result = with_regtest_controller_options(
+            dir,
+            &["prune_below_height=100"],
+            ui_sender,
+            |_controller, _config| Ok(()),
+        );
+        assert!(result.is_err_and(|error| error.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_stalled_sync_is_reported_to_the_ui() -> io::Result<()> {
+        let ui = Arc::new(RecordingUi::default());
+        let ui_sender: UiSender = ui.clone();
+        let options = ["sync_stall_timeout_seconds=1"];
+        let dir = "test_sync_stall_node";
+        with_regtest_controller_options(
+            dir,
+            &options,
+            ui_sender.clone(),
+            |mut controller, config| {
+                // an idle chain whose blocks caught up to its old headers isn't syncing
+                assert!(!controller.sync_progress().1);
+
+                let mut header = child_block(&controller.tallest_block, vec![]).header;
+                header.height = 1;
+                controller.tallest_header = header;
+                let (writer_chanel, _node_receiver) = mpsc::sync_channel(100);
+                let outer = OuterNetworkController {
+                    inner: Arc::new(RwLock::new(controller)),
+                    ui_sender,
+                    writer_chanel,
+                };
+                // the block of the tallest header never arrives
+                outer.watch_sync_progress(config);
+                let stalled = (NotificationKind::Error, "Sync stalled".to_string());
+                for _ in 0..30 {
+                    if ui
+                        .notifications
+                        .lock()
+                        .map_err(to_io_err)?
+                        .contains(&stalled)
+                    {
+                        return Ok(());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Stall not reported",
+                ))
+            },
+        )
+    }
+
+    #[test]
+    fn test_only_whitelisted_peers_get_the_tx_broadcast() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peers = [
+            TcpStream::connect(listener.local_addr()?)?,
+            TcpStream::connect(listener.local_addr()?)?,
+        ];
+        let whitelist = format!("whitelist={}", peers[0].local_addr()?);
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_whitelisted_tx_broadcast";
+        with_regtest_controller_options(
+            dir,
+            &[&whitelist],
+            ui_sender.clone(),
+            |mut controller, config| {
+                add_peers(&mut controller, &listener, peers.len(), ui_sender, &config)?;
+                let tx = tx_from_hex(SPENDING_TX_HEX)?;
+                let inv = InventoryVector::new(vec![Inventory::new(InvType::MSGTx, tx.txid())]);
+
+                controller
+                    .nodes
+                    .send_to_tx_relay_peers(&inv.serialize()?, &config)?;
+                assert_eq!(received_commands(&mut peers[0])?, vec!["inv"]);
+                assert!(received_commands(&mut peers[1])?.is_empty());
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_imported_address_is_watched_after_a_restart() -> io::Result<()> {
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_imported_address_restart";
+        let watch_file = format!("watch_addresses_file={}/watch_addresses.txt", dir);
+        with_regtest_controller_options(
+            dir,
+            &[&watch_file],
+            ui_sender.clone(),
+            |mut controller, config| {
+                let address = "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun";
+                controller.import_address(address.to_string(), 0, &config)?;
+
+                let mut wallets = HashMap::new();
+                NetworkController::load_watch_addresses(&config, ui_sender.as_ref(), &mut wallets);
+                assert!(wallets.get(address).is_some_and(Wallet::is_watch_only));
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_orphan_is_held_out_of_the_mempool_until_its_parent_arrives() -> io::Result<()> {
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_orphan_held_out_of_mempool";
+        let options = ["max_orphan_tx_bytes=100000"];
+        with_regtest_controller_options(dir, &options, ui_sender, |mut controller, config| {
+            controller.ibd.tip_changed((0, 0), true);
+            let (parent, child) = (tx_from_hex(FUNDING_TX_HEX)?, tx_from_hex(SPENDING_TX_HEX)?);
+            // the outputs the parent spends are confirmed
+            if let TxInputType::TxInput(inputs) = &parent.tx_in {
+                for input in inputs {
+                    let outpoint = (input.previous_output.hash, input.previous_output.index);
+                    controller
+                        .utxo_set
+                        .outpoint_index
+                        .insert(outpoint, (0, vec![]));
+                }
+            }
+            let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 18444));
+
+            controller.accept_transaction(child.clone(), source, &config)?;
+            assert!(!controller.tx_index.contains(&child.txid()));
+            controller.accept_transaction(parent.clone(), source, &config)?;
+            assert!(controller.tx_index.contains(&parent.txid()));
+            assert!(controller.tx_index.contains(&child.txid()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_only_the_missing_blocks_are_requested() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peer = TcpStream::connect(listener.local_addr()?)?;
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_missing_blocks_node";
+        with_regtest_controller(dir, ui_sender.clone(), |mut controller, config| {
+            add_peers(&mut controller, &listener, 1, ui_sender, &config)?;
+            let peer_addr = peer.local_addr()?;
+            // three headers on top of the genesis, only the block at height 2 was downloaded
+            let mut chain = vec![controller.tallest_block];
+            for height in 1..=3 {
+                let mut header = child_block(&chain[height - 1], vec![]).header;
+                header.height = height;
+                controller.headers.insert(header.hash(), header);
+                chain.push(header);
+            }
+            controller.tallest_header = chain[3];
+            let downloaded = Block::new(chain[2], 0, vec![]);
+            controller
+                .valid_blocks
+                .insert(downloaded.hash(), downloaded);
+
+            controller.request_missing_blocks(1..4, &config)?;
+            assert_eq!(controller.block_requests.in_flight(&peer_addr), 2);
+            assert_eq!(received_commands(&mut peer)?, vec!["getdata"]);
+            controller.request_missing_blocks(2..3, &config)?;
+            assert!(received_commands(&mut peer)?.is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_block_is_announced_by_each_peer_preference() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peers = [
+            TcpStream::connect(listener.local_addr()?)?,
+            TcpStream::connect(listener.local_addr()?)?,
+            TcpStream::connect(listener.local_addr()?)?,
+        ];
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_block_announcement_node";
+        with_regtest_controller(dir, ui_sender.clone(), |mut controller, config| {
+            add_peers(&mut controller, &listener, peers.len(), ui_sender, &config)?;
+            let source = peers[0].local_addr()?;
+            controller.nodes.update_prefers_headers(&source);
+            controller
+                .nodes
+                .update_prefers_headers(&peers[1].local_addr()?);
+            let header = child_block(&controller.tallest_block, vec![]).header;
+
+            controller
+                .nodes
+                .announce_block(header, None, source, &config)?;
+            assert!(received_commands(&mut peers[0])?.is_empty());
+            assert_eq!(received_commands(&mut peers[1])?, vec!["headers"]);
+            assert_eq!(received_commands(&mut peers[2])?, vec!["inv"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_compact_block_is_only_completed_by_the_peer_asked() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peers = [
+            TcpStream::connect(listener.local_addr()?)?,
+            TcpStream::connect(listener.local_addr()?)?,
+        ];
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_compact_block_txn_node";
+        with_regtest_controller(dir, ui_sender.clone(), |mut controller, config| {
+            add_peers(&mut controller, &listener, peers.len(), ui_sender, &config)?;
+            let (asked, other) = (peers[0].local_addr()?, peers[1].local_addr()?);
+            // a block with a transaction we haven't seen, which has to be requested
+            let parent = controller.tallest_block;
+            let payment = tx_from_hex(SPENDING_TX_HEX)?;
+            let mut txns = child_block(&parent, vec![]).txns;
+            txns.push(payment.clone());
+            let mut block = Block::new(parent, 2, txns);
+            let root = MerkleTree::generate_from_hashes(block.hash_transactions()).get_root();
+            block.header = BlockHeader::new(
+                1,
+                parent.hash(),
+                None,
+                HashId::new(root.to_byte_array()),
+                0,
+                0,
+                0,
+            );
+            let compact_block = CompactBlock::from_block(&block, 7);
+
+            let completed = controller.reconstruct_compact_block(compact_block, asked, &config)?;
+            assert!(completed.is_none());
+            assert_eq!(received_commands(&mut peers[0])?, vec!["getblocktxn"]);
+            let blocktxn = BlockTxn::new(block.hash(), vec![payment]);
+            assert!(controller
+                .complete_compact_block(blocktxn.clone(), other)
+                .is_none());
+            let completed = controller.complete_compact_block(blocktxn, asked);
+            assert_eq!(completed.map(|block| block.hash()), Some(block.hash()));
+
+            // a peer that can't be asked for the transactions isn't waited for
+            let gone = SocketAddr::from(([127, 0, 0, 2], 18444));
+            let compact_block = CompactBlock::from_block(&block, 8);
+            let completed = controller.reconstruct_compact_block(compact_block, gone, &config)?;
+            assert!(completed.is_none());
+            assert!(controller.partial_blocks.is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_only_the_trusted_peer_is_asked_for_headers() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peers = [
+            TcpStream::connect(listener.local_addr()?)?,
+            TcpStream::connect(listener.local_addr()?)?,
+        ];
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let dir = "test_trusted_peer_sync";
+        with_regtest_controller(dir, ui_sender.clone(), |mut controller, config| {
+            add_peers(&mut controller, &listener, peers.len(), ui_sender, &config)?;
+            assert_eq!(controller.nodes.sync_peers(&config).len(), 2);
+            // the controller would fail to start dialing the trusted peer, so it's only configured afterwards
+            let config_file = format!("{}/node.conf", dir);
+            let mut options = fs::OpenOptions::new().append(true).open(&config_file)?;
+            write!(options, "\ntrusted_peer={}", peers[0].local_addr()?)?;
+            let config = Config::from_file(config_file.into())?;
+
+            controller.request_headers(controller.tallest_header.hash(), &config)?;
+            assert!(received_commands(&mut peers[0])?.contains(&"getheaders".to_string()));
+            assert!(!received_commands(&mut peers[1])?.contains(&"getheaders".to_string()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_historical_block_not_served_once_upload_target_is_reached() -> io::Result<()> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+        let mut peer = TcpStream::connect(listener.local_addr()?)?;
+        let ui_sender: UiSender = Arc::new(HeadlessUi);
+        let options = ["max_upload_target=1"];
+        let dir = "test_upload_target_node";
+        with_regtest_controller_options(dir, &options, ui_sender.clone(), |controller, config| {
+            let (writer_end, _node_receiver) = mpsc::sync_channel(100);
+            let (stream, peer_addr) = listener.accept()?;
+            let version = Version::default_for_trans_addr(peer_addr);
+            let node = Node::spawn(
+                stream,
+                peer_addr,
+                version,
+                writer_end,
+                ui_sender,
+                config.clone(),
+            )?;
+            let recent = controller.tallest_block;
+            // mined long before the tip
+            let historical =
+                BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, 0);
+            let controller = Arc::new(RwLock::new(controller));
+            let mut inner = controller.write().map_err(to_io_err)?;
+            inner.nodes.add_node(node);
+            let block = Block::new(historical, 0, vec![]);
+            inner.valid_blocks.insert(block.hash(), block);
+            drop(inner);
+            let getdata = |headers| GetData::from_inv(headers, InvType::MSGBlock);
+
+            // nothing was sent yet, so the first request is served and exceeds the target
+            OuterNetworkController::handle_node_getdata_message(
+                controller.clone(),
+                peer_addr,
+                getdata(vec![historical]),
+                &config,
+            )?;
+            assert_eq!(received_commands(&mut peer)?, vec!["block"]);
+            OuterNetworkController::handle_node_getdata_message(
+                controller,
+                peer_addr,
+                getdata(vec![historical, recent]),
+                &config,
+            )?;
+            assert_eq!(received_commands(&mut peer)?, vec!["block", "notfound"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_unsolicited_tx_is_ignored_with_relay_off() -> io::Result<()> {
+        let dir = "test_relay_off_node";
+        let ui_sender = Arc::new(HeadlessUi);
+        with_regtest_controller_options(dir, &["relay=false"], ui_sender, |controller, config| {
+            let tx = tx_from_hex(SPENDING_TX_HEX)?;
+            let txid = tx.txid();
+            let source = SocketAddr::from(([127, 0, 0, 1], 18444));
+            let controller = Arc::new(RwLock::new(controller));
+            OuterNetworkController::handle_node_tx_message(
+                controller.clone(),
+                source,
+                tx.clone(),
+                &config,
+            )?;
+            assert!(!controller
+                .read()
+                .map_err(to_io_err)?
+                .tx_index
+                .contains(&txid));
+
+            // requested with getdata, it's only accepted from the peer it was asked from
+            controller
+                .write()
+                .map_err(to_io_err)?
+                .tx_requests
+                .requested(txid, source, Instant::now());
+            let other = SocketAddr::from(([127, 0, 0, 2], 18444));
+            OuterNetworkController::handle_node_tx_message(
+                controller.clone(),
+                other,
+                tx.clone(),
+                &config,
+            )?;
+            assert!(!controller
+                .read()
+                .map_err(to_io_err)?
+                .tx_index
+                .contains(&txid));
+            OuterNetworkController::handle_node_tx_message(
+                controller.clone(),
+                source,
+                tx,
+                &config,
+            )?;
+            assert!(controller
+                .read()
+                .map_err(to_io_err)?
+                .tx_index
+                .contains(&txid));
+            Ok(())
+        })
+    }
 
     #[test]
     #[ignore]
     fn test_handle_incoming_nodes() {
         let (ui_sender, _) = glib::MainContext::sync_channel(glib::PRIORITY_HIGH, 100);
+        let ui_sender: UiSender = Arc::new(ui_sender);
         let (writer_end, _) = std::sync::mpsc::sync_channel::<(SocketAddr, Message)>(100);
         let writer_end: SyncSender<(SocketAddr, Message)> = writer_end;
 
@@ -0,0 +1,263 @@
+use crate::messages::{BlockHeader, HashId};
+use crate::raw_transaction::script::{
+    AddressFormat, MAINNET_ADDRESSES, REGTEST_ADDRESSES, TESTNET_ADDRESSES,
+};
+use crate::utility::decode_hex;
+use std::io;
+use std::str::FromStr;
+
+/// Challenge script of the default signet (1-of-2 multisig)
+const SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+/// Merkle root of the genesis block, the same on every network since they share its only transaction
+const GENESIS_MERKLE_ROOT: &str =
+    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Blocks a coinbase output needs on top of it, including its own, before it can be spent
+pub const COINBASE_MATURITY: usize = 100;
+
+/// Subsidy of the blocks before the first halving, in satoshis
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+
+/// Bitcoin networks the node can follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+    Signet,
+}
+
+impl FromStr for Network {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            "signet" => Ok(Network::Signet),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown network {}", s),
+            )),
+        }
+    }
+}
+
+/// How the proof of work of a block is checked
+#[derive(Debug, Clone, PartialEq)]
+pub enum PowPolicy {
+    /// The header hash must not exceed the target encoded in its nbits
+    Full,
+    /// Any header is accepted, regtest blocks are mined with trivial difficulty
+    Trivial,
+    /// Blocks must be signed so they satisfy the given challenge script (BIP325)
+    Signet(Vec<u8>),
+}
+
+/// Header fields of the genesis block of a network
+#[derive(Debug, Clone, PartialEq)]
+pub struct Genesis {
+    pub version: i32,
+    pub merkle_root: HashId,
+    pub timestamp: u32,
+    pub nbits: u32,
+    pub nonce: u32,
+}
+
+impl Genesis {
+    fn new(network: Network) -> io::Result<Self> {
+        let (timestamp, nbits, nonce) = match network {
+            Network::Mainnet => (1231006505, 0x1d00ffff, 2083236893),
+            Network::Testnet => (1296688602, 0x1d00ffff, 414098458),
+            Network::Regtest => (1296688602, 0x207fffff, 2),
+            Network::Signet => (1598918400, 0x1e0377ae, 52613770),
+        };
+        Ok(Self {
+            version: 1,
+            merkle_root: HashId::from_hex_string(GENESIS_MERKLE_ROOT)?,
+            timestamp,
+            nbits,
+            nonce,
+        })
+    }
+}
+
+/// Soft forks whose rules apply to a block, depending on its height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftForks {
+    /// The coinbase must encode the block height (BIP34)
+    pub coinbase_height: bool,
+    /// Signatures must be strictly DER encoded (BIP66)
+    pub strict_der: bool,
+    /// OP_CHECKLOCKTIMEVERIFY is enforced (BIP65)
+    pub check_lock_time_verify: bool,
+    /// Input sequences are relative lock times (BIP68) and OP_CHECKSEQUENCEVERIFY is enforced (BIP112)
+    pub sequence_locks: bool,
+    /// Blocks commit to the witnesses of their transactions, which can't have witness data before (BIP141)
+    pub segwit: bool,
+}
+
+/// Consensus parameters that depend on the network being followed
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkParams {
+    pub network: Network,
+    pub pow_policy: PowPolicy,
+    /// First height whose coinbase must encode the block height (BIP34)
+    pub bip34_height: usize,
+    /// First height whose signatures must be strictly DER encoded (BIP66)
+    pub bip66_height: usize,
+    /// First height whose scripts enforce OP_CHECKLOCKTIMEVERIFY (BIP65)
+    pub bip65_height: usize,
+    /// First height whose input sequences are relative lock times (BIP68) and whose scripts enforce
+    /// OP_CHECKSEQUENCEVERIFY (BIP112)
+    pub csv_height: usize,
+    /// First height whose coinbase commits to the witness data of the block (BIP141)
+    pub segwit_height: usize,
+    pub genesis: Genesis,
+    /// Blocks a coinbase output needs on top of it, including its own, before it can be spent
+    pub coinbase_maturity: usize,
+    /// Blocks after which the block subsidy halves
+    pub subsidy_halving_interval: usize,
+    /// Base58 version bytes and segwit prefix of the addresses of the network
+    pub address_format: AddressFormat,
+}
+
+impl NetworkParams {
+    /// Returns the parameters of the given network. A custom signet challenge replaces the default one if not empty.
+    pub fn new(network: Network, signet_challenge: &[u8]) -> io::Result<Self> {
+        let pow_policy = match network {
+            Network::Mainnet | Network::Testnet => PowPolicy::Full,
+            Network::Regtest => PowPolicy::Trivial,
+            Network::Signet => match signet_challenge.is_empty() {
+                true => PowPolicy::Signet(decode_hex(SIGNET_CHALLENGE).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid signet challenge")
+                })?),
+                false => PowPolicy::Signet(signet_challenge.to_vec()),
+            },
+        };
+        let (bip34_height, bip66_height, bip65_height, csv_height, segwit_height) = match network {
+            Network::Mainnet => (227931, 363725, 388381, 419328, 481824),
+            Network::Testnet => (21111, 330776, 581885, 770112, 834624),
+            Network::Regtest | Network::Signet => (1, 1, 1, 1, 1),
+        };
+        Ok(Self {
+            network,
+            pow_policy,
+            bip34_height,
+            bip66_height,
+            bip65_height,
+            csv_height,
+            segwit_height,
+            genesis: Genesis::new(network)?,
+            coinbase_maturity: COINBASE_MATURITY,
+            subsidy_halving_interval: match network {
+                Network::Regtest => 150,
+                _ => 210_000,
+            },
+            address_format: match network {
+                Network::Mainnet => MAINNET_ADDRESSES,
+                Network::Regtest => REGTEST_ADDRESSES,
+                Network::Testnet | Network::Signet => TESTNET_ADDRESSES,
+            },
+        })
+    }
+
+    /// Overrides the coinbase maturity, which only regtest allows so coinbase outputs can be spent sooner in tests
+    pub fn set_coinbase_maturity(&mut self, coinbase_maturity: usize) -> io::Result<()> {
+        if self.network != Network::Regtest && coinbase_maturity != COINBASE_MATURITY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Coinbase maturity can only be changed on regtest",
+            ));
+        }
+        self.coinbase_maturity = coinbase_maturity;
+        Ok(())
+    }
+
+    /// Returns the satoshis a block of the given height creates on top of the fees it collects
+    pub fn block_subsidy(&self, height: usize) -> u64 {
+        match height / self.subsidy_halving_interval {
+            halvings @ 0..=63 => INITIAL_SUBSIDY >> halvings,
+            _ => 0,
+        }
+    }
+
+    /// Returns the soft forks active at the given height, which decide the rules its block is validated with
+    pub fn soft_forks(&self, height: usize) -> SoftForks {
+        SoftForks {
+            coinbase_height: height >= self.bip34_height,
+            strict_der: height >= self.bip66_height,
+            check_lock_time_verify: height >= self.bip65_height,
+            sequence_locks: height >= self.csv_height,
+            segwit: height >= self.segwit_height,
+        }
+    }
+
+    /// Returns the genesis block header of the network
+    pub fn genesis_header(&self) -> BlockHeader {
+        BlockHeader::genesis(&self.genesis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow_policy_per_network() -> io::Result<()> {
+        let testnet = NetworkParams::new(Network::from_str("testnet")?, &[])?;
+        assert_eq!(testnet.pow_policy, PowPolicy::Full);
+
+        let regtest = NetworkParams::new(Network::from_str("regtest")?, &[])?;
+        assert_eq!(regtest.pow_policy, PowPolicy::Trivial);
+
+        let signet = NetworkParams::new(Network::Signet, &[0x51])?;
+        assert_eq!(signet.pow_policy, PowPolicy::Signet(vec![0x51]));
+
+        assert!(Network::from_str("foo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genesis_header_hashes_to_known_genesis() -> io::Result<()> {
+        let known_hashes = [
+            (
+                Network::Mainnet,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            (
+                Network::Testnet,
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            (
+                Network::Regtest,
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+            (
+                Network::Signet,
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            ),
+        ];
+        for (network, hash) in known_hashes {
+            let genesis_header = NetworkParams::new(network, &[])?.genesis_header();
+            assert_eq!(genesis_header.hash, HashId::from_str(hash)?);
+            assert_eq!(genesis_header.height, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_soft_forks_apply_from_their_activation_height() -> io::Result<()> {
+        let testnet = NetworkParams::new(Network::Testnet, &[])?;
+        let before = testnet.soft_forks(testnet.bip65_height - 1);
+        assert!(before.coinbase_height && before.strict_der);
+        assert!(!before.check_lock_time_verify && !before.sequence_locks);
+
+        let after = testnet.soft_forks(testnet.csv_height);
+        assert!(after.check_lock_time_verify && after.sequence_locks);
+        assert!(!testnet.soft_forks(testnet.bip34_height - 1).coinbase_height);
+        Ok(())
+    }
+}
@@ -1,47 +1,78 @@
 use crate::config::Config;
+use crate::logger::MessageTracer;
 use crate::messages::{
-    constants::{commands, config::VERBOSE},
-    Block, GetData, GetHeader, Headers, InventoryVector, Message, MessageHeader, Ping, SendHeaders,
-    Serialize, VerAck, Version,
+    constants::{
+        commands,
+        config::{QUIET, VERBOSE},
+        header_constants::HEADER_SIZE,
+        messages::{MAX_MISBEHAVIOR_SCORE, MISBEHAVIOR_PENALTY},
+        version_constants::CMPCT_VERSION,
+    },
+    Block, BlockTxn, CompactBlock, FeeFilter, GetBlockTxn, GetData, GetHeader, Headers,
+    InventoryVector, Message, MessageHeader, Ping, SendCmpct, SendHeaders, Serialize, Services,
+    VerAck, Version,
 };
-use crate::raw_transaction::RawTransaction;
-use crate::utility::to_io_err;
+use crate::peer_info::{PeerInfo, PeerStats};
+use crate::raw_transaction::{RawTransaction, ScriptLimits};
+use crate::socks5;
+use crate::utility::{actual_timestamp_or_default, to_io_err};
 use std::io::{self, Write};
 use std::net::{SocketAddr, TcpStream};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 // gtk imports
-use crate::interface::GtkMessage;
-use gtk::glib::SyncSender;
+use crate::interface::{NotificationKind, UiSender};
 
 /// The Listener struct is responsible for listening to incoming messages from a peer and sending them to the writer thread.
 pub struct Listener {
     socket_addr: SocketAddr,
     stream: TcpStream,
     writer_channel: mpsc::SyncSender<(SocketAddr, Message)>,
+    misbehavior_score: u32,
+    stats: Arc<PeerStats>,
+    tracer: MessageTracer,
 }
 
 impl Listener {
     fn new(
         stream: TcpStream,
+        socket_addr: SocketAddr,
         writer_channel: mpsc::SyncSender<(SocketAddr, Message)>,
+        stats: Arc<PeerStats>,
+        tracer: MessageTracer,
     ) -> std::io::Result<Self> {
         Ok(Self {
-            socket_addr: stream.peer_addr()?, // handle this error
+            socket_addr,
             stream,
             writer_channel,
+            misbehavior_score: 0,
+            stats,
+            tracer,
         })
     }
 
     fn send(&mut self, payload: &[u8]) -> io::Result<()> {
         self.stream.write_all(payload)?;
         self.stream.flush()?;
+        self.stats.add_bytes_sent(payload.len());
+        self.tracer.sent(self.socket_addr, payload);
         Ok(())
     }
 
+    /// Reads the next message header and payload from the peer, accounting and tracing it
+    fn read_message(&mut self) -> io::Result<(MessageHeader, Vec<u8>)> {
+        let message_header = MessageHeader::from_stream(&mut self.stream)?;
+        let payload = message_header.read_payload(&mut self.stream)?;
+        let size = HEADER_SIZE + payload.len();
+        self.stats.add_bytes_received(size);
+        self.tracer
+            .received(self.socket_addr, &message_header.command_name, size);
+        Ok((message_header, payload))
+    }
+
     fn log_listen(mut self, config: &Config) -> io::Result<()> {
-        match self.listen() {
+        match self.listen(config) {
             Ok(..) => Ok(()),
             Err(e) => {
                 config.log(&format!("{:?}", e) as &str, VERBOSE);
@@ -61,28 +92,42 @@ impl Listener {
         Ok(())
     }
 
+    /// Returns the parsed message, or penalizes the peer if the payload of a known command is malformed.
+    fn parse_or_penalize(&mut self, parsed: io::Result<Message>) -> Message {
+        match parsed {
+            Ok(m) => m,
+            Err(..) => {
+                self.misbehavior_score = self.misbehavior_score.saturating_add(MISBEHAVIOR_PENALTY);
+                Message::Ignore
+            }
+        }
+    }
+
     fn process_other_message_payload(&mut self, command_name: &str, payload: Vec<u8>) -> Message {
         let dyn_message: Message = match command_name {
-            commands::BLOCK => match Block::deserialize(&payload) {
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
-            },
-            commands::INV => match InventoryVector::deserialize(&payload) {
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
-            },
-            commands::TX => match RawTransaction::deserialize(&payload) {
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
-            },
-            commands::GETHEADERS => match GetHeader::deserialize(&payload) {
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
-            },
-            commands::GETDATA => match GetData::deserialize(&payload) {
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
+            commands::BLOCK => self.parse_or_penalize(Block::deserialize(&payload)),
+            commands::INV => self.parse_or_penalize(InventoryVector::deserialize(&payload)),
+            commands::NOTFOUND => {
+                match self.parse_or_penalize(InventoryVector::deserialize(&payload)) {
+                    Message::Inv(inventories) => Message::NotFound(inventories),
+                    message => message,
+                }
+            }
+            commands::TX => match self.parse_or_penalize(RawTransaction::deserialize(&payload)) {
+                // scripts over the standard sizes are valid, the transaction just isn't relayed
+                Message::Transaction(tx) if !tx.has_scripts_within(&ScriptLimits::RELAY) => {
+                    Message::Ignore
+                }
+                message => message,
             },
+            commands::GETHEADERS => self.parse_or_penalize(GetHeader::deserialize(&payload)),
+            commands::GETDATA => self.parse_or_penalize(GetData::deserialize(&payload)),
+            commands::SENDCMPCT => self.parse_or_penalize(SendCmpct::deserialize(&payload)),
+            commands::FEEFILTER => self.parse_or_penalize(FeeFilter::deserialize(&payload)),
+            commands::SENDHEADERS => self.parse_or_penalize(SendHeaders::deserialize(&payload)),
+            commands::CMPCTBLOCK => self.parse_or_penalize(CompactBlock::deserialize(&payload)),
+            commands::GETBLOCKTXN => self.parse_or_penalize(GetBlockTxn::deserialize(&payload)),
+            commands::BLOCKTXN => self.parse_or_penalize(BlockTxn::deserialize(&payload)),
             _ => Message::Ignore,
         };
 
@@ -96,13 +141,14 @@ impl Listener {
     ) -> io::Result<Message> {
         let dyn_message: Message = match command_name {
             commands::HEADERS => match Headers::deserialize(&payload) {
-                Ok(Message::Headers(headers)) if headers.is_paginated() => {
-                    // request next headers
+                Ok(Message::Headers(headers))
+                    if headers.is_paginated() || headers.is_truncated() =>
+                {
+                    // request next headers, or the ones missing from a truncated message
                     self.handle_headers_msg(headers.clone())?;
                     Message::Headers(headers)
                 }
-                Ok(m) => m,
-                Err(..) => Message::Ignore,
+                parsed => self.parse_or_penalize(parsed),
             },
             commands::PING => {
                 if let Ok(reply) = &Ping::pong(&payload) {
@@ -110,24 +156,50 @@ impl Listener {
                 }
                 Message::Ignore
             }
+            commands::PONG => {
+                if let Message::Ping(pong) = self.parse_or_penalize(Ping::deserialize(&payload)) {
+                    self.stats.pong_received(pong.nonce)?;
+                }
+                Message::Ignore
+            }
             _ => self.process_other_message_payload(command_name, payload),
         };
         Ok(dyn_message)
     }
 
-    fn listen(&mut self) -> io::Result<()> {
+    /// Returns true for unknown or unimplemented commands, which are ignored without penalizing the peer.
+    fn is_ignored_command(message_header: &MessageHeader, config: &Config) -> bool {
+        let command_name = message_header.command_name.as_str();
+        if message_header.validate_header().is_err() {
+            config.log(
+                &format!("Ignoring unknown command: {:?}", command_name),
+                VERBOSE,
+            );
+            return true;
+        }
+        if commands::UNIMPLEMENTED.contains(&command_name) {
+            config.log(&format!("Ignoring command: {:?}", command_name), VERBOSE);
+            return true;
+        }
+        false
+    }
+
+    fn listen(&mut self, config: &Config) -> io::Result<()> {
         loop {
-            let message_header = MessageHeader::from_stream(&mut self.stream)?;
-            if message_header.validate_header().is_err() {
-                eprintln!(
-                    "Invalid or unimplemented header: {:?}, ignoring message",
-                    message_header
-                );
+            let (message_header, payload) = self.read_message()?;
+            if Self::is_ignored_command(&message_header, config) {
                 continue;
             }
 
-            let payload = message_header.read_payload(&mut self.stream)?;
-            match self.process_message_payload(&message_header.command_name, payload) {
+            let command_name = message_header.command_name.as_str();
+            let message = self.process_message_payload(command_name, payload);
+            if self.misbehavior_score >= MAX_MISBEHAVIOR_SCORE {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Peer exceeded the misbehavior score, disconnecting",
+                ));
+            }
+            match message {
                 Ok(Message::Ignore) => continue,
                 Ok(m) => {
                     self.writer_channel
@@ -140,46 +212,177 @@ impl Listener {
     }
 }
 
+/// Compact block relay state negotiated with a peer through sendcmpct messages (BIP152).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactRelay {
+    pub sent: bool,
+    pub high_bandwidth: bool,
+    pub version: Option<u64>,
+}
+
+impl CompactRelay {
+    /// Records a sendcmpct message sent to the peer.
+    pub fn on_sent(&mut self, sendcmpct: &SendCmpct) {
+        if sendcmpct.version == CMPCT_VERSION {
+            self.sent = true;
+        }
+    }
+
+    /// Records a sendcmpct message received from the peer, ignoring versions we don't support.
+    pub fn on_received(&mut self, sendcmpct: &SendCmpct) {
+        if sendcmpct.version != CMPCT_VERSION {
+            return;
+        }
+        self.high_bandwidth = sendcmpct.announce;
+        self.version = Some(sendcmpct.version);
+    }
+
+    /// Returns true if both ends agreed on a compact block version, so compact blocks may be used.
+    pub fn is_enabled(&self) -> bool {
+        self.sent && self.version.is_some()
+    }
+
+    /// Returns true if the peer asked for new blocks to be sent right away as compact blocks (high-bandwidth mode).
+    pub fn wants_compact_blocks(&self) -> bool {
+        self.is_enabled() && self.high_bandwidth
+    }
+}
+
+/// Transaction relay preferences of a peer: the relay flag of its version message and its fee filter (BIP133).
+/// Block-relay-only connections don't relay transactions either way, so they're harder to fingerprint.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TxRelay {
+    pub relay: bool,
+    pub relay_txs: bool, // false on block-relay-only connections, which neither announce nor request transactions
+    pub min_fee_rate: u64, // satoshis per kilo virtual byte, 0 if the peer sent no fee filter
+}
+
+impl TxRelay {
+    pub fn new(relay: bool) -> Self {
+        Self {
+            relay,
+            relay_txs: true,
+            min_fee_rate: 0,
+        }
+    }
+
+    /// Records a feefilter message received from the peer.
+    pub fn on_fee_filter(&mut self, feefilter: &FeeFilter) {
+        self.min_fee_rate = feefilter.fee_rate;
+    }
+
+    /// Returns true if a transaction of the given fee rate may be announced to the peer.
+    /// Transactions whose fee is unknown are only announced to peers without a fee filter.
+    pub fn accepts(&self, fee_rate: Option<u64>) -> bool {
+        self.relay && self.relay_txs && fee_rate.unwrap_or(0) >= self.min_fee_rate
+    }
+}
+
 /// The Node struct is responsible for spawning a listener thread and keeping track of the connection.
 #[derive(Debug)]
 pub struct Node {
     pub stream: TcpStream,
     pub address: SocketAddr,
+    pub compact_relay: CompactRelay,
+    pub tx_relay: TxRelay,
+    pub prefers_headers: bool, // new blocks are announced with headers instead of inv
+    peer_version: Version,
+    connected_since: i64,
+    stats: Arc<PeerStats>,
+    tracer: MessageTracer,
     _listener: JoinHandle<io::Result<()>>,
 }
 
 impl Node {
     fn new(
         stream: TcpStream,
-        listener: JoinHandle<io::Result<()>>,
-        ui_sender: SyncSender<GtkMessage>,
+        address: SocketAddr,
+        peer_version: Version,
+        listener: (JoinHandle<io::Result<()>>, Arc<PeerStats>),
+        ui_sender: UiSender,
         config: &Config,
     ) -> io::Result<Self> {
         let message = &format!("Established connection with node: {:?}", stream) as &str;
         config.log(message, VERBOSE);
 
         // update ui
-        let msg = format!("Connected to {}", stream.peer_addr()?);
-        let _ = ui_sender.send(GtkMessage::UpdateProgressBar((Some(msg), 1.0)));
-        let address = stream.peer_addr()?;
+        let msg = format!("Connected to {}", address);
+        let _ = ui_sender.update_progress(Some(&msg), 1.0);
 
         Ok(Self {
             stream,
             address,
-            _listener: listener,
+            compact_relay: CompactRelay::default(),
+            tx_relay: TxRelay::new(peer_version.relay()),
+            prefers_headers: false,
+            peer_version,
+            connected_since: actual_timestamp_or_default(),
+            stats: listener.1,
+            tracer: config.get_message_tracer(),
+            _listener: listener.0,
         })
     }
 
+    /// Spawns the listener thread of a connection to `address` whose handshake returned the given peer version.
+    /// The address is the peer's rather than the stream's, which is the proxy's when connecting through one.
     pub fn spawn(
         stream: TcpStream,
+        address: SocketAddr,
+        peer_version: Version,
         writer_channel: mpsc::SyncSender<(SocketAddr, Message)>,
-        ui_sender: SyncSender<GtkMessage>,
+        ui_sender: UiSender,
         config: Config,
     ) -> io::Result<Self> {
-        let listener = Listener::new(stream.try_clone()?, writer_channel)?;
+        let stats = Arc::new(PeerStats::default());
+        let listener = Listener::new(
+            stream.try_clone()?,
+            address,
+            writer_channel,
+            stats.clone(),
+            config.get_message_tracer(),
+        )?;
         let config_clone = config.clone();
         let handle = thread::spawn(move || listener.log_listen(&config));
-        Self::new(stream, handle, ui_sender, &config_clone)
+        Self::new(
+            stream,
+            address,
+            peer_version,
+            (handle, stats),
+            ui_sender,
+            &config_clone,
+        )
+    }
+
+    /// Returns the version message the peer sent during the handshake
+    pub fn peer_version(&self) -> &Version {
+        &self.peer_version
+    }
+
+    /// Returns the bytes received from the peer so far
+    pub fn bytes_received(&self) -> u64 {
+        self.stats.bytes_received()
+    }
+
+    /// Returns the bytes sent to the peer so far
+    pub fn bytes_sent(&self) -> u64 {
+        self.stats.bytes_sent()
+    }
+
+    /// Returns the diagnostic information of the connection
+    pub fn peer_info(&self) -> PeerInfo {
+        PeerInfo::new(
+            self.address,
+            self.connected_since,
+            self.peer_version(),
+            &self.stats,
+        )
+    }
+
+    /// Sends a ping to the peer to measure the ping time once its pong arrives.
+    pub fn send_ping(&mut self) -> io::Result<()> {
+        let ping = Ping::new(rand::random());
+        self.stats.ping_sent(ping.nonce)?;
+        self.send(&ping.build_ping()?)
     }
 
     fn _is_alive(&mut self, config: &Config) -> bool {
@@ -193,30 +396,79 @@ impl Node {
         }
     }
 
-    /// This function is used to establish a connection with a node.
+    /// This function is used to establish a connection with a node. A block-relay-only connection asks the peer
+    /// not to announce transactions in our version message and never relays them itself. Until the initial block
+    /// download is complete, peers that don't serve historical blocks are rejected.
     pub fn try_from_addr(
         node_addr: SocketAddr,
+        block_relay_only: bool,
+        ibd_complete: bool,
         writer_channel: mpsc::SyncSender<(SocketAddr, Message)>,
-        ui_sender: SyncSender<GtkMessage>,
+        ui_sender: UiSender,
         config: Config,
     ) -> io::Result<(SocketAddr, Node)> {
-        if !node_addr.is_ipv4() {
+        if !node_addr.is_ipv4() && config.get_onion_peer(&node_addr).is_none() {
             return Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "Ipv6 is not supported",
             ));
         }
-        let tcp_timeout = config.get_tcp_timeout();
-        let mut stream = TcpStream::connect_timeout(&node_addr, Duration::new(tcp_timeout, 0))?;
-        Node::handshake(&mut stream)?;
-        let mut node = Node::spawn(stream, writer_channel, ui_sender, config)?;
+        let mut stream = Node::dial(node_addr, &config)?;
+        let relay = config.get_relay() && !block_relay_only;
+        let peer_version = Node::handshake(&mut stream, node_addr, relay)?;
+        if config.get_require_peer_services() {
+            let services = peer_version.services();
+            match check_sync_services(services, !ibd_complete, config.get_download_witness()) {
+                // the trusted peer is the only one synced from, so it's kept whatever it serves
+                Err(e) if config.get_trusted_peer() == Some(node_addr) => {
+                    let msg = format!("Syncing from trusted peer {} anyway: {}", node_addr, e);
+                    config.log(&msg, QUIET);
+                    _ = ui_sender.notify(NotificationKind::Warning, "Trusted peer", &msg);
+                }
+                checked => checked?,
+            }
+        }
+        let mut node = Node::spawn(
+            stream,
+            node_addr,
+            peer_version,
+            writer_channel,
+            ui_sender,
+            config,
+        )?;
+        node.tx_relay.relay_txs = !block_relay_only;
         node.send(&SendHeaders::new().serialize()?)?;
+        node.send_sendcmpct(false)?;
+        node.send_ping()?;
         Ok((node.address, node))
     }
 
-    pub fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    /// Opens a connection to the node, through the SOCKS5 proxy if one is configured. Onion peers can only be
+    /// reached through it, since it's the proxy that resolves their host.
+    fn dial(node_addr: SocketAddr, config: &Config) -> io::Result<TcpStream> {
+        let tcp_timeout = Duration::new(config.get_tcp_timeout(), 0);
+        match (config.get_proxy(), config.get_onion_peer(&node_addr)) {
+            (Some(proxy), Some(onion_peer)) => {
+                socks5::connect_to_onion(proxy, onion_peer, tcp_timeout)
+            }
+            (Some(proxy), None) => socks5::connect(proxy, node_addr, tcp_timeout),
+            (None, Some(_)) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Onion peers need a proxy",
+            )),
+            (None, None) => TcpStream::connect_timeout(&node_addr, tcp_timeout),
+        }
+    }
+
+    /// Performs the version handshake with the peer at `peer_addr`, sending our relay flag, and returns the
+    /// peer's version message.
+    pub fn handshake(
+        stream: &mut TcpStream,
+        peer_addr: SocketAddr,
+        relay: bool,
+    ) -> io::Result<Version> {
         // send message
-        let msg_version = Version::default_for_trans_addr(stream.peer_addr()?);
+        let msg_version = Version::default_for_trans_addr(peer_addr).with_relay(relay);
         let payload = msg_version.serialize()?;
         stream.write_all(&payload)?;
         stream.flush()?;
@@ -232,7 +484,7 @@ impl Node {
             }
         };
 
-        if !msg_version.accepts(version_message) {
+        if !msg_version.accepts(&version_message) {
             return Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "Version not supported",
@@ -242,10 +494,11 @@ impl Node {
         let payload = VerAck::new().serialize()?;
         stream.write_all(&payload)?; // send verack
         stream.flush()?;
-        Ok(())
+        Ok(version_message)
     }
 
-    pub fn inverse_handshake(stream: &mut TcpStream) -> io::Result<()> {
+    /// Answers the version handshake started by the peer with our relay flag, returning the peer's version message.
+    pub fn inverse_handshake(stream: &mut TcpStream, relay: bool) -> io::Result<Version> {
         let message_header = MessageHeader::from_stream(stream)?;
         let payload_data = message_header.read_payload(stream)?;
 
@@ -260,12 +513,12 @@ impl Node {
         };
 
         // send message
-        let msg_version = Version::default_for_trans_addr(stream.peer_addr()?);
+        let msg_version = Version::default_for_trans_addr(stream.peer_addr()?).with_relay(relay);
         let payload = msg_version.serialize()?;
         stream.write_all(&payload)?;
         stream.flush()?;
 
-        if !msg_version.accepts(version_message) {
+        if !msg_version.accepts(&version_message) {
             return Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "Version not supported",
@@ -278,13 +531,338 @@ impl Node {
 
         VerAck::from_stream(stream)?; // receive verack
 
+        Ok(version_message)
+    }
+
+    /// Announces compact block relay support to the peer, asking it to send new blocks right away as compact
+    /// blocks if `announce` is set (high-bandwidth mode), or to announce them as usual otherwise.
+    pub fn send_sendcmpct(&mut self, announce: bool) -> io::Result<()> {
+        let sendcmpct = SendCmpct::new(announce, CMPCT_VERSION);
+        self.send(&sendcmpct.serialize()?)?;
+        self.compact_relay.on_sent(&sendcmpct);
         Ok(())
     }
 
+    /// Returns true if compact blocks were negotiated with this peer.
+    pub fn supports_compact_blocks(&self) -> bool {
+        self.compact_relay.is_enabled()
+    }
+
     /// This function is used to send a message to a node a payload.
     pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
         self.stream.write_all(payload)?;
         self.stream.flush()?;
+        self.stats.add_bytes_sent(payload.len());
+        self.tracer.sent(self.address, payload);
+        Ok(())
+    }
+}
+
+/// Checks the peer advertises the services we sync from: full blocks (NODE_NETWORK) while downloading historical
+/// ones, since pruned peers only advertise the recent ones (NODE_NETWORK_LIMITED), and witness data (NODE_WITNESS)
+/// if we download it
+fn check_sync_services(
+    services: Services,
+    historical_blocks: bool,
+    download_witness: bool,
+) -> io::Result<()> {
+    if historical_blocks && !services.is_node_network() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Peer doesn't serve historical blocks",
+        ));
+    }
+    if download_witness && !services.is_node_witness() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Peer doesn't serve witness data",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::HeadlessUi;
+    use crate::logger::Log;
+    use crate::messages::constants::header_constants::HEADER_SIZE;
+    use crate::messages::{BlockHeader, HashId, Hashable, InvType, Inventory};
+    use crate::raw_transaction::tx_input::TxInputType;
+    use crate::socks5::OnionPeer;
+    use crate::test_fixtures::{tx_from_hex, PAYMENT_TX_HEX};
+    use std::fs;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    fn loopback_listener() -> io::Result<Listener> {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(tcp_listener.local_addr()?)?;
+        let (_peer_stream, _) = tcp_listener.accept()?;
+        let (writer_channel, _receiver) = mpsc::sync_channel(1);
+        let socket_addr = stream.peer_addr()?;
+        Listener::new(
+            stream,
+            socket_addr,
+            writer_channel,
+            Arc::new(PeerStats::default()),
+            MessageTracer::default(),
+        )
+    }
+
+    #[test]
+    fn test_onion_peer_is_dialed_through_the_proxy() -> io::Result<()> {
+        let proxy = TcpListener::bind("127.0.0.1:0")?;
+        let host = "expyuzz4wqqyqhjn.onion";
+        let onion_peer = format!("{}:18333", host);
+        let dir = "test_onion_peer_dial";
+        fs::create_dir_all(dir)?;
+        let config_file = format!("{}/node.conf", dir);
+        let options = [
+            "network=regtest".to_string(),
+            format!("log_file={}/node.log", dir),
+            format!("proxy={}", proxy.local_addr()?),
+            format!("onion_peers={}", onion_peer),
+            "require_peer_services=false".to_string(),
+        ];
+        fs::write(&config_file, options.join("\n"))?;
+        let config = Config::from_file(config_file.into());
+        let mock_proxy = thread::spawn(move || -> io::Result<(Vec<u8>, TcpStream)> {
+            let (mut stream, _) = proxy.accept()?;
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting)?;
+            stream.write_all(&[0x05, 0x00])?;
+            let mut request = vec![0u8; 7 + host.len()];
+            stream.read_exact(&mut request)?;
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])?;
+            // the proxy relays the onion peer, which answers the handshake and stays connected
+            Node::inverse_handshake(&mut stream, true)?;
+            Ok((request, stream))
+        });
+
+        let result = config.and_then(|config| {
+            let addr = OnionPeer::from_str(&onion_peer)?.stand_in_addr();
+            let (writer_channel, _receiver) = mpsc::sync_channel(100);
+            let ui_sender = Arc::new(HeadlessUi);
+            let (node_addr, _node) =
+                Node::try_from_addr(addr, false, false, writer_channel, ui_sender, config)?;
+            assert_eq!(node_addr, addr);
+            let (request, _peer_stream) = mock_proxy
+                .join()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "Proxy thread panicked"))??;
+            // the host is sent for the proxy to resolve, followed by the port
+            let host = host.as_bytes();
+            assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, host.len() as u8]);
+            assert_eq!(request[5..5 + host.len()], *host);
+            assert_eq!(request[5 + host.len()..], 18333u16.to_be_bytes());
+            Ok(())
+        });
+        fs::remove_dir_all(dir)?;
+        result
+    }
+
+    #[test]
+    fn test_pruned_peer_is_rejected_for_ibd() {
+        let pruned = Services::new(0x0400);
+        assert!(check_sync_services(pruned, true, false).is_err());
+        // a full node is only rejected when witness data is needed and it doesn't serve it
+        let full = Services::new(0x01);
+        assert!(check_sync_services(full, true, false).is_ok());
+        assert!(check_sync_services(full, true, true).is_err());
+        assert!(check_sync_services(Services::new(0x09), true, true).is_ok());
+    }
+
+    #[test]
+    fn test_pruned_peer_is_accepted_after_ibd() {
+        let pruned = Services::new(0x0400);
+        assert!(check_sync_services(pruned, false, false).is_ok());
+        // witness data is still needed for new blocks
+        assert!(check_sync_services(pruned, false, true).is_err());
+        assert!(check_sync_services(Services::new(0x0408), false, true).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_script_sig_is_dropped_without_penalty() -> io::Result<()> {
+        let mut listener = loopback_listener()?;
+        let mut tx = tx_from_hex(PAYMENT_TX_HEX)?;
+        if let TxInputType::TxInput(inputs) = &mut tx.tx_in {
+            inputs[0].script_sig = vec![0x51; ScriptLimits::RELAY.script_sig + 1];
+            inputs[0].script_bytes = inputs[0].script_sig.len() as u64;
+        }
+        let message = listener.process_message_payload("tx", tx.serialize())?;
+        assert!(matches!(message, Message::Ignore));
+        assert_eq!(listener.misbehavior_score, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_command_is_ignored_without_penalty() -> io::Result<()> {
+        let mut listener = loopback_listener()?;
+        let message = listener.process_message_payload("wtxidrelay\0\0", vec![0x01, 0x02])?;
+        assert!(matches!(message, Message::Ignore));
+        assert_eq!(listener.misbehavior_score, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_known_command_is_penalized() -> io::Result<()> {
+        let mut listener = loopback_listener()?;
+        let message = listener.process_message_payload(commands::BLOCK, vec![0x01, 0x02])?;
+        assert!(matches!(message, Message::Ignore));
+        assert_eq!(listener.misbehavior_score, MISBEHAVIOR_PENALTY);
+        Ok(())
+    }
+
+    #[test]
+    fn test_notfound_is_told_apart_from_inv() -> io::Result<()> {
+        let mut listener = loopback_listener()?;
+        let missing = Inventory::new(InvType::MSGBlock, HashId::new([7; 32]));
+        let payload = InventoryVector::new(vec![missing]).build_payload()?;
+        match listener.process_message_payload(commands::NOTFOUND, payload)? {
+            Message::NotFound(inventories) => {
+                assert_eq!(inventories.items[0].hash, HashId::new([7; 32]));
+            }
+            _ => panic!("Expected the notfound inventories"),
+        }
+        assert_eq!(listener.misbehavior_score, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmpctblock_is_passed_on_by_the_listener() -> io::Result<()> {
+        let dir = "test_listener_cmpctblock";
+        fs::create_dir_all(dir)?;
+        let config_file = format!("{}/node.conf", dir);
+        fs::write(
+            &config_file,
+            format!("network=regtest\nlog_file={}/node.log", dir),
+        )?;
+        let result = Config::from_file(config_file.into()).and_then(|config| {
+            let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+            let stream = TcpStream::connect(tcp_listener.local_addr()?)?;
+            let (mut peer_stream, _) = tcp_listener.accept()?;
+            let (writer_channel, receiver) = mpsc::sync_channel(1);
+            let socket_addr = stream.peer_addr()?;
+            let stats = Arc::new(PeerStats::default());
+            let mut listener = Listener::new(
+                stream,
+                socket_addr,
+                writer_channel,
+                stats,
+                MessageTracer::default(),
+            )?;
+            thread::spawn(move || listener.listen(&config));
+
+            let header = BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, 0);
+            let block = Block::new(header, 1, vec![tx_from_hex(PAYMENT_TX_HEX)?]);
+            let compact_block = CompactBlock::from_block(&block, 7);
+            peer_stream.write_all(&compact_block.serialize()?)?;
+            match receiver
+                .recv_timeout(Duration::from_secs(5))
+                .map_err(to_io_err)?
+            {
+                (addr, Message::CompactBlock(received)) => {
+                    assert_eq!(addr, socket_addr);
+                    assert_eq!(received.header.hash(), block.header.hash());
+                    assert_eq!(received.prefilled, compact_block.prefilled);
+                }
+                _ => panic!("Expected the compact block"),
+            }
+            Ok(())
+        });
+        fs::remove_dir_all(dir)?;
+        result
+    }
+
+    #[test]
+    fn test_handshake_retains_peer_version() -> io::Result<()> {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = tcp_listener.local_addr()?;
+        let peer = thread::spawn(move || -> io::Result<Version> {
+            let (mut stream, _) = tcp_listener.accept()?;
+            Node::inverse_handshake(&mut stream, false)
+        });
+
+        let mut stream = TcpStream::connect(address)?;
+        let peer_version = Node::handshake(&mut stream, address, true)?;
+        let our_version = peer
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Peer thread panicked"))??;
+
+        let sent_version = Version::default_for_trans_addr(stream.local_addr()?);
+        assert_eq!(peer_version.version(), sent_version.version());
+        assert_eq!(peer_version.services(), sent_version.services());
+        assert_eq!(peer_version.user_agent(), sent_version.user_agent());
+        assert_eq!(peer_version.start_height(), sent_version.start_height());
+        // the peer asked not to be sent transactions
+        assert!(!peer_version.relay());
+        assert_eq!(our_version.user_agent(), sent_version.user_agent());
+        Ok(())
+    }
+
+    #[test]
+    fn test_received_message_is_traced() -> io::Result<()> {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(tcp_listener.local_addr()?)?;
+        let (mut peer_stream, _) = tcp_listener.accept()?;
+        let (writer_channel, _receiver) = mpsc::sync_channel(1);
+        let (log_sender, log_receiver) = mpsc::channel();
+        let tracer = MessageTracer::new(log_sender);
+        let stats = Arc::new(PeerStats::default());
+        let socket_addr = stream.peer_addr()?;
+        let mut listener = Listener::new(stream, socket_addr, writer_channel, stats, tracer)?;
+
+        let headers = Headers::default().serialize()?;
+        peer_stream.write_all(&headers)?;
+        let (message_header, _payload) = listener.read_message()?;
+        assert_eq!(message_header.command_name, commands::HEADERS);
+
+        let expected = format!(
+            "[trace] received headers {} ({} bytes)",
+            listener.socket_addr,
+            headers.len()
+        );
+        match log_receiver.try_recv().map_err(to_io_err)? {
+            Log::Trace(line) => assert_eq!(line, expected),
+            _ => panic!("Expected a trace log"),
+        }
+        Ok(())
+    }
+
+    fn receive(relay: &mut CompactRelay, sendcmpct: &SendCmpct) -> io::Result<()> {
+        let bytes = sendcmpct.serialize()?;
+        if let Message::SendCmpct(received) = SendCmpct::deserialize(&bytes[HEADER_SIZE..])? {
+            relay.on_received(&received);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sendcmpct_sets_compact_relay_flags() -> io::Result<()> {
+        let mut relay = CompactRelay::default();
+        assert!(!relay.is_enabled());
+
+        receive(&mut relay, &SendCmpct::new(true, CMPCT_VERSION))?;
+        assert!(relay.high_bandwidth);
+        assert_eq!(relay.version, Some(CMPCT_VERSION));
+        assert!(!relay.is_enabled());
+
+        relay.on_sent(&SendCmpct::new(false, CMPCT_VERSION));
+        assert!(relay.is_enabled());
+        assert!(relay.wants_compact_blocks());
+
+        receive(&mut relay, &SendCmpct::new(false, CMPCT_VERSION))?;
+        assert!(!relay.high_bandwidth);
+        assert!(relay.is_enabled());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sendcmpct_with_unsupported_version_is_ignored() -> io::Result<()> {
+        let mut relay = CompactRelay::default();
+        receive(&mut relay, &SendCmpct::new(true, CMPCT_VERSION + 1))?;
+        assert_eq!(relay, CompactRelay::default());
         Ok(())
     }
 }
@@ -1,59 +1,274 @@
-use crate::config::Config;
+use crate::config::{whitelist_contains, Config, WhitelistedPeer};
 use crate::messages::constants::config::QUIET;
-use crate::messages::Message;
-use crate::node::Node;
+use crate::messages::{
+    BlockHeader, CompactBlock, FeeFilter, HashId, Hashable, Headers, InvType, Inventory,
+    InventoryVector, Message, SendCmpct, Serialize,
+};
+use crate::node::{Node, TxRelay};
+use crate::peer_info::PeerInfo;
+use crate::socks5::OnionPeer;
+use crate::upload_target::UploadTarget;
 use std::collections::HashMap;
 use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::mpsc;
+use std::time::Instant;
 // gtk imports
-use crate::interface::GtkMessage;
-use gtk::glib::SyncSender;
+use crate::interface::UiSender;
+
+/// Peers asked to send new blocks right away as compact blocks, as many as BIP152 recommends
+const HIGH_BANDWIDTH_PEERS: usize = 3;
 
 /// The NodeController struct is responsible for managing all the nodes and sending messages to them.
 
 pub struct NodeController {
     nodes: HashMap<SocketAddr, Node>,
+    closed_bytes_received: u64, // bytes received from connections already closed
+    closed_bytes_sent: u64,     // bytes sent to connections already closed
+    upload_target: Option<UploadTarget>,
 }
 
+/// Returns the addresses found through the seed along with the onion peers, or only the trusted peer if there's one
 fn find_nodes(config: &Config) -> Result<std::vec::IntoIter<SocketAddr>, io::Error> {
+    if let Some(trusted_peer) = config.get_trusted_peer() {
+        return Ok(vec![trusted_peer].into_iter());
+    }
     let node_discovery_hostname = config.get_hostname();
-    node_discovery_hostname.to_socket_addrs()
+    let onion_peers = config
+        .get_onion_peers()
+        .iter()
+        .map(OnionPeer::stand_in_addr);
+    let nodes: Vec<SocketAddr> = node_discovery_hostname
+        .to_socket_addrs()?
+        .chain(onion_peers)
+        .collect();
+    Ok(nodes.into_iter())
 }
 
 impl NodeController {
     /// Creates a new NodeController and connects to the peers.
     pub fn connect_to_peers(
         writer_end: mpsc::SyncSender<(SocketAddr, Message)>,
-        sender: SyncSender<GtkMessage>,
+        sender: UiSender,
         config: Config,
     ) -> Result<Self, io::Error> {
-        let node_addresses = find_nodes(&config)?;
-        let mut nodes = HashMap::new();
-        for node_addr in node_addresses {
-            match Node::try_from_addr(
+        let block_relay_only = config.get_block_relay_connections();
+        let nodes =
+            Self::connect_to_new_peers(&[], block_relay_only, false, writer_end, sender, &config)?;
+        Ok(Self {
+            nodes: nodes.into_iter().collect(),
+            closed_bytes_received: 0,
+            closed_bytes_sent: 0,
+            upload_target: config
+                .get_max_upload_target()
+                .map(|bytes_per_day| UploadTarget::new(bytes_per_day, Instant::now())),
+        })
+    }
+
+    /// Connects to the nodes found through the seed, except the given ones, making up to the given number of the
+    /// connections block-relay-only. Returns the connected nodes.
+    /// Fails if the trusted peer isn't connected and can't be reached, since no other peer replaces it.
+    pub fn connect_to_new_peers(
+        known_peers: &[SocketAddr],
+        mut block_relay_only: usize,
+        ibd_complete: bool,
+        writer_end: mpsc::SyncSender<(SocketAddr, Message)>,
+        sender: UiSender,
+        config: &Config,
+    ) -> io::Result<Vec<(SocketAddr, Node)>> {
+        let mut nodes = vec![];
+        for node_addr in find_nodes(config)?.filter(|addr| !known_peers.contains(addr)) {
+            let block_relay = block_relay_only > 0
+                && may_be_block_relay_only(
+                    &node_addr,
+                    config.get_trusted_peer(),
+                    config.get_whitelist(),
+                );
+            let connected = Node::try_from_addr(
                 node_addr,
+                block_relay,
+                ibd_complete,
                 writer_end.clone(),
                 sender.clone(),
                 config.clone(),
-            ) {
-                Ok((peer_addr, node)) => {
-                    nodes.insert(peer_addr, node);
-                    // break; // uncomment this to use a single node as peer
-                }
-                Err(..) => continue,
+            );
+            if let Ok((peer_addr, node)) = connected {
+                block_relay_only -= block_relay as usize;
+                nodes.push((peer_addr, node));
+                // break; // uncomment this to use a single node as peer
             }
         }
-        Ok(Self { nodes })
+        match config.get_trusted_peer() {
+            Some(trusted_peer) if nodes.is_empty() && !known_peers.contains(&trusted_peer) => {
+                Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("Could not connect to trusted peer {}", trusted_peer),
+                ))
+            }
+            _ => Ok(nodes),
+        }
+    }
+
+    /// Returns the addresses of the connected peers
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.nodes.keys().copied().collect()
     }
 
     pub fn add_node(&mut self, node: Node) {
         self.nodes.insert(node.address, node);
     }
 
+    /// Updates the compact block relay state of a peer after it sent us a sendcmpct message.
+    pub fn update_compact_relay(&mut self, peer: &SocketAddr, sendcmpct: &SendCmpct) {
+        if let Some(node) = self.nodes.get_mut(peer) {
+            node.compact_relay.on_received(sendcmpct);
+        }
+    }
+
+    /// Returns true if compact blocks were negotiated with the peer
+    pub fn supports_compact_blocks(&self, peer: &SocketAddr) -> bool {
+        self.nodes
+            .get(peer)
+            .is_some_and(Node::supports_compact_blocks)
+    }
+
+    /// Asks up to `HIGH_BANDWIDTH_PEERS` of the given peers supporting compact blocks to send new blocks right away
+    /// as compact blocks, instead of announcing them first (high-bandwidth mode)
+    pub fn request_compact_block_announcements(&mut self, peers: &[SocketAddr], config: &Config) {
+        let candidates = self
+            .nodes
+            .values_mut()
+            .filter(|node| peers.contains(&node.address) && node.supports_compact_blocks());
+        for node in candidates.take(HIGH_BANDWIDTH_PEERS) {
+            if let Err(e) = node.send_sendcmpct(true) {
+                config.log(
+                    &format!("Could not send sendcmpct to {}: {}", node.address, e),
+                    QUIET,
+                );
+            }
+        }
+    }
+
+    /// Returns the number of block-relay-only connections missing to have the given number of them
+    pub fn missing_block_relay_only(&self, block_relay_connections: usize) -> usize {
+        let connected = self
+            .nodes
+            .values()
+            .filter(|node| !node.tx_relay.relay_txs)
+            .count();
+        block_relay_connections.saturating_sub(connected)
+    }
+
+    /// Returns true if the connection with the peer only relays blocks
+    pub fn is_block_relay_only(&self, peer: &SocketAddr) -> bool {
+        self.nodes
+            .get(peer)
+            .is_some_and(|node| !node.tx_relay.relay_txs)
+    }
+
+    /// Updates the fee rate below which transactions aren't announced to a peer after it sent us a feefilter message.
+    pub fn update_fee_filter(&mut self, peer: &SocketAddr, feefilter: &FeeFilter) {
+        if let Some(node) = self.nodes.get_mut(peer) {
+            node.tx_relay.on_fee_filter(feefilter);
+        }
+    }
+
+    /// Records that a peer wants new blocks announced with headers after it sent us a sendheaders message.
+    pub fn update_prefers_headers(&mut self, peer: &SocketAddr) {
+        if let Some(node) = self.nodes.get_mut(peer) {
+            node.prefers_headers = true;
+        }
+    }
+
+    /// Announces a new tip received from `source` to the other peers: the block itself as a compact block to the ones
+    /// that asked for it, if it's given, and headers to the ones that prefer them
+    pub fn announce_block(
+        &mut self,
+        header: BlockHeader,
+        compact_block: Option<&CompactBlock>,
+        source: SocketAddr,
+        config: &Config,
+    ) -> io::Result<()> {
+        let mut allowed = allowed_peers(self.nodes.keys(), config.get_whitelist());
+        if let Some(compact_block) = compact_block {
+            let compact_peers: Vec<SocketAddr> = self
+                .nodes
+                .values()
+                .filter(|node| node.address != source && node.compact_relay.wants_compact_blocks())
+                .map(|node| node.address)
+                .filter(|peer| allowed.contains(peer))
+                .collect();
+            self.send_to_peers(&compact_peers, &compact_block.serialize()?, config);
+            allowed.retain(|peer| !compact_peers.contains(peer));
+        }
+        let candidates = self
+            .nodes
+            .values()
+            .filter(|node| allowed.contains(&node.address))
+            .map(|node| (node.address, node.prefers_headers));
+        let (headers_peers, inv_peers) = block_announcement_peers(candidates, source);
+        let headers = Headers::new(1, vec![header]);
+        self.send_to_peers(&headers_peers, &headers.serialize()?, config);
+        let inv = InventoryVector::new(vec![Inventory::new(InvType::MSGBlock, header.hash())]);
+        self.send_to_peers(&inv_peers, &inv.serialize()?, config);
+        Ok(())
+    }
+
+    /// Announces a transaction received from `source` to the other peers whose relay flag and fee filter accept it.
+    pub fn announce_transaction(
+        &mut self,
+        txid: HashId,
+        fee_rate: Option<u64>,
+        source: SocketAddr,
+        config: &Config,
+    ) -> io::Result<()> {
+        let allowed = allowed_peers(self.nodes.keys(), config.get_whitelist());
+        let candidates = self
+            .nodes
+            .values()
+            .filter(|node| allowed.contains(&node.address))
+            .map(|node| (node.address, &node.tx_relay));
+        let peers = tx_relay_peers(candidates, source, fee_rate);
+        let inv = InventoryVector::new(vec![Inventory::new(InvType::MSGTx, txid)]);
+        self.send_to_peers(&peers, &inv.serialize()?, config);
+        Ok(())
+    }
+
+    /// Returns the diagnostic information of every connected peer.
+    pub fn peer_info(&self) -> Vec<PeerInfo> {
+        self.nodes.values().map(Node::peer_info).collect()
+    }
+
+    /// Returns the bytes received from all peers so far, including the ones no longer connected.
+    pub fn bytes_received(&self) -> u64 {
+        let connected: u64 = self.nodes.values().map(Node::bytes_received).sum();
+        self.closed_bytes_received + connected
+    }
+
+    /// Returns the bytes sent to all peers so far, including the ones no longer connected.
+    pub fn bytes_sent(&self) -> u64 {
+        let connected: u64 = self.nodes.values().map(Node::bytes_sent).sum();
+        self.closed_bytes_sent + connected
+    }
+
+    /// Returns true if the bytes sent today reached the upload target, false if there's none
+    pub fn upload_target_reached(&mut self) -> bool {
+        let sent = self.bytes_sent();
+        self.upload_target
+            .as_mut()
+            .is_some_and(|target| target.is_reached(sent, Instant::now()))
+    }
+
+    fn remove_node(&mut self, socket_addr: &SocketAddr) {
+        if let Some(node) = self.nodes.remove(socket_addr) {
+            self.closed_bytes_received += node.bytes_received();
+            self.closed_bytes_sent += node.bytes_sent();
+        }
+    }
+
     /// Kills a node and removes it from the list of nodes given its peer address.
     pub fn kill_node(&mut self, socket_addr: SocketAddr) -> io::Result<()> {
-        self.nodes.remove(&socket_addr);
+        self.remove_node(&socket_addr);
         if self.nodes.is_empty() {
             return Err(io::Error::new(
                 io::ErrorKind::NotConnected,
@@ -96,21 +311,201 @@ impl NodeController {
         }
     }
 
-    /// Broadcasts a message to all the nodes.
-    pub fn send_to_all(&mut self, payload: &[u8], config: &Config) -> io::Result<()> {
-        let mut alive_nodes: Vec<SocketAddr> = vec![];
+    /// Sends a message to the given peers, dropping the connections that fail.
+    fn send_to_peers(&mut self, peers: &[SocketAddr], payload: &[u8], config: &Config) {
+        let mut dead_nodes: Vec<SocketAddr> = vec![];
         for node in self.nodes.values_mut() {
-            match node.send(payload) {
-                Ok(_) => {
-                    alive_nodes.push(node.address);
-                }
-                Err(e) => config.log(
+            if !peers.contains(&node.address) {
+                continue;
+            }
+            if let Err(e) = node.send(payload) {
+                config.log(
                     &format!("Error writing to TCPStream: {:?}, Killing connection.", e) as &str,
                     QUIET,
-                ),
+                );
+                dead_nodes.push(node.address);
             }
         }
-        self.nodes.retain(|k, _v| alive_nodes.contains(k));
+        for dead_node in dead_nodes {
+            self.remove_node(&dead_node);
+        }
+    }
+
+    /// Broadcasts a transaction to all the nodes relay is allowed with, except block-relay-only connections.
+    pub fn send_to_tx_relay_peers(&mut self, payload: &[u8], config: &Config) -> io::Result<()> {
+        let peers: Vec<SocketAddr> = allowed_peers(self.nodes.keys(), config.get_whitelist())
+            .into_iter()
+            .filter(|peer| !self.is_block_relay_only(peer))
+            .collect();
+        self.send_to_peers(&peers, payload, config);
+        Ok(())
+    }
+
+    /// Returns the peers headers and blocks can be requested from, which may include peers outside the whitelist if allowed for syncing.
+    pub fn sync_peers(&self, config: &Config) -> Vec<SocketAddr> {
+        self.nodes
+            .keys()
+            .filter(|peer| config.is_sync_peer(peer))
+            .copied()
+            .collect()
+    }
+}
+
+/// Returns the peers a transaction received from `source` is announced to: every other peer accepting its fee rate.
+fn tx_relay_peers<'a>(
+    peers: impl Iterator<Item = (SocketAddr, &'a TxRelay)>,
+    source: SocketAddr,
+    fee_rate: Option<u64>,
+) -> Vec<SocketAddr> {
+    peers
+        .filter(|(peer, tx_relay)| *peer != source && tx_relay.accepts(fee_rate))
+        .map(|(peer, _)| peer)
+        .collect()
+}
+
+/// Returns the peers other than `source` a new block is announced to with headers, and the ones it's announced to with inv
+fn block_announcement_peers(
+    peers: impl Iterator<Item = (SocketAddr, bool)>,
+    source: SocketAddr,
+) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+    let (headers_peers, inv_peers): (Vec<_>, Vec<_>) = peers
+        .filter(|(peer, _)| *peer != source)
+        .partition(|(_, prefers_headers)| *prefers_headers);
+    (
+        headers_peers.into_iter().map(|(peer, _)| peer).collect(),
+        inv_peers.into_iter().map(|(peer, _)| peer).collect(),
+    )
+}
+
+/// Returns the peers that are in the whitelist, or all of them if the whitelist is empty.
+fn allowed_peers<'a>(
+    peers: impl Iterator<Item = &'a SocketAddr>,
+    whitelist: &[WhitelistedPeer],
+) -> Vec<SocketAddr> {
+    peers
+        .filter(|peer| whitelist.is_empty() || whitelist_contains(whitelist, peer))
+        .copied()
+        .collect()
+}
+
+/// Returns true if the connection with the peer may be block-relay-only. The trusted and whitelisted peers are the
+/// ones relied on, so they keep relaying transactions.
+fn may_be_block_relay_only(
+    peer: &SocketAddr,
+    trusted_peer: Option<SocketAddr>,
+    whitelist: &[WhitelistedPeer],
+) -> bool {
+    trusted_peer != Some(*peer) && !whitelist_contains(whitelist, peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::to_io_err;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_whitelist_mode_only_allows_whitelisted_peers() -> io::Result<()> {
+        let whitelisted: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let other: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let peers = [whitelisted, other];
+
+        assert_eq!(
+            allowed_peers(peers.iter(), &[whitelisted.into()]),
+            vec![whitelisted]
+        );
+        assert_eq!(allowed_peers(peers.iter(), &[]), peers.to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_whitelisted_ip_matches_inbound_peer_on_another_port() -> io::Result<()> {
+        let inbound: SocketAddr = "127.0.0.1:50123".parse().map_err(to_io_err)?;
+        let whitelist = [WhitelistedPeer::from_str("127.0.0.1")?];
+        assert_eq!(allowed_peers([inbound].iter(), &whitelist), vec![inbound]);
+
+        // an entry with a port only matches that port
+        let whitelist = [WhitelistedPeer::from_str("127.0.0.1:18333")?];
+        assert!(allowed_peers([inbound].iter(), &whitelist).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tx_from_peer_is_announced_to_the_others() -> io::Result<()> {
+        let peer_a: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let peer_b: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let peer_c: SocketAddr = "127.0.0.3:18333".parse().map_err(to_io_err)?;
+        let relaying = TxRelay::new(true);
+        let no_relay = TxRelay::new(false);
+        let mut filtering = TxRelay::new(true);
+        filtering.on_fee_filter(&FeeFilter::new(5000));
+
+        let peers = [
+            (peer_a, &relaying),
+            (peer_b, &relaying),
+            (peer_c, &no_relay),
+        ];
+        assert_eq!(
+            tx_relay_peers(peers.into_iter(), peer_a, Some(1000)),
+            vec![peer_b]
+        );
+
+        let peers = [(peer_a, &relaying), (peer_b, &filtering)];
+        assert!(tx_relay_peers(peers.into_iter(), peer_a, Some(1000)).is_empty());
+        assert!(tx_relay_peers(peers.into_iter(), peer_a, None).is_empty());
+        assert_eq!(
+            tx_relay_peers(peers.into_iter(), peer_a, Some(5000)),
+            vec![peer_b]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_block_is_announced_to_peers_other_than_the_sender() -> io::Result<()> {
+        let sender: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let inv_peer: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let headers_peer: SocketAddr = "127.0.0.3:18333".parse().map_err(to_io_err)?;
+
+        let peers = [(sender, true), (inv_peer, false), (headers_peer, true)];
+        assert_eq!(
+            block_announcement_peers(peers.into_iter(), sender),
+            (vec![headers_peer], vec![inv_peer])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_relay_only_peer_gets_block_invs_but_not_tx_invs() -> io::Result<()> {
+        let source: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let full_relay: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let block_relay: SocketAddr = "127.0.0.3:18333".parse().map_err(to_io_err)?;
+        let relaying = TxRelay::new(true);
+        let mut block_relay_only = TxRelay::new(true);
+        block_relay_only.relay_txs = false;
+
+        let peers = [(full_relay, &relaying), (block_relay, &block_relay_only)];
+        assert_eq!(
+            tx_relay_peers(peers.into_iter(), source, Some(1000)),
+            vec![full_relay]
+        );
+        let peers = [(full_relay, false), (block_relay, false)];
+        assert_eq!(
+            block_announcement_peers(peers.into_iter(), source),
+            (vec![], vec![full_relay, block_relay])
+        );
+
+        // the peers relied on keep relaying transactions
+        assert!(may_be_block_relay_only(
+            &block_relay,
+            Some(source),
+            &[full_relay.into()]
+        ));
+        assert!(!may_be_block_relay_only(&source, Some(source), &[]));
+        assert!(!may_be_block_relay_only(
+            &full_relay,
+            None,
+            &[full_relay.into()]
+        ));
         Ok(())
     }
 }
@@ -0,0 +1,77 @@
+use crate::messages::HashId;
+use crate::raw_transaction::RawTransaction;
+use crate::tx_pool::BoundedTxPool;
+use std::net::SocketAddr;
+
+/// Transactions whose lock time hasn't passed, held until they can be included in the next block
+#[derive(Debug)]
+pub struct NonFinalTxs {
+    pool: BoundedTxPool,
+}
+
+impl NonFinalTxs {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            pool: BoundedTxPool::new(max_bytes),
+        }
+    }
+
+    /// Holds the transaction sent by the peer, evicting the oldest held ones while over the size of the pool.
+    /// Returns false if it was already held or is too big to be held.
+    pub fn hold(&mut self, tx: RawTransaction, source: SocketAddr) -> bool {
+        let txid = tx.txid();
+        if self.pool.contains(&txid) {
+            return false;
+        }
+        self.pool.add(tx, source);
+        self.pool.contains(&txid)
+    }
+
+    /// Removes and returns the held transactions that can be included in a block of the given height and time,
+    /// along with the peers that sent them, oldest first
+    pub fn take_final(&mut self, height: usize, time: u32) -> Vec<(RawTransaction, SocketAddr)> {
+        self.pool.take_matching(|tx| tx.is_final(height, time))
+    }
+
+    /// Drops the held transaction, which was included in a block
+    pub fn remove(&mut self, txid: &HashId) {
+        self.pool.remove(txid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_transaction::tx_input::TxInputType;
+    use crate::test_fixtures::transaction;
+    use crate::utility::to_io_err;
+    use std::io;
+
+    #[test]
+    fn test_tx_locked_until_a_future_height_is_held_until_then() -> io::Result<()> {
+        let mut tx = transaction(105)?;
+        let source: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        assert!(tx.is_final(104, 0)); // the lock time is ignored while every input's sequence is final
+        if let TxInputType::TxInput(inputs) = &mut tx.tx_in {
+            inputs[0].sequence = 0xfffffffe;
+        }
+        assert!(!tx.is_final(105, u32::MAX));
+
+        let mut non_final = NonFinalTxs::new(tx.serialize_with_witness().len());
+        assert!(non_final.hold(tx.clone(), source));
+        assert!(!non_final.hold(tx.clone(), source));
+        assert!(non_final.take_final(104, 0).is_empty());
+        assert!(non_final.take_final(105, 0).is_empty());
+        // the first block that can include it is the one built on top of block 105
+        assert_eq!(non_final.take_final(106, 0), vec![(tx.clone(), source)]);
+        assert!(non_final.take_final(107, 0).is_empty());
+
+        // only room for one, so a newer one evicts it
+        let mut newer = tx.clone();
+        newer.lock_time = 106;
+        assert!(non_final.hold(tx, source));
+        assert!(non_final.hold(newer.clone(), source));
+        assert_eq!(non_final.take_final(107, 0), vec![(newer, source)]);
+        Ok(())
+    }
+}
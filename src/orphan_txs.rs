@@ -0,0 +1,125 @@
+use crate::messages::HashId;
+use crate::raw_transaction::tx_input::TxInputType;
+use crate::raw_transaction::RawTransaction;
+use crate::tx_pool::BoundedTxPool;
+use crate::utxo::OutPoint;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Transactions spending outputs of transactions we haven't seen, held back until their parents arrive.
+/// They're indexed by the outpoints they spend, so the children of a transaction are found without going
+/// over the whole pool.
+#[derive(Debug)]
+pub struct OrphanTxs {
+    pool: BoundedTxPool,
+    by_outpoint: HashMap<OutPoint, Vec<HashId>>,
+}
+
+impl OrphanTxs {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            pool: BoundedTxPool::new(max_bytes),
+            by_outpoint: HashMap::new(),
+        }
+    }
+
+    /// Holds the orphan sent by the peer, evicting the oldest ones while the pool is over its size.
+    /// Returns the number of orphans evicted.
+    pub fn add(&mut self, tx: RawTransaction, source: SocketAddr) -> usize {
+        let txid = tx.txid();
+        if self.pool.contains(&txid) {
+            return 0;
+        }
+        for outpoint in spent_outpoints(&tx) {
+            self.by_outpoint.entry(outpoint).or_default().push(txid);
+        }
+        let evicted = self.pool.add(tx, source);
+        for orphan in &evicted {
+            self.unindex(orphan);
+        }
+        evicted.len()
+    }
+
+    /// Removes and returns the orphans spending an output of the given transaction, oldest first
+    pub fn children_of(&mut self, parent: &RawTransaction) -> Vec<(RawTransaction, SocketAddr)> {
+        let parent_txid = parent.txid();
+        let mut children: Vec<HashId> = (0..parent.tx_out.len() as u32)
+            .filter_map(|index| self.by_outpoint.get(&(parent_txid, index)))
+            .flatten()
+            .copied()
+            .collect();
+        children.sort_by_key(|txid| self.pool.arrival(txid));
+        children.dedup();
+        let children: Vec<(RawTransaction, SocketAddr)> = children
+            .iter()
+            .filter_map(|txid| self.pool.remove(txid))
+            .collect();
+        for (child, _source) in &children {
+            self.unindex(child);
+        }
+        children
+    }
+
+    /// Drops the orphan from the outpoint index
+    fn unindex(&mut self, orphan: &RawTransaction) {
+        let txid = orphan.txid();
+        for outpoint in spent_outpoints(orphan) {
+            if let Some(spenders) = self.by_outpoint.get_mut(&outpoint) {
+                spenders.retain(|spender| *spender != txid);
+                if spenders.is_empty() {
+                    self.by_outpoint.remove(&outpoint);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the outpoints the inputs of the transaction spend
+fn spent_outpoints(tx: &RawTransaction) -> Vec<OutPoint> {
+    match &tx.tx_in {
+        TxInputType::TxInput(inputs) => inputs
+            .iter()
+            .map(|input| (input.previous_output.hash, input.previous_output.index))
+            .collect(),
+        TxInputType::CoinBaseInput(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{tx_from_hex, FUNDING_TX_HEX, SPENDING_TX_HEX};
+    use crate::utility::to_io_err;
+    use std::io;
+
+    #[test]
+    fn test_orphans_past_the_byte_cap_evict_the_oldest() -> io::Result<()> {
+        let parent = tx_from_hex(FUNDING_TX_HEX)?;
+        // spends of the parent told apart by their lock time
+        let orphans = (0..3)
+            .map(|lock_time| {
+                let mut orphan = tx_from_hex(SPENDING_TX_HEX)?;
+                orphan.lock_time = lock_time;
+                Ok(orphan)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let size = orphans[0].serialize_with_witness().len();
+        let source: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+
+        // room for two orphans of this size, the third one evicts the first
+        let mut pool = OrphanTxs::new(2 * size);
+        assert_eq!(pool.add(orphans[0].clone(), source), 0);
+        assert_eq!(pool.add(orphans[1].clone(), source), 0);
+        assert_eq!(pool.add(orphans[2].clone(), source), 1);
+
+        assert!(pool.children_of(&orphans[1]).is_empty());
+        let children = pool.children_of(&parent);
+        assert_eq!(
+            children,
+            vec![(orphans[1].clone(), source), (orphans[2].clone(), source)]
+        );
+        assert!(pool.children_of(&parent).is_empty());
+        assert!(pool.by_outpoint.is_empty());
+        Ok(())
+    }
+}
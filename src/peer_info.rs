@@ -0,0 +1,193 @@
+use crate::messages::{Services, Version};
+use crate::utility::to_io_err;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Traffic and latency counters of a connection, shared between the node and its listener thread.
+#[derive(Debug, Default)]
+pub struct PeerStats {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    pending_ping: Mutex<Option<(u64, Instant)>>,
+    ping_time: Mutex<Option<Duration>>,
+}
+
+impl PeerStats {
+    pub fn add_bytes_sent(&self, amount: usize) {
+        self.bytes_sent.fetch_add(amount as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_received(&self, amount: usize) {
+        self.bytes_received
+            .fetch_add(amount as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Stores the nonce of a ping sent to the peer, to measure the time until its pong arrives.
+    pub fn ping_sent(&self, nonce: u64) -> io::Result<()> {
+        *self.pending_ping.lock().map_err(to_io_err)? = Some((nonce, Instant::now()));
+        Ok(())
+    }
+
+    /// Updates the ping time if the pong nonce matches the last ping sent.
+    pub fn pong_received(&self, nonce: u64) -> io::Result<()> {
+        let mut pending_ping = self.pending_ping.lock().map_err(to_io_err)?;
+        if let Some((ping_nonce, sent_at)) = *pending_ping {
+            if ping_nonce == nonce {
+                *self.ping_time.lock().map_err(to_io_err)? = Some(sent_at.elapsed());
+                *pending_ping = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn ping_time(&self) -> Option<Duration> {
+        match self.ping_time.lock() {
+            Ok(ping_time) => *ping_time,
+            Err(..) => None,
+        }
+    }
+}
+
+/// Diagnostic information of a connected peer (getpeerinfo)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub address: SocketAddr,
+    pub connected_since: i64,
+    pub services: Services,
+    pub version: i32,
+    pub user_agent: String,
+    pub start_height: i32,
+    pub relay: bool,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub ping_time: Option<Duration>,
+}
+
+impl PeerInfo {
+    pub fn new(
+        address: SocketAddr,
+        connected_since: i64,
+        peer_version: &Version,
+        stats: &PeerStats,
+    ) -> Self {
+        Self {
+            address,
+            connected_since,
+            services: peer_version.services(),
+            version: peer_version.version(),
+            user_agent: peer_version.user_agent().to_string(),
+            start_height: peer_version.start_height(),
+            relay: peer_version.relay(),
+            bytes_sent: stats.bytes_sent(),
+            bytes_received: stats.bytes_received(),
+            ping_time: stats.ping_time(),
+        }
+    }
+
+    /// Returns the information as a JSON object with the field names of getpeerinfo, the ping time in seconds
+    pub fn to_json(&self) -> String {
+        let ping_time = self
+            .ping_time
+            .map_or("null".to_string(), |ping| ping.as_secs_f64().to_string());
+        format!(
+            "{{\"addr\":\"{}\",\"services\":\"{:016x}\",\"version\":{},\"subver\":\"{}\",\"startingheight\":{},\
+             \"relaytxes\":{},\"conntime\":{},\"bytessent\":{},\"bytesrecv\":{},\"pingtime\":{}}}",
+            self.address,
+            self.services.bitmap(),
+            self.version,
+            escape_json(&self.user_agent),
+            self.start_height,
+            self.relay,
+            self.connected_since,
+            self.bytes_sent,
+            self.bytes_received,
+            ping_time
+        )
+    }
+}
+
+/// Escapes the string to be placed between quotes in JSON, as the user agent is whatever the peer sent
+pub fn escape_json(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl std::fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} version: {} user agent: {} start height: {} sent: {}B received: {}B ping: {:?}",
+            self.address,
+            self.version,
+            self.user_agent,
+            self.start_height,
+            self.bytes_sent,
+            self.bytes_received,
+            self.ping_time
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::constants::version_constants::{LATEST_VERSION, USER_AGENT};
+    use crate::node::Node;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn test_peer_info_after_handshake() -> io::Result<()> {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = tcp_listener.local_addr()?;
+        let peer = thread::spawn(move || -> io::Result<()> {
+            let (mut stream, _) = tcp_listener.accept()?;
+            Node::inverse_handshake(&mut stream, true)?;
+            Ok(())
+        });
+
+        let mut stream = TcpStream::connect(address)?;
+        let peer_version = Node::handshake(&mut stream, address, true)?;
+        peer.join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Peer thread panicked"))??;
+
+        let stats = PeerStats::default();
+        stats.add_bytes_sent(10);
+        let info = PeerInfo::new(address, 0, &peer_version, &stats);
+        assert_eq!(info.user_agent, USER_AGENT);
+        assert_eq!(info.version, LATEST_VERSION);
+        assert_eq!(info.bytes_sent, 10);
+        assert_eq!(info.ping_time, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ping_time_measured_on_matching_pong() -> io::Result<()> {
+        let stats = PeerStats::default();
+        stats.ping_sent(7)?;
+        stats.pong_received(8)?;
+        assert_eq!(stats.ping_time(), None);
+        stats.pong_received(7)?;
+        assert!(stats.ping_time().is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,1056 @@
+use super::script::{
+    is_push_only, is_valid_der_signature, number_from_bytes, parse_script, push_data, sigop_count,
+    ScriptOp, ScriptType, MAX_PUBKEYS_PER_MULTISIG, MAX_SCRIPT_ELEMENT_SIZE, OP_0, OP_1, OP_16,
+    OP_1NEGATE, OP_CHECKLOCKTIMEVERIFY, OP_CHECKMULTISIGVERIFY, OP_CHECKSEQUENCEVERIFY,
+    OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_DUP, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160, OP_RETURN,
+};
+use super::sighash::{legacy_sig_hash, WitnessSigHashes};
+use super::tx_input::{TxInput, TxInputType};
+use super::RawTransaction;
+use crate::network_params::SoftForks;
+use crate::utility::to_io_err;
+use bitcoin_hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, VerifyOnly};
+use std::cell::OnceCell;
+use std::io;
+use std::sync::OnceLock;
+
+/// Largest script that can be run, and most opcodes other than pushes it may run
+const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Most items the main and alternate stacks may hold together
+const MAX_STACK_SIZE: usize = 1000;
+/// Longest numbers arithmetic opcodes take, and the lock times checked by the lock time opcodes, which go past
+/// 2^31 to hold every lock time of 32 bits
+const MAX_NUMBER_SIZE: usize = 4;
+const MAX_LOCK_TIME_SIZE: usize = 5;
+
+const OP_NOP: u8 = 0x61;
+const OP_IF: u8 = 0x63;
+const OP_NOTIF: u8 = 0x64;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_VERIFY: u8 = 0x69;
+const OP_TOALTSTACK: u8 = 0x6b;
+const OP_FROMALTSTACK: u8 = 0x6c;
+const OP_2DROP: u8 = 0x6d;
+const OP_2DUP: u8 = 0x6e;
+const OP_3DUP: u8 = 0x6f;
+const OP_2OVER: u8 = 0x70;
+const OP_2ROT: u8 = 0x71;
+const OP_2SWAP: u8 = 0x72;
+const OP_IFDUP: u8 = 0x73;
+const OP_DEPTH: u8 = 0x74;
+const OP_DROP: u8 = 0x75;
+const OP_NIP: u8 = 0x77;
+const OP_OVER: u8 = 0x78;
+const OP_PICK: u8 = 0x79;
+const OP_ROLL: u8 = 0x7a;
+const OP_ROT: u8 = 0x7b;
+const OP_SWAP: u8 = 0x7c;
+const OP_TUCK: u8 = 0x7d;
+const OP_SIZE: u8 = 0x82;
+const OP_1ADD: u8 = 0x8b;
+const OP_1SUB: u8 = 0x8c;
+const OP_NEGATE: u8 = 0x8f;
+const OP_ABS: u8 = 0x90;
+const OP_NOT: u8 = 0x91;
+const OP_0NOTEQUAL: u8 = 0x92;
+const OP_ADD: u8 = 0x93;
+const OP_SUB: u8 = 0x94;
+const OP_BOOLAND: u8 = 0x9a;
+const OP_BOOLOR: u8 = 0x9b;
+const OP_NUMEQUAL: u8 = 0x9c;
+const OP_NUMEQUALVERIFY: u8 = 0x9d;
+const OP_NUMNOTEQUAL: u8 = 0x9e;
+const OP_LESSTHAN: u8 = 0x9f;
+const OP_GREATERTHAN: u8 = 0xa0;
+const OP_LESSTHANOREQUAL: u8 = 0xa1;
+const OP_GREATERTHANOREQUAL: u8 = 0xa2;
+const OP_MIN: u8 = 0xa3;
+const OP_WITHIN: u8 = 0xa5;
+const OP_RIPEMD160: u8 = 0xa6;
+const OP_SHA1: u8 = 0xa7;
+const OP_SHA256: u8 = 0xa8;
+const OP_HASH256: u8 = 0xaa;
+const OP_CODESEPARATOR: u8 = 0xab;
+const OP_NOP1: u8 = 0xb0;
+const OP_NOP10: u8 = 0xb9;
+
+fn script_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Returns true for the opcodes disabled in 2010 (OP_CAT, OP_MUL and the like), which make a script fail even
+/// in a branch that doesn't run
+fn is_disabled(opcode: u8) -> bool {
+    matches!(opcode, 0x7e..=0x81 | 0x83..=0x86 | 0x8d | 0x8e | 0x95..=0x99)
+}
+
+/// Returns true if the item is a true value: anything but zero, negative zero included
+fn is_true(item: &[u8]) -> bool {
+    match item.split_last() {
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+/// Decodes a number taken from the stack, failing if it's longer than allowed
+fn decode_number(item: &[u8], max_size: usize) -> io::Result<i64> {
+    match item.len() <= max_size {
+        true => Ok(number_from_bytes(item)),
+        false => Err(script_error("Number on the stack is too long")),
+    }
+}
+
+/// Encodes a number in little endian with the fewest bytes, the sign in the top bit of the last one
+fn encode_number(number: i64) -> Vec<u8> {
+    let mut magnitude = number.unsigned_abs();
+    let mut bytes = vec![];
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    match bytes.last_mut() {
+        Some(last) if *last & 0x80 != 0 => bytes.push(if number < 0 { 0x80 } else { 0 }),
+        Some(last) if number < 0 => *last |= 0x80,
+        _ => {}
+    }
+    bytes
+}
+
+/// Fails unless the stack ends with a true value
+fn require_true(stack: &[Vec<u8>]) -> io::Result<()> {
+    match stack.last() {
+        Some(item) if is_true(item) => Ok(()),
+        _ => Err(script_error("Script evaluated to false")),
+    }
+}
+
+/// Returns the version and program of a witness program output script (BIP141): a version opcode followed by a
+/// single push of 2 to 40 bytes
+fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    let version = match *script.first()? {
+        OP_0 => 0,
+        opcode @ OP_1..=OP_16 => opcode - OP_1 + 1,
+        _ => return None,
+    };
+    match (4..=42).contains(&script.len()) && script[1] as usize == script.len() - 2 {
+        true => Some((version, &script[2..])),
+        false => None,
+    }
+}
+
+/// Context signatures are verified with, created once since it's expensive
+fn verifier() -> &'static Secp256k1<VerifyOnly> {
+    static VERIFIER: OnceLock<Secp256k1<VerifyOnly>> = OnceLock::new();
+    VERIFIER.get_or_init(Secp256k1::verification_only)
+}
+
+/// How signatures are hashed: the legacy way, or the one of version 0 witness programs (BIP143)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+}
+
+/// Stacks of a running script, along with the branches of the conditionals it's in and the opcodes it ran
+#[derive(Debug, Default)]
+struct Machine {
+    stack: Vec<Vec<u8>>,
+    alt_stack: Vec<Vec<u8>>,
+    conditions: Vec<bool>,
+    op_count: usize,
+}
+
+impl Machine {
+    /// Returns true unless the script is in a branch of a conditional that isn't taken
+    fn is_executing(&self) -> bool {
+        self.conditions.iter().all(|taken| *taken)
+    }
+
+    fn count_ops(&mut self, count: usize) -> io::Result<()> {
+        self.op_count += count;
+        match self.op_count > MAX_OPS_PER_SCRIPT {
+            true => Err(script_error("Script runs too many opcodes")),
+            false => Ok(()),
+        }
+    }
+
+    fn pop(&mut self) -> io::Result<Vec<u8>> {
+        self.stack
+            .pop()
+            .ok_or_else(|| script_error("Script pops an empty stack"))
+    }
+
+    fn pop_number(&mut self) -> io::Result<i64> {
+        decode_number(&self.pop()?, MAX_NUMBER_SIZE)
+    }
+
+    fn pop_bool(&mut self) -> io::Result<bool> {
+        Ok(is_true(&self.pop()?))
+    }
+
+    /// Pops a count that must be between 0 and the given maximum
+    fn pop_count(&mut self, max: usize) -> io::Result<usize> {
+        usize::try_from(self.pop_number()?)
+            .ok()
+            .filter(|count| *count <= max)
+            .ok_or_else(|| script_error("Count on the stack is out of range"))
+    }
+
+    /// Pops the given number of items, the top one first
+    fn pop_items(&mut self, count: usize) -> io::Result<Vec<Vec<u8>>> {
+        (0..count).map(|_| self.pop()).collect()
+    }
+
+    fn push_number(&mut self, number: i64) {
+        self.stack.push(encode_number(number));
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.stack.push(if value { vec![1] } else { vec![] });
+    }
+
+    fn verify(&mut self) -> io::Result<()> {
+        match self.pop_bool()? {
+            true => Ok(()),
+            false => Err(script_error("Script failed a verification")),
+        }
+    }
+
+    /// Returns the item the given number of places below the top one
+    fn peek(&self, depth: usize) -> io::Result<&Vec<u8>> {
+        self.stack
+            .len()
+            .checked_sub(depth + 1)
+            .map(|index| &self.stack[index])
+            .ok_or_else(|| script_error("Script reads past the bottom of the stack"))
+    }
+
+    /// Removes and returns the item the given number of places below the top one
+    fn take(&mut self, depth: usize) -> io::Result<Vec<u8>> {
+        let index = self
+            .stack
+            .len()
+            .checked_sub(depth + 1)
+            .ok_or_else(|| script_error("Script reads past the bottom of the stack"))?;
+        Ok(self.stack.remove(index))
+    }
+
+    /// Pushes copies of the items at the given depths, each one counted after the previous copy was pushed
+    fn copy(&mut self, depths: &[usize]) -> io::Result<()> {
+        for depth in depths {
+            let item = self.peek(*depth)?.clone();
+            self.stack.push(item);
+        }
+        Ok(())
+    }
+
+    /// Moves the items at the given depths to the top, each one counted after the previous one was moved
+    fn roll(&mut self, depths: &[usize]) -> io::Result<()> {
+        for depth in depths {
+            let item = self.take(*depth)?;
+            self.stack.push(item);
+        }
+        Ok(())
+    }
+
+    fn flow_op(&mut self, opcode: u8) -> io::Result<()> {
+        match opcode {
+            OP_NOP => {}
+            OP_IF | OP_NOTIF => {
+                let taken = self.is_executing() && self.pop_bool()? == (opcode == OP_IF);
+                self.conditions.push(taken);
+            }
+            OP_ELSE => {
+                let taken = self
+                    .conditions
+                    .last_mut()
+                    .ok_or_else(|| script_error("OP_ELSE outside a conditional"))?;
+                *taken = !*taken;
+            }
+            OP_ENDIF => {
+                self.conditions
+                    .pop()
+                    .ok_or_else(|| script_error("OP_ENDIF outside a conditional"))?;
+            }
+            OP_VERIFY => self.verify()?,
+            OP_RETURN => return Err(script_error("Script ran OP_RETURN")),
+            _ => return Err(script_error("Script has a reserved opcode")),
+        }
+        Ok(())
+    }
+
+    fn stack_op(&mut self, opcode: u8) -> io::Result<()> {
+        match opcode {
+            OP_TOALTSTACK => {
+                let item = self.pop()?;
+                self.alt_stack.push(item);
+            }
+            OP_FROMALTSTACK => {
+                let item = self
+                    .alt_stack
+                    .pop()
+                    .ok_or_else(|| script_error("Script pops an empty alternate stack"))?;
+                self.stack.push(item);
+            }
+            OP_2DROP => _ = self.pop_items(2)?,
+            OP_2DUP => self.copy(&[1, 1])?,
+            OP_3DUP => self.copy(&[2, 2, 2])?,
+            OP_2OVER => self.copy(&[3, 3])?,
+            OP_2ROT => self.roll(&[5, 5])?,
+            OP_2SWAP => self.roll(&[3, 3])?,
+            OP_IFDUP if is_true(self.peek(0)?) => self.copy(&[0])?,
+            OP_DEPTH => self.push_number(self.stack.len() as i64),
+            OP_DROP => _ = self.pop()?,
+            OP_DUP => self.copy(&[0])?,
+            OP_NIP => _ = self.take(1)?,
+            OP_OVER => self.copy(&[1])?,
+            _ => self.reorder_op(opcode)?,
+        }
+        Ok(())
+    }
+
+    fn reorder_op(&mut self, opcode: u8) -> io::Result<()> {
+        match opcode {
+            OP_PICK | OP_ROLL => {
+                let depth = usize::try_from(self.pop_number()?)
+                    .map_err(|_| script_error("Script reads a negative stack depth"))?;
+                match opcode {
+                    OP_PICK => self.copy(&[depth])?,
+                    _ => self.roll(&[depth])?,
+                }
+            }
+            OP_ROT => self.roll(&[2])?,
+            OP_SWAP => self.roll(&[1])?,
+            OP_TUCK => {
+                let top = self.peek(0)?.clone();
+                self.peek(1)?;
+                self.stack.insert(self.stack.len() - 2, top);
+            }
+            OP_SIZE => self.push_number(self.peek(0)?.len() as i64),
+            _ => {} // OP_IFDUP of a false value
+        }
+        Ok(())
+    }
+
+    fn arithmetic_op(&mut self, opcode: u8) -> io::Result<()> {
+        if opcode <= OP_0NOTEQUAL {
+            let a = self.pop_number()?;
+            let result = match opcode {
+                OP_1ADD => a + 1,
+                OP_1SUB => a - 1,
+                OP_NEGATE => -a,
+                OP_ABS => a.abs(),
+                OP_NOT => (a == 0) as i64,
+                _ => (a != 0) as i64,
+            };
+            self.push_number(result);
+            return Ok(());
+        }
+        if opcode == OP_WITHIN {
+            let (max, min, value) = (self.pop_number()?, self.pop_number()?, self.pop_number()?);
+            self.push_bool(min <= value && value < max);
+            return Ok(());
+        }
+        let (b, a) = (self.pop_number()?, self.pop_number()?);
+        self.push_number(binary_op(opcode, a, b));
+        match opcode {
+            OP_NUMEQUALVERIFY => self.verify(),
+            _ => Ok(()),
+        }
+    }
+
+    fn hash_op(&mut self, opcode: u8) -> io::Result<()> {
+        let item = self.pop()?;
+        let hash = match opcode {
+            OP_RIPEMD160 => ripemd160::Hash::hash(&item).to_byte_array().to_vec(),
+            OP_SHA1 => sha1::Hash::hash(&item).to_byte_array().to_vec(),
+            OP_SHA256 => sha256::Hash::hash(&item).to_byte_array().to_vec(),
+            OP_HASH160 => hash160::Hash::hash(&item).to_byte_array().to_vec(),
+            _ => sha256d::Hash::hash(&item).to_byte_array().to_vec(),
+        };
+        self.stack.push(hash);
+        Ok(())
+    }
+}
+
+/// Applies an arithmetic opcode taking two numbers
+fn binary_op(opcode: u8, a: i64, b: i64) -> i64 {
+    match opcode {
+        OP_ADD => a + b,
+        OP_SUB => a - b,
+        OP_BOOLAND => (a != 0 && b != 0) as i64,
+        OP_BOOLOR => (a != 0 || b != 0) as i64,
+        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+        OP_NUMNOTEQUAL => (a != b) as i64,
+        OP_LESSTHAN => (a < b) as i64,
+        OP_GREATERTHAN => (a > b) as i64,
+        OP_LESSTHANOREQUAL => (a <= b) as i64,
+        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+        OP_MIN => a.min(b),
+        _ => a.max(b),
+    }
+}
+
+/// Runs the scripts of the inputs of a transaction, sharing the hashes witness signatures commit to across them
+pub struct TxChecker<'a> {
+    pub tx: &'a RawTransaction,
+    pub inputs: &'a [TxInput],
+    witness_hashes: OnceCell<WitnessSigHashes>,
+}
+
+impl<'a> TxChecker<'a> {
+    pub fn new(tx: &'a RawTransaction) -> io::Result<Self> {
+        match &tx.tx_in {
+            TxInputType::TxInput(inputs) => Ok(Self {
+                tx,
+                inputs,
+                witness_hashes: OnceCell::new(),
+            }),
+            TxInputType::CoinBaseInput(_) => Err(script_error("A coinbase has no scripts to run")),
+        }
+    }
+
+    fn witness_hashes(&self) -> &WitnessSigHashes {
+        self.witness_hashes
+            .get_or_init(|| WitnessSigHashes::new(self.tx, self.inputs))
+    }
+
+    /// Checks the input at the given index unlocks the output it spends, of the given value and script: its
+    /// script sig followed by the output script must leave a true value, as must the redeem script of a P2SH
+    /// output (BIP16) and, once segwit is active, the witness of a version 0 witness program (BIP141). Programs
+    /// of later versions, like taproot's (BIP341), aren't checked.
+    pub fn verify_input(
+        &self,
+        index: usize,
+        (amount, lock): (u64, &[u8]),
+        soft_forks: SoftForks,
+    ) -> io::Result<()> {
+        let input = self
+            .inputs
+            .get(index)
+            .ok_or_else(|| script_error("Input index out of range"))?;
+        let witness = self.tx.witnesses.get(index).map_or(&[][..], Vec::as_slice);
+        let execution = Execution {
+            checker: self,
+            index,
+            amount,
+            soft_forks,
+            sig_version: SigVersion::Base,
+        };
+        let stack = execution.eval(&input.script_sig, vec![])?;
+        require_true(&execution.eval(lock, stack.clone())?)?;
+        let mut witness_used = false;
+        if let (true, Some(program)) = (soft_forks.segwit, witness_program(lock)) {
+            if !input.script_sig.is_empty() {
+                return Err(script_error("Witness program spend has a script sig"));
+            }
+            execution.verify_witness(program, witness)?;
+            witness_used = true;
+        }
+        if let ScriptType::P2sh(_) = ScriptType::classify(lock) {
+            witness_used |= execution.verify_p2sh(&input.script_sig, stack, witness)?;
+        }
+        match soft_forks.segwit && !witness_used && !witness.is_empty() {
+            true => Err(script_error("Input has a witness it doesn't use")),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the cost of the signature operations the input at the given index runs besides the ones in its
+    /// script sig and the script of the output it spends: the ones of a P2SH redeem script cost 4 each, and
+    /// once segwit is active the ones of a version 0 witness program, nested in it or not, 1 each (BIP141)
+    pub fn sigops_cost(&self, index: usize, lock: &[u8], soft_forks: SoftForks) -> usize {
+        let Some(input) = self.inputs.get(index) else {
+            return 0;
+        };
+        let witness = self.tx.witnesses.get(index).map_or(&[][..], Vec::as_slice);
+        let redeem_script = match ScriptType::classify(lock) {
+            ScriptType::P2sh(_) if is_push_only(&input.script_sig) => last_push(&input.script_sig),
+            _ => None,
+        };
+        let p2sh_sigops = redeem_script
+            .as_deref()
+            .map_or(0, |script| sigop_count(script, true));
+        let program =
+            witness_program(lock).or_else(|| redeem_script.as_deref().and_then(witness_program));
+        let witness_sigops = match (soft_forks.segwit, program) {
+            (true, Some((0, program))) => witness_sigops(program, witness),
+            _ => 0,
+        };
+        4 * p2sh_sigops + witness_sigops
+    }
+}
+
+/// Returns the data the script pushes last, None if it ends with another opcode or can't be parsed
+fn last_push(script: &[u8]) -> Option<Vec<u8>> {
+    match parse_script(script).ok()?.pop()? {
+        (_, ScriptOp::Push(data)) => Some(data),
+        _ => None,
+    }
+}
+
+/// Returns the signature operations a version 0 witness program runs: one for P2WPKH, the ones of the script
+/// the witness ends with for P2WSH
+fn witness_sigops(program: &[u8], witness: &[Vec<u8>]) -> usize {
+    match (program.len(), witness.last()) {
+        (20, _) => 1,
+        (32, Some(script)) => sigop_count(script, true),
+        _ => 0,
+    }
+}
+
+/// Runs the scripts of an input
+#[derive(Clone, Copy)]
+struct Execution<'a> {
+    checker: &'a TxChecker<'a>,
+    index: usize,
+    amount: u64,
+    soft_forks: SoftForks,
+    sig_version: SigVersion,
+}
+
+impl Execution<'_> {
+    /// Runs the script on the given stack, returning the stack it leaves
+    fn eval(&self, script: &[u8], stack: Vec<Vec<u8>>) -> io::Result<Vec<Vec<u8>>> {
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(script_error("Script is too large"));
+        }
+        let ops = parse_script(script).map_err(|_| script_error("Script has a truncated push"))?;
+        let mut machine = Machine {
+            stack,
+            ..Machine::default()
+        };
+        let mut code_start = 0; // signatures only sign the script after the last OP_CODESEPARATOR run
+        for (range, op) in ops {
+            match op {
+                ScriptOp::Push(data) if data.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                    return Err(script_error("Script pushes more than 520 bytes"))
+                }
+                ScriptOp::Push(data) if machine.is_executing() => machine.stack.push(data),
+                ScriptOp::Push(_) => {}
+                ScriptOp::Op(OP_CODESEPARATOR) if machine.is_executing() => {
+                    machine.count_ops(1)?;
+                    code_start = range.end;
+                }
+                ScriptOp::Op(opcode) => self.step(&mut machine, opcode, &script[code_start..])?,
+            }
+            if machine.stack.len() + machine.alt_stack.len() > MAX_STACK_SIZE {
+                return Err(script_error("Script overflows the stack"));
+            }
+        }
+        match machine.conditions.is_empty() {
+            true => Ok(machine.stack),
+            false => Err(script_error("Script has an unbalanced conditional")),
+        }
+    }
+
+    /// Counts the opcode and runs it, unless it's in a branch that isn't taken. Disabled opcodes fail either way,
+    /// and the conditionals are always run to keep track of the branches.
+    fn step(&self, machine: &mut Machine, opcode: u8, script_code: &[u8]) -> io::Result<()> {
+        machine.count_ops(usize::from(opcode > OP_16))?;
+        if is_disabled(opcode) {
+            return Err(script_error("Script has a disabled opcode"));
+        }
+        match machine.is_executing() || (OP_IF..=OP_ENDIF).contains(&opcode) {
+            true => self.execute(machine, opcode, script_code),
+            false => Ok(()),
+        }
+    }
+
+    fn execute(&self, machine: &mut Machine, opcode: u8, script_code: &[u8]) -> io::Result<()> {
+        match opcode {
+            OP_0 => machine.stack.push(vec![]),
+            OP_1NEGATE => machine.push_number(-1),
+            OP_1..=OP_16 => machine.push_number((opcode - OP_1 + 1) as i64),
+            OP_NOP | OP_IF..=OP_RETURN => machine.flow_op(opcode)?,
+            OP_TOALTSTACK..=OP_TUCK | OP_SIZE => machine.stack_op(opcode)?,
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let (b, a) = (machine.pop()?, machine.pop()?);
+                machine.push_bool(a == b);
+                if opcode == OP_EQUALVERIFY {
+                    machine.verify()?;
+                }
+            }
+            OP_1ADD..=OP_WITHIN => machine.arithmetic_op(opcode)?,
+            OP_RIPEMD160..=OP_HASH256 => machine.hash_op(opcode)?,
+            OP_CHECKSIG..=OP_CHECKMULTISIGVERIFY => {
+                self.signature_op(machine, opcode, script_code)?
+            }
+            OP_NOP1..=OP_NOP10 => self.lock_time_op(machine, opcode)?,
+            _ => return Err(script_error("Script has an invalid opcode")),
+        }
+        Ok(())
+    }
+
+    /// Runs OP_CHECKLOCKTIMEVERIFY (BIP65) and OP_CHECKSEQUENCEVERIFY (BIP112) once their soft forks are active,
+    /// checking the transaction meets the lock time on top of the stack, which they leave there. Before them,
+    /// like the other OP_NOPs, they do nothing.
+    fn lock_time_op(&self, machine: &Machine, opcode: u8) -> io::Result<()> {
+        let tx = self.checker.tx;
+        match opcode {
+            OP_CHECKLOCKTIMEVERIFY if self.soft_forks.check_lock_time_verify => {
+                let required = decode_number(machine.peek(0)?, MAX_LOCK_TIME_SIZE)?;
+                tx.check_lock_time_verify(self.index, required)
+            }
+            OP_CHECKSEQUENCEVERIFY if self.soft_forks.sequence_locks => {
+                let required = decode_number(machine.peek(0)?, MAX_LOCK_TIME_SIZE)?;
+                tx.check_sequence_verify(self.index, required)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn signature_op(
+        &self,
+        machine: &mut Machine,
+        opcode: u8,
+        script_code: &[u8],
+    ) -> io::Result<()> {
+        let valid = match opcode {
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let (public_key, signature) = (machine.pop()?, machine.pop()?);
+                let script_code =
+                    self.signed_script_code(script_code, std::slice::from_ref(&signature));
+                self.check_signature(&signature, &public_key, &script_code)?
+            }
+            _ => self.check_multisig(machine, script_code)?,
+        };
+        match opcode {
+            OP_CHECKSIGVERIFY | OP_CHECKMULTISIGVERIFY if !valid => {
+                Err(script_error("Script failed a signature verification"))
+            }
+            OP_CHECKSIGVERIFY | OP_CHECKMULTISIGVERIFY => Ok(()),
+            _ => {
+                machine.push_bool(valid);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks the signatures on the stack match the public keys in the same order, each key checked at most once.
+    /// The extra item the opcode pops must be empty once segwit is active (BIP147).
+    fn check_multisig(&self, machine: &mut Machine, script_code: &[u8]) -> io::Result<bool> {
+        let keys_count = machine.pop_count(MAX_PUBKEYS_PER_MULTISIG)?;
+        machine.count_ops(keys_count)?;
+        let keys = machine.pop_items(keys_count)?;
+        let signatures_count = machine.pop_count(keys_count)?;
+        let signatures = machine.pop_items(signatures_count)?;
+        if !machine.pop()?.is_empty() && self.soft_forks.segwit {
+            return Err(script_error("Multisig extra item isn't empty"));
+        }
+        let script_code = self.signed_script_code(script_code, &signatures);
+        let mut keys = keys.iter();
+        for (checked, signature) in signatures.iter().enumerate() {
+            let matched = loop {
+                match keys.next() {
+                    Some(_) if keys.len() + 1 < signatures.len() - checked => break false,
+                    Some(key) if self.check_signature(signature, key, &script_code)? => break true,
+                    Some(_) => {}
+                    None => break false,
+                }
+            };
+            if !matched {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the script code the signatures sign. Legacy signatures can't sign themselves, so their pushes are
+    /// removed from it, along with the OP_CODESEPARATORs.
+    fn signed_script_code(&self, script_code: &[u8], signatures: &[Vec<u8>]) -> Vec<u8> {
+        let ops = match (self.sig_version, parse_script(script_code)) {
+            (SigVersion::Base, Ok(ops)) => ops,
+            _ => return script_code.to_vec(),
+        };
+        let pushes: Vec<Vec<u8>> = signatures.iter().map(|s| push_data(s)).collect();
+        ops.into_iter()
+            .filter(|(_, op)| *op != ScriptOp::Op(OP_CODESEPARATOR))
+            .map(|(range, _)| &script_code[range])
+            .filter(|op| !pushes.iter().any(|push| push == op))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Returns true if the signature, ending with its sighash type, signs the transaction with the public key.
+    /// Once BIP66 is active a signature that isn't strictly DER encoded makes the script fail.
+    fn check_signature(
+        &self,
+        signature: &[u8],
+        public_key: &[u8],
+        script_code: &[u8],
+    ) -> io::Result<bool> {
+        let Some((sighash_type, der)) = signature.split_last() else {
+            return Ok(false);
+        };
+        if self.soft_forks.strict_der && !is_valid_der_signature(signature) {
+            return Err(script_error("Signature isn't strictly DER encoded"));
+        }
+        let parsed = match self.soft_forks.strict_der {
+            true => Signature::from_der(der),
+            false => Signature::from_der_lax(der),
+        };
+        let (Ok(mut parsed), Ok(public_key)) = (parsed, PublicKey::from_slice(public_key)) else {
+            return Ok(false);
+        };
+        parsed.normalize_s();
+        let z = self.sig_hash(script_code, *sighash_type as u32)?;
+        let message = Message::from_slice(&z).map_err(to_io_err)?;
+        Ok(verifier()
+            .verify_ecdsa(&message, &parsed, &public_key)
+            .is_ok())
+    }
+
+    /// Returns the hash signatures of the given type sign, the legacy one or the one of BIP143
+    fn sig_hash(&self, script_code: &[u8], sighash_type: u32) -> io::Result<[u8; 32]> {
+        let tx = self.checker.tx;
+        match self.sig_version {
+            SigVersion::Base => legacy_sig_hash(tx, script_code, self.index, sighash_type),
+            SigVersion::WitnessV0 => self.checker.witness_hashes().sig_hash(
+                tx,
+                (script_code, self.amount),
+                self.index,
+                sighash_type,
+            ),
+        }
+    }
+
+    /// Runs the redeem script the script sig of a P2SH spend pushes last on the rest of its pushes. Returns true
+    /// if the redeem script is a witness program, whose witness was checked then.
+    fn verify_p2sh(
+        &self,
+        script_sig: &[u8],
+        mut stack: Vec<Vec<u8>>,
+        witness: &[Vec<u8>],
+    ) -> io::Result<bool> {
+        if !is_push_only(script_sig) {
+            return Err(script_error("P2SH script sig isn't push only"));
+        }
+        let redeem_script = stack
+            .pop()
+            .ok_or_else(|| script_error("P2SH script sig has no redeem script"))?;
+        require_true(&self.eval(&redeem_script, stack)?)?;
+        match (self.soft_forks.segwit, witness_program(&redeem_script)) {
+            (true, Some(program)) => {
+                if script_sig != push_data(&redeem_script) {
+                    return Err(script_error(
+                        "Nested witness program spend pushes more than it",
+                    ));
+                }
+                self.verify_witness(program, witness)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Runs the witness of a version 0 witness program, which must leave a single true value. Programs of later
+    /// versions aren't checked.
+    fn verify_witness(&self, program: (u8, &[u8]), witness: &[Vec<u8>]) -> io::Result<()> {
+        let Some((script, stack)) = witness_script(program, witness)? else {
+            return Ok(());
+        };
+        if stack
+            .iter()
+            .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+        {
+            return Err(script_error("Witness item is larger than 520 bytes"));
+        }
+        let execution = Execution {
+            sig_version: SigVersion::WitnessV0,
+            ..*self
+        };
+        match execution.eval(&script, stack)?.as_slice() {
+            [item] if is_true(item) => Ok(()),
+            _ => Err(script_error(
+                "Witness script doesn't leave a single true value",
+            )),
+        }
+    }
+}
+
+/// Script a witness runs and the stack it starts with
+type WitnessScript = (Vec<u8>, Vec<Vec<u8>>);
+
+/// Returns the script a version 0 witness program runs and the stack it starts with: a P2WPKH one is spent like
+/// a P2PKH output of the same hash, a P2WSH one runs the script the witness ends with, which must hash to the
+/// program. None for programs of other versions.
+fn witness_script(
+    (version, program): (u8, &[u8]),
+    witness: &[Vec<u8>],
+) -> io::Result<Option<WitnessScript>> {
+    let script = match (version, program.len(), witness.split_last()) {
+        (0, 32, Some((script, stack))) => {
+            if sha256::Hash::hash(script).to_byte_array() != program {
+                return Err(script_error("Witness script doesn't match the program"));
+            }
+            (script.clone(), stack.to_vec())
+        }
+        (0, 20, _) if witness.len() == 2 => {
+            let script = [
+                &[OP_DUP, OP_HASH160, 0x14],
+                program,
+                &[OP_EQUALVERIFY, OP_CHECKSIG],
+            ];
+            (script.concat(), witness.to_vec())
+        }
+        (0, ..) => return Err(script_error("Witness doesn't match its program")),
+        _ => return Ok(None),
+    };
+    Ok(Some(script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::script::OP_CHECKMULTISIG;
+    use super::super::sighash::{SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_SINGLE};
+    use super::*;
+    use crate::network_params::{Network, NetworkParams};
+    use crate::test_fixtures::{tx_from_hex, PAYMENT_TX_HEX};
+    use crate::utility::decode_hex;
+    use secp256k1::SecretKey;
+
+    fn key(seed: u8) -> io::Result<(SecretKey, Vec<u8>)> {
+        let secret = SecretKey::from_slice(&[seed; 32]).map_err(to_io_err)?;
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret);
+        Ok((secret, public_key.serialize().to_vec()))
+    }
+
+    /// Signs the hash, appending the sighash type to the signature
+    fn sign(z: [u8; 32], secret: &SecretKey, sighash_type: u32) -> io::Result<Vec<u8>> {
+        let message = Message::from_slice(&z).map_err(to_io_err)?;
+        let signature = Secp256k1::new().sign_ecdsa(&message, secret);
+        Ok([&signature.serialize_der()[..], &[sighash_type as u8]].concat())
+    }
+
+    /// Returns the payment with the given script sig in its only input
+    fn payment_with_script_sig(script_sig: Vec<u8>) -> io::Result<RawTransaction> {
+        let mut tx = tx_from_hex(PAYMENT_TX_HEX)?;
+        if let TxInputType::TxInput(inputs) = &mut tx.tx_in {
+            inputs[0].script_bytes = script_sig.len() as u64;
+            inputs[0].script_sig = script_sig;
+        }
+        Ok(tx)
+    }
+
+    fn verify(tx: &RawTransaction, (amount, lock): (u64, &[u8])) -> io::Result<()> {
+        TxChecker::new(tx)?.verify_input(0, (amount, lock), soft_forks()?)
+    }
+
+    fn soft_forks() -> io::Result<SoftForks> {
+        Ok(NetworkParams::new(Network::Regtest, &[])?.soft_forks(1))
+    }
+
+    #[test]
+    fn test_witness_sig_hash_matches_the_bip143_native_p2wpkh_example() -> io::Result<()> {
+        let tx = tx_from_hex("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000")?;
+        let script_code =
+            decode_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").map_err(to_io_err)?;
+        let checker = TxChecker::new(&tx)?;
+        let z =
+            checker
+                .witness_hashes()
+                .sig_hash(&tx, (&script_code, 600_000_000), 1, SIGHASH_ALL)?;
+        let expected =
+            decode_hex("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+                .map_err(to_io_err)?;
+        assert_eq!(z.to_vec(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2sh_multisig_signature_only_commits_to_what_its_sighash_type_covers() -> io::Result<()>
+    {
+        let ((secret, public_key), (_, other_key)) = (key(1)?, key(2)?);
+        let redeem_script = [
+            vec![OP_1],
+            push_data(&public_key),
+            push_data(&other_key),
+            vec![OP_1 + 1, OP_CHECKMULTISIG],
+        ]
+        .concat();
+        let script_hash = hash160::Hash::hash(&redeem_script).to_byte_array();
+        let lock = [&[OP_HASH160, 0x14][..], &script_hash, &[OP_EQUAL]].concat();
+        let sighash_type = SIGHASH_SINGLE | SIGHASH_ANYONECANPAY;
+        let z = legacy_sig_hash(
+            &payment_with_script_sig(vec![])?,
+            &redeem_script,
+            0,
+            sighash_type,
+        )?;
+        let signature = sign(z, &secret, sighash_type)?;
+        let script_sig = [vec![OP_0], push_data(&signature), push_data(&redeem_script)].concat();
+        let mut tx = payment_with_script_sig(script_sig)?;
+        verify(&tx, (1000, &lock))?;
+        // each key of the redeem script's multisig costs as much as a legacy signature operation
+        let cost = TxChecker::new(&tx)?.sigops_cost(0, &lock, soft_forks()?);
+        assert_eq!(cost, 4 * 2);
+
+        // only the output at the input's index is signed
+        tx.tx_out[1].value += 1;
+        verify(&tx, (1000, &lock))?;
+        tx.tx_out[0].value += 1;
+        assert!(verify(&tx, (1000, &lock)).is_err());
+
+        // the redeem script must hash to the one the output locks to
+        let other_redeem_script = [&redeem_script[..], &[OP_NOP]].concat();
+        let script_sig = [
+            vec![OP_0],
+            push_data(&signature),
+            push_data(&other_redeem_script),
+        ]
+        .concat();
+        assert!(verify(&payment_with_script_sig(script_sig)?, (1000, &lock)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2wpkh_signature_commits_to_the_spent_amount() -> io::Result<()> {
+        let (secret, public_key) = key(3)?;
+        let pubkey_hash = hash160::Hash::hash(&public_key).to_byte_array();
+        let lock = [&[OP_0, 0x14][..], &pubkey_hash].concat();
+        let mut tx = payment_with_script_sig(vec![])?;
+        let script_code = [
+            &[OP_DUP, OP_HASH160, 0x14][..],
+            &pubkey_hash,
+            &[OP_EQUALVERIFY, OP_CHECKSIG],
+        ]
+        .concat();
+        let z = TxChecker::new(&tx)?.witness_hashes().sig_hash(
+            &tx,
+            (&script_code, 5000),
+            0,
+            SIGHASH_ALL,
+        )?;
+        tx.witnesses = vec![vec![sign(z, &secret, SIGHASH_ALL)?, public_key]];
+
+        verify(&tx, (5000, &lock))?;
+        assert!(verify(&tx, (5001, &lock)).is_err());
+        let cost = TxChecker::new(&tx)?.sigops_cost(0, &lock, soft_forks()?);
+        assert_eq!(cost, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditionals_pick_a_branch_and_disabled_opcodes_fail_anywhere() -> io::Result<()> {
+        // OP_IF 2 OP_ELSE 3 OP_ENDIF 3 OP_EQUAL
+        let lock = [
+            OP_IF,
+            OP_1 + 1,
+            OP_ELSE,
+            OP_1 + 2,
+            OP_ENDIF,
+            OP_1 + 2,
+            OP_EQUAL,
+        ];
+        verify(&payment_with_script_sig(vec![OP_0])?, (0, &lock))?;
+        assert!(verify(&payment_with_script_sig(vec![OP_1])?, (0, &lock)).is_err());
+        assert!(verify(&payment_with_script_sig(vec![OP_1, OP_IF])?, (0, &lock)).is_err());
+
+        // OP_0 OP_IF OP_CAT OP_ENDIF OP_1, whose OP_CAT never runs
+        let lock = [OP_0, OP_IF, 0x7e, OP_ENDIF, OP_1];
+        assert!(verify(&payment_with_script_sig(vec![])?, (0, &lock)).is_err());
+        verify(
+            &payment_with_script_sig(vec![])?,
+            (0, &[OP_0, OP_IF, OP_NOP, OP_ENDIF, OP_1]),
+        )
+    }
+
+    #[test]
+    fn test_stack_arithmetic_and_hash_opcodes() -> io::Result<()> {
+        let spend = |script_sig: Vec<u8>, lock: &[u8]| {
+            verify(&payment_with_script_sig(script_sig)?, (0, lock))
+        };
+        // 2 3 OP_ADD 5 OP_NUMEQUAL
+        spend(vec![], &[OP_1 + 1, OP_1 + 2, OP_ADD, OP_1 + 4, OP_NUMEQUAL])?;
+        // 3 2 OP_SUB leaves 1, while swapping them first leaves -1
+        spend(vec![OP_1 + 2, OP_1 + 1], &[OP_SUB, OP_1, OP_NUMEQUAL])?;
+        assert!(spend(
+            vec![OP_1 + 2, OP_1 + 1],
+            &[OP_SWAP, OP_SUB, OP_1, OP_NUMEQUAL]
+        )
+        .is_err());
+        // 5 is within [2, 6) but not within [2, 5)
+        spend(vec![OP_1 + 4], &[OP_1 + 1, OP_1 + 5, OP_WITHIN])?;
+        assert!(spend(vec![OP_1 + 4], &[OP_1 + 1, OP_1 + 4, OP_WITHIN]).is_err());
+
+        // 7 goes to the alternate stack, leaving the main one empty, and comes back
+        let lock = [
+            OP_TOALTSTACK,
+            OP_DEPTH,
+            OP_NOT,
+            OP_VERIFY,
+            OP_FROMALTSTACK,
+            OP_1 + 6,
+            OP_EQUAL,
+        ];
+        spend(vec![OP_1 + 6], &lock)?;
+        // 1 2 3 with 2 OP_ROLL becomes 2 3 1
+        let lock = [
+            OP_1 + 1,
+            OP_ROLL,
+            OP_1,
+            OP_NUMEQUALVERIFY,
+            OP_1 + 2,
+            OP_NUMEQUALVERIFY,
+            OP_1 + 1,
+            OP_NUMEQUAL,
+        ];
+        spend(vec![OP_1, OP_1 + 1, OP_1 + 2], &lock)?;
+
+        // OP_SHA256 <sha256("abc")> OP_EQUAL
+        let hash = sha256::Hash::hash(b"abc").to_byte_array();
+        let lock = [&[OP_SHA256][..], &push_data(&hash), &[OP_EQUAL]].concat();
+        spend(push_data(b"abc"), &lock)?;
+        assert!(spend(push_data(b"abd"), &lock).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scripts_fail_past_their_limits_or_on_failing_opcodes() -> io::Result<()> {
+        let spend = |lock: &[u8]| verify(&payment_with_script_sig(vec![])?, (0, lock));
+        spend(&[vec![OP_NOP; MAX_OPS_PER_SCRIPT], vec![OP_1]].concat())?;
+        assert!(spend(&[vec![OP_NOP; MAX_OPS_PER_SCRIPT + 1], vec![OP_1]].concat()).is_err());
+
+        // arithmetic takes numbers of up to 4 bytes
+        spend(&[push_data(&[0xff, 0xff, 0xff, 0x7f]), vec![OP_1ADD]].concat())?;
+        assert!(spend(&[push_data(&[1, 0, 0, 0, 0]), vec![OP_1ADD]].concat()).is_err());
+
+        assert!(spend(&[OP_0, OP_VERIFY, OP_1]).is_err());
+        assert!(spend(&[OP_1, OP_RETURN]).is_err());
+        assert!(spend(&[OP_1, OP_DROP]).is_err());
+        assert!(spend(&[OP_1, OP_IF, OP_1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_time_opcodes_check_the_number_they_leave_on_the_stack() -> io::Result<()> {
+        // <500000> OP_CHECKLOCKTIMEVERIFY OP_DROP <144> OP_CHECKSEQUENCEVERIFY
+        let lock = [
+            &[0x03, 0x20, 0xa1, 0x07, OP_CHECKLOCKTIMEVERIFY, OP_DROP][..],
+            &[0x02, 0x90, 0x00, OP_CHECKSEQUENCEVERIFY],
+        ]
+        .concat();
+        let mut tx = payment_with_script_sig(vec![])?;
+        tx.version = 2;
+        tx.lock_time = 500000;
+        if let TxInputType::TxInput(inputs) = &mut tx.tx_in {
+            inputs[0].sequence = 144;
+        }
+        verify(&tx, (0, &lock))?;
+        tx.lock_time = 499999;
+        assert!(verify(&tx, (0, &lock)).is_err());
+
+        // they're no-ops before their soft forks
+        let before = NetworkParams::new(Network::Regtest, &[])?.soft_forks(0);
+        TxChecker::new(&tx)?.verify_input(0, (0, &lock), before)?;
+        // the lock time may take 5 bytes, but no more
+        let lock = [0x06, 0, 0, 0, 0, 0x01, 0, OP_CHECKLOCKTIMEVERIFY];
+        assert!(verify(&tx, (0, &lock)).is_err());
+        Ok(())
+    }
+}
@@ -2,49 +2,114 @@ use crate::io::{self, Cursor};
 use crate::messages::constants::commands::TX;
 use crate::messages::constants::config::MAGIC;
 use crate::messages::utility::{
-    date_from_timestamp, read_from_varint, read_hash, to_compact_size_bytes, to_varint, StreamRead,
+    date_from_timestamp, read_from_varint, read_hash, to_compact_size_bytes, StreamRead,
 };
 use crate::messages::{HashId, MessageHeader, Serialize};
 
 use crate::utility::{double_hash, to_io_err};
-use crate::utxo::{Utxo, UtxoSet, UtxoTransaction, WalletUtxo};
-use bitcoin_hashes::Hash;
+use crate::utxo::{lock_address, OutPoint, Utxo, UtxoSet, WalletUtxo};
+use std::collections::HashSet;
 use std::io::{Error, Read};
 
-use gtk::glib::SyncSender;
+pub mod interpreter;
+pub mod partial;
+pub mod script;
+mod sighash;
+use script::{has_oversized_push, is_push_only, sigop_count, AddressFormat};
+use sighash::{legacy_sig_hash, SigHashCache, SIGHASH_ALL};
 pub mod tx_input;
-use tx_input::{CoinBaseInput, Outpoint, TxInput, TxInputType};
+use tx_input::{CoinBaseInput, TxInput, TxInputType};
 pub mod tx_output;
 use crate::interface::components::overview_panel::{TransactionDisplayInfo, TransactionRole};
-use crate::interface::GtkMessage;
+use crate::interface::{NotificationKind, UiNotifier};
 use tx_output::TxOutput;
 
 use super::messages::Message as Msg;
 
 use secp256k1::{All, Message, PublicKey, Secp256k1, SecretKey};
 
-const SIGHASH_ALL: u32 = 1;
+/// Smallest serialized input (outpoint, empty script and sequence) and output (value and empty script)
+const MIN_TX_INPUT_SIZE: u64 = 41;
+const MIN_TX_OUTPUT_SIZE: u64 = 9;
+
+/// Bytes that take the place of the input count in transactions serialized with their witnesses (BIP 144)
+const SEGWIT_MARKER: u8 = 0x00;
+const SEGWIT_FLAG: u8 = 0x01;
+
+/// Lock times below this are block heights, the rest unix timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Sequence of an input that opts out of the transaction's lock time
+const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// Bits of the sequence of an input that opt it out of a relative lock time, make it a time rather than
+/// a number of blocks and hold its value (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0xffff;
+
+/// Reads the count of the items that follow, failing fast if the rest of the buffer can't hold that many
+/// items of at least `min_size` bytes each, instead of looping until it runs out of bytes.
+fn read_count(cursor: &mut Cursor<&[u8]>, min_size: u64) -> io::Result<u64> {
+    let count = read_from_varint(cursor)?;
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if count.saturating_mul(min_size) > remaining {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Declared count {} doesn't fit in the remaining bytes",
+                count
+            ),
+        ));
+    }
+    Ok(count)
+}
+
+/// Largest scripts of a transaction. Parsing only bounds them by the message they're in, relayed transactions
+/// are then held to the standard sizes, which isn't a reason to penalize the peer that sent them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptLimits {
+    pub script_sig: usize,
+    pub script_pubkey: usize,
+}
+
+impl ScriptLimits {
+    pub const RELAY: Self = Self {
+        script_sig: 1650,
+        script_pubkey: 10_000,
+    };
+}
 
-fn read_coinbase_script(cursor: &mut Cursor<&[u8]>, count: usize) -> io::Result<Vec<u8>> {
+/// Reads a script of the declared length, failing before allocating if it's longer than the rest of the buffer
+fn read_script(cursor: &mut Cursor<&[u8]>, count: usize) -> io::Result<Vec<u8>> {
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if count as u64 > remaining {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Declared script length {} is over the limit", count),
+        ));
+    }
     let mut array = vec![0_u8; count];
     cursor.read_exact(&mut array)?;
     Ok(array)
 }
 
-fn der_sign_with_priv_key(z: &[u8], private_key: &SecretKey) -> io::Result<Vec<u8>> {
-    let message = &z;
-
-    let secp: Secp256k1<secp256k1::All> = Secp256k1::gen_new();
-    let message_slice: &[u8] = message;
-    let message_slice = Message::from_slice(message_slice).map_err(to_io_err)?;
-    let signature = secp.sign_ecdsa(&message_slice, private_key);
+/// Signs the sighash with the given context, which callers share across inputs since creating one is expensive
+fn der_sign_with_priv_key(
+    secp: &Secp256k1<All>,
+    z: &[u8],
+    private_key: &SecretKey,
+) -> io::Result<Vec<u8>> {
+    let message = Message::from_slice(z).map_err(to_io_err)?;
+    let signature = secp.sign_ecdsa(&message, private_key);
 
     // Convert the DER-encoded signature to bytes
     Ok(signature.serialize_der().to_vec())
 }
 
+/// Witness stack of a single input (BIP 144)
+pub type Witness = Vec<Vec<u8>>;
+
 /// A struct that represents a raw transaction (includes version, inputs, outputs, and locktime)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RawTransaction {
     pub version: u32,
     pub tx_in_count: u64,
@@ -52,64 +117,21 @@ pub struct RawTransaction {
     pub tx_out_count: u64,
     pub tx_out: Vec<TxOutput>,
     pub lock_time: u32,
+    pub witnesses: Vec<Witness>,
 }
 
-/// Enum that represents the state of a transaction (pending or in a block)
+/// Enum that represents the state of a transaction (pending, in a block, or abandoned after it never confirmed)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TransactionOrigin {
     Block,
     Pending,
+    Abandoned,
 }
 
 impl RawTransaction {
-    fn sig_txin(&self, s: &mut Vec<u8>, prev_pk_script: Vec<u8>, index: usize) {
-        if let TxInputType::TxInput(tx_ins) = &self.tx_in {
-            for (i, tx_in) in tx_ins.iter().enumerate() {
-                if i == index {
-                    let pubkey_script_bytes = prev_pk_script.clone();
-                    let tin = TxInput {
-                        previous_output: Outpoint {
-                            hash: tx_in.previous_output.hash,
-                            index: tx_in.previous_output.index,
-                        },
-                        script_bytes: pubkey_script_bytes.len() as u64,
-                        script_sig: pubkey_script_bytes,
-                        sequence: tx_in.sequence,
-                    };
-                    s.extend(tin._serialize());
-                } else {
-                    let tin = TxInput {
-                        previous_output: Outpoint {
-                            hash: tx_in.previous_output.hash,
-                            index: tx_in.previous_output.index,
-                        },
-                        script_bytes: 0,
-                        script_sig: vec![],
-                        sequence: tx_in.sequence,
-                    };
-                    s.extend(tin._serialize());
-                }
-            }
-        }
-    }
-
-    fn sig_hash(&self, prev_pk_script: Vec<u8>, index: usize) -> io::Result<[u8; 32]> {
-        let mut s = Vec::new();
-        s.extend(self.version.to_le_bytes());
-
-        s.extend(to_varint(self.tx_in_count));
-        self.sig_txin(&mut s, prev_pk_script, index);
-
-        s.extend(to_varint(self.tx_out_count));
-        for tx_out in self.tx_out.iter() {
-            s.extend(tx_out._serialize());
-        }
-        s.extend(self.lock_time.to_le_bytes());
-        s.extend(SIGHASH_ALL.to_le_bytes());
-
-        let h256 = double_hash(&s);
-        let bytes = h256.to_byte_array();
-        Ok(bytes)
+    /// Returns the SIGHASH_ALL hash of the input at the given index, spending the given pk script
+    pub fn sig_hash(&self, prev_pk_script: Vec<u8>, index: usize) -> io::Result<[u8; 32]> {
+        legacy_sig_hash(self, &prev_pk_script, index, SIGHASH_ALL)
     }
 
     /// Signs the input at the given index with the given private key
@@ -121,7 +143,37 @@ impl RawTransaction {
         index: usize,
     ) -> io::Result<()> {
         let z = self.sig_hash(prev_pk_script, index)?;
-        let der = der_sign_with_priv_key(&z, secret_key)?;
+        self.apply_signature(secp, secret_key, &z, index)
+    }
+
+    /// Signs every input with the given private key, where `prev_pk_scripts[i]` is the pk script
+    /// spent by input i. The parts of the sighash shared by all inputs are serialized only once.
+    pub fn sign_inputs(
+        &mut self,
+        secp: &Secp256k1<All>,
+        secret_key: &SecretKey,
+        prev_pk_scripts: Vec<Vec<u8>>,
+    ) -> io::Result<()> {
+        if let [prev_pk_script] = &prev_pk_scripts[..] {
+            // nothing to share with a single input
+            return self.sign_input(secp, secret_key, prev_pk_script.clone(), 0);
+        }
+        let cache = SigHashCache::new(self)?;
+        for (index, prev_pk_script) in prev_pk_scripts.iter().enumerate() {
+            let z = cache.sig_hash(self, prev_pk_script, index)?;
+            self.apply_signature(secp, secret_key, &z, index)?;
+        }
+        Ok(())
+    }
+
+    fn apply_signature(
+        &mut self,
+        secp: &Secp256k1<All>,
+        secret_key: &SecretKey,
+        z: &[u8],
+        index: usize,
+    ) -> io::Result<()> {
+        let der = der_sign_with_priv_key(secp, z, secret_key)?;
         let pub_key = PublicKey::from_secret_key(secp, secret_key)
             .serialize()
             .to_vec();
@@ -142,13 +194,95 @@ impl RawTransaction {
         Ok(())
     }
 
-    /// Checks if any of the inputs is from the given address
-    pub fn is_from_address(&self, address: &str) -> bool {
+    /// Checks the input at the given index meets the lock time an OP_CHECKLOCKTIMEVERIFY of its script requires
+    /// (BIP65): the lock time of the transaction must be of the same kind as the required one and not before it,
+    /// and the input can't opt out of it with a final sequence
+    pub fn check_lock_time_verify(&self, index: usize, required: i64) -> io::Result<()> {
+        let lock_time = self.lock_time as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        let unmet = required < 0
+            || (required < threshold) != (lock_time < threshold)
+            || required > lock_time
+            || self.input_sequence(index) == Some(SEQUENCE_FINAL);
+        match unmet {
+            true => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Lock time required by OP_CHECKLOCKTIMEVERIFY not met",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks the input at the given index meets the relative lock time an OP_CHECKSEQUENCEVERIFY of its script
+    /// requires (BIP112): unless the required one is disabled, the transaction must be of version 2 or later and
+    /// the sequence of the input a relative lock time of the same kind, not shorter than the required one
+    pub fn check_sequence_verify(&self, index: usize, required: i64) -> io::Result<()> {
+        let sequence = self.input_sequence(index).map(|sequence| sequence as i64);
+        let [disabled, kind, value] = [
+            SEQUENCE_LOCKTIME_DISABLE_FLAG,
+            SEQUENCE_LOCKTIME_TYPE_FLAG,
+            SEQUENCE_LOCKTIME_MASK,
+        ]
+        .map(|bits| bits as i64);
+        let unmet = required < 0
+            || (required & disabled == 0
+                && match sequence {
+                    Some(sequence) => {
+                        self.version < 2
+                            || sequence & disabled != 0
+                            || sequence & kind != required & kind
+                            || sequence & value < required & value
+                    }
+                    None => true,
+                });
+        match unmet {
+            true => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Relative lock time required by OP_CHECKSEQUENCEVERIFY not met",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the sequence of the input at the given index, None if there's no such input
+    fn input_sequence(&self, index: usize) -> Option<u32> {
+        match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs.get(index).map(|input| input.sequence),
+            TxInputType::CoinBaseInput(_) => None,
+        }
+    }
+
+    /// Checks the relative lock time of the input at the given index passed (BIP68), given the depth of the
+    /// block including the transaction over the one confirming the output it spends. Only transactions of
+    /// version 2 or later have them, and those in time rather than blocks are let through.
+    pub fn check_sequence_lock(&self, index: usize, depth: usize) -> io::Result<()> {
+        let sequence = self.input_sequence(index).filter(|_| self.version >= 2);
+        match sequence {
+            Some(sequence)
+                if sequence & (SEQUENCE_LOCKTIME_DISABLE_FLAG | SEQUENCE_LOCKTIME_TYPE_FLAG)
+                    == 0
+                    && depth < (sequence & SEQUENCE_LOCKTIME_MASK) as usize =>
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Input {} is locked for {} blocks after the output it spends",
+                        index,
+                        sequence & SEQUENCE_LOCKTIME_MASK
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if any of the inputs is from the given address of the given format
+    pub fn is_from_address(&self, address: &str, format: &AddressFormat) -> bool {
         match &self.tx_in {
             TxInputType::CoinBaseInput(_) => {}
             TxInputType::TxInput(tx_ins) => {
                 for txin in tx_ins {
-                    if txin.destined_from(address) {
+                    if txin.destined_from(address, format) {
                         return true;
                     }
                 }
@@ -157,28 +291,36 @@ impl RawTransaction {
         false
     }
 
-    /// Checks if any of the outputs is destined to the given address
-    pub fn is_destined_to_address(&self, address: &str) -> bool {
-        for txout in &self.tx_out {
-            if txout.destined_to(address) {
-                return true;
-            }
+    /// Returns the addresses of the inputs and outputs of the transaction in the given format, decoding each of
+    /// them once, so the wallets involved can be told apart without decoding the transaction again for each one
+    pub fn involved_addresses(&self, format: &AddressFormat) -> HashSet<String> {
+        let mut addresses = HashSet::new();
+        if let TxInputType::TxInput(tx_ins) = &self.tx_in {
+            addresses.extend(
+                tx_ins
+                    .iter()
+                    .filter_map(|txin| txin.get_address(format).ok()),
+            );
         }
-        false
+        addresses.extend(
+            self.tx_out
+                .iter()
+                .filter_map(|txout| txout.get_address(format)),
+        );
+        addresses
     }
 
-    /// Checks if the given address is involved in the transaction (either as input or output)
-    pub fn address_is_involved(&self, address: &str) -> bool {
-        if self.is_from_address(address) || self.is_destined_to_address(address) {
-            return true;
-        }
-
-        false
+    /// Checks if any of the given addresses is involved in the transaction (either as input or output).
+    /// Inputs and outputs are decoded once, no matter how many addresses are checked.
+    pub fn address_is_involved(&self, addresses: &[&str], format: &AddressFormat) -> bool {
+        self.involved_addresses(format)
+            .iter()
+            .any(|address| addresses.contains(&address.as_str()))
     }
 
     fn get_input_value(&self, address: &str, utxoset: &UtxoSet, txin: &TxInput) -> u64 {
         let mut value = 0_u64;
-        if !txin.destined_from(address) {
+        if !txin.destined_from(address, utxoset.address_format()) {
             return value;
         }
 
@@ -215,11 +357,172 @@ impl RawTransaction {
         total_value
     }
 
+    /// Returns the fee rate in satoshis per kilo virtual byte, None if an input spends an unknown output
+    pub fn fee_rate(&self, utxo_set: &UtxoSet) -> Option<u64> {
+        let fee =
+            self.fee(|outpoint| utxo_set.resolve_prevout(outpoint).map(|(value, _)| *value))?;
+        Some(fee * 1000 / self.vsize())
+    }
+
+    /// Returns the fee paid given the values of the outputs spent by the inputs, None if one of them is unknown
+    pub fn fee(&self, prevout_value: impl Fn(&OutPoint) -> Option<u64>) -> Option<u64> {
+        let inputs = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs,
+            TxInputType::CoinBaseInput(_) => return None,
+        };
+        let mut input_value: u64 = 0;
+        for input in inputs {
+            let outpoint = (input.previous_output.hash, input.previous_output.index);
+            input_value = input_value.checked_add(prevout_value(&outpoint)?)?;
+        }
+        input_value.checked_sub(self.get_total_output_value())
+    }
+
+    /// Returns the ids of the transactions whose outputs are spent by the inputs, none for a coinbase
+    pub fn parent_txids(&self) -> Vec<HashId> {
+        match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs
+                .iter()
+                .map(|input| input.previous_output.hash)
+                .collect(),
+            TxInputType::CoinBaseInput(_) => vec![],
+        }
+    }
+
+    /// Returns true if the transaction can be included in a block of the given height and time, the median time
+    /// past of its parent (BIP113): its lock time is unset or already passed, or every input opted out of it with
+    /// a final sequence
+    pub fn is_final(&self, height: usize, time: u32) -> bool {
+        let lock_time_passed = match self.lock_time {
+            0 => true,
+            lock_time if lock_time < LOCKTIME_THRESHOLD => (lock_time as usize) < height,
+            lock_time => lock_time < time,
+        };
+        lock_time_passed
+            || match &self.tx_in {
+                TxInputType::TxInput(inputs) => {
+                    inputs.iter().all(|input| input.sequence == SEQUENCE_FINAL)
+                }
+                TxInputType::CoinBaseInput(_) => true,
+            }
+    }
+
+    /// Returns the virtual size of the transaction, its weight divided by 4 and rounded up
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4) as u64
+    }
+
+    /// Returns the weight of the transaction (BIP 141): its size without witnesses counts 3 times,
+    /// on top of its size with them
+    pub fn weight(&self) -> usize {
+        3 * self.serialize().len() + self.serialize_with_witness().len()
+    }
+
+    /// Checks that no outpoint is spent by more than one input of the transaction
+    pub fn check_no_duplicate_inputs(&self) -> io::Result<()> {
+        let inputs = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs,
+            TxInputType::CoinBaseInput(_) => return Ok(()),
+        };
+        let mut spent = HashSet::new();
+        for input in inputs {
+            let outpoint = (input.previous_output.hash, input.previous_output.index);
+            if !spent.insert(outpoint) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Transaction spends the same outpoint twice",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the script sig of every input only pushes data, which is required to relay the transaction
+    pub fn check_push_only_inputs(&self) -> io::Result<()> {
+        let inputs = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs,
+            TxInputType::CoinBaseInput(_) => return Ok(()),
+        };
+        if inputs.iter().any(|input| !is_push_only(&input.script_sig)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Transaction has a script sig that isn't push only",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the signature operations in the script sigs and pk scripts of the transaction, counting multisigs
+    /// by their keys if accurate or as 20 each like blocks do
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let input_sigops = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs
+                .iter()
+                .map(|input| sigop_count(&input.script_sig, accurate))
+                .sum(),
+            TxInputType::CoinBaseInput(_) => 0,
+        };
+        let output_sigops: usize = self
+            .tx_out
+            .iter()
+            .map(|output| sigop_count(&output.pk_script, accurate))
+            .sum();
+        input_sigops + output_sigops
+    }
+
+    /// Checks the transaction has at most the given signature operations, as required to relay it
+    pub fn check_sigops(&self, max_sigops: usize) -> io::Result<()> {
+        let sigops = self.sigop_count(true);
+        if sigops > max_sigops {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Transaction has {} signature operations, over the limit of {}",
+                    sigops, max_sigops
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns true if every script is within the given sizes
+    pub fn has_scripts_within(&self, limits: &ScriptLimits) -> bool {
+        let script_sigs_within = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs
+                .iter()
+                .all(|input| input.script_sig.len() <= limits.script_sig),
+            TxInputType::CoinBaseInput(_) => true,
+        };
+        script_sigs_within
+            && self
+                .tx_out
+                .iter()
+                .all(|output| output.pk_script.len() <= limits.script_pubkey)
+    }
+
+    /// Checks that no script sig pushes data over the consensus element size, as blocks require
+    pub fn check_script_element_sizes(&self) -> io::Result<()> {
+        let inputs = match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs,
+            TxInputType::CoinBaseInput(_) => return Ok(()),
+        };
+        if inputs
+            .iter()
+            .any(|input| has_oversized_push(&input.script_sig))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Transaction has a script sig pushing an oversized element",
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns the change value for the given address (sum of all output values destined to the address)
-    fn get_change_value_for(&self, address: &str) -> u64 {
+    fn get_change_value_for(&self, address: &str, format: &AddressFormat) -> u64 {
         let mut total_value = 0_u64;
         for output in &self.tx_out {
-            if output.destined_to(address) {
+            if output.destined_to(address, format) {
                 total_value += output.value;
             }
         }
@@ -228,10 +531,22 @@ impl RawTransaction {
 
     /// Returns the hash of the transaction
     pub fn get_hash(&self) -> HashId {
+        self.txid()
+    }
+
+    /// Returns the transaction id. It is computed over the serialization without witness data,
+    /// so changes to the witnesses (signature malleability) can't change it.
+    pub fn txid(&self) -> HashId {
         let hash = double_hash(&self.serialize());
         HashId::from_hash(hash)
     }
 
+    /// Returns the witness transaction id, which also commits to the witness data (BIP 141)
+    pub fn _wtxid(&self) -> HashId {
+        let hash = double_hash(&self.serialize_with_witness());
+        HashId::from_hash(hash)
+    }
+
     /// Returns the transaction info for the given address
     pub fn transaction_info_for_pending(
         &self,
@@ -242,13 +557,14 @@ impl RawTransaction {
         let mut role = TransactionRole::Sender;
         let mut spent_value = 0;
 
-        if self.is_from_address(address) {
+        let format = utxo_set.address_format();
+        if self.is_from_address(address, format) {
             spent_value = self.get_total_input_value(address, utxo_set);
         } else {
             role = TransactionRole::Receiver;
         }
 
-        let change_value = self.get_change_value_for(address);
+        let change_value = self.get_change_value_for(address, format);
 
         TransactionDisplayInfo {
             role,
@@ -269,13 +585,14 @@ impl RawTransaction {
         let mut role = TransactionRole::Sender;
         let mut spent_value = 0;
 
-        if self.is_from_address(address) {
+        let format = utxo_set.address_format();
+        if self.is_from_address(address, format) {
             spent_value = self.get_total_input_value(address, utxo_set);
         } else {
             role = TransactionRole::Receiver;
         }
 
-        let change_value = self.get_change_value_for(address);
+        let change_value = self.get_change_value_for(address, format);
 
         TransactionDisplayInfo {
             role,
@@ -286,13 +603,19 @@ impl RawTransaction {
         }
     }
 
-    /// Read the coinbase transaction from the given bytes and returns a RawTransaction with only the coinbase input and the outputs
+    /// Read the coinbase transaction from the given bytes and returns a RawTransaction with only the coinbase input,
+    /// the outputs and its witness if it has one
     pub fn coinbase_from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Self, Error> {
         let version = u32::from_le_stream(cursor)?;
-        let tx_in_count = read_from_varint(cursor)?;
+        let (tx_in_count, has_witness) = Self::read_input_count(cursor)?;
         let tx_in = TxInputType::CoinBaseInput(CoinBaseInput::from_bytes(cursor)?);
-        let tx_out_count = read_from_varint(cursor)?;
+        let tx_out_count = read_count(cursor, MIN_TX_OUTPUT_SIZE)?;
         let tx_out = TxOutput::vec_from_bytes(cursor, tx_out_count as usize)?;
+        // the witness reserved value of blocks with a witness commitment (BIP141)
+        let witnesses = match has_witness {
+            true => Self::read_witnesses(cursor, tx_in_count)?,
+            false => vec![],
+        };
         let lock_time = u32::from_le_stream(cursor)?;
 
         let raw_transaction = RawTransaction {
@@ -302,11 +625,24 @@ impl RawTransaction {
             tx_out_count,
             tx_out,
             lock_time,
+            witnesses,
         };
 
         Ok(raw_transaction)
     }
 
+    /// Returns true if any input spends an output of the address of the given format
+    fn spends_from(&self, address: &str, format: &AddressFormat) -> bool {
+        match &self.tx_in {
+            TxInputType::TxInput(inputs) => inputs.iter().any(|input| {
+                input
+                    .get_address(format)
+                    .is_ok_and(|spent| spent == address)
+            }),
+            _ => false,
+        }
+    }
+
     fn generate_utxo_in(
         &self,
         utxo_set: &mut UtxoSet,
@@ -314,13 +650,16 @@ impl RawTransaction {
     ) -> io::Result<()> {
         if let TxInputType::TxInput(ref inputs) = self.tx_in {
             for input in inputs {
-                let address = match input.get_address() {
+                let address = match input.get_address(utxo_set.address_format()) {
                     Ok(a) => a,
                     _ => "no_address".to_string(),
                 };
 
                 let utxo_id = input.previous_output.hash;
                 let index = input.previous_output.index;
+                if origin == TransactionOrigin::Block {
+                    utxo_set.outpoint_index.remove(&(utxo_id, index));
+                }
                 match utxo_set.set.get_mut(&address) {
                     Some(wallet) => {
                         wallet.add_spent(utxo_id, index, origin);
@@ -337,100 +676,119 @@ impl RawTransaction {
         Ok(())
     }
 
-    fn get_utxo_addr(utxo_tx: &UtxoTransaction) -> String {
-        match utxo_tx.get_address() {
-            Ok(a) => a,
-            _ => "no_address".to_string(),
-        }
-    }
-
+    /// Adds the outputs to the utxo set, returning the addresses of the pending ones that are now confirmed.
+    /// Pending outputs paying back to an address the transaction spends from are marked as its change.
     fn generate_utxo_out(
         &self,
         utxo_set: &mut UtxoSet,
         origin: TransactionOrigin,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
-        active_addr: Option<&str>,
-    ) -> io::Result<()> {
+    ) -> io::Result<Vec<String>> {
+        let mut confirmed = vec![];
         let new_utxo_id = HashId::from_hash(double_hash(&self.serialize()));
         let new_utxo = Utxo::from_raw_transaction(self)?;
+        let format = *utxo_set.address_format();
         for (index, utxo_transaction) in new_utxo.transactions.iter().enumerate() {
-            let address = Self::get_utxo_addr(utxo_transaction);
-            match utxo_set.set.get_mut(&address) {
-                Some(wallet) => wallet.add_utxo(
-                    new_utxo_id,
-                    utxo_transaction.clone(),
-                    origin,
-                    index as u32,
-                    ui_sender,
-                    active_addr,
-                )?,
-                None => {
-                    let mut wallet = WalletUtxo::new();
-                    wallet.add_utxo(
-                        new_utxo_id,
-                        utxo_transaction.clone(),
-                        origin,
-                        index as u32,
-                        None,
-                        None,
-                    )?;
-                    utxo_set.set.insert(address, wallet);
-                }
+            let address = lock_address(&utxo_transaction.lock, &format);
+            if origin == TransactionOrigin::Block {
+                let outpoint = (new_utxo_id, index as u32);
+                utxo_set.index_output(
+                    &address,
+                    outpoint,
+                    utxo_transaction.value,
+                    &utxo_transaction.lock,
+                );
+            }
+            let wallet = utxo_set
+                .set
+                .entry(address.clone())
+                .or_insert_with(WalletUtxo::new);
+            let was_pending =
+                wallet.add_utxo(new_utxo_id, utxo_transaction.clone(), origin, index as u32);
+            if origin == TransactionOrigin::Pending && self.spends_from(&address, &format) {
+                wallet.mark_pending_change(new_utxo_id);
+            }
+            if was_pending {
+                confirmed.push(address);
             }
         }
 
-        Ok(())
+        Ok(confirmed)
     }
 
-    /// Generates the UTXO for the given transaction and adds it to the given UTXO set
+    /// Generates the UTXO for the given transaction and adds it to the given UTXO set, notifying the UI when a
+    /// pending output of the active address is now confirmed
     pub fn generate_utxo(
         &self,
         utxo_set: &mut UtxoSet,
         origin: TransactionOrigin,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
+        ui_sender: Option<&dyn UiNotifier>,
         active_addr: Option<&str>,
     ) -> io::Result<()> {
         self.generate_utxo_in(utxo_set, origin)?;
-        self.generate_utxo_out(utxo_set, origin, ui_sender, active_addr)?;
-
+        let confirmed = self.generate_utxo_out(utxo_set, origin)?;
+        if let (Some(sender), Some(active_addr)) = (ui_sender, active_addr) {
+            if confirmed.iter().any(|address| address == active_addr) {
+                let msg = format!("Transaction {} is now confirmed", self.txid());
+                let _ui = sender.notify(NotificationKind::Info, "Confirmed", &msg);
+            }
+        }
         Ok(())
     }
 
-    fn read_witnesses(cursor: &mut Cursor<&[u8]>, tx_in_count: u64) -> io::Result<()> {
+    /// Reads the input count, along with whether the transaction has witnesses. A zero count is the segwit
+    /// marker only if the flag that follows is set, otherwise the transaction is malformed.
+    fn read_input_count(cursor: &mut Cursor<&[u8]>) -> io::Result<(u64, bool)> {
+        let tx_in_count = read_count(cursor, MIN_TX_INPUT_SIZE)?;
+        if tx_in_count != SEGWIT_MARKER as u64 {
+            return Ok((tx_in_count, false));
+        }
+        if u8::from_le_stream(cursor)? != SEGWIT_FLAG {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Transaction has no inputs or an invalid segwit flag",
+            ));
+        }
+        Ok((read_count(cursor, MIN_TX_INPUT_SIZE)?, true))
+    }
+
+    /// Reads the witness of every input, failing if all of them are empty since the segwit flag would be superfluous
+    fn read_witnesses(cursor: &mut Cursor<&[u8]>, tx_in_count: u64) -> io::Result<Vec<Witness>> {
         let mut witnesses = Vec::new();
         for _ in 0..tx_in_count {
-            let witness_len = read_from_varint(cursor)?;
+            let witness_len = read_count(cursor, 1)?; // every item takes at least its length byte
+            let mut witness = Vec::new();
             for _ in 0..witness_len {
                 let length = read_from_varint(cursor)?;
                 let mut witness_data = vec![0u8; length as usize];
                 cursor.read_exact(&mut witness_data)?;
-                witnesses.push(witness_data);
+                witness.push(witness_data);
             }
+            witnesses.push(witness);
         }
-        Ok(())
+        if witnesses.iter().all(|witness| witness.is_empty()) {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Segwit transaction has no witness data",
+            ));
+        }
+        Ok(witnesses)
     }
 
     /// Reads the transaction from the given bytes and returns a RawTransaction (supports segwit transactions BIP 144)
     pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Self, Error> {
         let version = u32::from_le_stream(cursor)?;
 
-        let mut has_witness = false;
-
-        let mut tx_in_count = read_from_varint(cursor)?;
-        if tx_in_count == 0 {
-            let _flag: u8 = u8::from_le_stream(cursor)?;
-            tx_in_count = read_from_varint(cursor)?;
-            has_witness = true;
-        }
+        let (tx_in_count, has_witness) = Self::read_input_count(cursor)?;
 
         let tx_in = TxInputType::TxInput(TxInput::vec_from_bytes(cursor, tx_in_count as usize)?);
 
-        let tx_out_count = read_from_varint(cursor)?;
+        let tx_out_count = read_count(cursor, MIN_TX_OUTPUT_SIZE)?;
         let tx_out = TxOutput::vec_from_bytes(cursor, tx_out_count as usize)?;
 
-        if has_witness {
-            Self::read_witnesses(cursor, tx_in_count)?;
-        }
+        let witnesses = match has_witness {
+            true => Self::read_witnesses(cursor, tx_in_count)?,
+            false => vec![],
+        };
 
         let lock_time = u32::from_le_stream(cursor)?;
 
@@ -441,6 +799,7 @@ impl RawTransaction {
             tx_out_count,
             tx_out,
             lock_time,
+            witnesses,
         };
 
         Ok(raw_transaction)
@@ -470,11 +829,42 @@ impl RawTransaction {
         transaction_bytes
     }
 
-    /// build message to be broadcasted
+    /// Serializes the transaction including its witnesses (BIP 144). Equal to `serialize` if it has none.
+    pub fn serialize_with_witness(&self) -> Vec<u8> {
+        if self.witnesses.iter().all(|witness| witness.is_empty()) {
+            return self.serialize();
+        }
+        let mut transaction_bytes = vec![];
+        transaction_bytes.extend(self.version.to_le_bytes());
+        transaction_bytes.extend([SEGWIT_MARKER, SEGWIT_FLAG]);
+        transaction_bytes.extend(&to_compact_size_bytes(self.tx_in_count));
+        transaction_bytes.extend(self.tx_in.to_bytes());
+        transaction_bytes.extend(&to_compact_size_bytes(self.tx_out_count));
+        transaction_bytes.extend(TxOutput::serialize_vec(&self.tx_out));
+        for witness in self.witnesses.iter() {
+            transaction_bytes.extend(&to_compact_size_bytes(witness.len() as u64));
+            for item in witness.iter() {
+                transaction_bytes.extend(&to_compact_size_bytes(item.len() as u64));
+                transaction_bytes.extend(item);
+            }
+        }
+        transaction_bytes.extend(self.lock_time.to_le_bytes());
+        transaction_bytes
+    }
+
+    /// build message to be broadcasted (witnesses included, so relayed segwit transactions stay valid)
     pub fn build_message(&self) -> io::Result<Vec<u8>> {
-        let tx_hash = double_hash(&self.serialize());
+        Self::message_from_payload(self.serialize_with_witness())
+    }
+
+    /// build message for peers that asked for the transaction without its witnesses
+    pub fn build_stripped_message(&self) -> io::Result<Vec<u8>> {
+        Self::message_from_payload(self.serialize())
+    }
+
+    fn message_from_payload(payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        let tx_hash = double_hash(&payload);
 
-        let payload = self.serialize();
         let mut bytes = MessageHeader::new(
             MAGIC,
             TX.to_string(),
@@ -505,8 +895,71 @@ mod tests {
     use crate::utility::decode_hex;
 
     use super::*;
+    use crate::test_fixtures::{tx_from_hex, FUNDING_TX_HEX, PAYMENT_TX_HEX, SPENDING_TX_HEX};
     use crate::utxo::UtxoTransaction;
+    use bitcoin_hashes::Hash;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use script::{
+        push_data, MAX_SCRIPT_ELEMENT_SIZE, OP_1, OP_CHECKMULTISIG, OP_CHECKSIG, TESTNET_ADDRESSES,
+    };
     use std::fs;
+    use std::str::FromStr;
+    use tx_input::Outpoint;
+
+    fn random_bytes(rng: &mut StdRng, max_len: usize) -> Vec<u8> {
+        // lengths above 252 need a longer compact size
+        let mut bytes = vec![0_u8; rng.gen_range(0..=max_len)];
+        rng.fill(&mut bytes[..]);
+        bytes
+    }
+
+    /// Generates a valid non coinbase transaction of random inputs, outputs and lock time, with witnesses if asked
+    fn random_transaction(rng: &mut StdRng, with_witnesses: bool) -> RawTransaction {
+        let inputs: Vec<TxInput> = (0..rng.gen_range(1..=5))
+            .map(|_| {
+                let script_sig = random_bytes(rng, 300);
+                TxInput {
+                    previous_output: Outpoint {
+                        hash: HashId::new(rng.gen()),
+                        index: rng.gen(),
+                    },
+                    script_bytes: script_sig.len() as u64,
+                    script_sig,
+                    sequence: rng.gen(),
+                }
+            })
+            .collect();
+        let tx_out: Vec<TxOutput> = (0..rng.gen_range(0..=300))
+            .map(|_| {
+                let pk_script = random_bytes(rng, 40);
+                TxOutput {
+                    value: rng.gen(),
+                    pk_script_bytes: pk_script.len() as u64,
+                    pk_script,
+                }
+            })
+            .collect();
+        let witnesses = match with_witnesses {
+            true => (0..inputs.len())
+                .map(|_| {
+                    (0..rng.gen_range(1..=3))
+                        .map(|_| random_bytes(rng, 80))
+                        .collect()
+                })
+                .collect(),
+            false => vec![],
+        };
+        RawTransaction {
+            version: rng.gen_range(1..=2),
+            tx_in_count: inputs.len() as u64,
+            tx_in: TxInputType::TxInput(inputs),
+            tx_out_count: tx_out.len() as u64,
+            tx_out,
+            lock_time: rng.gen(),
+            witnesses,
+        }
+    }
 
     #[test]
     fn test_compactsize_serialization_u16() {
@@ -657,7 +1110,7 @@ mod tests {
 
     #[test]
     fn test_raw_transaction_deserial_and_serial() {
-        let bytes = decode_hex("01000000011acd5fe758ab56da34a0973c9c5dda0b63dcd79fe5860950813a366db1c92585010000006a4730440220046dc82c7c2e72665938c0aa7e10a135496d2467c2d1d105daa4ed1bab436898022064d9e36334d87c56454f7447c9da2c2eeb56cb77d3e9431feeac45649a23d9b901210387d7265c4973b153830aa72486d2488f964d194d2de869236fb87cc907d83971ffffffff0240420f00000000001976a9149144fda38182db2d26e5de88456accf241c898eb88aca0860100000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac00000000").unwrap();
+        let bytes = decode_hex(PAYMENT_TX_HEX).unwrap();
         let raw_transaction = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap();
         let serialized_raw_transaction = raw_transaction.serialize();
         assert_eq!(bytes, serialized_raw_transaction);
@@ -665,12 +1118,32 @@ mod tests {
 
     #[test]
     fn test_raw_transaction_address_is_envolved() {
-        let transaction_bytes = decode_hex("0100000001881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff0210270000000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac96051a00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac00000000");
+        let transaction_bytes = decode_hex(SPENDING_TX_HEX);
         let transaction =
             RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes.unwrap())).unwrap();
         let address = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
-        assert!(transaction.address_is_involved(address));
-        assert!(!transaction.address_is_involved("foo"));
+        assert!(transaction.address_is_involved(&[address], &TESTNET_ADDRESSES));
+        assert!(!transaction.address_is_involved(&["foo"], &TESTNET_ADDRESSES));
+    }
+
+    #[test]
+    fn test_raw_transaction_address_set_is_involved() {
+        let transaction_bytes = decode_hex(SPENDING_TX_HEX);
+        let transaction =
+            RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes.unwrap())).unwrap();
+
+        // receiver of the first output
+        assert!(transaction.address_is_involved(
+            &["foo", "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun"],
+            &TESTNET_ADDRESSES
+        ));
+        // sender, appearing both as input and change output
+        assert!(transaction.address_is_involved(
+            &["bar", "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX"],
+            &TESTNET_ADDRESSES
+        ));
+        assert!(!transaction.address_is_involved(&["foo", "bar"], &TESTNET_ADDRESSES));
+        assert!(!transaction.address_is_involved(&[], &TESTNET_ADDRESSES));
     }
 
     #[test]
@@ -690,6 +1163,7 @@ mod tests {
             index: txin.previous_output.index,
             value: 1925236,
             lock: vec![],
+            coinbase: false,
         };
 
         let mut wallet = WalletUtxo::new();
@@ -704,4 +1178,273 @@ mod tests {
 
         assert_eq!(transaction_info.amount, -10000);
     }
+
+    #[test]
+    fn test_sign_inputs_matches_per_input_signing() -> io::Result<()> {
+        let mut transaction = tx_from_hex(PAYMENT_TX_HEX)?;
+        if let TxInputType::TxInput(ref mut inputs) = transaction.tx_in {
+            for index in 1..3 {
+                let mut input = inputs[0].clone();
+                input.previous_output.index = index;
+                inputs.push(input);
+            }
+        }
+        transaction.tx_in_count = 3;
+
+        let secp = Secp256k1::new();
+        let secret_key =
+            SecretKey::from_str("8a0b1cdc8d3e4a5b6c7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f901234567")
+                .map_err(to_io_err)?;
+        let prev_pk_scripts: Vec<Vec<u8>> = (0..3)
+            .map(|i| [&[0x76, 0xa9, 0x14][..], &[i as u8; 20], &[0x88, 0xac]].concat())
+            .collect();
+
+        let mut per_input = transaction.clone();
+        for (index, prev_pk_script) in prev_pk_scripts.iter().enumerate() {
+            per_input.sign_input(&secp, &secret_key, prev_pk_script.clone(), index)?;
+        }
+        transaction.sign_inputs(&secp, &secret_key, prev_pk_scripts)?;
+
+        assert_eq!(transaction.serialize(), per_input.serialize());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_with_shared_context_is_unchanged() -> io::Result<()> {
+        let shared = Secp256k1::new();
+        let secret_key =
+            SecretKey::from_str("8a0b1cdc8d3e4a5b6c7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f901234567")
+                .map_err(to_io_err)?;
+        let z = double_hash(b"sighash").to_byte_array();
+
+        let der = der_sign_with_priv_key(&shared, &z, &secret_key)?;
+        assert_eq!(der, der_sign_with_priv_key(&shared, &z, &secret_key)?);
+        assert_eq!(
+            der,
+            der_sign_with_priv_key(&Secp256k1::new(), &z, &secret_key)?
+        );
+
+        let signature = secp256k1::ecdsa::Signature::from_der(&der).map_err(to_io_err)?;
+        let message = Message::from_slice(&z).map_err(to_io_err)?;
+        let public_key = PublicKey::from_secret_key(&shared, &secret_key);
+        shared
+            .verify_ecdsa(&message, &signature, &public_key)
+            .map_err(to_io_err)
+    }
+
+    #[test]
+    fn test_txid_ignores_witness_data() -> io::Result<()> {
+        let bytes = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        let segwit_tx = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+        assert_eq!(segwit_tx.serialize_with_witness(), bytes);
+
+        let mut stripped_tx = segwit_tx.clone();
+        stripped_tx.witnesses = vec![];
+        assert_eq!(segwit_tx.txid(), stripped_tx.txid());
+        assert_eq!(segwit_tx.txid(), segwit_tx.get_hash());
+        assert_ne!(segwit_tx.txid(), segwit_tx._wtxid());
+        assert_eq!(stripped_tx.txid(), stripped_tx._wtxid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_segwit_marker_needs_its_flag_and_witnesses() -> io::Result<()> {
+        let legacy = decode_hex(PAYMENT_TX_HEX).map_err(to_io_err)?;
+        let legacy_tx = RawTransaction::from_bytes(&mut Cursor::new(&legacy))?;
+        assert!(legacy_tx.witnesses.is_empty());
+        assert_eq!(legacy_tx.serialize(), legacy);
+
+        let segwit = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        let segwit_tx = RawTransaction::from_bytes(&mut Cursor::new(&segwit))?;
+        assert_eq!(segwit_tx.witnesses.len(), 1);
+
+        // no inputs followed by a zero flag is neither a legacy nor a segwit transaction
+        let mut ambiguous = segwit.clone();
+        ambiguous[5] = 0x00;
+        let error = RawTransaction::from_bytes(&mut Cursor::new(&ambiguous)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // the segwit flag with nothing but empty witnesses
+        let stripped = segwit_tx.serialize();
+        let (version, lock_time) = (&stripped[..4], &stripped[stripped.len() - 4..]);
+        let body = &stripped[4..stripped.len() - 4];
+        let bytes = [
+            version,
+            &[SEGWIT_MARKER, SEGWIT_FLAG],
+            body,
+            &[0x00],
+            lock_time,
+        ]
+        .concat();
+        let error = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_rate_needs_known_prevouts() -> io::Result<()> {
+        let bytes = decode_hex(PAYMENT_TX_HEX).map_err(to_io_err)?;
+        let transaction = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+        let mut utxo_set = UtxoSet::new();
+        assert_eq!(transaction.fee_rate(&utxo_set), None);
+
+        let prevout = match &transaction.tx_in {
+            TxInputType::TxInput(inputs) => &inputs[0].previous_output,
+            TxInputType::CoinBaseInput(_) => panic!("Expected a regular input"),
+        };
+        // outputs add up to 1100000 satoshis, leaving a 100000 satoshis fee
+        utxo_set.index_output("", (prevout.hash, prevout.index), 1200000, &[]);
+        assert_eq!(
+            transaction.fee_rate(&utxo_set),
+            Some(100000 * 1000 / bytes.len() as u64)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_absolute_and_relative_lock_times_of_inputs() -> io::Result<()> {
+        let mut transaction = tx_from_hex(PAYMENT_TX_HEX)?;
+        // the input opts out of lock times with a final sequence
+        transaction.lock_time = 500000;
+        assert!(transaction.check_lock_time_verify(0, 500000).is_err());
+
+        if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+            inputs[0].sequence = 10;
+        }
+        transaction.check_lock_time_verify(0, 500000)?;
+        assert!(transaction.check_lock_time_verify(0, -1).is_err());
+        transaction.lock_time = 499999;
+        assert!(transaction.check_lock_time_verify(0, 500000).is_err());
+
+        // relative lock times only apply from version 2
+        transaction.check_sequence_lock(0, 9)?;
+        transaction.version = 2;
+        assert!(transaction.check_sequence_lock(0, 9).is_err());
+        transaction.check_sequence_lock(0, 10)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_sequence_verify_needs_a_long_enough_relative_lock() -> io::Result<()> {
+        let mut transaction = tx_from_hex(PAYMENT_TX_HEX)?;
+        transaction.version = 2;
+        // the input's final sequence disables its relative lock time
+        assert!(transaction.check_sequence_verify(0, 144).is_err());
+        // unless the required one is disabled too, but never if it's negative
+        transaction.check_sequence_verify(0, SEQUENCE_LOCKTIME_DISABLE_FLAG as i64)?;
+        assert!(transaction.check_sequence_verify(0, -1).is_err());
+
+        let set_sequence = |transaction: &mut RawTransaction, sequence| {
+            if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+                inputs[0].sequence = sequence;
+            }
+        };
+        set_sequence(&mut transaction, 143);
+        assert!(transaction.check_sequence_verify(0, 144).is_err());
+        set_sequence(&mut transaction, 144);
+        transaction.check_sequence_verify(0, 144)?;
+        // a time based relative lock time doesn't meet one in blocks
+        set_sequence(&mut transaction, SEQUENCE_LOCKTIME_TYPE_FLAG | 144);
+        assert!(transaction.check_sequence_verify(0, 144).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_spending_an_outpoint_twice_is_rejected() -> io::Result<()> {
+        let mut transaction = tx_from_hex(PAYMENT_TX_HEX)?;
+        transaction.check_no_duplicate_inputs()?;
+
+        if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+            inputs.push(inputs[0].clone());
+        }
+        transaction.tx_in_count = 2;
+        let error = transaction.check_no_duplicate_inputs().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_with_massive_input_count_fails_fast() {
+        // version, input count of 2^64 - 1 and a few bytes that could never hold those inputs
+        let bytes = decode_hex("01000000ffffffffffffffffff00000000").unwrap();
+        let error = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // same for the outputs, after a single empty input
+        let input = "00".repeat(36) + "00ffffffff";
+        let bytes = decode_hex(&format!("0100000001{}ff00e1f50500000000", input)).unwrap();
+        let error = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_oversized_script_sig_is_over_the_relay_limits() -> io::Result<()> {
+        let mut rng = StdRng::seed_from_u64(1440);
+        let mut transaction = random_transaction(&mut rng, false);
+        if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+            inputs[0].script_sig = vec![OP_1; ScriptLimits::RELAY.script_sig + 1];
+            inputs[0].script_bytes = inputs[0].script_sig.len() as u64;
+        }
+        let bytes = transaction.serialize();
+
+        // it parses, but it's over the standard size when relayed
+        let parsed = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+        assert_eq!(parsed, transaction);
+        assert!(!parsed.has_scripts_within(&ScriptLimits::RELAY));
+
+        // a single push over the element size isn't allowed in blocks either
+        transaction.check_script_element_sizes()?;
+        if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+            inputs[0].script_sig = push_data(&[0; MAX_SCRIPT_ELEMENT_SIZE + 1]);
+        }
+        let error = transaction.check_script_element_sizes().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_over_the_sigop_limit_is_rejected() -> io::Result<()> {
+        let mut rng = StdRng::seed_from_u64(1467);
+        let mut transaction = random_transaction(&mut rng, false);
+        for output in transaction.tx_out.iter_mut() {
+            output.pk_script = vec![];
+        }
+        if let TxInputType::TxInput(inputs) = &mut transaction.tx_in {
+            for input in inputs.iter_mut() {
+                input.script_sig = vec![];
+            }
+        }
+        // a 2-of-3 multisig counts its keys, one without the count before it counts as 20
+        transaction.tx_out[0].pk_script = vec![OP_1 + 1, OP_1 + 2, OP_CHECKMULTISIG, OP_CHECKSIG];
+        assert_eq!(transaction.sigop_count(true), 4);
+        // blocks count every multisig as 20
+        assert_eq!(transaction.sigop_count(false), 21);
+        transaction.tx_out[0].pk_script = [push_data(&[0; 33]), vec![OP_CHECKMULTISIG]].concat();
+        assert_eq!(transaction.sigop_count(true), 20);
+
+        transaction.check_sigops(20)?;
+        let error = transaction.check_sigops(19).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_transactions_survive_serialization_roundtrip() -> io::Result<()> {
+        let mut rng = StdRng::seed_from_u64(1429);
+        for i in 0..200 {
+            let transaction = random_transaction(&mut rng, i % 2 == 1);
+            let bytes = transaction.serialize_with_witness();
+            let parsed = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+            assert_eq!(parsed, transaction);
+            assert_eq!(parsed.serialize_with_witness(), bytes);
+
+            // without witnesses the inputs, outputs and their order are kept as well
+            let mut stripped = transaction.clone();
+            stripped.witnesses = vec![];
+            let parsed = RawTransaction::from_bytes(&mut Cursor::new(&transaction.serialize()))?;
+            assert_eq!(parsed, stripped);
+            assert_eq!(parsed.txid(), transaction.txid());
+        }
+        Ok(())
+    }
 }
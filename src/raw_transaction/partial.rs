@@ -0,0 +1,214 @@
+use super::tx_input::TxInputType;
+use super::RawTransaction;
+use crate::messages::utility::{read_from_varint, to_varint, StreamRead};
+use crate::utxo::Lock;
+use secp256k1::{All, Secp256k1, SecretKey};
+use std::io::{self, Cursor, Read};
+
+/// Magic bytes of a serialized partial transaction, modeled on the "psbt" prefix of BIP174
+const PARTIAL_TX_MAGIC: [u8; 4] = [b'p', b't', b'x', 0xff];
+
+/// Value and pk script of the output spent by an input
+pub type Prevout = (u64, Lock);
+
+/// Unsigned transaction along with the outputs it spends, so it can be signed offline (a minimal BIP174).
+/// Signatures are stored apart from the transaction until every input is signed.
+#[derive(Debug, Clone)]
+pub struct PartialTransaction {
+    pub transaction: RawTransaction,
+    pub prevouts: Vec<Prevout>,
+    pub script_sigs: Vec<Option<Vec<u8>>>,
+}
+
+impl PartialTransaction {
+    /// Creates a partial transaction with no signatures, where `prevouts[i]` is the output spent by input i
+    pub fn new(transaction: RawTransaction, prevouts: Vec<Prevout>) -> io::Result<Self> {
+        if prevouts.len() as u64 != transaction.tx_in_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Every input needs the output it spends",
+            ));
+        }
+        Ok(Self {
+            transaction,
+            script_sigs: vec![None; prevouts.len()],
+            prevouts,
+        })
+    }
+
+    /// Signs the input at the given index with the given private key
+    pub fn sign_input(
+        &mut self,
+        secp: &Secp256k1<All>,
+        secret_key: &SecretKey,
+        index: usize,
+    ) -> io::Result<()> {
+        let (_value, lock) = self.prevouts.get(index).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Input index out of range")
+        })?;
+        let mut signed = self.transaction.clone();
+        signed.sign_input(secp, secret_key, lock.clone(), index)?;
+        if let TxInputType::TxInput(inputs) = signed.tx_in {
+            self.script_sigs[index] = Some(inputs[index].script_sig.clone());
+        }
+        Ok(())
+    }
+
+    /// Adds the signatures of another copy of the same transaction that are missing in this one
+    pub fn combine(&mut self, other: &PartialTransaction) -> io::Result<()> {
+        if self.transaction.txid() != other.transaction.txid() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Partial transactions don't belong to the same transaction",
+            ));
+        }
+        for (script_sig, other_sig) in self.script_sigs.iter_mut().zip(&other.script_sigs) {
+            if script_sig.is_none() {
+                *script_sig = other_sig.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the transaction with the signatures in place, ready to be broadcasted.
+    /// Fails if any input is not signed yet.
+    pub fn finalize(&self) -> io::Result<RawTransaction> {
+        let mut transaction = self.transaction.clone();
+        if let TxInputType::TxInput(ref mut inputs) = transaction.tx_in {
+            for (index, input) in inputs.iter_mut().enumerate() {
+                let script_sig = self
+                    .script_sigs
+                    .get(index)
+                    .and_then(Option::as_ref)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Input {} is not signed", index),
+                        )
+                    })?;
+                input.script_bytes = script_sig.len() as u64;
+                input.script_sig = script_sig.clone();
+            }
+        }
+        Ok(transaction)
+    }
+
+    /// Serializes the unsigned transaction followed by the prevout and signature of each input
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PARTIAL_TX_MAGIC.to_vec();
+        bytes.extend(self.transaction.serialize());
+        for ((value, lock), script_sig) in self.prevouts.iter().zip(&self.script_sigs) {
+            let script_sig = script_sig.as_deref().unwrap_or_default();
+            bytes.extend(value.to_le_bytes());
+            bytes.extend(to_varint(lock.len() as u64));
+            bytes.extend(lock);
+            bytes.extend(to_varint(script_sig.len() as u64));
+            bytes.extend(script_sig);
+        }
+        bytes
+    }
+
+    fn read_script(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut script = vec![0_u8; read_from_varint(cursor)? as usize];
+        cursor.read_exact(&mut script)?;
+        Ok(script)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let mut magic = [0_u8; 4];
+        cursor.read_exact(&mut magic)?;
+        if magic != PARTIAL_TX_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a partial transaction",
+            ));
+        }
+        let transaction = RawTransaction::from_bytes(&mut cursor)?;
+        let mut prevouts = Vec::new();
+        let mut script_sigs = Vec::new();
+        for _ in 0..transaction.tx_in_count {
+            let value = u64::from_le_stream(&mut cursor)?;
+            prevouts.push((value, Self::read_script(&mut cursor)?));
+            let script_sig = Self::read_script(&mut cursor)?;
+            script_sigs.push(Some(script_sig).filter(|script_sig| !script_sig.is_empty()));
+        }
+        Ok(Self {
+            transaction,
+            prevouts,
+            script_sigs,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::HashId;
+    use crate::raw_transaction::tx_input::{Outpoint, TxInput};
+    use crate::raw_transaction::tx_output::TxOutput;
+
+    fn unsigned_transaction(lock_time: u32) -> RawTransaction {
+        let input = |index| TxInput {
+            previous_output: Outpoint {
+                hash: HashId::new([7; 32]),
+                index,
+            },
+            script_bytes: 0,
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        };
+        RawTransaction {
+            version: 1,
+            tx_in_count: 2,
+            tx_in: TxInputType::TxInput(vec![input(0), input(1)]),
+            tx_out_count: 1,
+            tx_out: vec![TxOutput {
+                value: 1000,
+                pk_script_bytes: 1,
+                pk_script: vec![0x51],
+            }],
+            lock_time,
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn test_partial_transaction_bytes_roundtrip_and_combine() -> io::Result<()> {
+        let prevouts = vec![(600, vec![0x51]), (500, vec![0x52, 0x53])];
+        assert!(PartialTransaction::new(unsigned_transaction(0), vec![]).is_err());
+
+        let mut partial = PartialTransaction::new(unsigned_transaction(0), prevouts.clone())?;
+        let mut other = partial.clone();
+        other.script_sigs[1] = Some(vec![1, 2, 3]);
+        let other = PartialTransaction::from_bytes(&other.to_bytes())?;
+        assert_eq!(other.prevouts, prevouts);
+        assert_eq!(other.script_sigs, vec![None, Some(vec![1, 2, 3])]);
+
+        partial.combine(&other)?;
+        assert!(partial.finalize().is_err());
+        partial.script_sigs[0] = Some(vec![4]);
+        let finalized = partial.finalize()?;
+        match finalized.tx_in {
+            TxInputType::TxInput(inputs) => {
+                assert_eq!(inputs[0].script_sig, vec![4]);
+                assert_eq!(inputs[1].script_sig, vec![1, 2, 3]);
+                assert_eq!(inputs[1].script_bytes, 3);
+            }
+            TxInputType::CoinBaseInput(_) => panic!("Expected regular inputs"),
+        }
+
+        let different = PartialTransaction::new(unsigned_transaction(1), prevouts)?;
+        assert!(partial.combine(&different).is_err());
+        assert!(PartialTransaction::from_bytes(&[0, 1, 2, 3]).is_err());
+        Ok(())
+    }
+}
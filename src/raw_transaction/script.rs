@@ -0,0 +1,668 @@
+use crate::utility::{double_hash, encode_hex};
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::ops::Range;
+
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_1NEGATE: u8 = 0x4f;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+/// Testnet base58 version bytes of P2PKH and P2SH addresses
+pub const P2PKH_VERSION: u8 = 0x6f;
+pub const P2SH_VERSION: u8 = 0xc4;
+/// Mainnet base58 version bytes of P2PKH and P2SH addresses
+pub const MAINNET_P2PKH_VERSION: u8 = 0x00;
+pub const MAINNET_P2SH_VERSION: u8 = 0x05;
+/// Human readable part of testnet and signet segwit addresses (BIP173)
+pub const BECH32_HRP: &str = "tb";
+/// Human readable parts of mainnet and regtest segwit addresses
+pub const MAINNET_BECH32_HRP: &str = "bc";
+pub const REGTEST_BECH32_HRP: &str = "bcrt";
+/// Version bytes and segwit prefix the addresses of a network are encoded with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AddressFormat {
+    pub p2pkh_version: u8,
+    pub p2sh_version: u8,
+    pub bech32_hrp: &'static str,
+}
+
+/// Address format of testnet and signet, the one of the utxo set until the network followed sets its own
+pub const TESTNET_ADDRESSES: AddressFormat = AddressFormat {
+    p2pkh_version: P2PKH_VERSION,
+    p2sh_version: P2SH_VERSION,
+    bech32_hrp: BECH32_HRP,
+};
+pub const MAINNET_ADDRESSES: AddressFormat = AddressFormat {
+    p2pkh_version: MAINNET_P2PKH_VERSION,
+    p2sh_version: MAINNET_P2SH_VERSION,
+    bech32_hrp: MAINNET_BECH32_HRP,
+};
+/// Regtest keeps the testnet version bytes, only its segwit prefix is its own
+pub const REGTEST_ADDRESSES: AddressFormat = AddressFormat {
+    bech32_hrp: REGTEST_BECH32_HRP,
+    ..TESTNET_ADDRESSES
+};
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Largest data a script may push, any larger push makes the script fail
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Signature operations a multisig counts as when the number of keys it checks isn't known
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// A single operation of a script, either pushed data or an opcode
+#[derive(Debug, PartialEq)]
+pub enum ScriptOp {
+    Push(Vec<u8>),
+    Op(u8),
+}
+
+/// Reads the length of the data pushed by the opcode, None if it isn't a push
+fn read_push_len(cursor: &mut Cursor<&[u8]>, opcode: u8) -> io::Result<Option<usize>> {
+    let len_size = match opcode {
+        0x01..=0x4b => return Ok(Some(opcode as usize)),
+        OP_PUSHDATA1 => 1,
+        OP_PUSHDATA2 => 2,
+        OP_PUSHDATA4 => 4,
+        _ => return Ok(None),
+    };
+    let mut len = [0u8; 4];
+    cursor.read_exact(&mut len[..len_size])?;
+    Ok(Some(u32::from_le_bytes(len) as usize))
+}
+
+/// Reads the next operation of a script
+fn read_op(cursor: &mut Cursor<&[u8]>) -> io::Result<ScriptOp> {
+    let mut opcode = [0u8; 1];
+    cursor.read_exact(&mut opcode)?;
+    match read_push_len(cursor, opcode[0])? {
+        Some(len) if len > cursor.get_ref().len() - cursor.position() as usize => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Script push is longer than the script",
+            ))
+        }
+        Some(len) => {
+            let mut data = vec![0u8; len];
+            cursor.read_exact(&mut data)?;
+            Ok(ScriptOp::Push(data))
+        }
+        None => Ok(ScriptOp::Op(opcode[0])),
+    }
+}
+
+/// Splits a script into its operations, along with the byte range each one takes
+pub fn parse_script(script: &[u8]) -> io::Result<Vec<(Range<usize>, ScriptOp)>> {
+    let mut ops = Vec::new();
+    let mut cursor = Cursor::new(script);
+    while (cursor.position() as usize) < script.len() {
+        let start = cursor.position() as usize;
+        let op = read_op(&mut cursor)?;
+        ops.push((start..cursor.position() as usize, op));
+    }
+    Ok(ops)
+}
+
+/// Counts the signature operations of the script. An accurate count counts a multisig as the number of keys
+/// pushed right before it (OP_1 to OP_16), or as 20 if they aren't, the legacy count blocks are limited by
+/// always counts it as 20. Operations after a truncated push never run.
+pub fn sigop_count(script: &[u8], accurate: bool) -> usize {
+    let mut cursor = Cursor::new(script);
+    let (mut count, mut previous) = (0, None);
+    while (cursor.position() as usize) < script.len() {
+        let Ok(op) = read_op(&mut cursor) else {
+            break;
+        };
+        count += match (&op, previous) {
+            (ScriptOp::Op(OP_CHECKSIG | OP_CHECKSIGVERIFY), _) => 1,
+            (
+                ScriptOp::Op(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY),
+                Some(keys @ OP_1..=OP_16),
+            ) if accurate => (keys - OP_1 + 1) as usize,
+            (ScriptOp::Op(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY), _) => {
+                MAX_PUBKEYS_PER_MULTISIG
+            }
+            _ => 0,
+        };
+        previous = match op {
+            ScriptOp::Op(opcode) => Some(opcode),
+            ScriptOp::Push(_) => None,
+        };
+    }
+    count
+}
+
+/// Decodes a number of up to 5 bytes in little endian, with the sign in the top bit of the last one
+pub fn number_from_bytes(data: &[u8]) -> i64 {
+    let Some(last) = data.len().checked_sub(1) else {
+        return 0;
+    };
+    let value = data
+        .iter()
+        .rev()
+        .fold(0i64, |value, byte| (value << 8) | *byte as i64);
+    let sign_bit = 0x80 << (8 * last);
+    match value & sign_bit {
+        0 => value,
+        _ => -(value & !sign_bit),
+    }
+}
+
+/// Returns true if the signature, along with its trailing sighash type, is strictly DER encoded as BIP66 requires:
+/// a sequence of exactly two integers, R and S, whose lengths match the ones declared and which are neither
+/// negative nor padded with zeros they don't need
+pub fn is_valid_der_signature(sig: &[u8]) -> bool {
+    if !(9..=73).contains(&sig.len()) || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let r_len = sig[3] as usize;
+    if 5 + r_len >= sig.len() {
+        return false;
+    }
+    let s_len = sig[5 + r_len] as usize;
+    r_len + s_len + 7 == sig.len()
+        && sig[2] == 0x02
+        && is_valid_der_integer(&sig[4..4 + r_len])
+        && sig[4 + r_len] == 0x02
+        && is_valid_der_integer(&sig[6 + r_len..6 + r_len + s_len])
+}
+
+/// Returns true if the bytes are a DER integer that isn't empty, negative or padded with an unneeded zero
+fn is_valid_der_integer(integer: &[u8]) -> bool {
+    match integer {
+        [] => false,
+        [first, ..] if first & 0x80 != 0 => false,
+        [0x00, second, ..] => second & 0x80 != 0,
+        _ => true,
+    }
+}
+
+/// Returns true if the script only pushes data, as the script sigs of standard inputs must.
+/// The small number opcodes (OP_0, OP_1NEGATE and OP_1 to OP_16) count as pushes.
+pub fn is_push_only(script: &[u8]) -> bool {
+    match parse_script(script) {
+        Ok(ops) => ops.iter().all(|(_range, op)| match op {
+            ScriptOp::Push(_) => true,
+            ScriptOp::Op(opcode) => *opcode <= OP_16,
+        }),
+        Err(..) => false,
+    }
+}
+
+/// Returns true if the script pushes data larger than the consensus element size.
+/// Scripts that can't be parsed are left to fail when run.
+pub fn has_oversized_push(script: &[u8]) -> bool {
+    parse_script(script).is_ok_and(|ops| {
+        ops.iter().any(|(_range, op)| match op {
+            ScriptOp::Push(data) => data.len() > MAX_SCRIPT_ELEMENT_SIZE,
+            ScriptOp::Op(_) => false,
+        })
+    })
+}
+
+/// Serializes a push of the given data using the smallest push opcode
+pub fn push_data(data: &[u8]) -> Vec<u8> {
+    let mut bytes = match data.len() {
+        len if len < OP_PUSHDATA1 as usize => vec![len as u8],
+        len if len <= u8::MAX as usize => vec![OP_PUSHDATA1, len as u8],
+        len => [&[OP_PUSHDATA2][..], &(len as u16).to_le_bytes()].concat(),
+    };
+    bytes.extend(data);
+    bytes
+}
+
+/// Encodes the payload with its version byte and checksum in base58
+pub fn base58check(version: u8, payload: &[u8]) -> String {
+    let versioned = [&[version][..], payload].concat();
+    let hash = double_hash(&versioned);
+    bs58::encode([&versioned[..], &hash[..4]].concat()).into_string()
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Regroups 8 bit bytes into 5 bit groups, padding the last one with zeros
+fn to_base32(data: &[u8]) -> Vec<u8> {
+    let mut groups = Vec::new();
+    let (mut acc, mut bits) = (0_u32, 0);
+    for byte in data {
+        acc = ((acc << 8) | *byte as u32) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            groups.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        groups.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    groups
+}
+
+/// Regroups 5 bit groups back into bytes, None if the padding isn't made of fewer than 5 zero bits
+fn from_base32(groups: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let (mut acc, mut bits) = (0_u32, 0);
+    for group in groups {
+        acc = ((acc << 5) | *group as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((acc >> bits) as u8);
+        }
+    }
+    (bits < 5 && (acc & ((1 << bits) - 1)) == 0).then_some(data)
+}
+
+/// Expands the human readable part for the checksum computation
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|b| b & 31));
+    values
+}
+
+/// Encodes a version 0 witness program as a bech32 address with the given prefix (BIP173)
+fn segwit_address(program: &[u8], hrp: &str) -> String {
+    let mut data = vec![0];
+    data.extend(to_base32(program));
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend(&data);
+    values.extend([0; 6]);
+    let checksum = bech32_polymod(&values) ^ 1;
+    data.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+    let encoded: String = data
+        .iter()
+        .map(|d| BECH32_CHARSET[*d as usize] as char)
+        .collect();
+    format!("{}1{}", hrp, encoded)
+}
+
+/// Decodes a version 0 segwit address of any network into its human readable part, which tells the network
+/// apart, and its witness program (BIP173)
+pub fn decode_segwit_address(address: &str) -> io::Result<(&'static str, Vec<u8>)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid segwit address");
+    let (hrp, data) = [MAINNET_BECH32_HRP, BECH32_HRP, REGTEST_BECH32_HRP]
+        .into_iter()
+        .find_map(|hrp| {
+            let data = address.strip_prefix(hrp)?.strip_prefix('1')?;
+            Some((hrp, data))
+        })
+        .ok_or_else(invalid)?;
+    let values: Vec<u8> = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|d| *d == c).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    if values.len() < 7 || bech32_polymod(&[bech32_hrp_expand(hrp), values.clone()].concat()) != 1 {
+        return Err(invalid());
+    }
+    let program = from_base32(&values[1..values.len() - 6]).ok_or_else(invalid)?;
+    match (values[0], program.len()) {
+        (0, 20 | 32) => Ok((hrp, program)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Template an output script follows. Anything that doesn't match a standard template
+/// (including scripts that can't be parsed and witness versions above 0) is nonstandard.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptType {
+    P2pkh([u8; 20]),
+    P2sh([u8; 20]),
+    P2wpkh([u8; 20]),
+    P2wsh([u8; 32]),
+    OpReturn,
+    Multisig { required: usize, keys: usize },
+    NonStandard,
+}
+
+impl ScriptType {
+    /// Classifies an output script by matching it against the standard templates
+    pub fn classify(script: &[u8]) -> Self {
+        match script {
+            [OP_DUP, OP_HASH160, 0x14, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG] => {
+                hash.try_into().map_or(Self::NonStandard, Self::P2pkh)
+            }
+            [OP_HASH160, 0x14, hash @ .., OP_EQUAL] => {
+                hash.try_into().map_or(Self::NonStandard, Self::P2sh)
+            }
+            [OP_0, 0x14, program @ ..] => {
+                program.try_into().map_or(Self::NonStandard, Self::P2wpkh)
+            }
+            [OP_0, 0x20, program @ ..] => program.try_into().map_or(Self::NonStandard, Self::P2wsh),
+            [OP_RETURN, ..] => Self::OpReturn,
+            _ => Self::classify_multisig(script).unwrap_or(Self::NonStandard),
+        }
+    }
+
+    /// Matches a bare m-of-n multisig: OP_m <pubkey>... OP_n OP_CHECKMULTISIG
+    fn classify_multisig(script: &[u8]) -> Option<Self> {
+        let ops: Vec<ScriptOp> = parse_script(script)
+            .ok()?
+            .into_iter()
+            .map(|(_, op)| op)
+            .collect();
+        match &ops[..] {
+            [ScriptOp::Op(m @ OP_1..=OP_16), keys @ .., ScriptOp::Op(n @ OP_1..=OP_16), ScriptOp::Op(OP_CHECKMULTISIG)] =>
+            {
+                let required = (m - OP_1 + 1) as usize;
+                let total = (n - OP_1 + 1) as usize;
+                let valid_keys = keys.iter().all(
+                    |key| matches!(key, ScriptOp::Push(key) if key.len() == 33 || key.len() == 65),
+                );
+                (valid_keys && keys.len() == total && required <= total).then_some(Self::Multisig {
+                    required,
+                    keys: total,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the address the script pays to in the given format. OP_RETURN, multisig and nonstandard scripts
+    /// have none.
+    pub fn address(&self, format: &AddressFormat) -> Option<String> {
+        match self {
+            Self::P2pkh(hash) => Some(base58check(format.p2pkh_version, hash)),
+            Self::P2sh(hash) => Some(base58check(format.p2sh_version, hash)),
+            Self::P2wpkh(program) => Some(segwit_address(program, format.bech32_hrp)),
+            Self::P2wsh(program) => Some(segwit_address(program, format.bech32_hrp)),
+            Self::OpReturn | Self::Multisig { .. } | Self::NonStandard => None,
+        }
+    }
+}
+
+/// Returns the name of an opcode as shown in script disassembly, small integers are shown as numbers
+fn opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        OP_0 => "0",
+        OP_1NEGATE => "-1",
+        OP_1..=OP_16 => return (opcode - OP_1 + 1).to_string(),
+        0x61 => "OP_NOP",
+        0x63 => "OP_IF",
+        0x64 => "OP_NOTIF",
+        0x67 => "OP_ELSE",
+        0x68 => "OP_ENDIF",
+        0x69 => "OP_VERIFY",
+        OP_RETURN => "OP_RETURN",
+        0x75 => "OP_DROP",
+        OP_DUP => "OP_DUP",
+        OP_EQUAL => "OP_EQUAL",
+        OP_EQUALVERIFY => "OP_EQUALVERIFY",
+        0xa8 => "OP_SHA256",
+        OP_HASH160 => "OP_HASH160",
+        0xaa => "OP_HASH256",
+        OP_CHECKSIG => "OP_CHECKSIG",
+        OP_CHECKSIGVERIFY => "OP_CHECKSIGVERIFY",
+        OP_CHECKMULTISIG => "OP_CHECKMULTISIG",
+        OP_CHECKMULTISIGVERIFY => "OP_CHECKMULTISIGVERIFY",
+        OP_CHECKLOCKTIMEVERIFY => "OP_CHECKLOCKTIMEVERIFY",
+        OP_CHECKSEQUENCEVERIFY => "OP_CHECKSEQUENCEVERIFY",
+        _ => return format!("OP_UNKNOWN[0x{:02x}]", opcode),
+    };
+    name.to_string()
+}
+
+/// Disassembles a script, showing pushed data in hex. Scripts that can't be parsed show as "[error]".
+fn script_asm(script: &[u8]) -> String {
+    match parse_script(script) {
+        Ok(ops) => ops
+            .into_iter()
+            .map(|(_, op)| match op {
+                ScriptOp::Push(data) => encode_hex(&data),
+                ScriptOp::Op(opcode) => opcode_name(opcode),
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
+        Err(..) => "[error]".to_string(),
+    }
+}
+
+/// Decoded view of an output script: its template, the address it pays to and its disassembly
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptInfo {
+    pub script_type: ScriptType,
+    pub address: Option<String>,
+    pub asm: String,
+}
+
+impl fmt::Display for ScriptInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "type: {:?} address: {} asm: {}",
+            self.script_type,
+            self.address.as_deref().unwrap_or("none"),
+            self.asm
+        )
+    }
+}
+
+/// Classifies and disassembles an output script, showing its address in the given format
+pub fn decode_script(script: &[u8], format: &AddressFormat) -> ScriptInfo {
+    let script_type = ScriptType::classify(script);
+    ScriptInfo {
+        address: script_type.address(format),
+        script_type,
+        asm: script_asm(script),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::decode_hex;
+
+    fn classify_hex(script: &str) -> ScriptType {
+        ScriptType::classify(&decode_hex(script).unwrap())
+    }
+
+    #[test]
+    fn test_classify_standard_scripts() {
+        let p2pkh = classify_hex("76a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac");
+        assert!(matches!(p2pkh, ScriptType::P2pkh(_)));
+        assert_eq!(
+            p2pkh.address(&TESTNET_ADDRESSES),
+            Some("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX".to_string())
+        );
+
+        let p2sh = classify_hex("a914748284390f9e263a4b766a75d0633c50426eb87587");
+        assert!(matches!(p2sh, ScriptType::P2sh(_)));
+        assert_eq!(
+            p2sh.address(&TESTNET_ADDRESSES),
+            Some("2N3sGiyscxqd3r6DQSbgXT738ZwhUpBqkej".to_string())
+        );
+
+        // BIP173 test vectors
+        let p2wpkh = classify_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert!(matches!(p2wpkh, ScriptType::P2wpkh(_)));
+        assert_eq!(
+            p2wpkh.address(&TESTNET_ADDRESSES),
+            Some("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string())
+        );
+        assert_eq!(
+            p2wpkh.address(&MAINNET_ADDRESSES),
+            Some("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string())
+        );
+
+        let p2wsh =
+            classify_hex("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262");
+        assert!(matches!(p2wsh, ScriptType::P2wsh(_)));
+        assert_eq!(
+            p2wsh.address(&TESTNET_ADDRESSES),
+            Some("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_push_only_script_sigs() {
+        let sig = [0x30; 71];
+        let pubkey = [0x02; 33];
+        let script_sig = [push_data(&sig), push_data(&pubkey)].concat();
+        assert!(is_push_only(&script_sig));
+        assert!(is_push_only(&[OP_0, OP_1NEGATE, OP_1, OP_16]));
+
+        let with_opcode = [push_data(&sig), vec![OP_DUP], push_data(&pubkey)].concat();
+        assert!(!is_push_only(&with_opcode));
+        // a push running past the end of the script isn't a valid push
+        assert!(!is_push_only(&script_sig[..50]));
+    }
+
+    #[test]
+    fn test_decode_script_asm() {
+        let p2pkh = decode_script(
+            &decode_hex("76a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac").unwrap(),
+            &TESTNET_ADDRESSES,
+        );
+        assert!(matches!(p2pkh.script_type, ScriptType::P2pkh(_)));
+        assert_eq!(
+            p2pkh.address.as_deref(),
+            Some("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX")
+        );
+        assert_eq!(
+            p2pkh.asm,
+            "OP_DUP OP_HASH160 c9bc003bf72ebdc53a9572f7ea792ef49a2858d7 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+
+        let p2sh = decode_script(
+            &decode_hex("a914748284390f9e263a4b766a75d0633c50426eb87587").unwrap(),
+            &TESTNET_ADDRESSES,
+        );
+        assert!(matches!(p2sh.script_type, ScriptType::P2sh(_)));
+        assert_eq!(
+            p2sh.asm,
+            "OP_HASH160 748284390f9e263a4b766a75d0633c50426eb875 OP_EQUAL"
+        );
+
+        let op_return = decode_script(&decode_hex("6a0568656c6c6f").unwrap(), &TESTNET_ADDRESSES);
+        assert_eq!(op_return.script_type, ScriptType::OpReturn);
+        assert_eq!(op_return.address, None);
+        assert_eq!(op_return.asm, "OP_RETURN 68656c6c6f");
+
+        let multisig = decode_script(&decode_hex("5100ae").unwrap(), &TESTNET_ADDRESSES);
+        assert_eq!(multisig.asm, "1 0 OP_CHECKMULTISIG");
+        assert_eq!(
+            decode_script(&[0x4c, 0x05, 0xaa], &TESTNET_ADDRESSES).asm,
+            "[error]"
+        );
+        assert_eq!(
+            decode_script(&[0xba], &TESTNET_ADDRESSES).asm,
+            "OP_UNKNOWN[0xba]"
+        );
+    }
+
+    #[test]
+    fn test_decode_segwit_address() {
+        for script in [
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+        ] {
+            for format in [TESTNET_ADDRESSES, MAINNET_ADDRESSES, REGTEST_ADDRESSES] {
+                let address = classify_hex(script).address(&format).unwrap();
+                let (hrp, program) = decode_segwit_address(&address).unwrap();
+                assert_eq!(hrp, format.bech32_hrp);
+                assert_eq!(program, decode_hex(script).unwrap()[2..]);
+            }
+        }
+        let (hrp, program) =
+            decode_segwit_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        assert_eq!(hrp, MAINNET_BECH32_HRP);
+        assert_eq!(
+            program,
+            decode_hex("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+        );
+
+        // bad checksum, mainnet checksum under the testnet prefix and a base58 address
+        for address in [
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsy",
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX",
+        ] {
+            assert!(decode_segwit_address(address).is_err());
+        }
+    }
+
+    #[test]
+    fn test_classify_scripts_without_address() {
+        let op_return = classify_hex("6a24aa21a9ed");
+        assert_eq!(op_return, ScriptType::OpReturn);
+        assert_eq!(op_return.address(&TESTNET_ADDRESSES), None);
+
+        let key = "21".to_string() + &"02".repeat(33);
+        let multisig = classify_hex(&format!("51{}{}52ae", key, key));
+        assert_eq!(
+            multisig,
+            ScriptType::Multisig {
+                required: 1,
+                keys: 2
+            }
+        );
+        assert_eq!(multisig.address(&TESTNET_ADDRESSES), None);
+
+        // wrong key count, truncated push, truncated p2pkh and an unknown template
+        for script in [
+            format!("51{}52ae", key),
+            "4c05aa".to_string(),
+            "76a91400ac".to_string(),
+            "51".to_string(),
+            String::new(),
+        ] {
+            assert_eq!(classify_hex(&script), ScriptType::NonStandard);
+        }
+    }
+
+    #[test]
+    fn test_only_strictly_der_encoded_signatures_are_valid() {
+        let signature = decode_hex("30440220046dc82c7c2e72665938c0aa7e10a135496d2467c2d1d105daa4ed1bab436898022064d9e36334d87c56454f7447c9da2c2eeb56cb77d3e9431feeac45649a23d9b901").unwrap();
+        assert!(is_valid_der_signature(&signature));
+
+        // R padded with a zero it doesn't need
+        let padded = [&[0x30, 0x45, 0x02, 0x21, 0x00], &signature[4..]].concat();
+        assert!(!is_valid_der_signature(&padded));
+        // R negative
+        let mut negative = signature.clone();
+        negative[4] = 0x84;
+        assert!(!is_valid_der_signature(&negative));
+        // declared length not matching the signature
+        let mut truncated = signature.clone();
+        truncated.remove(10);
+        assert!(!is_valid_der_signature(&truncated));
+    }
+
+    #[test]
+    fn test_push_declaring_more_bytes_than_the_script_holds_fails() {
+        // OP_PUSHDATA4 of 4 GiB - 1 followed by a single byte
+        let script = [OP_PUSHDATA4, 0xff, 0xff, 0xff, 0xff, 0xaa];
+        let err = parse_script(&script).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sigop_count(&script, true), 0);
+        assert_eq!(ScriptType::classify(&script), ScriptType::NonStandard);
+    }
+}
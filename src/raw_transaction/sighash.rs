@@ -0,0 +1,247 @@
+use super::tx_input::{TxInput, TxInputType};
+use super::RawTransaction;
+use crate::messages::utility::{to_compact_size_bytes, to_varint};
+use crate::utility::double_hash;
+use bitcoin_hashes::Hash;
+use std::io;
+
+pub const SIGHASH_ALL: u32 = 1;
+/// Signatures of this type commit to none of the outputs, and of the next one only to the output at their
+/// input's index. Both let the other inputs change their sequences.
+pub const SIGHASH_NONE: u32 = 2;
+pub const SIGHASH_SINGLE: u32 = 3;
+/// Flag of the signatures that only commit to their own input, so others can be added
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+/// Bits of the sighash type telling which outputs it commits to
+const SIGHASH_OUTPUT_MASK: u32 = 0x1f;
+
+/// Parts of the legacy sighash preimage shared by every input of a transaction.
+/// Computed once so signing n inputs doesn't serialize the whole transaction n times.
+pub struct SigHashCache {
+    prefix: Vec<u8>,            // version and input count
+    blank_inputs: Vec<Vec<u8>>, // inputs serialized with an empty script
+    suffix: Vec<u8>,            // outputs, lock time and sighash type
+}
+
+impl SigHashCache {
+    pub fn new(transaction: &RawTransaction) -> io::Result<Self> {
+        let tx_ins = Self::tx_inputs(transaction)?;
+
+        let mut prefix = Vec::new();
+        prefix.extend(transaction.version.to_le_bytes());
+        prefix.extend(to_varint(transaction.tx_in_count));
+
+        let blank_inputs = tx_ins
+            .iter()
+            .map(|tx_in| Self::with_script(tx_in, vec![])._serialize())
+            .collect();
+
+        let mut suffix = Vec::new();
+        suffix.extend(to_varint(transaction.tx_out_count));
+        for tx_out in transaction.tx_out.iter() {
+            suffix.extend(tx_out._serialize());
+        }
+        suffix.extend(transaction.lock_time.to_le_bytes());
+        suffix.extend(SIGHASH_ALL.to_le_bytes());
+
+        Ok(Self {
+            prefix,
+            blank_inputs,
+            suffix,
+        })
+    }
+
+    fn tx_inputs(transaction: &RawTransaction) -> io::Result<&Vec<TxInput>> {
+        match &transaction.tx_in {
+            TxInputType::TxInput(tx_ins) => Ok(tx_ins),
+            TxInputType::CoinBaseInput(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Coinbase inputs can't be signed",
+            )),
+        }
+    }
+
+    fn with_script(tx_in: &TxInput, script: Vec<u8>) -> TxInput {
+        TxInput {
+            previous_output: tx_in.previous_output.clone(),
+            script_bytes: script.len() as u64,
+            script_sig: script,
+            sequence: tx_in.sequence,
+        }
+    }
+
+    /// Returns the SIGHASH_ALL hash of the input at the given index, spending the given pk script
+    pub fn sig_hash(
+        &self,
+        transaction: &RawTransaction,
+        prev_pk_script: &[u8],
+        index: usize,
+    ) -> io::Result<[u8; 32]> {
+        let tx_ins = Self::tx_inputs(transaction)?;
+        let tx_in = tx_ins.get(index).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Input index out of range")
+        })?;
+
+        let mut s = self.prefix.clone();
+        for (i, blank_input) in self.blank_inputs.iter().enumerate() {
+            match i == index {
+                true => s.extend(Self::with_script(tx_in, prev_pk_script.to_vec())._serialize()),
+                false => s.extend(blank_input),
+            }
+        }
+        s.extend(&self.suffix);
+
+        Ok(double_hash(&s).to_byte_array())
+    }
+}
+
+/// Returns the input at the given index, failing for a coinbase or an index out of range
+fn input_at(transaction: &RawTransaction, index: usize) -> io::Result<&TxInput> {
+    SigHashCache::tx_inputs(transaction)?
+        .get(index)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Input index out of range"))
+}
+
+/// Returns the legacy hash the input at the given index signs with the given sighash type, spending the given
+/// script. A SIGHASH_SINGLE signature of an input without an output at its index signs the number 1, like
+/// Bitcoin Core does.
+pub fn legacy_sig_hash(
+    transaction: &RawTransaction,
+    script_code: &[u8],
+    index: usize,
+    sighash_type: u32,
+) -> io::Result<[u8; 32]> {
+    let tx_ins = SigHashCache::tx_inputs(transaction)?;
+    let tx_in = input_at(transaction, index)?;
+    let outputs = sighash_type & SIGHASH_OUTPUT_MASK;
+    if outputs == SIGHASH_SINGLE && index >= transaction.tx_out.len() {
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        return Ok(one);
+    }
+    let mut s = transaction.version.to_le_bytes().to_vec();
+    match sighash_type & SIGHASH_ANYONECANPAY {
+        0 => {
+            s.extend(to_varint(tx_ins.len() as u64));
+            for (i, other) in tx_ins.iter().enumerate() {
+                s.extend(legacy_sig_input(other, i == index, script_code, outputs)._serialize());
+            }
+        }
+        _ => {
+            s.extend(to_varint(1));
+            s.extend(SigHashCache::with_script(tx_in, script_code.to_vec())._serialize());
+        }
+    }
+    s.extend(legacy_sig_outputs(transaction, index, outputs));
+    s.extend(transaction.lock_time.to_le_bytes());
+    s.extend(sighash_type.to_le_bytes());
+    Ok(double_hash(&s).to_byte_array())
+}
+
+/// Returns the input as the legacy hash serializes it: with the spent script if it's the one signed, with an
+/// empty one and, unless all the outputs are signed, a zero sequence otherwise
+fn legacy_sig_input(tx_in: &TxInput, signed: bool, script_code: &[u8], outputs: u32) -> TxInput {
+    if signed {
+        return SigHashCache::with_script(tx_in, script_code.to_vec());
+    }
+    let mut blank = SigHashCache::with_script(tx_in, vec![]);
+    if outputs == SIGHASH_NONE || outputs == SIGHASH_SINGLE {
+        blank.sequence = 0;
+    }
+    blank
+}
+
+/// Serializes the outputs the legacy hash commits to. With SIGHASH_SINGLE the ones before the input's index
+/// are blanked out, with a value of -1 and an empty script.
+fn legacy_sig_outputs(transaction: &RawTransaction, index: usize, outputs: u32) -> Vec<u8> {
+    match outputs {
+        SIGHASH_NONE => to_varint(0),
+        SIGHASH_SINGLE => {
+            let mut s = to_varint(index as u64 + 1);
+            for _ in 0..index {
+                s.extend(u64::MAX.to_le_bytes());
+                s.push(0);
+            }
+            s.extend(transaction.tx_out[index]._serialize());
+            s
+        }
+        _ => {
+            let mut s = to_varint(transaction.tx_out.len() as u64);
+            for tx_out in &transaction.tx_out {
+                s.extend(tx_out._serialize());
+            }
+            s
+        }
+    }
+}
+
+/// Hashes of the outpoints, sequences and outputs of a transaction that the signatures of version 0 witness
+/// programs commit to (BIP143). They're the same for every input, so they're only computed once.
+pub struct WitnessSigHashes {
+    prevouts: [u8; 32],
+    sequences: [u8; 32],
+    outputs: [u8; 32],
+}
+
+impl WitnessSigHashes {
+    pub fn new(transaction: &RawTransaction, tx_ins: &[TxInput]) -> Self {
+        let (mut prevouts, mut sequences, mut outputs) = (vec![], vec![], vec![]);
+        for tx_in in tx_ins {
+            prevouts.extend(tx_in.previous_output.hash.iter());
+            prevouts.extend(tx_in.previous_output.index.to_le_bytes());
+            sequences.extend(tx_in.sequence.to_le_bytes());
+        }
+        for tx_out in &transaction.tx_out {
+            outputs.extend(tx_out._serialize());
+        }
+        Self {
+            prevouts: double_hash(&prevouts).to_byte_array(),
+            sequences: double_hash(&sequences).to_byte_array(),
+            outputs: double_hash(&outputs).to_byte_array(),
+        }
+    }
+
+    /// Returns the hash the input at the given index signs with the given sighash type, spending an output
+    /// of the given amount with the given script code
+    pub fn sig_hash(
+        &self,
+        transaction: &RawTransaction,
+        (script_code, amount): (&[u8], u64),
+        index: usize,
+        sighash_type: u32,
+    ) -> io::Result<[u8; 32]> {
+        let tx_in = input_at(transaction, index)?;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let outputs = sighash_type & SIGHASH_OUTPUT_MASK;
+        let all_outputs = outputs != SIGHASH_NONE && outputs != SIGHASH_SINGLE;
+        let mut s = transaction.version.to_le_bytes().to_vec();
+        s.extend(match anyone_can_pay {
+            true => [0; 32],
+            false => self.prevouts,
+        });
+        s.extend(match anyone_can_pay || !all_outputs {
+            true => [0; 32],
+            false => self.sequences,
+        });
+        s.extend(tx_in.previous_output.hash.iter());
+        s.extend(tx_in.previous_output.index.to_le_bytes());
+        s.extend(to_compact_size_bytes(script_code.len() as u64));
+        s.extend(script_code);
+        s.extend(amount.to_le_bytes());
+        s.extend(tx_in.sequence.to_le_bytes());
+        s.extend(self.outputs_hash(transaction, index, outputs));
+        s.extend(transaction.lock_time.to_le_bytes());
+        s.extend(sighash_type.to_le_bytes());
+        Ok(double_hash(&s).to_byte_array())
+    }
+
+    /// Returns the hash of the outputs the signature commits to: all of them, the one at the input's index
+    /// with SIGHASH_SINGLE, or none
+    fn outputs_hash(&self, transaction: &RawTransaction, index: usize, outputs: u32) -> [u8; 32] {
+        match (outputs, transaction.tx_out.get(index)) {
+            (SIGHASH_NONE, _) | (SIGHASH_SINGLE, None) => [0; 32],
+            (SIGHASH_SINGLE, Some(tx_out)) => double_hash(&tx_out._serialize()).to_byte_array(),
+            _ => self.outputs,
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use crate::messages::{utility::StreamRead, HashId};
 use crate::raw_transaction::{
-    read_coinbase_script, read_from_varint, read_hash, to_compact_size_bytes,
+    read_from_varint, read_hash, read_script,
+    script::{is_push_only, AddressFormat},
+    to_compact_size_bytes,
 };
 use crate::utxo::p2pkh_to_address;
 use bitcoin_hashes::{hash160, Hash};
 use std::io::{self, Cursor, Error, ErrorKind, Read};
 
 /// Store a tx input (previous output, script sig, sequence)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TxInput {
     pub previous_output: Outpoint,
     pub script_bytes: u64,
@@ -16,8 +18,14 @@ pub struct TxInput {
 }
 
 impl TxInput {
-    /// Read the address from the script sig
-    pub fn get_address(&self) -> io::Result<String> {
+    /// Read the address from the script sig, which must only push the signature and public key, in the given format
+    pub fn get_address(&self, format: &AddressFormat) -> io::Result<String> {
+        if !is_push_only(&self.script_sig) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Script sig isn't push only",
+            ));
+        }
         let script_bytes = self.script_sig.clone();
         let mut cursor: Cursor<&[u8]> = Cursor::new(&script_bytes);
 
@@ -36,12 +44,12 @@ impl TxInput {
 
         // get address
         let h160 = hash160::Hash::hash(&pubkey).to_byte_array();
-        Ok(p2pkh_to_address(h160))
+        Ok(p2pkh_to_address(h160, format))
     }
 
-    /// Check if the input is destined to the given address    
-    pub fn destined_from(&self, address: &str) -> bool {
-        match self.get_address() {
+    /// Check if the input is destined to the given address of the given format
+    pub fn destined_from(&self, address: &str, format: &AddressFormat) -> bool {
+        match self.get_address(format) {
             Ok(addr) => addr == address,
             Err(..) => false,
         }
@@ -51,7 +59,7 @@ impl TxInput {
     pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Self, Error> {
         let previous_output = Outpoint::from_bytes(cursor)?;
         let script_bytes = read_from_varint(cursor)?;
-        let script_sig = read_coinbase_script(cursor, script_bytes as usize)?;
+        let script_sig = read_script(cursor, script_bytes as usize)?;
         let sequence = u32::from_le_stream(cursor)?;
 
         let tx_input = TxInput {
@@ -107,7 +115,7 @@ impl TxInput {
 }
 
 /// Represent outpoint (hash of previous utxo, index of previous utxo)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Outpoint {
     pub hash: HashId,
     pub index: u32,
@@ -121,10 +129,15 @@ impl Outpoint {
         let outpoint = Outpoint { hash, index };
         Ok(outpoint)
     }
+
+    /// Returns true if this is the null outpoint coinbase inputs spend
+    pub fn is_null(&self) -> bool {
+        self.hash == HashId::default() && self.index == u32::MAX
+    }
 }
 
 /// Represent a tx input type (coinbase or tx input vector)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TxInputType {
     CoinBaseInput(CoinBaseInput),
     TxInput(Vec<TxInput>),
@@ -141,12 +154,12 @@ impl TxInputType {
 }
 
 /// Represent a coinbase input
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoinBaseInput {
     pub _hash: HashId,
     pub _index: u32,
     pub _script_bytes: u64,
-    pub _height: u32,
+    pub height: u32,
     pub _coinbase_script: Vec<u8>,
     pub _sequence: u32,
 }
@@ -180,7 +193,7 @@ impl CoinBaseInput {
         let _hash = read_hash(cursor)?;
         let _index = u32::from_le_stream(cursor)?;
         let _script_bytes = read_from_varint(cursor)?;
-        let _height = match read_height(cursor) {
+        let height = match read_height(cursor) {
             Ok(height) => height,
             Err(err) => {
                 println!("Invalid height, script bytes was set to {}", _script_bytes);
@@ -188,14 +201,14 @@ impl CoinBaseInput {
             }
         };
 
-        let _coinbase_script = read_coinbase_script(cursor, (_script_bytes - 4) as usize)?;
+        let _coinbase_script = read_script(cursor, (_script_bytes - 4) as usize)?;
         let _sequence = u32::from_le_stream(cursor)?;
 
         let coinbase_input = CoinBaseInput {
             _hash,
             _index,
             _script_bytes,
-            _height,
+            height,
             _coinbase_script,
             _sequence,
         };
@@ -203,14 +216,19 @@ impl CoinBaseInput {
         Ok(coinbase_input)
     }
 
+    /// Returns true if the input spends the null outpoint, as a coinbase must
+    pub fn has_null_prevout(&self) -> bool {
+        self._hash == HashId::default() && self._index == u32::MAX
+    }
+
     /// Serialize a coinbase input to bytes
     pub fn _serialize(&self) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.extend(self._hash.iter());
         bytes.extend_from_slice(&self._index.to_le_bytes());
         bytes.extend_from_slice(&to_compact_size_bytes(self._script_bytes));
-        // bytes.extend_from_slice(remove_right_zero_bytes(&self._height.to_le_bytes()));
-        bytes.extend_from_slice(&serialize_height(self._height));
+        // bytes.extend_from_slice(remove_right_zero_bytes(&self.height.to_le_bytes()));
+        bytes.extend_from_slice(&serialize_height(self.height));
         bytes.extend_from_slice(&self._coinbase_script);
         bytes.extend_from_slice(&self._sequence.to_le_bytes());
         bytes
@@ -221,6 +239,7 @@ impl CoinBaseInput {
 mod tests {
 
     use super::*;
+    use crate::raw_transaction::script::TESTNET_ADDRESSES;
     use crate::{raw_transaction::RawTransaction, utility::decode_hex};
 
     #[test]
@@ -258,7 +277,7 @@ mod tests {
             assert_eq!(coinbase_input._hash, HashId::default());
             assert_eq!(coinbase_input._index, 0xffffffff);
             assert_eq!(coinbase_input._script_bytes, 29);
-            assert_eq!(coinbase_input._height, 1281295);
+            assert_eq!(coinbase_input.height, 1281295);
             assert_eq!(
                 coinbase_input._coinbase_script,
                 [
@@ -318,7 +337,7 @@ mod tests {
         let txin_bytes = decode_hex("881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff").unwrap();
         let txin = TxInput::from_bytes(&mut Cursor::new(&txin_bytes)).unwrap();
         let address = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
-        assert!(txin.destined_from(address));
-        assert!(!txin.destined_from("foo"));
+        assert!(txin.destined_from(address, &TESTNET_ADDRESSES));
+        assert!(!txin.destined_from("foo", &TESTNET_ADDRESSES));
     }
 }
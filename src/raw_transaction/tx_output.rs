@@ -1,29 +1,10 @@
 use crate::messages::utility::StreamRead;
-use crate::raw_transaction::{read_coinbase_script, read_from_varint, to_compact_size_bytes};
-use crate::utxo::p2pkh_to_address;
-use bitcoin_hashes::{ripemd160, sha256, Hash};
-use std::io::{self, Cursor, Error, Read};
-
-/// Struct that represents the pk_script data of a transaction output
-#[derive(Debug, Clone)]
-pub struct PkScriptData {
-    pub pk_hash: [u8; 20],
-}
-
-impl PkScriptData {
-    /// Creates a new `PkScriptData` from a pk_script
-    pub fn from_pk_script_bytes(pk_script_bytes: &[u8]) -> Result<Self, Error> {
-        let first_hash = sha256::Hash::hash(pk_script_bytes);
-        let second_hash = ripemd160::Hash::hash(&first_hash[..]);
-
-        let mut bytes = [0u8; 20];
-        bytes.copy_from_slice(&second_hash[..]);
-        Ok(PkScriptData { pk_hash: bytes })
-    }
-}
+use crate::raw_transaction::script::{AddressFormat, ScriptType};
+use crate::raw_transaction::{read_from_varint, read_script, to_compact_size_bytes};
+use std::io::{Cursor, Error};
 
 /// Struct that represents a transaction output (value and pk_script)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TxOutput {
     pub value: u64,
     pub pk_script_bytes: u64,
@@ -31,38 +12,27 @@ pub struct TxOutput {
 }
 
 impl TxOutput {
-    /// Returns the destined address of the transaction output
-    pub fn get_address(&self) -> io::Result<String> {
-        let script_bytes = self.pk_script.clone();
-        let mut cursor: Cursor<&[u8]> = Cursor::new(&script_bytes);
-
-        // consume cursor until reading 0x14
-        let buf = &mut [0; 1];
-        while buf[0] != 0x14 {
-            cursor.read_exact(buf)?;
-        }
-
-        let mut pk_hash = [0; 20];
-        cursor.read_exact(&mut pk_hash)?;
+    /// Returns the template the pk_script of the output follows
+    pub fn script_type(&self) -> ScriptType {
+        ScriptType::classify(&self.pk_script)
+    }
 
-        Ok(p2pkh_to_address(pk_hash))
+    /// Returns the destined address of the transaction output in the given format, None if its script has no
+    /// address (OP_RETURN, multisig or nonstandard)
+    pub fn get_address(&self, format: &AddressFormat) -> Option<String> {
+        self.script_type().address(format)
     }
 
-    /// Checks if the transaction output is destined to the given address
-    pub fn destined_to(&self, address: &str) -> bool {
-        match self.get_address() {
-            Ok(a) => a == address,
-            Err(_) => false,
-        }
+    /// Checks if the transaction output is destined to the given address of the given format
+    pub fn destined_to(&self, address: &str, format: &AddressFormat) -> bool {
+        self.get_address(format).is_some_and(|a| a == address)
     }
 
     /// Deserialize a `TxOutput` from a byte array.
     pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Self, Error> {
         let value = u64::from_le_stream(cursor)?; // this is actually a float?
         let pk_script_bytes = read_from_varint(cursor)?;
-        let pk_script = read_coinbase_script(cursor, pk_script_bytes as usize)?;
-
-        let _pk_script_data = PkScriptData::from_pk_script_bytes(&pk_script)?;
+        let pk_script = read_script(cursor, pk_script_bytes as usize)?;
 
         let tx_output = TxOutput {
             value,
@@ -104,10 +74,6 @@ impl TxOutput {
         }
         bytes
     }
-
-    pub fn _get_pk_script_data(&self) -> Result<PkScriptData, Error> {
-        PkScriptData::from_pk_script_bytes(&self.pk_script)
-    }
 }
 
 #[cfg(test)]
@@ -115,6 +81,7 @@ mod tests {
     use crate::utility::decode_hex;
 
     use super::*;
+    use crate::raw_transaction::script::TESTNET_ADDRESSES;
 
     #[test]
     fn test_txou_serialization() {
@@ -161,7 +128,18 @@ mod tests {
                 .unwrap();
         let txou = TxOutput::from_bytes(&mut Cursor::new(&bytes)).unwrap();
         let address = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
-        assert!(txou.destined_to(address));
-        assert!(!txou.destined_to("foo"));
+        assert!(txou.destined_to(address, &TESTNET_ADDRESSES));
+        assert!(!txou.destined_to("foo", &TESTNET_ADDRESSES));
+    }
+
+    #[test]
+    fn test_txout_without_address() {
+        // OP_RETURN output and an output whose script is a truncated push
+        for hex in ["00000000000000000452534b42", "0000000000000000024c05"] {
+            let bytes = decode_hex(hex).unwrap();
+            let txou = TxOutput::from_bytes(&mut Cursor::new(&bytes)).unwrap();
+            assert_eq!(txou.get_address(&TESTNET_ADDRESSES), None);
+            assert!(!txou.destined_to("no_address", &TESTNET_ADDRESSES));
+        }
     }
 }
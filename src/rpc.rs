@@ -0,0 +1,190 @@
+use crate::chain_tip::ChainTip;
+use crate::messages::HashId;
+use crate::peer_info::{escape_json, PeerInfo};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Request answered by the RPC server, sent as a line holding the method and its parameters
+/// separated by spaces. Timeouts are in milliseconds, 0 waiting for as long as it takes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcRequest {
+    /// `waitforblockheight <height> [timeout]`: waits for the tip to reach the height
+    WaitForBlockHeight(usize, Duration),
+    /// `waitfornewblock [timeout]`: waits for a block taller than the tip
+    WaitForNewBlock(Duration),
+    /// `getpeerinfo`: lists the connected peers
+    GetPeerInfo,
+}
+
+/// Result of a request: the tip it waited for, or the connected peers
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcAnswer {
+    Tip(usize, HashId),
+    Peers(Vec<PeerInfo>),
+}
+
+impl RpcRequest {
+    /// Parses the line of a request
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        let mut words = line.split_whitespace();
+        let method = words.next().unwrap_or_default();
+        let params = words
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|_| invalid("Parameters must be numbers"))?;
+        let timeout = |param: Option<&u64>| match param.copied().unwrap_or(0) {
+            0 => Duration::MAX,
+            millis => Duration::from_millis(millis),
+        };
+        match (method, params.as_slice()) {
+            ("waitforblockheight", [height, rest @ ..]) if rest.len() <= 1 => Ok(
+                Self::WaitForBlockHeight(*height as usize, timeout(rest.first())),
+            ),
+            ("waitfornewblock", rest) if rest.len() <= 1 => {
+                Ok(Self::WaitForNewBlock(timeout(rest.first())))
+            }
+            ("getpeerinfo", []) => Ok(Self::GetPeerInfo),
+            _ => Err(invalid(&format!("Unknown method or parameters: {}", line))),
+        }
+    }
+
+    /// Waits as requested and returns the height and hash of the tip then, which is still below the
+    /// requested height if the timeout passed. Peer info is taken from the node when requested.
+    pub fn answer(
+        &self,
+        chain_tip: &ChainTip,
+        peer_info: &impl Fn() -> io::Result<Vec<PeerInfo>>,
+    ) -> io::Result<RpcAnswer> {
+        let (height, timeout) = match self {
+            Self::WaitForBlockHeight(height, timeout) => (*height, *timeout),
+            Self::WaitForNewBlock(timeout) => (chain_tip.tip()?.0 + 1, *timeout),
+            Self::GetPeerInfo => return peer_info().map(RpcAnswer::Peers),
+        };
+        let (height, hash) = match chain_tip.wait_for_height(height, timeout) {
+            Err(e) if e.kind() != io::ErrorKind::TimedOut => return Err(e),
+            _ => chain_tip.tip()?,
+        };
+        Ok(RpcAnswer::Tip(height, hash))
+    }
+}
+
+/// Returns the JSON reply to a request: the tip it waited for, the connected peers, or the error
+pub fn reply(answer: &io::Result<RpcAnswer>) -> String {
+    match answer {
+        Ok(RpcAnswer::Tip(height, hash)) => format!(
+            "{{\"result\":{{\"hash\":\"{}\",\"height\":{}}},\"error\":null}}",
+            hash, height
+        ),
+        Ok(RpcAnswer::Peers(peers)) => {
+            let peers: Vec<String> = peers.iter().map(PeerInfo::to_json).collect();
+            format!("{{\"result\":[{}],\"error\":null}}", peers.join(","))
+        }
+        Err(e) => format!(
+            "{{\"result\":null,\"error\":\"{}\"}}",
+            escape_json(&e.to_string())
+        ),
+    }
+}
+
+/// Answers the requests sent through the connection, one reply line per request line, until it's closed
+pub fn serve_connection(
+    stream: TcpStream,
+    chain_tip: &ChainTip,
+    peer_info: impl Fn() -> io::Result<Vec<PeerInfo>>,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let answer =
+            RpcRequest::parse(&line?).and_then(|request| request.answer(chain_tip, &peer_info));
+        writeln!(writer, "{}", reply(&answer))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Services;
+    use crate::utility::to_io_err;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_requests_are_parsed_with_optional_timeouts() -> io::Result<()> {
+        assert_eq!(
+            RpcRequest::parse("waitforblockheight 10 500")?,
+            RpcRequest::WaitForBlockHeight(10, Duration::from_millis(500))
+        );
+        assert_eq!(
+            RpcRequest::parse("waitfornewblock")?,
+            RpcRequest::WaitForNewBlock(Duration::MAX)
+        );
+        assert_eq!(RpcRequest::parse("getpeerinfo")?, RpcRequest::GetPeerInfo);
+        let invalid_requests = [
+            "waitforblockheight",
+            "waitfornewblock a",
+            "getblock 1",
+            "getpeerinfo 1",
+            "",
+        ];
+        for invalid in invalid_requests {
+            assert!(RpcRequest::parse(invalid).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_at_requested_height_answers_waiting_request() -> io::Result<()> {
+        let chain_tip = ChainTip::new(0, HashId::new([0; 32]));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        let serving_tip = chain_tip.clone();
+        thread::spawn(move || serve_connection(stream, &serving_tip, || Ok(vec![])));
+
+        writeln!(client, "waitforblockheight 2 10000")?;
+        writeln!(client, "waitfornewblock 10")?;
+        chain_tip.block_connected(1, HashId::new([1; 32]))?;
+        chain_tip.block_connected(2, HashId::new([2; 32]))?;
+        let mut replies = BufReader::new(client).lines();
+        let expected = reply(&Ok(RpcAnswer::Tip(2, HashId::new([2; 32]))));
+        assert_eq!(replies.next().transpose()?, Some(expected.clone()));
+        // no block arrives within the timeout, so the tip is the same
+        assert_eq!(replies.next().transpose()?, Some(expected));
+        Ok(())
+    }
+    #[test]
+    fn test_getpeerinfo_lists_the_connected_peers() -> io::Result<()> {
+        let peer = PeerInfo {
+            address: "127.0.0.1:18333".parse().map_err(to_io_err)?,
+            connected_since: 1700000000,
+            services: Services::new(0x409),
+            version: 70015,
+            user_agent: "/Satoshi:\"25.0\"/".to_string(),
+            start_height: 100,
+            relay: true,
+            bytes_sent: 10,
+            bytes_received: 20,
+            ping_time: Some(Duration::from_millis(250)),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        let chain_tip = ChainTip::new(0, HashId::new([0; 32]));
+        let peers = vec![peer];
+        let served_peers = peers.clone();
+        thread::spawn(move || serve_connection(stream, &chain_tip, || Ok(served_peers.clone())));
+
+        writeln!(client, "getpeerinfo")?;
+        let mut replies = BufReader::new(client).lines();
+        let expected = "{\"result\":[{\"addr\":\"127.0.0.1:18333\",\"services\":\"0000000000000409\",\
+                        \"version\":70015,\"subver\":\"/Satoshi:\\\"25.0\\\"/\",\"startingheight\":100,\
+                        \"relaytxes\":true,\"conntime\":1700000000,\"bytessent\":10,\"bytesrecv\":20,\
+                        \"pingtime\":0.25}],\"error\":null}";
+        assert_eq!(replies.next().transpose()?, Some(expected.to_string()));
+        assert_eq!(reply(&Ok(RpcAnswer::Peers(peers))), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use bitcoin_hashes::{sha256, Hash};
+use std::io::{self, Read, Write};
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CONNECT: u8 = 0x01;
+const SUCCEEDED: u8 = 0x00;
+const IPV4: u8 = 0x01;
+const DOMAIN_NAME: u8 = 0x03;
+const IPV6: u8 = 0x04;
+/// Prefix of the addresses onion peers are known by, the OnionCat range Bitcoin Core maps onion services to
+const ONION_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+/// A peer behind a Tor onion service, whose host only the proxy can resolve. Since peers are known by their
+/// socket address, it stands in for an address of the onion range derived from the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnionPeer {
+    host: String,
+    port: u16,
+}
+
+impl FromStr for OnionPeer {
+    type Err = io::Error;
+
+    /// Parses a host ending in `.onion` followed by its port
+    fn from_str(peer: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Onion peer is invalid");
+        let (host, port) = peer.rsplit_once(':').ok_or_else(invalid)?;
+        if !host.ends_with(".onion") || host.len() > u8::MAX as usize {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port: port.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl OnionPeer {
+    /// Returns the address the peer is known by
+    pub fn stand_in_addr(&self) -> SocketAddr {
+        let hash = sha256::Hash::hash(self.host.as_bytes()).to_byte_array();
+        let mut octets = [0u8; 16];
+        octets[..6].copy_from_slice(&ONION_PREFIX);
+        octets[6..].copy_from_slice(&hash[..10]);
+        SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(octets), self.port, 0, 0))
+    }
+}
+
+/// Connects to the target through the SOCKS5 proxy (such as Tor), returning the stream once the proxy
+/// relays it. The negotiation must finish within the timeout, later reads on the stream have none.
+pub fn connect(proxy: SocketAddr, target: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    connect_with_request(proxy, &connect_request(target), timeout)
+}
+
+/// Connects to the onion peer through the proxy, which resolves its host
+pub fn connect_to_onion(
+    proxy: SocketAddr,
+    peer: &OnionPeer,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0x00, DOMAIN_NAME];
+    request.push(peer.host.len() as u8);
+    request.extend(peer.host.as_bytes());
+    request.extend(peer.port.to_be_bytes());
+    connect_with_request(proxy, &request, timeout)
+}
+
+fn connect_with_request(
+    proxy: SocketAddr,
+    request: &[u8],
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    negotiate(&mut stream, request)?;
+    stream.set_read_timeout(None)?;
+    Ok(stream)
+}
+
+/// Greets the proxy without authentication and sends it the connect request
+fn negotiate(stream: &mut TcpStream, request: &[u8]) -> io::Result<()> {
+    stream.write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "Proxy requires an unsupported authentication method",
+        ));
+    }
+    stream.write_all(request)?;
+    read_reply(stream)
+}
+
+fn connect_request(target: SocketAddr) -> Vec<u8> {
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0x00];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(IPV4);
+            request.extend(addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(IPV6);
+            request.extend(addr.ip().octets());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    request
+}
+
+/// Reads the reply to the connect request, failing if the proxy couldn't reach the target
+fn read_reply(stream: &mut TcpStream) -> io::Result<()> {
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != SUCCEEDED {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "Proxy could not connect to the target, reply code {}",
+                reply[1]
+            ),
+        ));
+    }
+    // the address the proxy bound for the connection isn't needed, but must be consumed
+    let address_len = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN_NAME => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Proxy replied with an unknown address type",
+            ))
+        }
+    };
+    let mut bound_address = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_onion_peer_stands_in_for_an_onion_range_address() -> io::Result<()> {
+        let peer = OnionPeer::from_str("expyuzz4wqqyqhjn.onion:18333")?;
+        let SocketAddr::V6(addr) = peer.stand_in_addr() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Expected an IPv6 address",
+            ));
+        };
+        assert_eq!(addr.ip().octets()[..6], ONION_PREFIX);
+        assert_eq!(addr.port(), 18333);
+        assert!(OnionPeer::from_str("example.com:18333").is_err());
+        assert!(OnionPeer::from_str("expyuzz4wqqyqhjn.onion").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_negotiates_with_proxy() -> io::Result<()> {
+        let proxy = TcpListener::bind("127.0.0.1:0")?;
+        let proxy_addr = proxy.local_addr()?;
+        let target = SocketAddr::from(([10, 0, 0, 1], 18333));
+        let mock_proxy = thread::spawn(move || -> io::Result<Vec<u8>> {
+            let (mut stream, _) = proxy.accept()?;
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting)?;
+            stream.write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])?;
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request)?;
+            stream.write_all(&[SOCKS_VERSION, SUCCEEDED, 0x00, IPV4, 0, 0, 0, 0, 0, 0])?;
+            stream.write_all(b"relayed")?;
+            Ok([&greeting[..], &request[..]].concat())
+        });
+
+        let mut stream = connect(proxy_addr, target, Duration::from_secs(10))?;
+        let mut relayed = [0u8; 7];
+        stream.read_exact(&mut relayed)?;
+        assert_eq!(&relayed, b"relayed");
+
+        let negotiation = mock_proxy
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Proxy thread panicked"))??;
+        assert_eq!(negotiation[..3], [SOCKS_VERSION, 1, NO_AUTHENTICATION]);
+        assert_eq!(
+            negotiation[3..],
+            [SOCKS_VERSION, CONNECT, 0x00, IPV4, 10, 0, 0, 1, 0x47, 0x9d]
+        );
+        Ok(())
+    }
+}
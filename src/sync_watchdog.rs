@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+/// Detects a sync that stopped making progress: neither the tallest header nor the tallest block
+/// grew within the timeout while still syncing. A node that caught up isn't considered stalled.
+#[derive(Debug, Clone)]
+pub struct SyncWatchdog {
+    timeout: Duration,
+    heights: (usize, usize), // tallest header and block heights when progress was last seen
+    last_progress: Instant,
+}
+
+impl SyncWatchdog {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            heights: (0, 0),
+            last_progress: now,
+        }
+    }
+
+    /// Records the current tallest header and block heights. Returns true if sync made no progress
+    /// within the timeout, which is reported again after every further timeout without progress.
+    pub fn stalled(&mut self, heights: (usize, usize), syncing: bool, now: Instant) -> bool {
+        if !syncing || heights != self.heights {
+            self.heights = heights;
+            self.last_progress = now;
+            return false;
+        }
+        if now.duration_since(self.last_progress) < self.timeout {
+            return false;
+        }
+        self.last_progress = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_progress_triggers_stall() {
+        let timeout = Duration::from_secs(300);
+        let start = Instant::now();
+        let mut watchdog = SyncWatchdog::new(timeout, start);
+
+        assert!(!watchdog.stalled((2000, 10), true, start));
+        assert!(!watchdog.stalled((2000, 10), true, start + timeout / 2));
+        // new blocks are progress, even if headers don't grow
+        assert!(!watchdog.stalled((2000, 11), true, start + timeout));
+        assert!(watchdog.stalled((2000, 11), true, start + timeout * 2));
+        // reported again only after another timeout without progress
+        assert!(!watchdog.stalled((2000, 11), true, start + timeout * 2));
+        assert!(watchdog.stalled((2000, 11), true, start + timeout * 3));
+
+        // once caught up, no new blocks for a while isn't a stall
+        assert!(!watchdog.stalled((2000, 2000), false, start + timeout * 4));
+        assert!(!watchdog.stalled((2000, 2000), false, start + timeout * 6));
+    }
+}
@@ -0,0 +1,54 @@
+//! Transactions and wallets shared by the tests of several modules
+
+use crate::messages::HashId;
+use crate::raw_transaction::RawTransaction;
+use crate::utility::{decode_hex, to_io_err};
+use crate::utxo::{UtxoSet, UtxoTransaction, WalletUtxo};
+use std::io::{self, Cursor};
+
+/// Testnet address the funding and spending transactions pay to
+pub const ADDRESS: &str = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
+
+/// Testnet segwit transaction whose first output pays 1815366 satoshis to `ADDRESS`
+pub const FUNDING_TX_HEX: &str = "020000000001011216d10ae3afe6119529c0a01abe7833641e0e9d37eb880ae5547cfb7c6c7bca0000000000fdffffff0246b31b00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac731f2001020000001976a914d617966c3f29cfe50f7d9278dd3e460e3f084b7b88ac02473044022059570681a773748425ddd56156f6af3a0a781a33ae3c42c74fafd6cc2bd0acbc02200c4512c250f88653fae4d73e0cab419fa2ead01d6ba1c54edee69e15c1618638012103e7d8e9b09533ae390d0db3ad53cc050a54f89a987094bffac260f25912885b834b2c2500";
+
+/// Transaction signed by `ADDRESS` spending the first output of the funding one, paying 10000 satoshis
+/// away and 1705366 back to `ADDRESS`
+pub const SPENDING_TX_HEX: &str = "0100000001881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff0210270000000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac96051a00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac00000000";
+
+/// Testnet P2PKH payment of 225 bytes with two outputs adding up to 1100000 satoshis
+pub const PAYMENT_TX_HEX: &str = "01000000011acd5fe758ab56da34a0973c9c5dda0b63dcd79fe5860950813a366db1c92585010000006a4730440220046dc82c7c2e72665938c0aa7e10a135496d2467c2d1d105daa4ed1bab436898022064d9e36334d87c56454f7447c9da2c2eeb56cb77d3e9431feeac45649a23d9b901210387d7265c4973b153830aa72486d2488f964d194d2de869236fb87cc907d83971ffffffff0240420f00000000001976a9149144fda38182db2d26e5de88456accf241c898eb88aca0860100000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac00000000";
+
+/// Parses the transaction of the given hex
+pub fn tx_from_hex(hex: &str) -> io::Result<RawTransaction> {
+    let bytes = decode_hex(hex).map_err(to_io_err)?;
+    RawTransaction::from_bytes(&mut Cursor::new(&bytes))
+}
+
+/// Returns the payment with the given lock time, which tells apart transactions that are otherwise the same
+pub fn transaction(lock_time: u32) -> io::Result<RawTransaction> {
+    let mut tx = tx_from_hex(PAYMENT_TX_HEX)?;
+    tx.lock_time = lock_time;
+    Ok(tx)
+}
+
+/// Returns an output of the given value at the given index, locked by an empty script
+pub fn utxo(index: u32, value: u64, coinbase: bool) -> UtxoTransaction {
+    UtxoTransaction {
+        index,
+        value,
+        lock: vec![],
+        coinbase,
+    }
+}
+
+/// Returns a utxo set where `ADDRESS` owns the given outputs of the transaction with the given id
+pub fn utxo_set_owning(txid: HashId, utxos: Vec<UtxoTransaction>) -> UtxoSet {
+    let mut wallet = WalletUtxo::new();
+    for utxo in utxos {
+        wallet.utxos.insert((txid, utxo.index), utxo);
+    }
+    let mut utxo_set = UtxoSet::new();
+    utxo_set.set.insert(ADDRESS.to_string(), wallet);
+    utxo_set
+}
@@ -0,0 +1,272 @@
+use crate::messages::{Block, HashId, Hashable};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/// Event sent to subscribers when a tracked transaction is included in a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxConfirmed {
+    pub txid: HashId,
+    pub block_hash: HashId,
+    pub height: usize,
+}
+
+/// Keeps the transactions waiting for a confirmation and the channels their confirmations are sent to,
+/// so consumers other than the UI can learn when a transaction confirms.
+#[derive(Debug, Default)]
+pub struct TxConfirmations {
+    tracked: HashSet<HashId>,
+    subscribers: Vec<Sender<TxConfirmed>>,
+}
+
+impl TxConfirmations {
+    /// Returns a receiver of the confirmations of every tracked transaction from now on
+    pub fn subscribe(&mut self) -> Receiver<TxConfirmed> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Waits for the confirmation of the given transaction
+    pub fn track(&mut self, txid: HashId) {
+        self.tracked.insert(txid);
+    }
+
+    /// Stops waiting for the confirmation of a transaction that was abandoned
+    pub fn untrack(&mut self, txid: &HashId) {
+        self.tracked.remove(txid);
+    }
+
+    /// Sends an event for every tracked transaction in the block and stops tracking them.
+    /// Subscribers whose receiver was dropped are removed.
+    pub fn block_connected(&mut self, block: &Block) {
+        for tx in &block.txns {
+            let txid = tx.txid();
+            if !self.tracked.remove(&txid) {
+                continue;
+            }
+            let event = TxConfirmed {
+                txid,
+                block_hash: block.hash(),
+                height: block.header.height,
+            };
+            self.subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+}
+
+/// Transactions we broadcast, along with the peers that relayed them back to us and so already have them.
+/// A peer announcing one proves it accepted it, so those awaiting propagation stop waiting once one does.
+/// The ones that neither confirm nor are relayed back for too long are stale, likely dropped by the network.
+#[derive(Debug, Default)]
+pub struct SentTransactions {
+    peers_with: HashMap<HashId, HashSet<SocketAddr>>,
+    unconfirmed: HashMap<HashId, Instant>, // along with when they were broadcast
+    awaiting_propagation: HashSet<HashId>,
+}
+
+impl SentTransactions {
+    /// Records a transaction we broadcast at the given instant
+    pub fn sent(&mut self, txid: HashId, sent_at: Instant) {
+        self.peers_with.entry(txid).or_default();
+        self.unconfirmed.insert(txid, sent_at);
+    }
+
+    /// Records that the peer has the transaction if it's one we sent. Returns the number of peers
+    /// known to have it, None if we didn't send it.
+    pub fn relayed_back(&mut self, txid: &HashId, peer: SocketAddr) -> Option<usize> {
+        let peers = self.peers_with.get_mut(txid)?;
+        peers.insert(peer);
+        self.awaiting_propagation.remove(txid);
+        Some(peers.len())
+    }
+
+    /// Returns true if the transaction is one we broadcast that isn't confirmed yet
+    pub fn is_unconfirmed(&self, txid: &HashId) -> bool {
+        self.unconfirmed.contains_key(txid)
+    }
+
+    /// Waits for a peer to relay back the transaction
+    pub fn await_propagation(&mut self, txid: HashId) {
+        self.awaiting_propagation.insert(txid);
+    }
+
+    /// Returns true if no peer relayed back the transaction yet and it wasn't reported as not propagated
+    pub fn is_awaiting_propagation(&self, txid: &HashId) -> bool {
+        self.awaiting_propagation.contains(txid)
+    }
+
+    /// Returns true if a transaction awaiting propagation wasn't relayed back within the timeout
+    pub fn has_unpropagated(&self, timeout: Duration, now: Instant) -> bool {
+        !self
+            .sent_before(now, timeout, |txid| {
+                self.awaiting_propagation.contains(txid)
+            })
+            .is_empty()
+    }
+
+    /// Stops waiting for the transactions no peer relayed back within the timeout, and returns them
+    pub fn take_unpropagated(&mut self, timeout: Duration, now: Instant) -> Vec<HashId> {
+        let expired = self.sent_before(now, timeout, |txid| {
+            self.awaiting_propagation.contains(txid)
+        });
+        for txid in &expired {
+            self.awaiting_propagation.remove(txid);
+        }
+        expired
+    }
+
+    /// Forgets the transactions no peer relayed back that didn't confirm within the timeout, and returns them
+    pub fn take_stale(&mut self, timeout: Duration, now: Instant) -> Vec<HashId> {
+        let stale = self.sent_before(now, timeout, |txid| {
+            self.peers_with.get(txid).is_none_or(HashSet::is_empty)
+        });
+        for txid in &stale {
+            self.forget(txid);
+        }
+        stale
+    }
+
+    /// Forgets a transaction that was abandoned
+    pub fn forget(&mut self, txid: &HashId) {
+        self.peers_with.remove(txid);
+        self.unconfirmed.remove(txid);
+        self.awaiting_propagation.remove(txid);
+    }
+
+    /// Forgets the transactions of the block, which are confirmed
+    pub fn block_connected(&mut self, block: &Block) {
+        for tx in &block.txns {
+            let txid = tx.txid();
+            self.peers_with.remove(&txid);
+            self.unconfirmed.remove(&txid);
+            self.awaiting_propagation.remove(&txid);
+        }
+    }
+
+    /// Returns the unconfirmed transactions matching the filter that were broadcast at least the timeout ago
+    fn sent_before(
+        &self,
+        now: Instant,
+        timeout: Duration,
+        filter: impl Fn(&HashId) -> bool,
+    ) -> Vec<HashId> {
+        self.unconfirmed
+            .iter()
+            .filter(|(txid, sent_at)| now.duration_since(**sent_at) >= timeout && filter(txid))
+            .map(|(txid, _sent_at)| *txid)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::BlockHeader;
+    use crate::test_fixtures::{tx_from_hex, PAYMENT_TX_HEX};
+    use crate::utility::to_io_err;
+    use std::io;
+
+    #[test]
+    fn test_own_transaction_relayed_back_is_detected() -> io::Result<()> {
+        let peer_a: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let peer_b: SocketAddr = "127.0.0.2:18333".parse().map_err(to_io_err)?;
+        let (ours, other) = (HashId::new([1; 32]), HashId::new([2; 32]));
+
+        let mut sent_txs = SentTransactions::default();
+        sent_txs.sent(ours, Instant::now());
+        assert_eq!(sent_txs.relayed_back(&other, peer_a), None);
+        assert_eq!(sent_txs.relayed_back(&ours, peer_a), Some(1));
+        assert_eq!(sent_txs.relayed_back(&ours, peer_a), Some(1));
+        assert_eq!(sent_txs.relayed_back(&ours, peer_b), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_announcing_our_tx_marks_it_propagated() -> io::Result<()> {
+        let peer: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let (echoed, ignored) = (HashId::new([1; 32]), HashId::new([2; 32]));
+        let (sent_at, timeout) = (Instant::now(), Duration::from_secs(30));
+
+        let mut sent_txs = SentTransactions::default();
+        for txid in [echoed, ignored] {
+            sent_txs.sent(txid, sent_at);
+            sent_txs.await_propagation(txid);
+        }
+        assert_eq!(sent_txs.relayed_back(&echoed, peer), Some(1));
+        assert!(!sent_txs.is_awaiting_propagation(&echoed));
+        assert!(sent_txs.is_awaiting_propagation(&ignored));
+
+        assert!(!sent_txs.has_unpropagated(timeout, sent_at));
+        assert!(sent_txs.take_unpropagated(timeout, sent_at).is_empty());
+        assert!(sent_txs.has_unpropagated(timeout, sent_at + timeout));
+        assert_eq!(
+            sent_txs.take_unpropagated(timeout, sent_at + timeout),
+            vec![ignored]
+        );
+        // it's only reported once, and not as accepted if it's relayed back later
+        assert!(!sent_txs.has_unpropagated(timeout, sent_at + timeout));
+        assert!(sent_txs
+            .take_unpropagated(timeout, sent_at + timeout)
+            .is_empty());
+        assert!(!sent_txs.is_awaiting_propagation(&ignored));
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirming_tracked_tx_emits_event() -> io::Result<()> {
+        let tx = tx_from_hex(PAYMENT_TX_HEX)?;
+        let txid = tx.txid();
+        let mut header = BlockHeader::new(1, HashId::default(), None, txid, 0, 0, 0);
+        header.height = 2430000;
+        let block = Block::new(header, 1, vec![tx]);
+
+        let mut confirmations = TxConfirmations::default();
+        let events = confirmations.subscribe();
+        confirmations.block_connected(&block);
+        assert!(events.try_recv().is_err());
+
+        confirmations.track(txid);
+        confirmations.block_connected(&block);
+        assert_eq!(
+            events.try_recv().map_err(to_io_err)?,
+            TxConfirmed {
+                txid,
+                block_hash: block.hash(),
+                height: 2430000,
+            }
+        );
+
+        // the transaction is no longer tracked once confirmed
+        confirmations.block_connected(&block);
+        assert!(events.try_recv().is_err());
+
+        // nor once abandoned
+        confirmations.track(txid);
+        confirmations.untrack(&txid);
+        confirmations.block_connected(&block);
+        assert!(events.try_recv().is_err());
+        assert!(confirmations.tracked.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirmed_sent_tx_is_forgotten() -> io::Result<()> {
+        let peer: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+        let tx = tx_from_hex(PAYMENT_TX_HEX)?;
+        let txid = tx.txid();
+        let header = BlockHeader::new(1, HashId::default(), None, txid, 0, 0, 0);
+        let block = Block::new(header, 1, vec![tx]);
+
+        let mut sent_txs = SentTransactions::default();
+        sent_txs.sent(txid, Instant::now());
+        assert_eq!(sent_txs.relayed_back(&txid, peer), Some(1));
+        sent_txs.block_connected(&block);
+        assert!(!sent_txs.is_unconfirmed(&txid));
+        assert!(sent_txs.peers_with.is_empty());
+        assert_eq!(sent_txs.relayed_back(&txid, peer), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use crate::messages::{Block, BlockSet, HashId, Hashable};
+use crate::raw_transaction::RawTransaction;
+use crate::utility::actual_timestamp_or_default;
+use crate::utxo::{OutPoint, UtxoSet};
+use std::collections::{HashMap, HashSet};
+
+/// Locates transactions by their id: the block holding each confirmed one, and the pending ones that haven't
+/// been included in a block yet. Pending transactions are linked to the pending ones spending them, so the
+/// descendants of a pending transaction are found without going over the whole mempool.
+#[derive(Debug, Default)]
+pub struct TxIndex {
+    blocks: HashMap<HashId, HashId>, // block hash of every transaction on the best chain
+    mempool: HashMap<HashId, RawTransaction>,
+    children: HashMap<HashId, HashSet<HashId>>, // pending transactions spending outputs of each transaction
+    received_at: HashMap<HashId, u32>,          // unix time each pending transaction was received
+}
+
+impl TxIndex {
+    /// Records a transaction waiting to be included in a block, received now
+    pub fn add_pending(&mut self, tx: RawTransaction) {
+        let txid = tx.txid();
+        self.insert_pending(tx);
+        self.received_at
+            .entry(txid)
+            .or_insert(actual_timestamp_or_default() as u32);
+    }
+
+    /// Removes and returns a pending transaction that was abandoned
+    pub fn remove_pending(&mut self, txid: &HashId) -> Option<RawTransaction> {
+        self.received_at.remove(txid);
+        self.take_pending(txid)
+    }
+
+    /// Removes and returns a pending transaction that was abandoned along with the pending ones spending from
+    /// it, directly or not, which can't confirm without it
+    pub fn remove_with_descendants(&mut self, txid: &HashId) -> Vec<RawTransaction> {
+        self.with_descendants(txid)
+            .iter()
+            .filter_map(|txid| self.remove_pending(txid))
+            .collect()
+    }
+
+    /// Adds the transaction to the mempool, linking it to the transactions it spends from
+    fn insert_pending(&mut self, tx: RawTransaction) {
+        let txid = tx.txid();
+        for parent in tx.parent_txids() {
+            self.children.entry(parent).or_default().insert(txid);
+        }
+        self.mempool.insert(txid, tx);
+    }
+
+    /// Removes the transaction from the mempool and unlinks it from the transactions it spends from. The
+    /// links to its own children stay until they go too, in case it's pending again after a reorg.
+    fn take_pending(&mut self, txid: &HashId) -> Option<RawTransaction> {
+        let tx = self.mempool.remove(txid)?;
+        for parent in tx.parent_txids() {
+            if let Some(children) = self.children.get_mut(&parent) {
+                children.remove(txid);
+                if children.is_empty() {
+                    self.children.remove(&parent);
+                }
+            }
+        }
+        Some(tx)
+    }
+
+    /// Returns the unix time the pending transaction was received, None if it isn't pending
+    pub fn received_at(&self, txid: &HashId) -> Option<u32> {
+        self.mempool.get(txid)?;
+        self.received_at.get(txid).copied()
+    }
+
+    /// Sets when a pending transaction was received, for the ones read back after a restart
+    pub fn set_received_at(&mut self, txid: HashId, received_at: u32) {
+        if self.mempool.contains_key(&txid) {
+            self.received_at.insert(txid, received_at);
+        }
+    }
+
+    /// Returns the transactions waiting to be included in a block
+    pub fn pending(&self) -> impl Iterator<Item = &RawTransaction> {
+        self.mempool.values()
+    }
+
+    /// Returns the fee rate in satoshis per kilo virtual byte of the pending transaction along with its pending
+    /// ancestors, which a miner gets by including them together. None if it isn't pending or a fee is unknown.
+    pub fn ancestor_fee_rate(&self, txid: &HashId, utxo_set: &UtxoSet) -> Option<u64> {
+        let (mut fee, mut vsize) = (0u64, 0u64);
+        for txid in self.with_ancestors(txid) {
+            let tx = self.mempool.get(&txid)?;
+            fee = fee.checked_add(tx.fee(|outpoint| self.prevout_value(outpoint, utxo_set))?)?;
+            vsize += tx.vsize();
+        }
+        (vsize > 0).then(|| fee * 1000 / vsize)
+    }
+
+    /// Returns the fee rate the pending transaction is likely mined at: the best ancestor fee rate among it and its
+    /// pending descendants, so a child paying a high fee raises the rate of a parent paying a low one (CPFP)
+    pub fn package_fee_rate(&self, txid: &HashId, utxo_set: &UtxoSet) -> Option<u64> {
+        self.with_descendants(txid)
+            .iter()
+            .map(|pending| self.ancestor_fee_rate(pending, utxo_set))
+            .max()
+            .flatten()
+    }
+
+    /// Returns the pending transaction along with the pending ones spending from it, directly or not
+    fn with_descendants(&self, txid: &HashId) -> HashSet<HashId> {
+        let mut package = HashSet::new();
+        let mut to_visit = vec![*txid];
+        while let Some(txid) = to_visit.pop() {
+            if self.mempool.contains_key(&txid) && package.insert(txid) {
+                to_visit.extend(self.children.get(&txid).into_iter().flatten());
+            }
+        }
+        package
+    }
+
+    /// Returns the pending transaction along with the pending ones it spends from, directly or not
+    fn with_ancestors(&self, txid: &HashId) -> HashSet<HashId> {
+        let mut package = HashSet::new();
+        let mut to_visit = vec![*txid];
+        while let Some(txid) = to_visit.pop() {
+            if let Some(tx) = self.mempool.get(&txid) {
+                if package.insert(txid) {
+                    to_visit.extend(tx.parent_txids());
+                }
+            }
+        }
+        package
+    }
+
+    /// Returns the value of a confirmed unspent output or of an output of a pending transaction
+    fn prevout_value(&self, outpoint: &OutPoint, utxo_set: &UtxoSet) -> Option<u64> {
+        self.resolve_prevout(outpoint, utxo_set)
+            .map(|(value, _lock)| value)
+    }
+
+    /// Returns the value and script of a confirmed unspent output or of an output of a pending transaction
+    pub fn resolve_prevout<'a>(
+        &'a self,
+        outpoint: &OutPoint,
+        utxo_set: &'a UtxoSet,
+    ) -> Option<(u64, &'a [u8])> {
+        if let Some((value, lock)) = utxo_set.resolve_prevout(outpoint) {
+            return Some((*value, lock));
+        }
+        let (txid, index) = outpoint;
+        let output = self.mempool.get(txid)?.tx_out.get(*index as usize)?;
+        Some((output.value, &output.pk_script))
+    }
+
+    /// Indexes the transactions of the block that became the tip. If it isn't on the branch of the
+    /// old tip, the blocks of the old branch down to the fork are disconnected and those of the new
+    /// one connected, so every transaction resolves to its block on the best chain.
+    pub fn tip_changed(&mut self, blocks: &BlockSet, old_tip: HashId, new_tip: &Block) {
+        let (disconnected, connected) =
+            branches_from_fork(blocks, old_tip, new_tip.header.prev_block_hash);
+        for block in disconnected {
+            self.block_disconnected(block);
+        }
+        for block in connected.into_iter().rev() {
+            self.block_connected(block);
+        }
+        self.block_connected(new_tip);
+    }
+
+    /// Indexes the transactions of the block, which are no longer pending
+    fn block_connected(&mut self, block: &Block) {
+        let block_hash = block.hash();
+        for tx in &block.txns {
+            let txid = tx.txid();
+            self.take_pending(&txid);
+            self.received_at.remove(&txid);
+            self.blocks.insert(txid, block_hash);
+        }
+    }
+
+    /// Removes the transactions of a block left out of the best chain from the index,
+    /// the ones other than the coinbase are pending again
+    fn block_disconnected(&mut self, block: &Block) {
+        let block_hash = block.hash();
+        for (i, tx) in block.txns.iter().enumerate() {
+            let txid = tx.txid();
+            if self.blocks.get(&txid) == Some(&block_hash) {
+                self.blocks.remove(&txid);
+            }
+            if i > 0 {
+                self.insert_pending(tx.clone());
+            }
+        }
+    }
+
+    /// Returns true if the transaction is pending or in one of the indexed blocks
+    pub fn contains(&self, txid: &HashId) -> bool {
+        self.mempool.contains_key(txid) || self.blocks.contains_key(txid)
+    }
+
+    /// Returns the hash of the block on the best chain confirming the transaction
+    pub fn block_hash(&self, txid: &HashId) -> Option<HashId> {
+        self.blocks.get(txid).copied()
+    }
+
+    /// Forgets the transactions of the pruned blocks, which can no longer be served
+    pub fn blocks_pruned(&mut self, pruned: &HashSet<HashId>) {
+        self.blocks
+            .retain(|_txid, block_hash| !pruned.contains(block_hash));
+    }
+
+    /// Returns the transaction along with the hash of the block holding it, None for a pending one
+    pub fn find(
+        &self,
+        txid: &HashId,
+        blocks: &BlockSet,
+    ) -> Option<(RawTransaction, Option<HashId>)> {
+        if let Some(tx) = self.mempool.get(txid) {
+            return Some((tx.clone(), None));
+        }
+        let block_hash = self.blocks.get(txid)?;
+        let tx = blocks
+            .get(block_hash)?
+            .txns
+            .iter()
+            .find(|tx| tx.txid() == *txid)?;
+        Some((tx.clone(), Some(*block_hash)))
+    }
+}
+
+/// Walks back from the old tip and from the new one to the block both branches share, returning the
+/// blocks of each branch from the tip down. Stops early if a branch runs past the known blocks.
+fn branches_from_fork(
+    blocks: &BlockSet,
+    mut old: HashId,
+    mut new: HashId,
+) -> (Vec<&Block>, Vec<&Block>) {
+    let (mut old_branch, mut new_branch) = (vec![], vec![]);
+    while old != new {
+        let (old_block, new_block) = match (blocks.get(&old), blocks.get(&new)) {
+            (Some(old_block), Some(new_block)) => (old_block, new_block),
+            _ => break,
+        };
+        if old_block.header.height >= new_block.header.height {
+            old_branch.push(old_block);
+            old = old_block.header.prev_block_hash;
+        }
+        if new_block.header.height >= old_block.header.height {
+            new_branch.push(new_block);
+            new = new_block.header.prev_block_hash;
+        }
+    }
+    (old_branch, new_branch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::BlockHeader;
+    use crate::raw_transaction::tx_input::TxInputType;
+    use crate::test_fixtures::transaction;
+    use std::io;
+
+    /// Builds a block on the given parent, the nonce tells apart siblings holding the same transactions
+    fn block(prev: &Block, nonce: u32, txns: Vec<RawTransaction>) -> Block {
+        let mut header = BlockHeader::new(1, prev.hash(), None, HashId::default(), 0, 0, nonce);
+        header.height = prev.header.height + 1;
+        Block::new(header, txns.len(), txns)
+    }
+
+    #[test]
+    fn test_known_tx_is_found_with_its_block_hash() -> io::Result<()> {
+        let tx = transaction(0)?;
+        let txid = tx.txid();
+        let header = BlockHeader::new(1, HashId::default(), None, txid, 0, 0, 0);
+        let block = Block::new(header, 1, vec![tx.clone()]);
+        let blocks = BlockSet::from([(block.hash(), block.clone())]);
+
+        let mut index = TxIndex::default();
+        assert!(!index.contains(&txid));
+        index.add_pending(tx.clone());
+        assert_eq!(index.find(&txid, &blocks), Some((tx.clone(), None)));
+
+        index.tip_changed(&blocks, HashId::default(), &block);
+        assert_eq!(index.find(&txid, &blocks), Some((tx, Some(block.hash()))));
+
+        index.blocks_pruned(&HashSet::from([block.hash()]));
+        assert_eq!(index.find(&txid, &blocks), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_high_fee_child_raises_the_package_fee_rate_of_its_parent() -> io::Result<()> {
+        let parent = transaction(0)?;
+        let mut utxo_set = UtxoSet::new();
+        if let TxInputType::TxInput(inputs) = &parent.tx_in {
+            let prevout = &inputs[0].previous_output;
+            // outputs add up to 1100000 satoshis, leaving a 100 satoshis fee
+            utxo_set.index_output("", (prevout.hash, prevout.index), 1100100, &[]);
+        }
+        let mut child = transaction(1)?;
+        if let TxInputType::TxInput(inputs) = &mut child.tx_in {
+            inputs[0].previous_output.hash = parent.txid();
+            inputs[0].previous_output.index = 0;
+        }
+        // spends the 1000000 satoshis output of the parent, paying 400000 in fees
+        child.tx_out[0].value = 500000;
+
+        let mut index = TxIndex::default();
+        index.add_pending(parent.clone());
+        let parent_rate = 100 * 1000 / parent.vsize();
+        assert_eq!(
+            index.ancestor_fee_rate(&parent.txid(), &utxo_set),
+            Some(parent_rate)
+        );
+        assert_eq!(
+            index.package_fee_rate(&parent.txid(), &utxo_set),
+            Some(parent_rate)
+        );
+
+        index.add_pending(child.clone());
+        let package_rate = (100 + 400000) * 1000 / (parent.vsize() + child.vsize());
+        assert_eq!(
+            index.ancestor_fee_rate(&child.txid(), &utxo_set),
+            Some(package_rate)
+        );
+        assert_eq!(
+            index.ancestor_fee_rate(&parent.txid(), &utxo_set),
+            Some(parent_rate)
+        );
+        assert_eq!(
+            index.package_fee_rate(&parent.txid(), &utxo_set),
+            Some(package_rate)
+        );
+        assert!(package_rate > parent_rate);
+
+        // once the child is gone the parent is back to its own rate, and nothing links them anymore
+        index.remove_pending(&child.txid());
+        assert_eq!(
+            index.package_fee_rate(&parent.txid(), &utxo_set),
+            Some(parent_rate)
+        );
+        assert!(!index.children.contains_key(&parent.txid()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorg_moves_txs_to_the_new_branch() -> io::Result<()> {
+        let (tx, left_out) = (transaction(0)?, transaction(1)?);
+        let (coinbase_a, coinbase_b) = (transaction(2)?, transaction(3)?);
+        let root_header = BlockHeader::new(1, HashId::default(), None, HashId::default(), 0, 0, 0);
+        let root = Block::new(root_header, 0, vec![]);
+        let old_tip = block(
+            &root,
+            0,
+            vec![coinbase_a.clone(), tx.clone(), left_out.clone()],
+        );
+        let sibling = block(&root, 1, vec![coinbase_b.clone()]);
+        let new_tip = block(&sibling, 0, vec![coinbase_b.clone(), tx.clone()]);
+        let blocks: BlockSet = [&root, &old_tip, &sibling, &new_tip]
+            .into_iter()
+            .map(|block| (block.hash(), block.clone()))
+            .collect();
+
+        let mut index = TxIndex::default();
+        index.tip_changed(&blocks, HashId::default(), &root);
+        index.tip_changed(&blocks, root.hash(), &old_tip);
+        assert_eq!(
+            index.find(&tx.txid(), &blocks),
+            Some((tx.clone(), Some(old_tip.hash())))
+        );
+
+        index.tip_changed(&blocks, old_tip.hash(), &new_tip);
+        assert_eq!(
+            index.find(&tx.txid(), &blocks),
+            Some((tx, Some(new_tip.hash())))
+        );
+        assert_eq!(
+            index.find(&left_out.txid(), &blocks),
+            Some((left_out, None))
+        );
+        assert!(!index.contains(&coinbase_a.txid()));
+        assert_eq!(
+            index.find(&coinbase_b.txid(), &blocks),
+            Some((coinbase_b, Some(new_tip.hash())))
+        );
+        Ok(())
+    }
+}
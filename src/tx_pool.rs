@@ -0,0 +1,135 @@
+use crate::messages::HashId;
+use crate::raw_transaction::RawTransaction;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+/// A held transaction along with the peer that sent it, its serialized size and its place in the arrival order
+#[derive(Debug)]
+struct HeldTx {
+    tx: RawTransaction,
+    source: SocketAddr,
+    size: usize,
+    arrival: u64,
+}
+
+/// Transactions held back from the mempool, along with the peer that sent each one. They vary wildly in size,
+/// so the pool is bounded by their serialized bytes rather than their count, evicting the oldest ones first.
+#[derive(Debug)]
+pub struct BoundedTxPool {
+    max_bytes: usize,
+    bytes: usize,
+    next_arrival: u64,
+    txs: HashMap<HashId, HeldTx>,
+    by_arrival: BTreeMap<u64, HashId>,
+}
+
+impl BoundedTxPool {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            bytes: 0,
+            next_arrival: 0,
+            txs: HashMap::new(),
+            by_arrival: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, txid: &HashId) -> bool {
+        self.txs.contains_key(txid)
+    }
+
+    /// Holds the transaction sent by the peer, unless it's already held, evicting the oldest ones while the
+    /// pool is over its size. Returns the evicted transactions, which include the new one if it didn't fit.
+    pub fn add(&mut self, tx: RawTransaction, source: SocketAddr) -> Vec<RawTransaction> {
+        let txid = tx.txid();
+        if self.txs.contains_key(&txid) {
+            return vec![];
+        }
+        let size = tx.serialize_with_witness().len();
+        self.bytes += size;
+        self.by_arrival.insert(self.next_arrival, txid);
+        let held = HeldTx {
+            tx,
+            source,
+            size,
+            arrival: self.next_arrival,
+        };
+        self.txs.insert(txid, held);
+        self.next_arrival += 1;
+        let mut evicted = vec![];
+        while self.bytes > self.max_bytes {
+            let Some((_arrival, oldest)) = self.by_arrival.first_key_value() else {
+                break;
+            };
+            let oldest = *oldest;
+            evicted.extend(self.remove(&oldest).map(|(tx, _source)| tx));
+        }
+        evicted
+    }
+
+    /// Returns the place of the transaction in the arrival order, lower for older ones
+    pub fn arrival(&self, txid: &HashId) -> Option<u64> {
+        self.txs.get(txid).map(|held| held.arrival)
+    }
+
+    /// Drops the transaction, returning it along with the peer that sent it
+    pub fn remove(&mut self, txid: &HashId) -> Option<(RawTransaction, SocketAddr)> {
+        let held = self.txs.remove(txid)?;
+        self.bytes -= held.size;
+        self.by_arrival.remove(&held.arrival);
+        Some((held.tx, held.source))
+    }
+
+    /// Removes and returns the transactions matching the predicate along with the peers that sent them,
+    /// oldest first
+    pub fn take_matching(
+        &mut self,
+        predicate: impl Fn(&RawTransaction) -> bool,
+    ) -> Vec<(RawTransaction, SocketAddr)> {
+        let matching: Vec<HashId> = self
+            .by_arrival
+            .values()
+            .filter(|txid| self.txs.get(txid).is_some_and(|held| predicate(&held.tx)))
+            .copied()
+            .collect();
+        matching
+            .iter()
+            .filter_map(|txid| self.remove(txid))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::transaction;
+    use crate::utility::to_io_err;
+    use std::io;
+
+    #[test]
+    fn test_pool_past_the_byte_cap_evicts_the_oldest() -> io::Result<()> {
+        let txs = (0..3).map(transaction).collect::<io::Result<Vec<_>>>()?;
+        let size = txs[0].serialize_with_witness().len();
+        let source: SocketAddr = "127.0.0.1:18333".parse().map_err(to_io_err)?;
+
+        let mut pool = BoundedTxPool::new(2 * size);
+        assert!(pool.add(txs[0].clone(), source).is_empty());
+        assert!(pool.add(txs[0].clone(), source).is_empty());
+        assert!(pool.add(txs[1].clone(), source).is_empty());
+        assert_eq!(pool.add(txs[2].clone(), source), vec![txs[0].clone()]);
+        assert!(pool.arrival(&txs[1].txid()) < pool.arrival(&txs[2].txid()));
+
+        let taken = pool.take_matching(|_tx| true);
+        assert_eq!(
+            taken,
+            vec![(txs[1].clone(), source), (txs[2].clone(), source)]
+        );
+        assert_eq!(pool.bytes, 0);
+        assert!(pool.txs.is_empty() && pool.by_arrival.is_empty());
+
+        // a transaction bigger than the pool evicts itself
+        let mut tiny = BoundedTxPool::new(size - 1);
+        assert_eq!(tiny.add(txs[0].clone(), source), vec![txs[0].clone()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use crate::messages::HashId;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Time a peer has to send a transaction requested from it before the request is forgotten
+const TX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Transactions requested from peers with getdata, the only ones accepted with relay off. A request is only
+/// answered by the peer it was sent to, and it's forgotten once the peer says it doesn't have the transaction
+/// or doesn't send it in time, so requests that are never answered don't pile up.
+#[derive(Debug, Default)]
+pub struct TxRequests {
+    requested: HashMap<(HashId, SocketAddr), Instant>, // when each transaction was asked from each peer
+}
+
+impl TxRequests {
+    /// Records the transaction was requested from the peer now
+    pub fn requested(&mut self, txid: HashId, peer: SocketAddr, now: Instant) {
+        self.expire(now);
+        self.requested.insert((txid, peer), now);
+    }
+
+    /// Forgets the request of the transaction sent by the peer, returning true if it was asked from it in time
+    pub fn take(&mut self, txid: HashId, peer: SocketAddr, now: Instant) -> bool {
+        self.expire(now);
+        self.requested.remove(&(txid, peer)).is_some()
+    }
+
+    /// Forgets the request of a transaction the peer doesn't have
+    pub fn not_found(&mut self, txid: HashId, peer: SocketAddr) {
+        self.requested.remove(&(txid, peer));
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.requested.retain(|_request, requested_at| {
+            now.duration_since(*requested_at) < TX_REQUEST_TIMEOUT
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_is_only_answered_by_its_peer_in_time() {
+        let (asked, other) = (
+            SocketAddr::from(([127, 0, 0, 1], 18444)),
+            SocketAddr::from(([127, 0, 0, 2], 18444)),
+        );
+        let txid = HashId::new([1; 32]);
+        let now = Instant::now();
+        let mut requests = TxRequests::default();
+
+        requests.requested(txid, asked, now);
+        assert!(!requests.take(txid, other, now));
+        assert!(requests.take(txid, asked, now));
+        assert!(!requests.take(txid, asked, now));
+
+        // a request left unanswered expires, and one the peer doesn't have is dropped
+        requests.requested(txid, asked, now);
+        assert!(!requests.take(txid, asked, now + TX_REQUEST_TIMEOUT));
+        requests.requested(txid, asked, now);
+        requests.not_found(txid, asked);
+        assert!(requests.requested.is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+/// Length of the cycle the upload target is measured over
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Caps the bytes uploaded per day, like the `maxuploadtarget` of Bitcoin Core: once the bytes sent since the
+/// current day started reach the target, historical blocks stop being served until the next day.
+#[derive(Debug, Clone)]
+pub struct UploadTarget {
+    bytes_per_day: u64,
+    day_started: Instant,
+    sent_before_day: u64, // bytes sent before the current day started
+}
+
+impl UploadTarget {
+    /// Creates a target of the given bytes per day, whose first day starts now
+    pub fn new(bytes_per_day: u64, now: Instant) -> Self {
+        Self {
+            bytes_per_day,
+            day_started: now,
+            sent_before_day: 0,
+        }
+    }
+
+    /// Returns true if the bytes sent in the current day reached the target given the bytes sent so far,
+    /// starting a new day once the current one is over
+    pub fn is_reached(&mut self, sent: u64, now: Instant) -> bool {
+        if now.duration_since(self.day_started) >= DAY {
+            self.day_started = now;
+            self.sent_before_day = sent;
+        }
+        sent.saturating_sub(self.sent_before_day) >= self.bytes_per_day
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_is_reset_every_day() {
+        let start = Instant::now();
+        let mut target = UploadTarget::new(1000, start);
+        assert!(!target.is_reached(999, start));
+        assert!(target.is_reached(1000, start + Duration::from_secs(60)));
+        // the bytes sent the day before don't count
+        assert!(!target.is_reached(1500, start + DAY));
+        assert!(target.is_reached(2500, start + DAY + Duration::from_secs(60)));
+    }
+}
@@ -1,20 +1,30 @@
-use crate::interface::GtkMessage;
+use crate::messages::utility::{read_from_varint, read_hash, to_varint, StreamRead};
 use crate::messages::HashId;
-use crate::raw_transaction::{tx_output::TxOutput, RawTransaction, TransactionOrigin};
-use crate::utility::{double_hash, to_io_err};
-use gtk::glib::SyncSender;
-use std::collections::HashMap;
+use crate::network_params::COINBASE_MATURITY;
+use crate::raw_transaction::script::{base58check, AddressFormat, ScriptType, TESTNET_ADDRESSES};
+use crate::raw_transaction::{
+    tx_input::TxInputType, tx_output::TxOutput, RawTransaction, TransactionOrigin,
+};
+use crate::utility::to_io_err;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
 use std::io::{self, Cursor, Read};
 
 pub type Lock = Vec<u8>;
 type Address = String;
 pub type Index = u32;
+pub type OutPoint = (HashId, Index);
 
-/// Struct that represents a UTXOs pending to be spent
+/// Size in bytes of a signed P2PKH input: outpoint (36), script length (1), script sig (107) and sequence (4)
+const P2PKH_INPUT_SIZE: u64 = 148;
+
+/// Struct that represents a UTXOs pending to be spent, along with the ids of the pending ones that are change:
+/// created by transactions that also spend outputs of the wallet
 #[derive(Debug, Clone)]
 pub struct PendingUtxo {
     pub utxos: HashMap<HashId, UtxoTransaction>,
     pub spent: HashMap<HashId, Vec<Index>>,
+    change: HashSet<HashId>,
 }
 
 impl PendingUtxo {
@@ -23,6 +33,7 @@ impl PendingUtxo {
         Self {
             utxos: HashMap::new(),
             spent: HashMap::new(), // is this really needed?
+            change: HashSet::new(),
         }
     }
 }
@@ -46,36 +57,90 @@ impl WalletUtxo {
 
     /// Returns the UTXOs that are available to be spent
     pub fn get_available_utxos(&self) -> Vec<(HashId, UtxoTransaction)> {
-        let mut available_utxos: Vec<(HashId, UtxoTransaction)> = Vec::new();
+        self.utxos
+            .iter()
+            .filter(|((utxo_id, index), _utxo)| !is_in(&self.spent, utxo_id, *index))
+            .map(|((utxo_id, _index), utxo)| (*utxo_id, utxo.clone()))
+            .collect()
+    }
 
-        for ((utxo_id, index), utxo) in &self.utxos {
-            if let Some(spent) = self.spent.get(utxo_id) {
-                if !spent.contains(index) {
-                    available_utxos.push((*utxo_id, utxo.clone()));
-                }
-            } else {
-                available_utxos.push((*utxo_id, utxo.clone()));
-            }
-        }
+    /// Returns the available UTXOs that no pending transaction spends, the ones a new transaction can spend
+    fn get_unspent_by_pending_utxos(&self) -> Vec<(HashId, UtxoTransaction)> {
+        let mut utxos = self.get_available_utxos();
+        utxos.retain(|(utxo_id, utxo)| !is_in(&self.pending.spent, utxo_id, utxo.index));
+        utxos
+    }
 
-        available_utxos
+    /// Returns the pending change outputs that aren't spent by other pending transactions
+    fn get_unspent_change(&self) -> Vec<(HashId, UtxoTransaction)> {
+        self.pending
+            .utxos
+            .iter()
+            .filter(|(utxo_id, utxo)| {
+                self.pending.change.contains(utxo_id) && !self.is_spent(utxo_id, utxo.index)
+            })
+            .map(|(utxo_id, utxo)| (*utxo_id, utxo.clone()))
+            .collect()
     }
 
     /// Returns the sum of the UTXOs that are available to be spent
     pub fn get_balance(&self) -> u64 {
-        let mut balance = 0;
+        self.get_available_utxos()
+            .iter()
+            .map(|(_utxo_id, utxo)| utxo.value)
+            .sum()
+    }
 
-        for ((utxo_id, index), utxo) in &self.utxos {
-            if let Some(spent) = self.spent.get(utxo_id) {
-                if !spent.contains(index) {
-                    balance += utxo.value;
-                }
-            } else {
-                balance += utxo.value;
+    /// Returns true if the output is spent, either in a block or by a pending transaction
+    fn is_spent(&self, utxo_id: &HashId, index: Index) -> bool {
+        is_in(&self.spent, utxo_id, index) || is_in(&self.pending.spent, utxo_id, index)
+    }
+
+    /// Returns the sum of the available UTXOs worth more than the fee needed to spend them,
+    /// at the given fee rate in satoshis per byte
+    pub fn get_economically_spendable_balance(&self, fee_rate: u64) -> u64 {
+        let spend_cost = fee_rate * P2PKH_INPUT_SIZE;
+        self.get_available_utxos()
+            .iter()
+            .map(|(_utxo_id, utxo)| utxo.value)
+            .filter(|value| *value > spend_cost)
+            .sum()
+    }
+
+    /// Serializes the confirmed and spent UTXOs, pending ones aren't persisted
+    fn to_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(to_varint(self.utxos.len() as u64));
+        for ((txid, index), utxo) in &self.utxos {
+            bytes.extend(txid.hash);
+            bytes.extend(index.to_le_bytes());
+            utxo.to_bytes(bytes);
+        }
+        bytes.extend(to_varint(self.spent.len() as u64));
+        for (txid, indexes) in &self.spent {
+            bytes.extend(txid.hash);
+            bytes.extend(to_varint(indexes.len() as u64));
+            for index in indexes {
+                bytes.extend(index.to_le_bytes());
             }
         }
+    }
 
-        balance
+    fn from_bytes(cursor: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        let mut wallet = Self::new();
+        for _ in 0..read_from_varint(cursor)? {
+            let outpoint = (read_hash(cursor)?, Index::from_le_stream(cursor)?);
+            wallet
+                .utxos
+                .insert(outpoint, UtxoTransaction::from_bytes(cursor)?);
+        }
+        for _ in 0..read_from_varint(cursor)? {
+            let txid = read_hash(cursor)?;
+            let indexes = (0..read_from_varint(cursor)?)
+                .map(|_| Index::from_le_stream(cursor))
+                .collect::<io::Result<Vec<Index>>>()?;
+            wallet.spent.insert(txid, indexes);
+        }
+        Ok(wallet)
     }
 
     /// Returns the sum of the UTXOs that are pending
@@ -91,38 +156,23 @@ impl WalletUtxo {
         balance
     }
 
-    /// Adds a UTXO to the wallet
+    /// Adds a UTXO to the wallet, returning true if it was pending and is now confirmed
     pub fn add_utxo(
         &mut self,
         utxo_id: HashId,
         utxo: UtxoTransaction,
         origin: TransactionOrigin,
         index: u32,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
-        active_addr: Option<&str>,
-    ) -> io::Result<()> {
+    ) -> bool {
         if origin == TransactionOrigin::Pending {
             self.add_pending_utxo(utxo_id, utxo);
-            return Ok(());
+            return false;
         }
 
-        if let Some(_pending) = self.pending.utxos.remove(&utxo_id) {
-            if let (Some(addr), Some(sender)) = (active_addr, ui_sender) {
-                if addr == utxo.get_address()? {
-                    println!("pending utxo is now confirmed!");
-                    let msg = format!("Transaction {} is now confirmed", utxo_id);
-                    let _ui = sender
-                        .send(GtkMessage::CreateNotification((
-                            gtk::MessageType::Info,
-                            "Confirmed".to_string(),
-                            msg,
-                        )))
-                        .map_err(to_io_err);
-                }
-            }
-        }
+        self.pending.change.remove(&utxo_id);
+        let confirmed = self.pending.utxos.remove(&utxo_id).is_some();
         self.utxos.insert((utxo_id, index), utxo);
-        Ok(())
+        confirmed
     }
 
     /// Adds a spent UTXO to the wallet
@@ -140,6 +190,29 @@ impl WalletUtxo {
         }
     }
 
+    /// Marks the pending UTXO as change, created by a transaction spending outputs of the wallet
+    pub fn mark_pending_change(&mut self, utxo_id: HashId) {
+        if self.pending.utxos.contains_key(&utxo_id) {
+            self.pending.change.insert(utxo_id);
+        }
+    }
+
+    /// Drops the pending UTXO created by an abandoned transaction
+    fn abandon_pending_utxo(&mut self, utxo_id: &HashId) {
+        self.pending.change.remove(utxo_id);
+        self.pending.utxos.remove(utxo_id);
+    }
+
+    /// Makes the output spent by an abandoned pending transaction available again
+    fn release_pending_spent(&mut self, utxo_id: &HashId, index: Index) {
+        if let Some(spent) = self.pending.spent.get_mut(utxo_id) {
+            spent.retain(|spent_index| *spent_index != index);
+            if spent.is_empty() {
+                self.pending.spent.remove(utxo_id);
+            }
+        }
+    }
+
     /// Adds a pending UTXO to the wallet
     fn add_pending_utxo(&mut self, utxo_id: HashId, utxo: UtxoTransaction) {
         self.pending.utxos.insert(utxo_id, utxo);
@@ -155,23 +228,157 @@ impl WalletUtxo {
     }
 }
 
-/// Struct that represents the UTXO set of the blockchain as a hashmap of wallets
+/// Returns true if the output is among the spent ones
+fn is_in(spent: &HashMap<HashId, Vec<Index>>, utxo_id: &HashId, index: Index) -> bool {
+    spent
+        .get(utxo_id)
+        .is_some_and(|indexes| indexes.contains(&index))
+}
+
+/// Struct that represents the UTXO set of the blockchain as a hashmap of wallets,
+/// along with the value and script of every confirmed unspent output by outpoint
+/// and the heights of the transactions confirmed in the last blocks, as deep as the coinbase maturity.
+/// The transactions are also kept by block, oldest first, so only the blocks buried too deep are visited to forget them.
 #[derive(Debug, Clone)]
 pub struct UtxoSet {
     pub set: HashMap<Address, WalletUtxo>,
+    pub outpoint_index: HashMap<OutPoint, (u64, Vec<u8>)>,
+    confirmation_heights: HashMap<HashId, usize>,
+    recent_blocks: VecDeque<(usize, Vec<HashId>)>,
+    tip_height: usize,
+    min_confirmations: usize,
+    spend_own_change: bool,
+    coinbase_maturity: usize,
+    address_format: AddressFormat,
 }
 
 impl UtxoSet {
     pub fn new() -> Self {
         Self {
             set: HashMap::new(),
+            outpoint_index: HashMap::new(),
+            confirmation_heights: HashMap::new(),
+            recent_blocks: VecDeque::new(),
+            tip_height: 0,
+            min_confirmations: 0,
+            spend_own_change: false,
+            coinbase_maturity: COINBASE_MATURITY,
+            address_format: TESTNET_ADDRESSES,
         }
     }
 
-    /// returns available utxos for a given address
+    /// Sets the blocks that must be built on top of the one confirming a coin before it's used to fund transactions
+    pub fn set_min_confirmations(&mut self, min_confirmations: usize) {
+        self.min_confirmations = min_confirmations;
+    }
+
+    /// Sets whether the unconfirmed change of the wallet's own transactions funds transactions, regardless of
+    /// the minimum confirmations
+    pub fn set_spend_own_change(&mut self, spend_own_change: bool) {
+        self.spend_own_change = spend_own_change;
+    }
+
+    /// Sets the blocks a coinbase output needs on top of it, including its own, before it can be spent
+    pub fn set_coinbase_maturity(&mut self, coinbase_maturity: usize) {
+        self.coinbase_maturity = coinbase_maturity;
+    }
+
+    /// Sets the format of the addresses outputs are kept under, the one of the network followed
+    pub fn set_address_format(&mut self, address_format: AddressFormat) {
+        self.address_format = address_format;
+    }
+
+    /// Returns the format of the addresses outputs are kept under
+    pub fn address_format(&self) -> &AddressFormat {
+        &self.address_format
+    }
+
+    /// Records the transactions of a connected block, forgetting the ones buried deeper than
+    /// the coinbase maturity and the minimum confirmations need to tell apart.
+    /// Transactions of blocks that weren't connected (like those only in a snapshot) are considered buried.
+    pub fn block_connected(&mut self, txids: impl IntoIterator<Item = HashId>, height: usize) {
+        self.tip_height = self.tip_height.max(height);
+        let txids: Vec<HashId> = txids.into_iter().collect();
+        for txid in &txids {
+            self.confirmation_heights.insert(*txid, height);
+        }
+        self.recent_blocks.push_back((height, txids));
+        let depth = self.coinbase_maturity.max(self.min_confirmations + 1);
+        while let Some((height, txids)) = self.recent_blocks.front() {
+            if self.tip_height + 1 < *height + depth {
+                break;
+            }
+            for txid in txids {
+                // a transaction confirmed again after a reorg is kept at its new height
+                if self.confirmation_heights.get(txid) == Some(height) {
+                    self.confirmation_heights.remove(txid);
+                }
+            }
+            self.recent_blocks.pop_front();
+        }
+    }
+
+    /// Returns the blocks built on top of the one confirming the transaction, which is 0 for the tip.
+    /// Transactions buried deeper than the tracked ones have `usize::MAX`.
+    pub fn confirmations(&self, txid: &HashId) -> usize {
+        self.confirmation_heights
+            .get(txid)
+            .map_or(usize::MAX, |height| self.tip_height.saturating_sub(*height))
+    }
+
+    fn is_immature(&self, txid: &HashId, utxo: &UtxoTransaction) -> bool {
+        utxo.coinbase && self.confirmations(txid) < self.coinbase_maturity.saturating_sub(1)
+    }
+
+    /// Returns true if the outpoint is a coinbase output that can't be spent in the next block
+    pub fn is_immature_coinbase(&self, outpoint: &OutPoint) -> bool {
+        self.get_txout(&outpoint.0, outpoint.1)
+            .is_some_and(|(_value, _lock, coinbase)| coinbase)
+            && self.confirmations(&outpoint.0) < self.coinbase_maturity.saturating_sub(1)
+    }
+
+    /// Returns the values of the available coinbase outputs of the wallet that can't be spent yet
+    fn immature_values(&self, wallet: &WalletUtxo) -> Vec<u64> {
+        wallet
+            .get_available_utxos()
+            .iter()
+            .filter(|(txid, utxo)| self.is_immature(txid, utxo))
+            .map(|(_txid, utxo)| utxo.value)
+            .collect()
+    }
+
+    /// Adds a confirmed output of the given address to the outpoint index, unless its spend was already seen
+    pub fn index_output(&mut self, address: &str, outpoint: OutPoint, value: u64, lock: &[u8]) {
+        let (txid, index) = &outpoint;
+        let is_spent = self.set.get(address).is_some_and(|wallet| {
+            wallet
+                .spent
+                .get(txid)
+                .is_some_and(|indexes| indexes.contains(index))
+        });
+        if !is_spent {
+            self.outpoint_index.insert(outpoint, (value, lock.to_vec()));
+        }
+    }
+
+    /// Returns the value and script of the output spent by an input, if it's a known unspent output
+    pub fn resolve_prevout(&self, outpoint: &OutPoint) -> Option<&(u64, Vec<u8>)> {
+        self.outpoint_index.get(outpoint)
+    }
+
+    /// returns available utxos for a given address, leaving out the immature coinbase outputs,
+    /// the ones with fewer confirmations than the minimum and the ones pending transactions spend, along
+    /// with the unconfirmed change of the wallet's own transactions if it's spendable
     pub fn get_wallet_available_utxos(&self, address: &str) -> Vec<(HashId, UtxoTransaction)> {
         if let Some(wallet) = self.set.get(address) {
-            return wallet.get_available_utxos();
+            let mut utxos = wallet.get_unspent_by_pending_utxos();
+            utxos.retain(|(txid, utxo)| {
+                !self.is_immature(txid, utxo) && self.confirmations(txid) >= self.min_confirmations
+            });
+            if self.spend_own_change {
+                utxos.extend(wallet.get_unspent_change());
+            }
+            return utxos;
         }
 
         // eval check that it's not on pending spent either
@@ -179,16 +386,127 @@ impl UtxoSet {
         Vec::new()
     }
 
-    /// Gets the wallet balance for a given address (sum of available utxos)
+    /// Gets the wallet balance for a given address (sum of available utxos, except immature coinbase outputs)
     // Maybe we should combine this method with the one bellow
     pub fn get_wallet_balance(&self, address: &str) -> u64 {
         if let Some(wallet) = self.set.get(address) {
-            return wallet.get_balance();
+            return wallet.get_balance() - self.immature_values(wallet).iter().sum::<u64>();
         }
 
         0
     }
 
+    /// Gets the wallet balance for a given address, excluding the utxos that cost more to spend than they're worth
+    pub fn get_wallet_spendable_balance(&self, address: &str, fee_rate: u64) -> u64 {
+        let spend_cost = fee_rate * P2PKH_INPUT_SIZE;
+        match self.set.get(address) {
+            Some(wallet) => {
+                let immature_values = self.immature_values(wallet).into_iter();
+                wallet.get_economically_spendable_balance(fee_rate)
+                    - immature_values
+                        .filter(|value| *value > spend_cost)
+                        .sum::<u64>()
+            }
+            None => 0,
+        }
+    }
+
+    /// Gets the sum of the coinbase outputs of the given address that haven't matured yet
+    pub fn get_immature_wallet_balance(&self, address: &str) -> u64 {
+        match self.set.get(address) {
+            Some(wallet) => self.immature_values(wallet).iter().sum(),
+            None => 0,
+        }
+    }
+
+    /// Returns the sum of the values of every confirmed unspent output, which can't exceed the coins issued
+    /// up to the tip (subsidies minus burned coins) unless outputs are being tracked wrong
+    pub fn total_value(&self) -> u64 {
+        self.outpoint_index
+            .values()
+            .map(|(value, _lock)| value)
+            .sum()
+    }
+
+    /// Returns the value, script and coinbase flag of a confirmed unspent output, None if it's spent or unknown.
+    pub fn get_txout(&self, txid: &HashId, vout: Index) -> Option<(u64, Vec<u8>, bool)> {
+        let (value, lock) = self.resolve_prevout(&(*txid, vout))?;
+        let coinbase = self
+            .set
+            .get(&lock_address(lock, &self.address_format))
+            .and_then(|wallet| wallet.utxos.get(&(*txid, vout)))
+            .is_some_and(|utxo| utxo.coinbase);
+        Some((*value, lock.clone(), coinbase))
+    }
+
+    /// Serializes the UTXOs of every address and the outpoint index
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = to_varint(self.set.len() as u64);
+        for (address, wallet) in &self.set {
+            write_script(&mut bytes, address.as_bytes());
+            wallet.to_bytes(&mut bytes);
+        }
+        bytes.extend(to_varint(self.outpoint_index.len() as u64));
+        for ((txid, index), (value, lock)) in &self.outpoint_index {
+            bytes.extend(txid.hash);
+            bytes.extend(index.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+            write_script(&mut bytes, lock);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(cursor: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        let mut utxo_set = Self::new();
+        for _ in 0..read_from_varint(cursor)? {
+            let address = String::from_utf8(read_script(cursor)?).map_err(to_io_err)?;
+            utxo_set
+                .set
+                .insert(address, WalletUtxo::from_bytes(cursor)?);
+        }
+        for _ in 0..read_from_varint(cursor)? {
+            let outpoint = (read_hash(cursor)?, Index::from_le_stream(cursor)?);
+            let value = u64::from_le_stream(cursor)?;
+            utxo_set
+                .outpoint_index
+                .insert(outpoint, (value, read_script(cursor)?));
+        }
+        Ok(utxo_set)
+    }
+
+    /// Undoes a pending transaction that was abandoned: drops the outputs it created and makes the ones it
+    /// spent available again
+    pub fn abandon_pending(&mut self, tx: &RawTransaction) {
+        let txid = tx.txid();
+        for output in &tx.tx_out {
+            if let Some(wallet) = self
+                .set
+                .get_mut(&lock_address(&output.pk_script, &self.address_format))
+            {
+                wallet.abandon_pending_utxo(&txid);
+            }
+        }
+        if let TxInputType::TxInput(inputs) = &tx.tx_in {
+            for input in inputs {
+                let Ok(address) = input.get_address(&self.address_format) else {
+                    continue;
+                };
+                if let Some(wallet) = self.set.get_mut(&address) {
+                    let (utxo_id, index) =
+                        (input.previous_output.hash, input.previous_output.index);
+                    wallet.release_pending_spent(&utxo_id, index);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the address received coins, whether they're confirmed, pending or already spent
+    pub fn has_received(&self, address: &str) -> bool {
+        self.set
+            .get(address)
+            .is_some_and(|wallet| !wallet.utxos.is_empty() || !wallet.pending.utxos.is_empty())
+    }
+
     /// Gets the wallet pending balance for a given address (sum of pending utxos)
     // Maybe we should combine this method with the one above
     pub fn get_pending_wallet_balance(&self, address: &str) -> u64 {
@@ -200,49 +518,84 @@ impl UtxoSet {
     }
 }
 
-/// Struct that represents a UTXO transaction (index, value, lock)
+/// Struct that represents a UTXO transaction (index, value, lock, created by a coinbase)
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UtxoTransaction {
     pub index: u32,
     pub value: u64,
     pub lock: Lock,
+    pub coinbase: bool,
 }
 
-/// Translate a P2PKH address to an address
-pub fn p2pkh_to_address(p2pkh: [u8; 20]) -> String {
-    let version_prefix: [u8; 1] = [0x6f];
+/// Translate a P2PKH address to an address of the given format
+pub fn p2pkh_to_address(p2pkh: [u8; 20], format: &AddressFormat) -> String {
+    base58check(format.p2pkh_version, &p2pkh)
+}
 
-    let hash = double_hash(&[&version_prefix[..], &p2pkh[..]].concat());
+/// Returns the address a lock pays to in the given format, which is the key of its output in the UTXO set.
+/// Locks without an address are kept together under "no_address".
+pub fn lock_address(lock: &[u8], format: &AddressFormat) -> String {
+    ScriptType::classify(lock)
+        .address(format)
+        .unwrap_or_else(|| "no_address".to_string())
+}
 
-    let checksum = &hash[..4];
+fn write_script(bytes: &mut Vec<u8>, script: &[u8]) {
+    bytes.extend(to_varint(script.len() as u64));
+    bytes.extend(script);
+}
 
-    let input = [&version_prefix[..], &p2pkh[..], checksum].concat();
+fn read_script(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<u8>> {
+    let mut script = vec![0_u8; read_from_varint(cursor)? as usize];
+    cursor.read_exact(&mut script)?;
+    Ok(script)
+}
 
-    bs58::encode(input).into_string()
+/// Writes a snapshot of the utxo set (serialized with `UtxoSet::to_bytes`) and the hash of the block it corresponds to.
+/// It's written to a temporary file which is then renamed, so a crash while writing keeps the previous snapshot.
+pub fn write_utxo_snapshot(file_name: &str, tip: HashId, utxo_set_bytes: &[u8]) -> io::Result<()> {
+    let temp_file_name = format!("{}.tmp", file_name);
+    let mut bytes = tip.hash.to_vec();
+    bytes.extend(utxo_set_bytes);
+    fs::write(&temp_file_name, bytes)?;
+    fs::rename(temp_file_name, file_name)
 }
 
-impl UtxoTransaction {
-    /// Returns the address of the UTXO
-    pub fn get_address(&self) -> io::Result<String> {
-        // iterate lock one byte at a time until 0x14 is found
-        let mut cursor = Cursor::new(self.lock.clone());
-
-        let buf = &mut [0; 1];
-        while buf[0] != 0x14 {
-            cursor.read_exact(buf)?;
-        }
+/// Reads a snapshot written by `write_utxo_snapshot`, returns the hash of its block and the utxo set
+pub fn read_utxo_snapshot(file_name: &str) -> io::Result<(HashId, UtxoSet)> {
+    let bytes = fs::read(file_name)?;
+    let mut cursor = Cursor::new(&bytes[..]);
+    let tip = read_hash(&mut cursor)?;
+    Ok((tip, UtxoSet::from_bytes(&mut cursor)?))
+}
 
-        let mut pk_hash = [0; 20];
-        cursor.read_exact(&mut pk_hash)?;
+impl UtxoTransaction {
+    fn to_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.index.to_le_bytes());
+        bytes.extend(self.value.to_le_bytes());
+        write_script(bytes, &self.lock);
+        bytes.push(self.coinbase as u8);
+    }
 
-        Ok(p2pkh_to_address(pk_hash))
+    fn from_bytes(cursor: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        Ok(Self {
+            index: u32::from_le_stream(cursor)?,
+            value: u64::from_le_stream(cursor)?,
+            lock: read_script(cursor)?,
+            coinbase: u8::from_le_stream(cursor)? != 0,
+        })
     }
 
     /// Returns the UTXO from a TxOutput
-    pub fn from_tx_output(tx_output: &TxOutput, index: u32) -> io::Result<Self> {
+    pub fn from_tx_output(tx_output: &TxOutput, index: u32, coinbase: bool) -> io::Result<Self> {
         let value = tx_output.value;
         let lock = tx_output.pk_script.clone();
-        Ok(Self { index, value, lock })
+        Ok(Self {
+            index,
+            value,
+            lock,
+            coinbase,
+        })
     }
 }
 
@@ -259,8 +612,10 @@ impl Utxo {
             transactions: Vec::new(),
         };
 
+        let coinbase = matches!(raw_transaction.tx_in, TxInputType::CoinBaseInput(_));
         for (index, tx_output) in raw_transaction.tx_out.iter().enumerate() {
-            let utxo_transaction = UtxoTransaction::from_tx_output(tx_output, index as u32)?;
+            let utxo_transaction =
+                UtxoTransaction::from_tx_output(tx_output, index as u32, coinbase)?;
             utxo.transactions.push(utxo_transaction);
         }
         Ok(utxo)
@@ -270,6 +625,10 @@ impl Utxo {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::raw_transaction::script::MAINNET_ADDRESSES;
+    use crate::test_fixtures::{
+        tx_from_hex, utxo, utxo_set_owning, ADDRESS, FUNDING_TX_HEX, SPENDING_TX_HEX,
+    };
     use crate::{raw_transaction::TransactionOrigin, utility::decode_hex};
 
     #[test]
@@ -278,18 +637,33 @@ mod tests {
             0x7a, 0xa8, 0x18, 0x46, 0x85, 0xca, 0x1f, 0x06, 0xf5, 0x43, 0xb6, 0x4a, 0x50, 0x2e,
             0xb3, 0xb6, 0x13, 0x5d, 0x67, 0x20,
         ];
-        let actual = p2pkh_to_address(p2pkh);
+        let actual = p2pkh_to_address(p2pkh, &TESTNET_ADDRESSES);
         let expected = "mrhW6tcF2LDetj3kJvaDTvatrVxNK64NXk".to_string();
         assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn test_output_to_a_mainnet_address_is_found_under_it() -> io::Result<()> {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.set_address_format(MAINNET_ADDRESSES);
+        // the funding output pays the key hash of `ADDRESS`, whose mainnet address has another version byte
+        let funding = tx_from_hex(FUNDING_TX_HEX)?;
+        funding.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+
+        let mainnet_address = "1KPg36FQjYsDSR2uZR78NBCsmcrcDrWGei";
+        assert_eq!(utxo_set.get_wallet_balance(mainnet_address), 1815366);
+        assert_eq!(utxo_set.get_wallet_balance(ADDRESS), 0);
+        let (_value, lock, _coinbase) = utxo_set.get_txout(&funding.txid(), 0).unwrap();
+        assert_eq!(lock_address(&lock, &MAINNET_ADDRESSES), mainnet_address);
+        Ok(())
+    }
+
     #[test]
     fn test_get_wallet_balance_from_various_tx() {
         let mut utxo_set = UtxoSet::new();
 
         // read tx_a that generates utxoA
-        let bytes = decode_hex("020000000001011216d10ae3afe6119529c0a01abe7833641e0e9d37eb880ae5547cfb7c6c7bca0000000000fdffffff0246b31b00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac731f2001020000001976a914d617966c3f29cfe50f7d9278dd3e460e3f084b7b88ac02473044022059570681a773748425ddd56156f6af3a0a781a33ae3c42c74fafd6cc2bd0acbc02200c4512c250f88653fae4d73e0cab419fa2ead01d6ba1c54edee69e15c1618638012103e7d8e9b09533ae390d0db3ad53cc050a54f89a987094bffac260f25912885b834b2c2500").unwrap();
-        let tx_a = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap();
+        let tx_a = tx_from_hex(FUNDING_TX_HEX).unwrap();
         tx_a.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
             .unwrap();
 
@@ -299,8 +673,7 @@ mod tests {
         );
 
         // read tx_b that generates utxoB, but spends utxoA
-        let bytes = decode_hex("0100000001881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff0210270000000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac96051a00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac00000000").unwrap();
-        let tx_b = RawTransaction::from_bytes(&mut Cursor::new(&bytes)).unwrap();
+        let tx_b = tx_from_hex(SPENDING_TX_HEX).unwrap();
         tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
             .unwrap();
 
@@ -308,6 +681,12 @@ mod tests {
             utxo_set.get_wallet_balance("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX"),
             1705366
         );
+        // utxoA was spent by tx_b while its outputs are unspent
+        assert_eq!(utxo_set.get_txout(&tx_a.txid(), 0), None);
+        let (value, script, coinbase) = utxo_set.get_txout(&tx_b.txid(), 1).unwrap();
+        assert_eq!(value, 1705366);
+        assert_eq!(script, tx_b.tx_out[1].pk_script);
+        assert!(!coinbase);
 
         // read pending tx_c that generates utxoC
         let bytes = decode_hex("020000000001011caf1fc6e053c6048b7108c3d22be0f57f95cc676ae688ef22e7793d853afd860100000000fdffffff02c81d1e00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ace6964cbe010000001976a914bbfb2d931dd19e1d3a503d0bfaba40cc2d3203fb88ac024730440220239f9521c30a2bb7df61011e0486712ab01a5fb43009ff872023051433f94a93022044f647c595894eba610b9089db5f34faea06aa0c58a684220c755fc38929f29201210394e3ae4d013b556c51d514a77ac5b5aae2f4e81edaedb192fc8b45b7a97d52ac9b2f2500").unwrap();
@@ -345,4 +724,179 @@ mod tests {
             1705366 + 1973704
         );
     }
+
+    #[test]
+    fn test_outpoint_index_resolves_and_removes_spent_prevouts() {
+        let mut utxo_set = UtxoSet::new();
+        let tx_a = tx_from_hex(FUNDING_TX_HEX).unwrap();
+        tx_a.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+
+        let prevout = (tx_a.txid(), 0);
+        let (value, script) = utxo_set.resolve_prevout(&prevout).unwrap();
+        assert_eq!(*value, 1815366);
+        assert_eq!(*script, tx_a.tx_out[0].pk_script);
+
+        // tx_b spends the first output of tx_a
+        let tx_b = tx_from_hex(SPENDING_TX_HEX).unwrap();
+        tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Pending, None, None)
+            .unwrap();
+        assert!(utxo_set.resolve_prevout(&prevout).is_some());
+
+        tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+        assert!(utxo_set.resolve_prevout(&prevout).is_none());
+        assert!(utxo_set.resolve_prevout(&(tx_a.txid(), 1)).is_some());
+    }
+
+    #[test]
+    fn test_total_value_is_outputs_minus_spends() {
+        let mut utxo_set = UtxoSet::new();
+        assert_eq!(utxo_set.total_value(), 0);
+        let tx_a = tx_from_hex(FUNDING_TX_HEX).unwrap();
+        tx_a.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+        assert_eq!(utxo_set.total_value(), tx_a.get_total_output_value());
+
+        // tx_b spends the first output of tx_a, which only counts once it's in a block
+        let tx_b = tx_from_hex(SPENDING_TX_HEX).unwrap();
+        tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Pending, None, None)
+            .unwrap();
+        assert_eq!(utxo_set.total_value(), tx_a.get_total_output_value());
+
+        tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+        let spent = tx_a.tx_out[0].value;
+        assert_eq!(
+            utxo_set.total_value(),
+            tx_a.get_total_output_value() + tx_b.get_total_output_value() - spent
+        );
+    }
+
+    #[test]
+    fn test_utxo_in_tip_block_excluded_below_min_confirmations() {
+        let address = ADDRESS;
+        let txid = HashId::new([4; 32]);
+        let mut utxo_set = utxo_set_owning(txid, vec![utxo(0, 100_000, false)]);
+        utxo_set.set_min_confirmations(1);
+
+        // confirmed in the tip block, so it has no confirmations on top yet
+        utxo_set.block_connected([txid], 500);
+        assert!(utxo_set.get_wallet_available_utxos(address).is_empty());
+        assert_eq!(utxo_set.get_wallet_balance(address), 100_000);
+        utxo_set.set_min_confirmations(0);
+        assert_eq!(utxo_set.get_wallet_available_utxos(address).len(), 1);
+
+        utxo_set.set_min_confirmations(1);
+        utxo_set.block_connected([HashId::new([5; 32])], 501);
+        assert_eq!(utxo_set.get_wallet_available_utxos(address).len(), 1);
+    }
+
+    #[test]
+    fn test_own_unconfirmed_change_is_only_spent_if_enabled() -> io::Result<()> {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.set_min_confirmations(1);
+        let funding = tx_from_hex(FUNDING_TX_HEX)?;
+        funding.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+        assert_eq!(utxo_set.get_wallet_available_utxos(ADDRESS).len(), 1);
+
+        // the spending tx pays its change back to the address it spends from
+        let spending = tx_from_hex(SPENDING_TX_HEX)?;
+        spending.generate_utxo(&mut utxo_set, TransactionOrigin::Pending, None, None)?;
+        assert!(utxo_set.get_wallet_available_utxos(ADDRESS).is_empty());
+        utxo_set.set_spend_own_change(true);
+        let available = utxo_set.get_wallet_available_utxos(ADDRESS);
+        assert_eq!(available.len(), 1);
+        assert_eq!(available[0].0, spending.txid());
+        assert_eq!(available[0].1.value, 1705366);
+
+        // once confirmed in the tip block it's no longer change, but a coin without confirmations on top
+        spending.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+        utxo_set.block_connected([spending.txid()], 10);
+        assert!(utxo_set.get_wallet_available_utxos(ADDRESS).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_immature_coinbase_is_neither_available_nor_pending() {
+        let address = ADDRESS;
+        let coinbase_txid = HashId::new([1; 32]);
+        let mut utxo_set = utxo_set_owning(coinbase_txid, vec![utxo(0, 5_000_000_000, true)]);
+        utxo_set.block_connected([coinbase_txid], 1000);
+
+        assert_eq!(utxo_set.get_immature_wallet_balance(address), 5_000_000_000);
+        assert_eq!(utxo_set.get_wallet_balance(address), 0);
+        assert_eq!(utxo_set.get_wallet_spendable_balance(address, 1), 0);
+        assert_eq!(utxo_set.get_pending_wallet_balance(address), 0);
+        assert!(utxo_set.get_wallet_available_utxos(address).is_empty());
+
+        // a depth of 99 blocks isn't enough, the 100th makes it spendable
+        utxo_set.block_connected([HashId::new([2; 32])], 1098);
+        assert_eq!(utxo_set.get_immature_wallet_balance(address), 5_000_000_000);
+        utxo_set.block_connected([HashId::new([3; 32])], 1099);
+        assert_eq!(utxo_set.get_immature_wallet_balance(address), 0);
+        assert_eq!(utxo_set.get_wallet_balance(address), 5_000_000_000);
+        assert_eq!(utxo_set.get_wallet_available_utxos(address).len(), 1);
+    }
+
+    #[test]
+    fn test_coinbase_spendable_one_block_later_with_maturity_of_one() {
+        let address = ADDRESS;
+        let coinbase_txid = HashId::new([1; 32]);
+        let mut utxo_set = utxo_set_owning(coinbase_txid, vec![utxo(0, 5_000_000_000, true)]);
+        utxo_set.set_coinbase_maturity(1);
+
+        // once its block is the tip, a spend of the coinbase can go in the next block
+        utxo_set.block_connected([coinbase_txid], 1000);
+        assert_eq!(utxo_set.get_immature_wallet_balance(address), 0);
+        assert_eq!(utxo_set.get_wallet_balance(address), 5_000_000_000);
+        assert_eq!(utxo_set.get_wallet_available_utxos(address).len(), 1);
+    }
+
+    #[test]
+    fn test_dust_utxo_excluded_from_spendable_balance() {
+        let mut wallet = WalletUtxo::new();
+        let txid = HashId::new([1; 32]);
+        wallet.utxos.insert((txid, 0), utxo(0, 100, false));
+        wallet.utxos.insert((txid, 1), utxo(1, 50_000, false));
+        wallet.utxos.insert((txid, 2), utxo(2, 20_000, false));
+        wallet.spent.insert(txid, vec![2]);
+
+        assert_eq!(wallet.get_balance(), 50_100);
+        // spending an input costs 148 bytes, 14800 satoshis at 100 sat/byte
+        assert_eq!(wallet.get_economically_spendable_balance(100), 50_000);
+        assert_eq!(wallet.get_economically_spendable_balance(0), 50_100);
+        assert_eq!(wallet.get_economically_spendable_balance(1000), 0);
+    }
+
+    #[test]
+    fn test_utxo_snapshot_roundtrip() -> io::Result<()> {
+        let mut utxo_set = UtxoSet::new();
+        let tx_a = tx_from_hex(FUNDING_TX_HEX)?;
+        tx_a.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+        let tx_b = tx_from_hex(SPENDING_TX_HEX)?;
+        tx_b.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+
+        let file_name = "test_utxo_snapshot.dat";
+        let tip = HashId::new([9; 32]);
+        write_utxo_snapshot(file_name, tip, &utxo_set.to_bytes())?;
+        let snapshot = read_utxo_snapshot(file_name);
+        fs::remove_file(file_name)?;
+        let (snapshot_tip, snapshot_set) = snapshot?;
+
+        assert_eq!(snapshot_tip, tip);
+        let address = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX";
+        assert!(snapshot_set.get_wallet_balance(address) > 0);
+        assert_eq!(
+            snapshot_set.get_wallet_balance(address),
+            utxo_set.get_wallet_balance(address)
+        );
+        assert_eq!(snapshot_set.get_txout(&tx_a.txid(), 0), None);
+        assert_eq!(
+            snapshot_set.get_txout(&tx_b.txid(), 1),
+            utxo_set.get_txout(&tx_b.txid(), 1)
+        );
+        assert_eq!(snapshot_set.to_bytes().len(), utxo_set.to_bytes().len());
+        Ok(())
+    }
 }
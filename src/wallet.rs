@@ -1,28 +1,122 @@
 use crate::config::Config;
-use crate::interface::components::overview_panel::TransactionDisplayInfo;
+use crate::hd_keys::{ExtendedKey, HARDENED};
+use crate::interface::components::overview_panel::{TransactionDisplayInfo, TransactionRole};
 use crate::interface::components::send_panel::TransactionInfo;
-use crate::interface::GtkMessage;
-use crate::messages::HashId;
+use crate::interface::{NotificationKind, UiNotifier};
+use crate::messages::{Block, HashId};
+use crate::network_params::{Network, NetworkParams};
 use crate::raw_transaction::TransactionOrigin;
 use crate::raw_transaction::{
+    partial::{PartialTransaction, Prevout},
+    script::{
+        decode_segwit_address, push_data, AddressFormat, MAINNET_P2PKH_VERSION,
+        MAINNET_P2SH_VERSION, OP_0, OP_EQUAL, OP_HASH160, P2PKH_VERSION, P2SH_VERSION,
+        TESTNET_ADDRESSES,
+    },
     tx_input::{Outpoint, TxInput, TxInputType},
     tx_output::TxOutput,
     RawTransaction,
 };
-use crate::utility::{double_hash, to_io_err};
-use crate::utxo::{Lock, UtxoSet, UtxoTransaction};
+use crate::utility::{decode_hex, double_hash, to_io_err};
+use crate::utxo::{lock_address, UtxoSet, UtxoTransaction};
 use bitcoin_hashes::{hash160, Hash};
-use gtk::glib::SyncSender;
 use rand::rngs::OsRng;
-use secp256k1::{Secp256k1, SecretKey};
-use std::collections::HashMap;
+use secp256k1::{All, Secp256k1, SecretKey};
+use std::collections::{HashMap, HashSet};
 use std::fs::DirEntry;
 use std::io::{self, Write};
 use std::str::FromStr;
 
+/// Upper bound for the size of a signed P2PKH script_sig (push 72 byte signature + push 33 byte key)
+const P2PKH_SCRIPT_SIG_SIZE: usize = 107;
+
+/// Change below this value costs more to spend than it's worth (the dust limit of P2PKH outputs),
+/// so it's left to the fee instead of creating an output for it
+const DUST_CHANGE: u64 = 546;
+
+/// Purpose of the BIP44 paths the keys of HD accounts are derived at, and the coin types of mainnet coins and
+/// of those of the test networks
+const BIP44_PURPOSE: u32 = 44;
+const BIP44_MAINNET_COIN: u32 = 0;
+const BIP44_TESTNET_COIN: u32 = 1;
+/// Chains of the keys of an account that receive payments and that receive its change (BIP44)
+const RECEIVE_CHAIN: u32 = 0;
+const CHANGE_CHAIN: u32 = 1;
+
+fn overflow_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Amounts and fee add up to more than the maximum value",
+    )
+}
+
+/// Returns the sum of the amounts sent to the recipients, failing if it overflows
+fn recipients_amount(transaction_info: &TransactionInfo) -> io::Result<u64> {
+    transaction_info
+        .recipients
+        .iter()
+        .try_fold(0u64, |total, (_addr, _label, amount)| {
+            total.checked_add(*amount)
+        })
+        .ok_or_else(overflow_error)
+}
+
+/// Returns the change left after paying the amount and fee out of the used balance, which is 0 if it's dust
+fn change_value(used_balance: u64, amount: u64, fee: u64) -> io::Result<u64> {
+    let needed = amount.checked_add(fee).ok_or_else(overflow_error)?;
+    let change = used_balance.checked_sub(needed).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "Selected coins don't cover the amount and fee",
+        )
+    })?;
+    Ok(if change < DUST_CHANGE { 0 } else { change })
+}
+
+/// Decodes a base58check address into its version byte, the hash it pays to and its checksum. Fails if the
+/// checksum doesn't match, as it doesn't for a mistyped address, or the version isn't a P2PKH or P2SH one.
+/// Whether the version is the one of the network followed is checked by `check_recipient_networks`.
 fn hash_address(address: &str) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
     let bytes = bs58::decode(address).into_vec().map_err(to_io_err)?;
-    Ok(bytes)
+    if bytes.len() != 25 {
+        return Err(invalid("Invalid address length"));
+    }
+    let checksum = double_hash(&bytes[..21]);
+    if checksum[..4] != bytes[21..] {
+        return Err(invalid("Invalid address checksum"));
+    }
+    match bytes[0] {
+        P2PKH_VERSION | P2SH_VERSION | MAINNET_P2PKH_VERSION | MAINNET_P2SH_VERSION => Ok(bytes),
+        _ => Err(invalid("Unknown address version")),
+    }
+}
+
+/// Checks the recipients of the transaction are addresses of the network followed, by their base58 version or
+/// segwit prefix, since paying one of another network locks the coins to a script its owner doesn't expect here
+pub fn check_recipient_networks(
+    transaction_info: &TransactionInfo,
+    network_params: &NetworkParams,
+) -> io::Result<()> {
+    for (address, _label, _amount) in &transaction_info.recipients {
+        let of_network = match decode_segwit_address(address) {
+            Ok((hrp, _program)) => hrp == network_params.address_format.bech32_hrp,
+            Err(_) => {
+                let format = &network_params.address_format;
+                [format.p2pkh_version, format.p2sh_version].contains(&hash_address(address)?[0])
+            }
+        };
+        if !of_network {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Address {} isn't a {:?} address",
+                    address, network_params.network
+                ),
+            ));
+        }
+    }
+    Ok(())
 }
 
 fn build_p2pkh_script(hashed_pk: Vec<u8>) -> io::Result<Vec<u8>> {
@@ -41,57 +135,283 @@ fn build_p2pkh_script(hashed_pk: Vec<u8>) -> io::Result<Vec<u8>> {
     Ok(pk_script)
 }
 
+/// Builds the output script paying to the address, P2WPKH or P2WSH for segwit addresses and P2PKH or P2SH
+/// for base58check ones, depending on their version
+fn build_output_script(address: &str) -> io::Result<Vec<u8>> {
+    if let Ok((_hrp, program)) = decode_segwit_address(address) {
+        return Ok([&[OP_0][..], &push_data(&program)].concat());
+    }
+    let bytes = hash_address(address)?;
+    match bytes[0] {
+        P2SH_VERSION | MAINNET_P2SH_VERSION => {
+            Ok([&[OP_HASH160][..], &push_data(&bytes[1..21]), &[OP_EQUAL]].concat())
+        }
+        _ => build_p2pkh_script(bytes),
+    }
+}
+
+/// Checks that the address is a valid segwit or base58check encoded address
+fn validate_address(address: &str) -> io::Result<()> {
+    if decode_segwit_address(address).is_ok() {
+        return Ok(());
+    }
+    hash_address(address).map(|_| ())
+}
+
 /// The Wallet struct is responsible for managing the wallet's secret key and address can be used to send transactions.
+/// Watch-only wallets have no secret key, they only track the balance and history of an address.
 #[derive(PartialEq, Debug, Clone)]
 pub struct Wallet {
-    pub secret_key: SecretKey,
+    pub secret_key: Option<SecretKey>,
     pub address: String,
     pub history: Vec<TransactionDisplayInfo>,
+    fee_to_confirm: Option<TransactionInfo>, // last transaction refused for its fee, sending it again pays it
+    change_address: Option<String>, // where HD accounts send their change, instead of back to the address
+    change_key: Option<SecretKey>,  // key of the change address, if the account holds its coins
 }
 
 impl Wallet {
+    /// Returns the latest n transactions of the history, oldest first. All of them if there are fewer than n
     pub fn get_last_n_transactions(&self, n: usize) -> Vec<TransactionDisplayInfo> {
-        let mut history = self.history.clone();
-        history.reverse();
-        history.truncate(n);
-        history.reverse();
-        history
+        self.history[self.history.len().saturating_sub(n)..].to_vec()
     }
 
+    /// Adds a transaction to the history. A transaction already in it is never added twice (a peer may
+    /// relay back one we sent), and a pending one is marked as confirmed once it's found in a block.
+    /// An abandoned transaction seen again is pending or confirmed once more.
     pub fn update_history(&mut self, transaction_info: TransactionDisplayInfo) {
-        if transaction_info.origin == TransactionOrigin::Block {
-            // try to remove the pending transaction from the history
-            // completely not optimal as it could be a hashmap
-            for (i, tx) in self.history.iter_mut().enumerate() {
-                if tx.hash == transaction_info.hash {
-                    self.history[i].origin = TransactionOrigin::Block;
-                    return;
-                }
+        // completely not optimal as it could be a hashmap
+        if let Some(tx) = self
+            .history
+            .iter_mut()
+            .find(|tx| tx.hash == transaction_info.hash)
+        {
+            if transaction_info.origin == TransactionOrigin::Block
+                || tx.origin == TransactionOrigin::Abandoned
+            {
+                tx.origin = transaction_info.origin;
             }
+            return;
         }
         self.history.push(transaction_info);
     }
 
-    fn get_address_from_secret_key(secret_key: &SecretKey) -> String {
+    /// Marks the pending transaction as abandoned in the history
+    pub fn abandon(&mut self, txid: &HashId) {
+        for tx in self.history.iter_mut() {
+            if tx.hash == *txid && tx.origin == TransactionOrigin::Pending {
+                tx.origin = TransactionOrigin::Abandoned;
+            }
+        }
+    }
+
+    /// Adds the transactions of the given blocks involving the address to the history, for a wallet
+    /// imported after they were processed. Returns the number of transactions found.
+    pub fn rescan<'a>(
+        &mut self,
+        blocks: impl IntoIterator<Item = &'a Block>,
+        utxo_set: &mut UtxoSet,
+    ) -> usize {
+        let mut found = 0;
+        for block in blocks {
+            for txn in &block.txns {
+                if txn.address_is_involved(&self.addresses(), utxo_set.address_format()) {
+                    let txn_info = self.transaction_info(|address| {
+                        txn.transaction_info_for(address, block.header.timestamp, utxo_set)
+                    });
+                    self.update_history(txn_info);
+                    found += 1;
+                }
+            }
+        }
+        found
+    }
+
+    /// Clears the history and rebuilds it from the given blocks, oldest first, fixing the entries that drifted
+    /// from the chain, like pending ones whose confirmation was missed. Returns the number of transactions found.
+    pub fn rebuild_history<'a>(
+        &mut self,
+        blocks: impl IntoIterator<Item = &'a Block>,
+        utxo_set: &mut UtxoSet,
+    ) -> usize {
+        self.history.clear();
+        self.rescan(blocks, utxo_set)
+    }
+
+    /// Returns the addresses whose coins the wallet holds: its own and, if it holds the coins of its change,
+    /// the change address of the HD account
+    pub fn addresses(&self) -> Vec<&str> {
+        let mut addresses = vec![self.address.as_str()];
+        if let (Some(change_address), Some(_)) = (&self.change_address, self.change_key) {
+            addresses.push(change_address);
+        }
+        addresses
+    }
+
+    /// Returns the balance of the addresses of the wallet
+    fn balance(&self, utxo_set: &UtxoSet) -> u64 {
+        self.addresses()
+            .into_iter()
+            .map(|address| utxo_set.get_wallet_balance(address))
+            .sum()
+    }
+
+    /// Returns true if any of the addresses of the wallet is among the given ones, those a transaction involves
+    pub fn is_involved(&self, involved: &HashSet<String>) -> bool {
+        self.addresses()
+            .iter()
+            .any(|address| involved.contains(*address))
+    }
+
+    /// Returns the history entry of the transaction, adding up the value it moves from and to each address
+    /// of the wallet, so change sent to the change address isn't counted as spent
+    pub fn transaction_info(
+        &self,
+        mut info_for: impl FnMut(&str) -> TransactionDisplayInfo,
+    ) -> TransactionDisplayInfo {
+        let mut addresses = self.addresses().into_iter();
+        let mut info = info_for(addresses.next().unwrap_or_default());
+        for address in addresses {
+            let other = info_for(address);
+            info.amount += other.amount;
+            if other.role == TransactionRole::Sender {
+                info.role = TransactionRole::Sender;
+            }
+        }
+        info
+    }
+
+    /// Returns the key signing the outputs of the given address of the wallet
+    fn key_for(&self, address: &str) -> Option<SecretKey> {
+        if address == self.address {
+            return self.secret_key;
+        }
+        self.change_key
+            .filter(|_| self.change_address.as_deref() == Some(address))
+    }
+
+    fn get_address_from_secret_key(secret_key: &SecretKey, version: u8) -> String {
         let secp = Secp256k1::new();
         let pubkey = secret_key.public_key(&secp).serialize();
         let h160 = hash160::Hash::hash(&pubkey).to_byte_array();
-        let version_prefix: [u8; 1] = [0x6f];
+        let version_prefix: [u8; 1] = [version];
         let hash = double_hash(&[&version_prefix[..], &h160[..]].concat());
         let checksum = &hash[..4];
         let input = [&version_prefix[..], &h160[..], checksum].concat();
         bs58::encode(input).into_string()
     }
 
-    /// Creates a new Wallet with a random secret key and address.
-    pub fn new() -> Self {
+    /// Creates the wallet of the given key, whose address has the given P2PKH version byte
+    fn from_key(secret_key: SecretKey, version: u8) -> Self {
+        Self {
+            secret_key: Some(secret_key),
+            address: Self::get_address_from_secret_key(&secret_key, version),
+            history: Vec::new(),
+            fee_to_confirm: None,
+            change_address: None,
+            change_key: None,
+        }
+    }
+
+    /// Creates a new Wallet with a random secret key and its address in the given format.
+    pub fn new(format: &AddressFormat) -> Self {
         let secp = Secp256k1::new();
         let (sk, _addr) = secp.generate_keypair(&mut OsRng);
-        Self {
-            secret_key: sk,
-            address: Self::get_address_from_secret_key(&sk),
+        Self::from_key(sk, format.p2pkh_version)
+    }
+
+    /// Creates the wallet of the given hex secret key, with its address in the given format
+    pub fn from_secret_key(secret_key: &str, format: &AddressFormat) -> io::Result<Self> {
+        let key = SecretKey::from_str(secret_key).map_err(to_io_err)?;
+        Ok(Self::from_key(key, format.p2pkh_version))
+    }
+
+    /// Creates the wallet of the given account of an HD seed, holding the first keys of its receive chain
+    /// (m/44'/coin'/account'/0/0) and of its change chain (m/44'/coin'/account'/1/0), where its change is sent.
+    /// The coin type is 0 on mainnet and 1 on the test networks, and the addresses have the version of the network.
+    /// Funds kept apart in accounts are all recovered from the one seed.
+    pub fn from_seed(
+        seed: &[u8],
+        account: u32,
+        network_params: &NetworkParams,
+    ) -> io::Result<Self> {
+        if account >= HARDENED {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Account index {} is too large", account),
+            ));
+        }
+        let coin = match network_params.network {
+            Network::Mainnet => BIP44_MAINNET_COIN,
+            _ => BIP44_TESTNET_COIN,
+        };
+        let account_key = ExtendedKey::from_seed(seed)?.derive_path(&[
+            BIP44_PURPOSE | HARDENED,
+            coin | HARDENED,
+            account | HARDENED,
+        ])?;
+        let receive_key = account_key.derive_path(&[RECEIVE_CHAIN, 0])?.secret_key;
+        let change_key = account_key.derive_path(&[CHANGE_CHAIN, 0])?.secret_key;
+        let version = network_params.address_format.p2pkh_version;
+        let mut wallet = Self::from_key(receive_key, version);
+        wallet.change_address = Some(Self::get_address_from_secret_key(&change_key, version));
+        wallet.change_key = Some(change_key);
+        Ok(wallet)
+    }
+
+    /// Hands the coins of the change address of the HD account to a wallet of their own, which is returned,
+    /// so the balance of the account only shows those of its receive address. Its change is still sent there.
+    pub fn split_change(&mut self) -> Option<Wallet> {
+        let change_key = self.change_key.take()?;
+        Some(Self {
+            secret_key: Some(change_key),
+            address: self.change_address.clone()?,
             history: Vec::new(),
+            fee_to_confirm: None,
+            change_address: None,
+            change_key: None,
+        })
+    }
+
+    /// Creates a watch-only wallet for the given address, which can't sign transactions.
+    pub fn watch_only(address: &str) -> io::Result<Self> {
+        validate_address(address)?;
+        Ok(Self {
+            secret_key: None,
+            address: address.to_string(),
+            history: Vec::new(),
+            fee_to_confirm: None,
+            change_address: None,
+            change_key: None,
+        })
+    }
+
+    pub fn is_watch_only(&self) -> bool {
+        self.secret_key.is_none()
+    }
+
+    /// Reads a newline-delimited file of addresses to watch.
+    /// Returns the watch-only wallets of the valid addresses and the invalid addresses found.
+    pub fn watch_only_from_file(path: &str) -> io::Result<(Vec<Wallet>, Vec<String>)> {
+        let content = std::fs::read_to_string(path)?;
+        let mut wallets: Vec<Wallet> = Vec::new();
+        let mut invalid: Vec<String> = Vec::new();
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match Self::watch_only(line) {
+                Ok(wallet) => wallets.push(wallet),
+                Err(..) => invalid.push(line.to_string()),
+            }
         }
+        Ok((wallets, invalid))
+    }
+
+    /// Adds the address to the newline-delimited file of addresses to watch, creating it if needed
+    pub fn save_watch_address(path: &str, address: &str) -> io::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", address)
     }
 
     /// Saves a wallet to a file in the wallets directory.
@@ -102,47 +422,40 @@ impl Wallet {
         let file = std::fs::File::create(file_path)?;
         let mut writer = std::io::BufWriter::new(file);
 
-        let secret_key = self.secret_key.display_secret().to_string();
+        let secret_key = match &self.secret_key {
+            Some(secret_key) => secret_key.display_secret().to_string(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Watch-only wallets are not saved to disk",
+                ))
+            }
+        };
         writer.write_all(secret_key.as_bytes())
     }
 
-    fn create_and_save(
-        config: &Config,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
-    ) -> io::Result<Self> {
-        let wallet = Wallet::new();
+    fn create_and_save(config: &Config, ui_sender: Option<&dyn UiNotifier>) -> io::Result<Self> {
+        let wallet = Wallet::new(&config.get_network_params().address_format);
         if let Some(sender) = ui_sender {
-            _ = sender.send(GtkMessage::CreateNotification((
-                    gtk::MessageType::Info,
-                    "New wallet created".to_string(),
-                    format!(
-                        "No wallet found, created new wallet of address: {}\nStored on wallets directory",
-                        wallet.address
-                    ),
-                )));
+            let msg = format!(
+                "No wallet found, created new wallet of address: {}\nStored on wallets directory",
+                wallet.address
+            );
+            _ = sender.notify(NotificationKind::Info, "New wallet created", &msg);
             Self::update_ui_wallet_entry(sender, &wallet.address, true)
         }
         wallet.save_to_disk(config)?;
         Ok(wallet)
     }
 
-    pub fn update_ui_wallet_entry(
-        sender: &SyncSender<GtkMessage>,
-        address: &str,
-        is_main_wallet: bool,
-    ) {
-        let _ = sender
-            .send(GtkMessage::AddWalletEntry(
-                address.to_string(),
-                is_main_wallet,
-            ))
-            .map_err(to_io_err);
+    pub fn update_ui_wallet_entry(sender: &dyn UiNotifier, address: &str, is_main_wallet: bool) {
+        let _ = sender.add_wallet_entry(address, is_main_wallet);
     }
 
     fn from_dir_entry(
         file: DirEntry,
         config: &Config,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
+        ui_sender: Option<&dyn UiNotifier>,
         wallets: &mut HashMap<String, Wallet>,
         active_wallet: &mut String,
     ) -> io::Result<()> {
@@ -150,20 +463,51 @@ impl Wallet {
             Some(p) => p.to_string(),
             None => return Ok(()),
         };
-        let wallet = Config::wallet_from_file(path_string)?;
+        let wallet = config.wallet_from_file(path_string)?;
         if let Some(w) = wallet {
-            let mut is_main_wallet = false;
-            if w.address == config.get_default_wallet_addr() || wallets.is_empty() {
-                *active_wallet = w.address.clone();
-                is_main_wallet = true;
-            }
+            Self::add_wallet(w, config, ui_sender, wallets, active_wallet);
+        }
+        Ok(())
+    }
 
-            if let Some(sender) = ui_sender {
-                Self::update_ui_wallet_entry(sender, &w.address, is_main_wallet)
+    /// Adds the wallet to the loaded ones and to the wallet switcher, making it the active one if it's the
+    /// default wallet or the first one loaded
+    fn add_wallet(
+        w: Wallet,
+        config: &Config,
+        ui_sender: Option<&dyn UiNotifier>,
+        wallets: &mut HashMap<String, Wallet>,
+        active_wallet: &mut String,
+    ) {
+        let mut is_main_wallet = false;
+        if w.address == config.get_default_wallet_addr() || wallets.is_empty() {
+            *active_wallet = w.address.clone();
+            is_main_wallet = true;
+        }
+
+        if let Some(sender) = ui_sender {
+            Self::update_ui_wallet_entry(sender, &w.address, is_main_wallet)
+        }
+        wallets.insert(w.address.clone(), w);
+    }
+
+    /// Returns the wallets of the configured number of accounts of the HD seed, none if there's no seed file.
+    /// The coins of their change addresses are wallets of their own unless their balance includes the change.
+    fn hd_accounts(config: &Config) -> io::Result<Vec<Wallet>> {
+        let seed_file = config.get_hd_seed_file();
+        if seed_file.is_empty() {
+            return Ok(vec![]);
+        }
+        let seed = decode_hex(std::fs::read_to_string(seed_file)?.trim()).map_err(to_io_err)?;
+        let mut wallets = vec![];
+        for account in 0..config.get_hd_accounts() {
+            let mut wallet = Self::from_seed(&seed, account, config.get_network_params())?;
+            if !config.get_hd_aggregate_change() {
+                wallets.extend(wallet.split_change());
             }
-            wallets.insert(w.address.clone(), w);
+            wallets.push(wallet);
         }
-        Ok(())
+        Ok(wallets)
     }
 
     /// Iterates all files in the wallet directory
@@ -171,7 +515,7 @@ impl Wallet {
     /// and the first wallet as the default wallet
     pub fn init_all(
         config: &Config,
-        ui_sender: Option<&SyncSender<GtkMessage>>,
+        ui_sender: Option<&dyn UiNotifier>,
     ) -> io::Result<(String, HashMap<String, Wallet>)> {
         let mut wallets: HashMap<String, Wallet> = HashMap::new();
         let mut active_wallet: String = String::default();
@@ -181,6 +525,9 @@ impl Wallet {
         for file in dir.flatten() {
             Self::from_dir_entry(file, config, ui_sender, &mut wallets, &mut active_wallet)?;
         }
+        for wallet in Self::hd_accounts(config)? {
+            Self::add_wallet(wallet, config, ui_sender, &mut wallets, &mut active_wallet);
+        }
 
         if wallets.is_empty() {
             let wallet = Self::create_and_save(config, ui_sender)?;
@@ -201,40 +548,79 @@ impl Wallet {
     fn fill_needed(
         amount: u64,
         available_utxos: Vec<(HashId, UtxoTransaction)>,
-    ) -> (Vec<(HashId, UtxoTransaction, Lock)>, u64) {
-        let mut used_utxos: Vec<(HashId, UtxoTransaction, Lock)> = Vec::new();
+    ) -> io::Result<(Vec<(HashId, UtxoTransaction)>, u64)> {
+        let mut used_utxos: Vec<(HashId, UtxoTransaction)> = Vec::new();
         let mut used_balance: u64 = 0;
         for (utxo_id, utxo) in available_utxos {
-            used_balance += utxo.value;
-            let lock = utxo.lock.clone();
-            used_utxos.push((utxo_id, utxo, lock));
+            used_balance = used_balance
+                .checked_add(utxo.value)
+                .ok_or_else(overflow_error)?;
+            used_utxos.push((utxo_id, utxo));
             if used_balance >= amount {
-                break;
+                return Ok((used_utxos, used_balance));
             }
         }
 
-        (used_utxos, used_balance)
+        // the balance was checked, but some utxos may lack the minimum confirmations
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Not enough funds with the minimum confirmations",
+        ))
     }
 
-    fn fill_txins(
+    /// Chooses the available utxos of the wallet that fund the amount, returning them and their total value
+    fn select_utxos(
         &self,
-        utxo_set: &mut UtxoSet,
+        utxo_set: &UtxoSet,
         amount: u64,
-    ) -> io::Result<(Vec<TxInput>, u64, Vec<Lock>)> {
-        // get available utxos
-        let available_utxos: Vec<(HashId, UtxoTransaction)> =
-            utxo_set.get_wallet_available_utxos(&self.address);
+    ) -> io::Result<(Vec<(HashId, UtxoTransaction)>, u64)> {
+        let available_utxos: Vec<(HashId, UtxoTransaction)> = self
+            .addresses()
+            .into_iter()
+            .flat_map(|address| utxo_set.get_wallet_available_utxos(address))
+            .collect();
 
         if available_utxos.is_empty() {
             return Err(io::Error::new(io::ErrorKind::Other, "No available utxos"));
         }
 
-        let (used_utxos, used_balance) = Self::fill_needed(amount, available_utxos);
+        Self::fill_needed(amount, available_utxos)
+    }
+
+    /// Returns the outpoints and values of the utxos coin selection would spend to send the transaction,
+    /// without building nor signing it, so the coins can be shown before confirming
+    pub fn preview_inputs(
+        &self,
+        utxo_set: &UtxoSet,
+        transaction_info: &TransactionInfo,
+    ) -> io::Result<Vec<(HashId, u32, u64)>> {
+        let needed = recipients_amount(transaction_info)?
+            .checked_add(transaction_info.fee)
+            .ok_or_else(overflow_error)?;
+        let (used_utxos, _used_balance) = self.select_utxos(utxo_set, needed)?;
+        Ok(used_utxos
+            .into_iter()
+            .map(|(utxo_id, utxo)| (utxo_id, utxo.index, utxo.value))
+            .collect())
+    }
+
+    fn fill_txins(
+        &self,
+        utxo_set: &UtxoSet,
+        amount: u64,
+    ) -> io::Result<(Vec<TxInput>, u64, Vec<Prevout>)> {
+        let (used_utxos, used_balance) = self.select_utxos(utxo_set, amount)?;
 
         // build txins
         let mut txins: Vec<TxInput> = Vec::new();
-        let mut locks: Vec<Lock> = Vec::new();
-        for (utxo_id, utxo, lock) in used_utxos {
+        let mut prevouts: Vec<Prevout> = Vec::new();
+        for (utxo_id, utxo) in used_utxos {
+            // the lock being signed is the one of the unspent output in the set, or the one the wallet recorded
+            // for outputs the set doesn't index, which coin selection already counted as spendable
+            let (value, lock) = match utxo_set.get_txout(&utxo_id, utxo.index) {
+                Some((value, lock, _coinbase)) => (value, lock),
+                None => (utxo.value, utxo.lock),
+            };
             let txin = TxInput {
                 previous_output: Outpoint {
                     hash: utxo_id,
@@ -245,11 +631,11 @@ impl Wallet {
                 sequence: 0xffffffff,
             };
             txins.push(txin);
-            locks.push(lock);
+            prevouts.push((value, lock));
         }
 
         // return used utxos and used balance
-        Ok((txins, used_balance, locks))
+        Ok((txins, used_balance, prevouts))
     }
 
     fn fill_txouts(
@@ -262,77 +648,197 @@ impl Wallet {
 
         //  the first txout is destined for the receiver
         for (recv_addr, _label, spec_amount) in transaction_info.recipients {
-            let recv_hashed_pk = hash_address(&recv_addr)?;
-            let first_pk_script = build_p2pkh_script(recv_hashed_pk)?;
+            let first_pk_script = build_output_script(&recv_addr)?;
             txout.push(TxOutput {
                 value: spec_amount,
                 pk_script_bytes: first_pk_script.len() as u64,
                 pk_script: first_pk_script,
             });
         }
-        //  the last txout is our "change"
-        let self_hashed_pk = hash_address(&self.address)?;
-        let second_pk_script = build_p2pkh_script(self_hashed_pk)?;
-        let value: u64 = match used_balance > (amount + transaction_info.fee) {
-            true => used_balance - amount - transaction_info.fee,
-            false => 0,
-        };
-        txout.push(TxOutput {
-            value,
-            pk_script_bytes: second_pk_script.len() as u64,
-            pk_script: second_pk_script,
-        });
+        //  the last txout is our "change", to our change address or of the same type as our address, unless
+        //  there's none left
+        let value = change_value(used_balance, amount, transaction_info.fee)?;
+        if value > 0 {
+            let change_address = self.change_address.as_ref().unwrap_or(&self.address);
+            let second_pk_script = build_output_script(change_address)?;
+            txout.push(TxOutput {
+                value,
+                pk_script_bytes: second_pk_script.len() as u64,
+                pk_script: second_pk_script,
+            });
+        }
 
         Ok(txout)
     }
 
-    /// Generates a transaction from the wallet's utxos, filling the transaction with the given transaction info.
-    /// Removes the used utxos from the utxo set.
-    /// If the wallet does not have enough funds, returns an error.
-    pub fn generate_transaction(
+    fn build_unsigned_with_prevouts(
         &self,
-        utxo_set: &mut UtxoSet,
+        utxo_set: &UtxoSet,
         transaction_info: TransactionInfo,
-    ) -> io::Result<RawTransaction> {
-        let secp = Secp256k1::new();
-        let amount = transaction_info
-            .recipients
-            .iter()
-            .fold(0, |acc, x| acc + x.2);
+    ) -> io::Result<(RawTransaction, Vec<Prevout>, u64)> {
+        let amount = recipients_amount(&transaction_info)?;
+        let needed = amount
+            .checked_add(transaction_info.fee)
+            .ok_or_else(overflow_error)?;
 
-        if utxo_set.get_wallet_balance(&self.address) <= amount {
+        if self.balance(utxo_set) <= amount {
             return Err(io::Error::new(io::ErrorKind::Other, "Not enough funds"));
         }
 
-        let (txin, used_balance, locks) =
-            self.fill_txins(utxo_set, transaction_info.fee + amount)?;
+        let (txin, used_balance, prevouts) = self.fill_txins(utxo_set, needed)?;
         let txout = self.fill_txouts(amount, used_balance, transaction_info)?;
-        let mut transaction = RawTransaction {
+        let spent = txout
+            .iter()
+            .try_fold(0u64, |total, output| total.checked_add(output.value))
+            .ok_or_else(overflow_error)?;
+        let transaction = RawTransaction {
             version: 1,
             tx_in_count: txin.len() as u64,
-            tx_in: TxInputType::TxInput(txin.clone()),
+            tx_in: TxInputType::TxInput(txin),
             tx_out_count: txout.len() as u64,
             tx_out: txout,
             lock_time: 0,
+            witnesses: vec![],
         };
+        let fee = used_balance.checked_sub(spent).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Outputs add up to more than the inputs",
+            )
+        })?;
+        Ok((transaction, prevouts, fee))
+    }
 
-        for (index, _) in locks.iter().enumerate().take(txin.len()) {
-            let prev_pk_script = locks[index].clone();
-            transaction.sign_input(&secp, &self.secret_key, prev_pk_script, index)?;
+    /// Performs coin selection and builds the outputs of a transaction without signing nor broadcasting it.
+    /// Returns the unsigned transaction, the fee it would pay and its estimated size once signed.
+    pub fn build_unsigned(
+        &self,
+        utxo_set: &UtxoSet,
+        transaction_info: TransactionInfo,
+    ) -> io::Result<(RawTransaction, u64, usize)> {
+        let (transaction, _prevouts, fee) =
+            self.build_unsigned_with_prevouts(utxo_set, transaction_info)?;
+        let size = transaction.serialize().len()
+            + transaction.tx_in_count as usize * P2PKH_SCRIPT_SIG_SIZE;
+        Ok((transaction, fee, size))
+    }
+
+    /// Builds and signs a transaction from the wallet's utxos paying the given transaction info. The utxos aren't
+    /// marked as spent until the transaction is read back as pending.
+    /// If the wallet does not have enough funds, or the fee paid exceeds the given ceiling, returns an error.
+    /// Sending the transaction refused for its fee again confirms paying it.
+    pub fn generate_transaction(
+        &mut self,
+        utxo_set: &mut UtxoSet,
+        transaction_info: TransactionInfo,
+        max_fee: Option<u64>,
+    ) -> io::Result<RawTransaction> {
+        let secret_key = match self.secret_key {
+            Some(secret_key) => secret_key,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Watch-only wallets can't sign transactions",
+                ))
+            }
+        };
+        let secp = Secp256k1::new();
+        let (mut transaction, prevouts) =
+            self.build_below_max_fee(utxo_set, transaction_info, max_fee)?;
+        let locks = prevouts.into_iter().map(|(_value, lock)| lock).collect();
+        match self.change_key {
+            Some(_) => {
+                self.sign_with_own_keys(&secp, &mut transaction, locks, utxo_set.address_format())?
+            }
+            None => transaction.sign_inputs(&secp, &secret_key, locks)?,
         }
         Ok(transaction)
     }
+
+    /// Signs each input with the key of the address whose output it spends, for wallets holding several keys
+    fn sign_with_own_keys(
+        &self,
+        secp: &Secp256k1<All>,
+        transaction: &mut RawTransaction,
+        locks: Vec<Vec<u8>>,
+        format: &AddressFormat,
+    ) -> io::Result<()> {
+        for (index, lock) in locks.into_iter().enumerate() {
+            let secret_key = self.key_for(&lock_address(&lock, format)).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Input spends an output of another wallet",
+                )
+            })?;
+            transaction.sign_input(secp, &secret_key, lock, index)?;
+        }
+        Ok(())
+    }
+
+    /// Builds a transaction like `build_unsigned`, along with the outputs it spends so it can be signed offline.
+    /// The fee is held to the same ceiling as the transactions the wallet signs.
+    pub fn build_partial(
+        &mut self,
+        utxo_set: &UtxoSet,
+        transaction_info: TransactionInfo,
+        max_fee: Option<u64>,
+    ) -> io::Result<PartialTransaction> {
+        let (transaction, prevouts) =
+            self.build_below_max_fee(utxo_set, transaction_info, max_fee)?;
+        PartialTransaction::new(transaction, prevouts)
+    }
+
+    /// Builds the unsigned transaction, refusing it if its fee exceeds the ceiling unless it's the one last
+    /// refused, sent again to confirm paying it
+    fn build_below_max_fee(
+        &mut self,
+        utxo_set: &UtxoSet,
+        transaction_info: TransactionInfo,
+        max_fee: Option<u64>,
+    ) -> io::Result<(RawTransaction, Vec<Prevout>)> {
+        let confirmed = self.fee_to_confirm.take().as_ref() == Some(&transaction_info);
+        let (transaction, prevouts, fee) =
+            self.build_unsigned_with_prevouts(utxo_set, transaction_info.clone())?;
+        match max_fee.filter(|max_fee| fee > *max_fee && !confirmed) {
+            Some(max_fee) => {
+                self.fee_to_confirm = Some(transaction_info);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Fee of {} satoshis exceeds the max_fee of {}, send the transaction again to confirm paying it",
+                        fee, max_fee
+                    ),
+                ))
+            }
+            None => Ok((transaction, prevouts)),
+        }
+    }
+
+    /// Signs the inputs of the partial transaction that spend outputs of this wallet, whose addresses have the
+    /// given format. Returns the amount of inputs signed.
+    pub fn sign_partial(
+        &self,
+        partial: &mut PartialTransaction,
+        format: &AddressFormat,
+    ) -> io::Result<usize> {
+        let secp = Secp256k1::new();
+        let mut signed = 0;
+        for index in 0..partial.prevouts.len() {
+            if let Some(secret_key) =
+                self.key_for(&lock_address(&partial.prevouts[index].1, format))
+            {
+                partial.sign_input(&secp, &secret_key, index)?;
+                signed += 1;
+            }
+        }
+        Ok(signed)
+    }
 }
 
 impl TryFrom<&str> for Wallet {
     type Error = io::Error;
     fn try_from(secret_key: &str) -> io::Result<Wallet> {
-        let key = SecretKey::from_str(secret_key).map_err(to_io_err)?;
-        Ok(Self {
-            secret_key: key,
-            address: Self::get_address_from_secret_key(&key),
-            history: Vec::new(),
-        })
+        Self::from_secret_key(secret_key, &TESTNET_ADDRESSES)
     }
 }
 
@@ -340,11 +846,15 @@ impl TryFrom<&str> for Wallet {
 mod tests {
     use crate::{
         interface::components::overview_panel::TransactionRole,
+        messages::BlockHeader,
+        network_params::Network,
         raw_transaction::{RawTransaction, TransactionOrigin},
-        utility::{_encode_hex, decode_hex},
+        utility::{decode_hex, encode_hex},
     };
 
     use super::*;
+    use crate::raw_transaction::interpreter::TxChecker;
+    use crate::test_fixtures::{tx_from_hex, FUNDING_TX_HEX, SPENDING_TX_HEX};
     use std::io::Cursor;
 
     #[test]
@@ -356,7 +866,7 @@ mod tests {
 
     #[test]
     fn create_wallet() {
-        let my_wallet = Wallet::new();
+        let my_wallet = Wallet::new(&TESTNET_ADDRESSES);
         println!("Wallet: {:?}", my_wallet);
     }
 
@@ -367,6 +877,40 @@ mod tests {
         let res = hash_address(address).unwrap();
         let expected = decode_hex("6fc9bc003bf72ebdc53a9572f7ea792ef49a2858d78fc12f84").unwrap();
         assert_eq!(res, expected);
+
+        // a mistyped character breaks the checksum
+        let error = hash_address("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUY").unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn test_mainnet_recipient_rejected_on_testnet() -> io::Result<()> {
+        let network_params = NetworkParams::new(Network::Testnet, &[])?;
+        let paying = |address: &str| TransactionInfo {
+            recipients: vec![(address.to_string(), String::new(), 1000)],
+            fee: 100,
+        };
+
+        let mainnet = paying("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2");
+        let error = check_recipient_networks(&mainnet, &network_params).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let testnet = paying("mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun");
+        check_recipient_networks(&testnet, &network_params)
+    }
+
+    #[test]
+    fn test_testnet_segwit_recipient_rejected_on_mainnet() -> io::Result<()> {
+        let network_params = NetworkParams::new(Network::Mainnet, &[])?;
+        let paying = |address: &str| TransactionInfo {
+            recipients: vec![(address.to_string(), String::new(), 1000)],
+            fee: 100,
+        };
+
+        let testnet = paying("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        let error = check_recipient_networks(&testnet, &network_params).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mainnet = paying("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        check_recipient_networks(&mainnet, &network_params)
     }
 
     #[test]
@@ -376,9 +920,7 @@ mod tests {
             .try_into()
             .unwrap();
 
-        let transaction_bytes = decode_hex(
-            "020000000001011216d10ae3afe6119529c0a01abe7833641e0e9d37eb880ae5547cfb7c6c7bca0000000000fdffffff0246b31b00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac731f2001020000001976a914d617966c3f29cfe50f7d9278dd3e460e3f084b7b88ac02473044022059570681a773748425ddd56156f6af3a0a781a33ae3c42c74fafd6cc2bd0acbc02200c4512c250f88653fae4d73e0cab419fa2ead01d6ba1c54edee69e15c1618638012103e7d8e9b09533ae390d0db3ad53cc050a54f89a987094bffac260f25912885b834b2c2500"
-        ).unwrap();
+        let transaction_bytes = decode_hex(FUNDING_TX_HEX).unwrap();
         let transaction = RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes)).unwrap();
         transaction
             .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
@@ -395,9 +937,7 @@ mod tests {
             .try_into()
             .unwrap();
 
-        let transaction_1_bytes = decode_hex(
-            "020000000001011216d10ae3afe6119529c0a01abe7833641e0e9d37eb880ae5547cfb7c6c7bca0000000000fdffffff0246b31b00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac731f2001020000001976a914d617966c3f29cfe50f7d9278dd3e460e3f084b7b88ac02473044022059570681a773748425ddd56156f6af3a0a781a33ae3c42c74fafd6cc2bd0acbc02200c4512c250f88653fae4d73e0cab419fa2ead01d6ba1c54edee69e15c1618638012103e7d8e9b09533ae390d0db3ad53cc050a54f89a987094bffac260f25912885b834b2c2500"
-        ).unwrap();
+        let transaction_1_bytes = decode_hex(FUNDING_TX_HEX).unwrap();
         let transaction_1 =
             RawTransaction::from_bytes(&mut Cursor::new(&transaction_1_bytes)).unwrap();
         transaction_1
@@ -413,9 +953,7 @@ mod tests {
             .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
             .unwrap();
 
-        let transaction_3_bytes = decode_hex(
-            "0100000001881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff0210270000000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac96051a00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac00000000"
-        ).unwrap();
+        let transaction_3_bytes = decode_hex(SPENDING_TX_HEX).unwrap();
         let transaction_3 =
             RawTransaction::from_bytes(&mut Cursor::new(&transaction_3_bytes)).unwrap();
         transaction_3
@@ -428,15 +966,13 @@ mod tests {
 
     #[test]
     fn test_generate_raw_transaction() {
-        let wallet: Wallet = "E7C33EA70CF2DBB24AA71F0604D7956CCBC5FE8F8F20C51328A14AC8725BE0F5"
+        let mut wallet: Wallet = "E7C33EA70CF2DBB24AA71F0604D7956CCBC5FE8F8F20C51328A14AC8725BE0F5"
             .try_into()
             .unwrap();
         let mut utxo_set: UtxoSet = UtxoSet::new();
 
         // this transactions should give enough balance to send 1 tBTC
-        let transaction_bytes = decode_hex(
-            "020000000001011216d10ae3afe6119529c0a01abe7833641e0e9d37eb880ae5547cfb7c6c7bca0000000000fdffffff0246b31b00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac731f2001020000001976a914d617966c3f29cfe50f7d9278dd3e460e3f084b7b88ac02473044022059570681a773748425ddd56156f6af3a0a781a33ae3c42c74fafd6cc2bd0acbc02200c4512c250f88653fae4d73e0cab419fa2ead01d6ba1c54edee69e15c1618638012103e7d8e9b09533ae390d0db3ad53cc050a54f89a987094bffac260f25912885b834b2c2500"
-        ).unwrap();
+        let transaction_bytes = decode_hex(FUNDING_TX_HEX).unwrap();
         let transaction = RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes)).unwrap();
         transaction
             .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
@@ -449,7 +985,7 @@ mod tests {
             fee: 100000,
         };
         let raw_transaction = wallet
-            .generate_transaction(&mut utxo_set, transaction_info)
+            .generate_transaction(&mut utxo_set, transaction_info, None)
             .unwrap();
 
         let bytes = raw_transaction.serialize();
@@ -459,8 +995,299 @@ mod tests {
         assert_eq!(res.tx_out[0].value, 10000);
         assert_eq!(res.tx_out[1].value, 1705366); // deducted fee of 10000
 
-        let expected = "0100000001881468a1a95473ed788c8a13bcdb7e524eac4f1088b1e2606ffb95492e239b10000000006a473044022021dc538aab629f2be56304937e796884356d1e79499150f5df03e8b8a545d17702205b76bda9c238035c907cbf6a39fa723d65f800ebb8082bdbb62d016d7937d990012102a953c8d6e15c569ea2192933593518566ca7f49b59b91561c01e30d55b0e1922ffffffff0210270000000000001976a9144a82aaa02eba3c31cd86ee83345c4f91986743fe88ac96051a00000000001976a914c9bc003bf72ebdc53a9572f7ea792ef49a2858d788ac00000000";
-        assert_eq!(expected, _encode_hex(&bytes));
+        let expected = SPENDING_TX_HEX;
+        assert_eq!(expected, encode_hex(&bytes));
+    }
+
+    fn funded_wallet() -> (Wallet, UtxoSet) {
+        let wallet: Wallet = "E7C33EA70CF2DBB24AA71F0604D7956CCBC5FE8F8F20C51328A14AC8725BE0F5"
+            .try_into()
+            .unwrap();
+        let mut utxo_set: UtxoSet = UtxoSet::new();
+
+        let transaction_bytes = decode_hex(FUNDING_TX_HEX).unwrap();
+        let transaction = RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes)).unwrap();
+        transaction
+            .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+        (wallet, utxo_set)
+    }
+
+    #[test]
+    fn test_build_unsigned_fee_matches_signed_transaction() {
+        let (mut wallet, mut utxo_set) = funded_wallet();
+        let recipients = vec![(
+            "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+            "foo".to_string(),
+            10000,
+        )];
+        let transaction_info = TransactionInfo {
+            recipients,
+            fee: 100000,
+        };
+        let (unsigned, fee, size) = wallet
+            .build_unsigned(&utxo_set, transaction_info.clone())
+            .unwrap();
+        let signed = wallet
+            .generate_transaction(&mut utxo_set, transaction_info, None)
+            .unwrap();
+
+        let signed_outputs: u64 = signed.tx_out.iter().map(|output| output.value).sum();
+        assert_eq!(fee, 1815366 - signed_outputs);
+        assert_eq!(fee, 100000);
+        assert_eq!(
+            TxOutput::serialize_vec(&unsigned.tx_out),
+            TxOutput::serialize_vec(&signed.tx_out)
+        );
+        let signed_size = signed.serialize().len();
+        assert!(size >= signed_size && size - signed_size <= 2);
+    }
+
+    #[test]
+    fn test_utxo_missing_from_the_outpoint_index_is_still_spent() {
+        let (mut wallet, mut utxo_set) = funded_wallet();
+        utxo_set.outpoint_index.clear();
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                10000,
+            )],
+            fee: 1000,
+        };
+        let signed = wallet
+            .generate_transaction(&mut utxo_set, transaction_info, None)
+            .unwrap();
+        assert_eq!(signed.tx_in_count, 1);
+    }
+
+    #[test]
+    fn test_fee_above_the_ceiling_is_rejected() {
+        let (mut wallet, mut utxo_set) = funded_wallet();
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                10000,
+            )],
+            fee: 100000,
+        };
+
+        let error = wallet
+            .generate_transaction(&mut utxo_set, transaction_info.clone(), Some(99999))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("max_fee"));
+        assert!(wallet
+            .generate_transaction(&mut utxo_set, transaction_info.clone(), Some(100000))
+            .is_ok());
+
+        // sending the refused transaction again confirms its fee, unless another one was refused since
+        let mut other = transaction_info.clone();
+        other.fee += 1;
+        for refused in [&transaction_info, &other, &transaction_info] {
+            assert!(wallet
+                .generate_transaction(&mut utxo_set, refused.clone(), Some(99999))
+                .is_err());
+        }
+        assert!(wallet
+            .generate_transaction(&mut utxo_set, transaction_info.clone(), Some(99999))
+            .is_ok());
+        let mut watch_only = Wallet::watch_only(&wallet.address).unwrap();
+        assert!(watch_only
+            .build_partial(&utxo_set, transaction_info, Some(99999))
+            .is_err());
+    }
+
+    #[test]
+    fn test_previewed_inputs_are_the_ones_spent() {
+        let (mut wallet, _) = funded_wallet();
+        // several coins of the wallet, only some of them needed to pay
+        let mut funding = tx_from_hex(FUNDING_TX_HEX).unwrap();
+        let pk_script = build_output_script(&wallet.address).unwrap();
+        funding.tx_out = [20000, 50000, 30000, 40000]
+            .into_iter()
+            .map(|value| TxOutput {
+                value,
+                pk_script_bytes: pk_script.len() as u64,
+                pk_script: pk_script.clone(),
+            })
+            .collect();
+        funding.tx_out_count = funding.tx_out.len() as u64;
+        let mut utxo_set = UtxoSet::new();
+        funding
+            .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                60000,
+            )],
+            fee: 1000,
+        };
+        let previewed = wallet.preview_inputs(&utxo_set, &transaction_info).unwrap();
+        let transaction = wallet
+            .generate_transaction(&mut utxo_set, transaction_info, None)
+            .unwrap();
+
+        let spent: Vec<(HashId, u32)> = match &transaction.tx_in {
+            TxInputType::TxInput(inputs) => inputs
+                .iter()
+                .map(|input| (input.previous_output.hash, input.previous_output.index))
+                .collect(),
+            TxInputType::CoinBaseInput(_) => vec![],
+        };
+        let previewed_outpoints: Vec<(HashId, u32)> = previewed
+            .iter()
+            .map(|(txid, index, _value)| (*txid, *index))
+            .collect();
+        assert_eq!(previewed_outpoints, spent);
+        assert!(previewed.len() > 1 && previewed.len() < 4);
+        let previewed_value: u64 = previewed.iter().map(|(_txid, _index, value)| value).sum();
+        let spent_value = previewed_value - transaction.get_total_output_value();
+        assert_eq!(spent_value, 1000);
+    }
+
+    #[test]
+    fn test_overflowing_amount_and_fee_are_rejected() {
+        let (wallet, utxo_set) = funded_wallet();
+        let recipient = |amount| {
+            (
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                amount,
+            )
+        };
+        let overflowing = [
+            TransactionInfo {
+                recipients: vec![recipient(u64::MAX)],
+                fee: 1,
+            },
+            TransactionInfo {
+                recipients: vec![recipient(u64::MAX / 2 + 1), recipient(u64::MAX / 2 + 1)],
+                fee: 0,
+            },
+        ];
+        for transaction_info in overflowing {
+            let result = wallet.build_unsigned(&utxo_set, transaction_info);
+            assert_eq!(
+                result.map_err(|e| e.kind()).err(),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+    }
+
+    #[test]
+    fn test_change_at_the_exact_boundary_is_left_out() {
+        let (wallet, utxo_set) = funded_wallet();
+        let transaction_info = |amount| TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                amount,
+            )],
+            fee: 100000,
+        };
+
+        // the balance covers exactly the amount and fee, there's no change
+        let (unsigned, fee, _) = wallet
+            .build_unsigned(&utxo_set, transaction_info(1715366))
+            .unwrap();
+        assert_eq!(unsigned.tx_out.len(), 1);
+        assert_eq!(fee, 100000);
+
+        // dust change goes to the fee, one satoshi more is kept as change
+        let (unsigned, fee, _) = wallet
+            .build_unsigned(&utxo_set, transaction_info(1715366 - DUST_CHANGE + 1))
+            .unwrap();
+        assert_eq!(unsigned.tx_out.len(), 1);
+        assert_eq!(fee, 100000 + DUST_CHANGE - 1);
+        let (unsigned, fee, _) = wallet
+            .build_unsigned(&utxo_set, transaction_info(1715366 - DUST_CHANGE))
+            .unwrap();
+        assert_eq!(unsigned.tx_out[1].value, DUST_CHANGE);
+        assert_eq!(fee, 100000);
+
+        assert!(change_value(1815366, 1715366, 100001).is_err());
+    }
+
+    #[test]
+    fn test_segwit_wallet_change_is_p2wpkh() {
+        let wallet = Wallet::watch_only("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        let pk_script = decode_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let funding = RawTransaction {
+            version: 1,
+            tx_in_count: 1,
+            tx_in: TxInputType::TxInput(vec![TxInput {
+                previous_output: Outpoint {
+                    hash: HashId::new([7; 32]),
+                    index: 0,
+                },
+                script_bytes: 0,
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }]),
+            tx_out_count: 1,
+            tx_out: vec![TxOutput {
+                value: 50000,
+                pk_script_bytes: pk_script.len() as u64,
+                pk_script: pk_script.clone(),
+            }],
+            lock_time: 0,
+            witnesses: vec![],
+        };
+        let mut utxo_set = UtxoSet::new();
+        funding
+            .generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)
+            .unwrap();
+
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                10000,
+            )],
+            fee: 1000,
+        };
+        let (unsigned, fee, _) = wallet.build_unsigned(&utxo_set, transaction_info).unwrap();
+        assert_eq!(fee, 1000);
+        assert_eq!(&unsigned.tx_out[0].pk_script[..3], [0x76, 0xa9, 0x14]);
+        let change = &unsigned.tx_out[1];
+        assert_eq!(change.value, 39000);
+        assert_eq!(&change.pk_script[..2], [0x00, 0x14]);
+        assert_eq!(change.pk_script, pk_script);
+    }
+
+    #[test]
+    fn test_partial_transaction_signed_offline() -> io::Result<()> {
+        let (mut wallet, mut utxo_set) = funded_wallet();
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                10000,
+            )],
+            fee: 100000,
+        };
+        let mut watch_only = Wallet::watch_only(&wallet.address)?;
+        let mut partial = watch_only.build_partial(&utxo_set, transaction_info.clone(), None)?;
+        assert_eq!(
+            watch_only.sign_partial(&mut partial, &TESTNET_ADDRESSES)?,
+            0
+        );
+        assert!(partial.finalize().is_err());
+
+        // the offline signer only gets the serialized partial transaction
+        let mut offline = PartialTransaction::from_bytes(&partial.to_bytes())?;
+        assert_eq!(wallet.sign_partial(&mut offline, &TESTNET_ADDRESSES)?, 1);
+        let signed = PartialTransaction::from_bytes(&offline.to_bytes())?;
+
+        partial.combine(&signed)?;
+        let finalized = partial.finalize()?;
+        let expected = wallet.generate_transaction(&mut utxo_set, transaction_info, None)?;
+        assert_eq!(finalized.serialize(), expected.serialize());
+        Ok(())
     }
 
     #[test]
@@ -485,9 +1312,12 @@ mod tests {
     #[test]
     fn test_update_transaction_history_from_pending() {
         let mut wallet = Wallet {
-            secret_key: SecretKey::new(&mut OsRng),
+            secret_key: Some(SecretKey::new(&mut OsRng)),
             address: "bar".to_string(),
             history: Vec::new(),
+            fee_to_confirm: None,
+            change_address: None,
+            change_key: None,
         };
 
         let mut transaction_info = TransactionDisplayInfo {
@@ -507,4 +1337,212 @@ mod tests {
         assert_eq!(wallet.history.len(), 1);
         assert_eq!(wallet.history[0].origin, TransactionOrigin::Block);
     }
+
+    #[test]
+    fn test_bounced_transaction_is_not_duplicated_in_history() {
+        let mut wallet = Wallet {
+            secret_key: Some(SecretKey::new(&mut OsRng)),
+            address: "bar".to_string(),
+            history: Vec::new(),
+            fee_to_confirm: None,
+            change_address: None,
+            change_key: None,
+        };
+        let transaction_info = TransactionDisplayInfo {
+            role: TransactionRole::Sender,
+            origin: TransactionOrigin::Pending,
+            date: "date".to_string(),
+            amount: 10,
+            hash: HashId::new([1_u8; 32]),
+        };
+
+        wallet.update_history(transaction_info.clone());
+        // a peer relays our own transaction back to us
+        wallet.update_history(transaction_info);
+        assert_eq!(wallet.history.len(), 1);
+        assert_eq!(wallet.history[0].origin, TransactionOrigin::Pending);
+    }
+
+    #[test]
+    fn test_watch_only_wallets_from_file() {
+        let file_name = "test_watch_addresses.txt";
+        let addresses = "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX\nnotAnAddress\nmnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun\n";
+        std::fs::write(file_name, addresses).unwrap();
+
+        let (wallets, invalid) = Wallet::watch_only_from_file(file_name).unwrap();
+        std::fs::remove_file(file_name).unwrap();
+
+        assert_eq!(wallets.len(), 2);
+        assert!(wallets.iter().all(|w| w.is_watch_only()));
+        assert_eq!(wallets[0].address, "myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX");
+        assert_eq!(wallets[1].address, "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun");
+        assert_eq!(invalid, vec!["notAnAddress".to_string()]);
+    }
+
+    #[test]
+    fn test_watch_only_wallet_cannot_sign() {
+        let (_, mut utxo_set) = funded_wallet();
+        let mut wallet = Wallet::watch_only("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX").unwrap();
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                10000,
+            )],
+            fee: 100000,
+        };
+        assert!(wallet
+            .build_unsigned(&utxo_set, transaction_info.clone())
+            .is_ok());
+        assert!(wallet
+            .generate_transaction(&mut utxo_set, transaction_info, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_imported_address_shows_earlier_funds_after_rescan() -> io::Result<()> {
+        let transaction_bytes = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        let transaction = RawTransaction::from_bytes(&mut Cursor::new(&transaction_bytes))?;
+        let header = BlockHeader::new(1, HashId::default(), None, transaction.txid(), 0, 0, 0);
+        let block = Block::new(header, 1, vec![transaction]);
+
+        // the block is processed before the address is imported
+        let mut utxo_set = UtxoSet::new();
+        block.expand_utxo(&mut utxo_set, None, &mut HashMap::new(), None)?;
+
+        let mut wallet = Wallet::watch_only("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX")?;
+        assert_eq!(wallet.rescan([&block], &mut utxo_set), 1);
+        assert_eq!(wallet.history[0].role, TransactionRole::Receiver);
+        assert_eq!(wallet.history[0].amount, 1815366);
+        assert_eq!(utxo_set.get_wallet_balance(&wallet.address), 1815366);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuilt_history_follows_the_chain() -> io::Result<()> {
+        let funding_bytes = decode_hex(FUNDING_TX_HEX).map_err(to_io_err)?;
+        // spends the output of the funding transaction paying the address, sending the change back to it
+        let spending_bytes = decode_hex(SPENDING_TX_HEX).map_err(to_io_err)?;
+        let mut blocks = vec![];
+        let mut utxo_set = UtxoSet::new();
+        for bytes in [funding_bytes, spending_bytes] {
+            let transaction = RawTransaction::from_bytes(&mut Cursor::new(&bytes))?;
+            let header = BlockHeader::new(1, HashId::default(), None, transaction.txid(), 0, 0, 0);
+            let block = Block::new(header, 1, vec![transaction]);
+            block.expand_utxo(&mut utxo_set, None, &mut HashMap::new(), None)?;
+            blocks.push(block);
+        }
+
+        // a pending entry that never resolved
+        let mut wallet = Wallet::watch_only("myudL9LPYaJUDXWXGz5WC6RCdcTKCAWMUX")?;
+        wallet.update_history(TransactionDisplayInfo {
+            role: TransactionRole::Receiver,
+            origin: TransactionOrigin::Pending,
+            date: String::new(),
+            amount: 1,
+            hash: HashId::default(),
+        });
+
+        assert_eq!(wallet.rebuild_history(&blocks, &mut utxo_set), 2);
+        let history: Vec<(HashId, TransactionRole)> = wallet
+            .history
+            .iter()
+            .map(|tx| (tx.hash, tx.role.clone()))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (blocks[0].txns[0].txid(), TransactionRole::Receiver),
+                (blocks[1].txns[0].txid(), TransactionRole::Sender)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hd_accounts_derive_distinct_addresses() -> io::Result<()> {
+        let seed = decode_hex("000102030405060708090a0b0c0d0e0f").map_err(to_io_err)?;
+        let testnet = NetworkParams::new(Network::Testnet, &[])?;
+        let account_0 = Wallet::from_seed(&seed, 0, &testnet)?;
+        let account_1 = Wallet::from_seed(&seed, 1, &testnet)?;
+        assert_ne!(account_0.address, account_1.address);
+        assert_ne!(account_0.secret_key, account_1.secret_key);
+        assert_ne!(account_0.change_address, Some(account_0.address.clone()));
+        // the same account is recovered from the seed
+        assert_eq!(Wallet::from_seed(&seed, 1, &testnet)?, account_1);
+        assert!(Wallet::from_seed(&seed, HARDENED, &testnet).is_err());
+
+        // mainnet keys are derived with their own coin type
+        let mainnet = Wallet::from_seed(&seed, 0, &NetworkParams::new(Network::Mainnet, &[])?)?;
+        assert_ne!(mainnet.secret_key, account_0.secret_key);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mainnet_hd_account_sees_the_funds_of_its_address() -> io::Result<()> {
+        let seed = decode_hex("000102030405060708090a0b0c0d0e0f").map_err(to_io_err)?;
+        let mainnet = NetworkParams::new(Network::Mainnet, &[])?;
+        let wallet = Wallet::from_seed(&seed, 0, &mainnet)?;
+        assert!(wallet.address.starts_with('1'));
+        let pk_script = build_output_script(&wallet.address)?;
+        let mut funding = tx_from_hex(SPENDING_TX_HEX)?;
+        funding.tx_out = vec![TxOutput {
+            value: 30000,
+            pk_script_bytes: pk_script.len() as u64,
+            pk_script,
+        }];
+        funding.tx_out_count = 1;
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.set_address_format(mainnet.address_format);
+        funding.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+        assert_eq!(utxo_set.get_wallet_balance(&wallet.address), 30000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hd_account_spends_both_chains_and_sends_change_to_the_change_chain() -> io::Result<()> {
+        let seed = decode_hex("000102030405060708090a0b0c0d0e0f").map_err(to_io_err)?;
+        let mut wallet = Wallet::from_seed(&seed, 0, &NetworkParams::new(Network::Testnet, &[])?)?;
+        let change_address = wallet.change_address.clone().unwrap_or_default();
+        let output = |address: &str| -> io::Result<TxOutput> {
+            let pk_script = build_output_script(address)?;
+            Ok(TxOutput {
+                value: 30000,
+                pk_script_bytes: pk_script.len() as u64,
+                pk_script,
+            })
+        };
+        let mut funding = tx_from_hex(SPENDING_TX_HEX)?;
+        funding.tx_out = vec![output(&wallet.address)?, output(&change_address)?];
+        let mut utxo_set = UtxoSet::new();
+        funding.generate_utxo(&mut utxo_set, TransactionOrigin::Block, None, None)?;
+        let transaction_info = TransactionInfo {
+            recipients: vec![(
+                "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_string(),
+                "foo".to_string(),
+                50000,
+            )],
+            fee: 1000,
+        };
+
+        let tx = wallet.generate_transaction(&mut utxo_set, transaction_info.clone(), None)?;
+        assert_eq!(tx.tx_in_count, 2);
+        assert_eq!(tx.tx_out[1].value, 9000);
+        assert_eq!(
+            tx.tx_out[1].pk_script,
+            build_output_script(&change_address)?
+        );
+        let soft_forks = NetworkParams::new(Network::Testnet, &[])?.soft_forks(1);
+        let checker = TxChecker::new(&tx)?;
+        for (index, tx_out) in funding.tx_out.iter().enumerate() {
+            checker.verify_input(index, (tx_out.value, &tx_out.pk_script), soft_forks)?;
+        }
+
+        // with the change shown apart, each wallet only spends its own coins
+        let change = wallet.split_change();
+        assert_eq!(change.map(|change| change.address), Some(change_address));
+        assert_eq!(wallet.addresses(), vec![wallet.address.as_str()]);
+        assert!(wallet.build_unsigned(&utxo_set, transaction_info).is_err());
+        Ok(())
+    }
 }